@@ -2,6 +2,7 @@ use std::{
     io::{BufRead, Write},
     path::Path,
     process::{ChildStdin, Command, Stdio},
+    sync::Arc,
     thread::{self, sleep},
     time::Duration,
 };
@@ -13,6 +14,7 @@ use tempfile::{tempfile, NamedTempFile};
 
 use crate::{
     android::adb::{command::local_service::ShellCommand, utils::execute_adb_command, Device},
+    clock::{Clock, SystemClock},
     Toucher,
 };
 
@@ -39,6 +41,8 @@ enum Cmd {
 pub struct MiniTouch {
     minitouch_stdin: ChildStdin,
     state: MiniTouchState,
+    /// 滑动时每一次移动之间的等待
+    clock: Arc<dyn Clock>,
 
     cmd_tx: async_channel::Sender<Cmd>,
 }
@@ -231,6 +235,7 @@ impl App for MiniTouch {
         Ok(MiniTouch {
             minitouch_stdin: child_in,
             state: minitouch_state,
+            clock: Arc::new(SystemClock),
             cmd_tx,
         })
     }
@@ -283,6 +288,11 @@ impl MiniTouch {
         self.write_command(format!("u {contact}").as_str())
     }
 
+    /// 替换滑动时用于等待的时钟
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
     pub fn wait(&mut self, duration: Duration) -> anyhow::Result<()> {
         self.write_command(format!("w {}", duration.as_millis()).as_str())
     }
@@ -335,13 +345,14 @@ impl Toucher for MiniTouch {
             self.mv(0, cur_x as i32, cur_y as i32, self.state.max_pressure)?;
             self.commit()?;
             self.wait(Duration::from_millis(SWIPE_DELAY_MS as u64))?;
-            thread::sleep(Duration::from_millis(SWIPE_DELAY_MS as u64));
+            self.clock
+                .sleep(Duration::from_millis(SWIPE_DELAY_MS as u64));
         }
 
         // self.mv(0, end.0, end.1, 0)?;
         self.wait(Duration::from_millis(200))?;
         self.commit()?;
-        thread::sleep(Duration::from_millis(200));
+        self.clock.sleep(Duration::from_millis(200));
         self.up(0)?;
         self.commit()?;
 
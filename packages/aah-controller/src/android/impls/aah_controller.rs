@@ -11,7 +11,7 @@ use crate::{
     Toucher,
 };
 
-use crate::{android::app::minitouch::MiniTouch, Controller};
+use crate::{android::app::minitouch::MiniTouch, clock::Clock, Controller};
 use anyhow::{Context, Result};
 
 /// An implementation of [`crate::Controller`]
@@ -149,6 +149,10 @@ impl Controller for AahController {
             .execute_command_by_process("shell input keyevent 111")?;
         Ok(())
     }
+
+    fn set_clock(&self, clock: Arc<dyn Clock>) {
+        self.minitouch.lock().unwrap().set_clock(clock);
+    }
 }
 
 #[cfg(test)]
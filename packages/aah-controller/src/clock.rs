@@ -0,0 +1,93 @@
+//! 时钟
+//!
+//! 所有的等待与计时都通过 [`Clock`] 进行，测试时可以使用 [`VirtualClock`]，
+//! 等待不会真正阻塞，而是直接推进虚拟的时间，使得依赖时间的逻辑可以在几毫秒内模拟完成。
+
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// 获取当前时间与等待
+pub trait Clock: Send + Sync {
+    /// 当前时间
+    fn now(&self) -> Instant;
+    /// 等待 `duration`
+    fn sleep(&self, duration: Duration);
+    /// 从 `earlier` 到现在经过的时间
+    fn elapsed(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+/// 系统时钟，即 [`Instant::now`] 与 [`thread::sleep`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// 虚拟时钟，[`Clock::sleep`] 会立即返回并将时间向后推进
+#[derive(Debug)]
+pub struct VirtualClock {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// 创建以来经过的虚拟时间
+    pub fn total_elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+
+    /// 将时间向后推进 `duration`
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + self.total_elapsed()
+    }
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock() {
+        let clock = VirtualClock::new();
+        let start = clock.now();
+        let real = Instant::now();
+        clock.sleep(Duration::from_secs(3600));
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(clock.elapsed(start), Duration::from_millis(3_600_500));
+        assert_eq!(clock.total_elapsed(), Duration::from_millis(3_600_500));
+        assert!(real.elapsed() < Duration::from_secs(1));
+    }
+}
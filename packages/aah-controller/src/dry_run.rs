@@ -0,0 +1,207 @@
+//! 不进行任何触控操作的 [`Controller`]
+//!
+//! [`DryRunController`] 的截图来自一张给定的图片或是另一个 [`Controller`]，
+//! 而点击、滑动、按键等操作只会被打印并记录下来（[`PlannedOp`]），不会被发送到设备上。
+
+use std::{
+    fmt::{self, Display, Formatter},
+    io::Cursor,
+    sync::Mutex,
+    time::Duration,
+};
+
+use color_print::cprintln;
+use image::DynamicImage;
+
+use crate::{Controller, Rect};
+
+/// 一个被记录下来的操作
+#[derive(Debug, Clone)]
+pub enum PlannedOp {
+    Click {
+        x: u32,
+        y: u32,
+    },
+    /// 在矩形框中点击，坐标为矩形框的中心
+    ClickInRect {
+        rect: Rect,
+        x: u32,
+        y: u32,
+    },
+    Swipe {
+        start: (u32, u32),
+        end: (i32, i32),
+        duration: Duration,
+    },
+    PressHome,
+    PressEsc,
+}
+
+impl Display for PlannedOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PlannedOp::Click { x, y } => write!(f, "click ({x}, {y})"),
+            PlannedOp::ClickInRect { rect, x, y } => write!(
+                f,
+                "click ({x}, {y}) in rect ({}, {}) {}x{}",
+                rect.x, rect.y, rect.width, rect.height
+            ),
+            PlannedOp::Swipe {
+                start,
+                end,
+                duration,
+            } => write!(
+                f,
+                "swipe ({}, {}) -> ({}, {}) in {:.2}s",
+                start.0,
+                start.1,
+                end.0,
+                end.1,
+                duration.as_secs_f32()
+            ),
+            PlannedOp::PressHome => write!(f, "press home"),
+            PlannedOp::PressEsc => write!(f, "press esc"),
+        }
+    }
+}
+
+enum Screen {
+    Image(DynamicImage),
+    Controller(Box<dyn Controller + Send + Sync>),
+}
+
+pub struct DryRunController {
+    screen: Screen,
+    ops: Mutex<Vec<PlannedOp>>,
+}
+
+impl DryRunController {
+    /// 使用一张固定的截图
+    pub fn with_screenshot(screenshot: DynamicImage) -> Self {
+        Self {
+            screen: Screen::Image(screenshot),
+            ops: Mutex::new(vec![]),
+        }
+    }
+
+    /// 使用 `controller` 截取实时画面，但不通过它进行任何操作
+    pub fn with_controller(controller: Box<dyn Controller + Send + Sync>) -> Self {
+        Self {
+            screen: Screen::Controller(controller),
+            ops: Mutex::new(vec![]),
+        }
+    }
+
+    /// 到目前为止记录下的所有操作
+    pub fn planned_ops(&self) -> Vec<PlannedOp> {
+        self.ops.lock().unwrap().clone()
+    }
+
+    fn plan(&self, op: PlannedOp) -> anyhow::Result<()> {
+        cprintln!("<y><strong>[DryRun]</strong></y>: {}", op);
+        self.ops.lock().unwrap().push(op);
+        Ok(())
+    }
+}
+
+impl Controller for DryRunController {
+    fn screen_size(&self) -> (u32, u32) {
+        match &self.screen {
+            Screen::Image(image) => (image.width(), image.height()),
+            Screen::Controller(controller) => controller.screen_size(),
+        }
+    }
+
+    fn click_in_rect(&self, rect: Rect) -> anyhow::Result<()> {
+        self.plan(PlannedOp::ClickInRect {
+            rect,
+            x: rect.x + rect.width / 2,
+            y: rect.y + rect.height / 2,
+        })
+    }
+
+    fn click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        self.plan(PlannedOp::Click { x, y })
+    }
+
+    fn swipe(
+        &self,
+        start: (u32, u32),
+        end: (i32, i32),
+        duration: Duration,
+        _slope_in: f32,
+        _slope_out: f32,
+    ) -> anyhow::Result<()> {
+        self.plan(PlannedOp::Swipe {
+            start,
+            end,
+            duration,
+        })
+    }
+
+    fn raw_screencap(&self) -> anyhow::Result<Vec<u8>> {
+        match &self.screen {
+            Screen::Image(image) => {
+                let mut png = Cursor::new(vec![]);
+                image.write_to(&mut png, image::ImageFormat::Png)?;
+                Ok(png.into_inner())
+            }
+            Screen::Controller(controller) => controller.raw_screencap(),
+        }
+    }
+
+    fn screencap(&self) -> anyhow::Result<DynamicImage> {
+        match &self.screen {
+            Screen::Image(image) => Ok(image.clone()),
+            Screen::Controller(controller) => controller.screencap(),
+        }
+    }
+
+    fn press_home(&self) -> anyhow::Result<()> {
+        self.plan(PlannedOp::PressHome)
+    }
+
+    fn press_esc(&self) -> anyhow::Result<()> {
+        self.plan(PlannedOp::PressEsc)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dry_run_controller() {
+        let controller = DryRunController::with_screenshot(DynamicImage::new_rgb8(1920, 1080));
+        assert_eq!(controller.screen_size(), (1920, 1080));
+
+        controller.click(10, 20).unwrap();
+        controller
+            .click_in_rect(Rect {
+                x: 100,
+                y: 100,
+                width: 50,
+                height: 20,
+            })
+            .unwrap();
+        controller
+            .swipe((0, 0), (100, -100), Duration::from_secs(1), 0.0, 0.0)
+            .unwrap();
+        controller.press_esc().unwrap();
+
+        let ops = controller
+            .planned_ops()
+            .iter()
+            .map(|op| op.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ops,
+            vec![
+                "click (10, 20)",
+                "click (125, 110) in rect (100, 100) 50x20",
+                "swipe (0, 0) -> (100, -100) in 1.00s",
+                "press esc",
+            ]
+        );
+    }
+}
@@ -1,7 +1,7 @@
 //! aah-controller contains the basic device manuplating functions like
 //! adb connecting, touch, swipe, adb command executing, etc.
 
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::Context;
 use anyhow::Result;
@@ -10,8 +10,10 @@ use image::DynamicImage;
 
 #[cfg(feature = "android")]
 pub mod android;
+pub mod clock;
 #[cfg(feature = "desktop")]
 pub mod desktop;
+pub mod dry_run;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Rect {
@@ -143,6 +145,9 @@ pub trait Controller {
     fn press_home(&self) -> anyhow::Result<()>;
 
     fn press_esc(&self) -> anyhow::Result<()>;
+
+    /// 替换操作中（如滑动）用于等待的时钟，默认不使用时钟的控制器忽略此调用
+    fn set_clock(&self, _clock: Arc<dyn clock::Clock>) {}
 }
 
 impl<T: Controller + 'static + ?Sized> Controller for Box<T> {
@@ -181,6 +186,10 @@ impl<T: Controller + 'static + ?Sized> Controller for Box<T> {
     fn press_esc(&self) -> anyhow::Result<()> {
         self.as_ref().press_esc()
     }
+
+    fn set_clock(&self, clock: Arc<dyn clock::Clock>) {
+        self.as_ref().set_clock(clock)
+    }
 }
 
 /// A toucher contains [`Toucher::click`] and [`Toucher::swipe`]
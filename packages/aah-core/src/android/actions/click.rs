@@ -1,14 +1,20 @@
 use aah_controller::Controller;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::{Core, TaskRecipe};
+use crate::{
+    task::retry::{ActionError, ErrorKind},
+    Core, TaskRecipe,
+};
 
 use super::ActionSet;
 
 /// An action for clicking the specific coordinate on the screen
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Click {
+    /// 横坐标（像素）
     x: u32,
+    /// 纵坐标（像素）
     y: u32,
 }
 
@@ -31,9 +37,11 @@ where
 {
     type Res = ();
     fn run(&self, runner: &T) -> anyhow::Result<Self::Res> {
-        runner
-            .controller()
-            .click(self.x, self.y)
-            .map_err(|err| anyhow::anyhow!("controller error: {:?}", err))
+        runner.controller().click(self.x, self.y).map_err(|err| {
+            anyhow::Error::new(ActionError::new(
+                ErrorKind::ControllerError,
+                format!("controller error: {err:?}"),
+            ))
+        })
     }
 }
@@ -1,19 +1,57 @@
-use std::fmt::Debug;
+use std::iter;
 
-use aah_controller::Controller;
+use aah_controller::{Controller, DEFAULT_HEIGHT};
+use image::DynamicImage;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
 
 use crate::{
-    resource::ResRoot,
-    vision::analyzer::{single_match::SingleMatchAnalyzer, Analyzer},
+    report::Recognition,
+    resource::{validate::ActionRefs, ResRoot},
+    task::retry::{ActionError, ErrorKind},
+    utils::resource::template_path,
+    vision::{
+        analyzer::{
+            matching::{MatchOptions, ScaleMemory},
+            multi_match::MultiMatchAnalyzer,
+            single_match::SingleMatchAnalyzer,
+        },
+        utils::Rect,
+    },
     Core, TaskRecipe,
 };
 
 use super::ActionSet;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// An action for clicking the position matched by a template
+///
+/// 除了模板外，还可以在任务中设置匹配选项（`roi`、`threshold`、`method`、`color_mask`、`binarize_threshold`）、
+/// 点击位置的偏移、多个匹配时点击第几个，以及备选模板：
+///
+/// ```toml
+/// [steps.action.ClickMatchTemplate]
+/// template = "confirm.png"
+/// alternatives = ["confirm-light.png"]
+/// roi = [[0.5, 0.5], [1.0, 1.0]]
+/// threshold = 0.9
+/// offset = [0, 80]
+/// ```
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ClickMatchTemplate {
+    /// 模板文件名（相对于 `templates/1920x1080`）
     template: String,
+    /// 备选模板，`template` 匹配失败时依次尝试，点击第一个匹配成功的
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    alternatives: Vec<String>,
+    /// 点击位置相对于匹配位置的偏移（1920x1080 下的像素）
+    offset: Option<(i32, i32)>,
+    /// 有多个匹配时点击第几个（从上到下、从左到右，从 0 开始），设置后会查找所有匹配
+    index: Option<usize>,
+    /// 匹配选项
+    #[serde(flatten)]
+    options: MatchOptions,
 }
 
 impl Into<ActionSet> for ClickMatchTemplate {
@@ -26,8 +64,76 @@ impl ClickMatchTemplate {
     pub fn new(template: impl AsRef<str>) -> Self {
         Self {
             template: template.as_ref().to_string(),
+            alternatives: vec![],
+            offset: None,
+            index: None,
+            options: MatchOptions::default(),
         }
     }
+
+    pub fn with_alternative(mut self, template: impl AsRef<str>) -> Self {
+        self.alternatives.push(template.as_ref().to_string());
+        self
+    }
+
+    pub fn with_offset(mut self, dx: i32, dy: i32) -> Self {
+        self.offset = Some((dx, dy));
+        self
+    }
+
+    pub fn with_index(mut self, index: usize) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    pub fn with_options(mut self, options: MatchOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// 在 `screen` 中匹配 `template`，返回匹配到的位置与得分
+    fn match_template(
+        &self,
+        template: &str,
+        screen: &DynamicImage,
+        res: &impl ResRoot,
+        scale_memory: Option<ScaleMemory>,
+    ) -> anyhow::Result<(Option<Rect>, f32, DynamicImage)> {
+        match self.index {
+            Some(index) => {
+                let output = MultiMatchAnalyzer::from_resource(res, template)?
+                    .with_options(self.options.clone())
+                    .remember_scale(scale_memory, template)
+                    .analyze_image(screen)?;
+                let res = output.res;
+                // 匹配结果按得分从优到劣排列，没有第 `index` 个时报告最优的得分
+                let best = res.scores.first().copied().unwrap_or(0.0);
+                let mut matches = res.rects.into_iter().zip(res.scores).collect::<Vec<_>>();
+                matches.sort_by_key(|(rect, _)| (rect.y, rect.x));
+                let (rect, score) = match matches.into_iter().nth(index) {
+                    Some((rect, score)) => (Some(rect), score),
+                    None => (None, best),
+                };
+                Ok((rect, score, *output.annotated_screen))
+            }
+            None => {
+                let output = SingleMatchAnalyzer::from_resource(res, template)?
+                    .with_options(self.options.clone())
+                    .remember_scale(scale_memory, template)
+                    .analyze_image(screen)?;
+                Ok((output.res.rect, output.res.score, *output.annotated_screen))
+            }
+        }
+    }
+}
+
+impl ActionRefs for ClickMatchTemplate {
+    fn templates(&self) -> Vec<String> {
+        let mut templates = vec![self.template.clone()];
+        templates.extend(self.alternatives.iter().cloned());
+        templates.extend(self.options.templates());
+        templates
+    }
 }
 
 // TODO: create a new trait like Controller
@@ -39,18 +145,215 @@ where
 {
     type Res = ();
     fn run(&self, aah: &T) -> anyhow::Result<Self::Res> {
-        let mut analyzer = SingleMatchAnalyzer::new(&aah.resource().res_root(), &self.template);
-        let output = analyzer
-            .analyze(aah)
-            .map_err(|err| anyhow::anyhow!("failed to analyze: {err}"))?;
-        let rect = output
-            .res
-            .rect
-            .ok_or(anyhow::anyhow!("failed to match {}", self.template))?
-            .into();
-        aah.controller()
-            .click_in_rect(rect)
-            .map_err(|err| anyhow::anyhow!("controller error: {:?}", err))?;
-        Ok(())
+        let res_dir = aah.resource().res_root();
+        for template in self.templates() {
+            if !template_path(&template, res_dir).is_file() {
+                return Err(ActionError::new(
+                    ErrorKind::ResourceNotFound,
+                    format!("template not found: {template}"),
+                )
+                .into());
+            }
+        }
+        let screen = aah.controller().screencap().map_err(|err| {
+            ActionError::new(
+                ErrorKind::ControllerError,
+                format!("controller error: {err:?}"),
+            )
+        })?;
+
+        // `templates()` 还包括遮罩图片，只点击模板与备选模板
+        let mut failed = None;
+        for template in iter::once(&self.template).chain(&self.alternatives) {
+            let (rect, score, annotated_screen) = self
+                .match_template(template, &screen, aah.resource(), aah.scale_memory())
+                .map_err(|err| anyhow::anyhow!("failed to analyze: {err}"))?;
+            if let Some(reporter) = aah.reporter() {
+                let recognition = Recognition::new(template, rect.clone(), score);
+                reporter.record_recognition(match rect {
+                    Some(_) => recognition,
+                    None => recognition.with_screenshot(&annotated_screen),
+                });
+            }
+            let Some(rect) = rect else {
+                failed.get_or_insert((template, score));
+                continue;
+            };
+
+            let (dx, dy) = self.offset.unwrap_or((0, 0));
+            let scale = screen.height() as f32 / DEFAULT_HEIGHT as f32;
+            let rect = aah_controller::Rect {
+                x: (rect.x as i32 + (dx as f32 * scale) as i32).max(0) as u32,
+                y: (rect.y as i32 + (dy as f32 * scale) as i32).max(0) as u32,
+                width: rect.width,
+                height: rect.height,
+            };
+            aah.controller().click_in_rect(rect).map_err(|err| {
+                ActionError::new(
+                    ErrorKind::ControllerError,
+                    format!("controller error: {err:?}"),
+                )
+            })?;
+            return Ok(());
+        }
+
+        let (template, score) = failed.unwrap();
+        Err(ActionError::new(
+            ErrorKind::TemplateNotFound,
+            format!("failed to match {template} (best score: {score})"),
+        )
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use aah_controller::dry_run::PlannedOp;
+
+    use crate::{
+        test_utils::{old_resources, resource, screenshot, TestCore},
+        vision::analyzer::matching::{scale_template, TemplateMask},
+    };
+
+    use super::*;
+
+    fn clicks_on(
+        action: ClickMatchTemplate,
+        screen: DynamicImage,
+    ) -> (anyhow::Result<()>, Vec<PlannedOp>) {
+        let core = TestCore::new(screen, resource(old_resources(), vec![]));
+        let res = action.run(&core);
+        (res, core.controller.planned_ops())
+    }
+
+    fn clicks(action: ClickMatchTemplate) -> (anyhow::Result<()>, Vec<PlannedOp>) {
+        clicks_on(action, screenshot("main.png"))
+    }
+
+    /// 唯一一次点击的矩形框的左上角
+    fn clicked(ops: &[PlannedOp]) -> (u32, u32) {
+        match ops {
+            [PlannedOp::ClickInRect { rect, .. }] => (rect.x, rect.y),
+            ops => panic!("expected a single click, got {ops:?}"),
+        }
+    }
+
+    #[test]
+    fn test_click_match_template() {
+        let (res, ops) = clicks(ClickMatchTemplate::new("main_base.png"));
+        res.unwrap();
+        let (x, y) = clicked(&ops);
+
+        // 偏移为 1920x1080 下的像素，截图的高度为 360
+        let (res, ops) = clicks(ClickMatchTemplate::new("main_base.png").with_offset(-30, 90));
+        res.unwrap();
+        assert_eq!(clicked(&ops), (x - 10, y + 30));
+        let (res, ops) = clicks(ClickMatchTemplate::new("main_base.png").with_offset(-6000, 0));
+        res.unwrap();
+        assert_eq!(clicked(&ops), (0, y));
+
+        // 备选模板
+        let (res, ops) =
+            clicks(ClickMatchTemplate::new("start_start.png").with_alternative("main_base.png"));
+        res.unwrap();
+        assert_eq!(ops.len(), 1);
+
+        let (res, ops) = clicks(
+            ClickMatchTemplate::new("main_base.png")
+                .with_options(MatchOptions::default().with_roi((0.0, 0.0), (0.5, 0.5))),
+        );
+        let err = res.unwrap_err();
+        assert_eq!(ActionError::kind_of(&err), ErrorKind::TemplateNotFound);
+        assert!(ops.is_empty());
+
+        let (res, _) = clicks(ClickMatchTemplate::new("missing.png"));
+        assert_eq!(
+            ActionError::kind_of(&res.unwrap_err()),
+            ErrorKind::ResourceNotFound
+        );
+    }
+
+    #[test]
+    fn test_click_match_template_index() {
+        // 在空白的截图上放置两个模板，按照从上到下、从左到右的顺序编号
+        let template =
+            image::open(old_resources().join("templates/1920x1080/main_base.png")).unwrap();
+        let template = scale_template(&template, 360, 1.0);
+        let mut screen = DynamicImage::new_rgb8(640, 360);
+        image::imageops::overlay(&mut screen, &template, 300, 40);
+        image::imageops::overlay(&mut screen, &template, 50, 200);
+
+        let action = ClickMatchTemplate::new("main_base.png")
+            .with_options(MatchOptions::default().with_threshold(0.9));
+        let (res, ops) = clicks_on(action.clone().with_index(0), screen.clone());
+        res.unwrap();
+        assert_eq!(clicked(&ops), (300, 40));
+        let (res, ops) = clicks_on(action.clone().with_index(1), screen.clone());
+        res.unwrap();
+        assert_eq!(clicked(&ops), (50, 200));
+        let (res, ops) = clicks_on(action.with_index(1).with_offset(30, 0), screen.clone());
+        res.unwrap();
+        assert_eq!(clicked(&ops), (60, 200));
+
+        let (res, ops) = clicks_on(
+            ClickMatchTemplate::new("main_base.png").with_index(2),
+            screen,
+        );
+        let err = res.unwrap_err();
+        assert_eq!(ActionError::kind_of(&err), ErrorKind::TemplateNotFound);
+        assert!(!err.to_string().contains("best score: 0)"));
+        assert!(ops.is_empty());
+    }
+
+    #[test]
+    fn test_click_match_template_mask() {
+        // 遮罩图片是截图中存在的 `main_base.png`，而模板是其反色，不在截图中
+        let root = std::env::temp_dir().join(format!("aah-mask-{}", std::process::id()));
+        let dir = root.join("templates/1920x1080");
+        fs::create_dir_all(&dir).unwrap();
+        let mask = image::open(old_resources().join("templates/1920x1080/main_base.png")).unwrap();
+        mask.save(dir.join("mask.png")).unwrap();
+        let mut template = mask.clone();
+        template.invert();
+        template.save(dir.join("inverted.png")).unwrap();
+
+        let core = TestCore::new(screenshot("main.png"), resource(root.clone(), vec![]));
+        let action = ClickMatchTemplate::new("inverted.png").with_options(
+            MatchOptions::default().with_mask(TemplateMask::Image("mask.png".to_string())),
+        );
+        assert_eq!(action.templates(), vec!["inverted.png", "mask.png"]);
+        let res = action.run(&core);
+        fs::remove_dir_all(&root).unwrap();
+
+        // 不会把遮罩当作模板去匹配
+        let err = res.unwrap_err();
+        assert_eq!(ActionError::kind_of(&err), ErrorKind::TemplateNotFound);
+        assert!(err.to_string().contains("inverted.png"));
+        assert!(!err.to_string().contains("mask.png"));
+        assert!(core.controller.planned_ops().is_empty());
+    }
+
+    #[test]
+    fn test_serde_click_match_template() {
+        let action: ClickMatchTemplate = toml::from_str(
+            r#"
+template = "confirm.png"
+alternatives = ["confirm-light.png"]
+roi = [[0.5, 0.5], [1.0, 1.0]]
+threshold = 0.9
+offset = [0, 80]
+index = 1
+"#,
+        )
+        .unwrap();
+        assert_eq!(action.templates(), vec!["confirm.png", "confirm-light.png"]);
+        assert_eq!(action.offset, Some((0, 80)));
+        assert_eq!(action.options.roi, [(0.5, 0.5), (1.0, 1.0)]);
+
+        // 默认的匹配选项不会被序列化
+        let toml = toml::to_string(&ClickMatchTemplate::new("confirm.png")).unwrap();
+        assert_eq!(toml.trim(), r#"template = "confirm.png""#);
     }
 }
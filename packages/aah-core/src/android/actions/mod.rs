@@ -2,6 +2,7 @@ pub mod click;
 pub mod click_match_template;
 pub mod press;
 pub mod swipe;
+pub mod wait;
 
 use std::time::Duration;
 
@@ -9,22 +10,35 @@ use aah_controller::Controller;
 pub use click::Click;
 pub use click_match_template::ClickMatchTemplate;
 pub use press::Press;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 pub use swipe::Swipe;
+pub use wait::{WaitForStableScreen, WaitForTemplate, WaitUntilGone};
 
-use crate::{resource::ResRoot, Core, TaskRecipe};
+use crate::{
+    resource::{validate::ActionRefs, ResRoot},
+    task::vars::{to_value, Value},
+    Core, TaskRecipe,
+};
 
 /// Action are the tasks you can use in the configuration file
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "AndroidActionSet")]
 pub enum ActionSet {
-    /// [`Press`]
+    /// 按下一个按键，见 [`Press`]
     Press(Press),
-    /// [`Click`]
+    /// 点击屏幕上的指定坐标，见 [`Click`]
     Click(Click),
-    /// [`Swipe`]
+    /// 从一个坐标滑动到另一个坐标，见 [`Swipe`]
     Swipe(Swipe),
-    /// [`ClickMatchTemplate`]
+    /// 在屏幕上匹配模板并点击匹配到的位置，见 [`ClickMatchTemplate`]
     ClickMatchTemplate(ClickMatchTemplate),
+    /// 等待模板出现在屏幕上，见 [`WaitForTemplate`]
+    WaitForTemplate(WaitForTemplate),
+    /// 等待模板从屏幕上消失，见 [`WaitUntilGone`]
+    WaitUntilGone(WaitUntilGone),
+    /// 等待画面不再变化，见 [`WaitForStableScreen`]
+    WaitForStableScreen(WaitForStableScreen),
 }
 
 impl ActionSet {
@@ -49,6 +63,29 @@ impl ActionSet {
     pub fn click_match_template(template: impl AsRef<str>) -> Self {
         Self::ClickMatchTemplate(ClickMatchTemplate::new(template))
     }
+    pub fn wait_for_template(template: impl AsRef<str>) -> Self {
+        Self::WaitForTemplate(WaitForTemplate::new(template))
+    }
+    pub fn wait_until_gone(template: impl AsRef<str>) -> Self {
+        Self::WaitUntilGone(WaitUntilGone::new(template))
+    }
+    pub fn wait_for_stable_screen() -> Self {
+        Self::WaitForStableScreen(WaitForStableScreen::new())
+    }
+}
+
+impl ActionRefs for ActionSet {
+    fn templates(&self) -> Vec<String> {
+        match self {
+            ActionSet::ClickMatchTemplate(action) => action.templates(),
+            ActionSet::WaitForTemplate(action) => action.templates(),
+            ActionSet::WaitUntilGone(action) => action.templates(),
+            ActionSet::Press(_)
+            | ActionSet::Click(_)
+            | ActionSet::Swipe(_)
+            | ActionSet::WaitForStableScreen(_) => vec![],
+        }
+    }
 }
 
 impl<T, C, R> TaskRecipe<T> for ActionSet
@@ -57,13 +94,16 @@ where
     R: ResRoot,
     T: Core<Controller = C, Resource = R>,
 {
-    type Res = ();
+    type Res = Value;
     fn run(&self, aah: &T) -> anyhow::Result<Self::Res> {
         match self {
-            ActionSet::Press(action) => action.run(aah),
-            ActionSet::Click(action) => action.run(aah),
-            ActionSet::Swipe(action) => action.run(aah),
-            ActionSet::ClickMatchTemplate(action) => action.run(aah),
+            ActionSet::Press(action) => action.run(aah).and_then(to_value),
+            ActionSet::Click(action) => action.run(aah).and_then(to_value),
+            ActionSet::Swipe(action) => action.run(aah).and_then(to_value),
+            ActionSet::ClickMatchTemplate(action) => action.run(aah).and_then(to_value),
+            ActionSet::WaitForTemplate(action) => action.run(aah).and_then(to_value),
+            ActionSet::WaitUntilGone(action) => action.run(aah).and_then(to_value),
+            ActionSet::WaitForStableScreen(action) => action.run(aah).and_then(to_value),
         }
     }
 }
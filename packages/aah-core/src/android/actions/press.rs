@@ -1,18 +1,26 @@
 use aah_controller::Controller;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::{Core, TaskRecipe};
+use crate::{
+    task::retry::{ActionError, ErrorKind},
+    Core, TaskRecipe,
+};
 
 use super::ActionSet;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum Key {
+    /// 返回键
     Esc,
+    /// Home 键
     Home,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// An action for pressing a key
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Press {
+    /// 要按下的按键
     key: Key,
 }
 
@@ -42,6 +50,11 @@ where
             Key::Esc => aah.controller().press_esc(),
             Key::Home => aah.controller().press_home(),
         }
-        .map_err(|err| anyhow::anyhow!("controller error: {:?}", err))
+        .map_err(|err| {
+            anyhow::Error::new(ActionError::new(
+                ErrorKind::ControllerError,
+                format!("controller error: {err:?}"),
+            ))
+        })
     }
 }
@@ -1,9 +1,13 @@
 use std::time::Duration;
 
 use aah_controller::Controller;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::{Core, TaskRecipe};
+use crate::{
+    task::retry::{ActionError, ErrorKind},
+    Core, TaskRecipe,
+};
 
 use super::ActionSet;
 
@@ -25,13 +29,20 @@ mod duration_as_sec_f32 {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// An action for swiping from `p1` to `p2`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Swipe {
+    /// 起点坐标
     p1: (u32, u32),
+    /// 终点坐标
     p2: (i32, i32),
+    /// 滑动持续时间（秒）
     #[serde(with = "duration_as_sec_f32")]
+    #[schemars(with = "f32")]
     duration: Duration,
+    /// 起始阶段的斜率
     slope_in: f32,
+    /// 结束阶段的斜率
     slope_out: f32,
 }
 
@@ -74,7 +85,12 @@ where
                 self.slope_in,
                 self.slope_out,
             )
-            .map_err(|err| anyhow::anyhow!("controller error: {:?}", err))
+            .map_err(|err| {
+                anyhow::Error::new(ActionError::new(
+                    ErrorKind::ControllerError,
+                    format!("controller error: {err:?}"),
+                ))
+            })
     }
 }
 
@@ -0,0 +1,592 @@
+//! 等待界面就绪的操作
+//!
+//! 与其用 `delay_sec` 猜测界面需要多久才能就绪，不如轮询画面，在满足条件的瞬间继续：
+//!
+//! - [`WaitForTemplate`]：等待模板出现
+//! - [`WaitUntilGone`]：等待模板消失
+//! - [`WaitForStableScreen`]：等待画面不再变化（如动画结束）
+//!
+//! dry-run 模式下画面不会变化，只会检查一次。
+
+use std::time::{Duration, Instant};
+
+use aah_controller::Controller;
+use anyhow::Context;
+use image::DynamicImage;
+use log::info;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::{
+    report::Recognition,
+    resource::{validate::ActionRefs, ResRoot},
+    task::retry::{ActionError, ErrorKind},
+    utils::resource::template_path,
+    vision::{
+        analyzer::{
+            matching::{MatchOptions, ScaleMemory},
+            single_match::SingleMatchAnalyzer,
+        },
+        utils::Rect,
+    },
+    Core, TaskRecipe,
+};
+
+use super::ActionSet;
+
+/// 默认的超时时间（秒）
+const DEFAULT_TIMEOUT_SEC: f32 = 10.0;
+/// 默认的轮询间隔（秒）
+const DEFAULT_POLL_INTERVAL_SEC: f32 = 0.5;
+
+/// 将以秒为单位的配置 `name` 转换为 [`Duration`]，负数、NaN 或过大时返回错误
+pub(crate) fn secs(name: &str, sec: f32) -> anyhow::Result<Duration> {
+    Duration::try_from_secs_f32(sec).with_context(|| format!("invalid {name}: {sec}"))
+}
+
+/// 每隔 `interval` 截图并调用 `check`，直到其返回 `true` 或超过 `timeout`，返回是否在超时前满足
+fn poll_screen<T, C>(
+    aah: &T,
+    timeout: Duration,
+    interval: Duration,
+    mut check: impl FnMut(&DynamicImage) -> anyhow::Result<bool>,
+) -> anyhow::Result<bool>
+where
+    C: Controller,
+    T: Core<Controller = C>,
+{
+    let clock = aah.clock();
+    let start = clock.now();
+    loop {
+        let screen = aah.controller().screencap().map_err(|err| {
+            anyhow::Error::new(ActionError::new(
+                ErrorKind::ControllerError,
+                format!("controller error: {err:?}"),
+            ))
+        })?;
+        if check(&screen)? {
+            return Ok(true);
+        }
+        let elapsed = clock.elapsed(start);
+        if aah.dry_run() || elapsed >= timeout {
+            return Ok(false);
+        }
+        clock.sleep(interval.min(timeout - elapsed));
+    }
+}
+
+/// 加载模板并创建 [`SingleMatchAnalyzer`]，模板不存在时返回 [`ErrorKind::ResourceNotFound`]
+pub(crate) fn template_analyzer(
+    template: &str,
+    options: &MatchOptions,
+    res: &impl ResRoot,
+    scale_memory: Option<ScaleMemory>,
+) -> anyhow::Result<SingleMatchAnalyzer> {
+    if !template_path(template, res.res_root()).is_file() {
+        return Err(ActionError::new(
+            ErrorKind::ResourceNotFound,
+            format!("template not found: {template}"),
+        )
+        .into());
+    }
+    Ok(SingleMatchAnalyzer::from_resource(res, template)?
+        .with_options(options.clone())
+        .remember_scale(scale_memory, template))
+}
+
+/// 等待模板出现在画面上，返回匹配到的位置
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WaitForTemplate {
+    /// 模板文件名（相对于 `templates/1920x1080`）
+    template: String,
+    /// 最长等待时间（秒），默认为 10 秒
+    timeout_sec: Option<f32>,
+    /// 轮询间隔（秒），默认为 0.5 秒
+    poll_interval_sec: Option<f32>,
+    /// 匹配选项，如匹配区域 `roi`、阈值 `threshold` 等
+    #[serde(flatten)]
+    options: MatchOptions,
+}
+
+impl From<WaitForTemplate> for ActionSet {
+    fn from(action: WaitForTemplate) -> Self {
+        ActionSet::WaitForTemplate(action)
+    }
+}
+
+impl WaitForTemplate {
+    pub fn new(template: impl AsRef<str>) -> Self {
+        Self {
+            template: template.as_ref().to_string(),
+            timeout_sec: None,
+            poll_interval_sec: None,
+            options: MatchOptions::default(),
+        }
+    }
+
+    pub fn with_timeout(mut self, sec: f32) -> Self {
+        self.timeout_sec = Some(sec);
+        self
+    }
+
+    pub fn with_poll_interval(mut self, sec: f32) -> Self {
+        self.poll_interval_sec = Some(sec);
+        self
+    }
+
+    pub fn with_options(mut self, options: MatchOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl ActionRefs for WaitForTemplate {
+    fn templates(&self) -> Vec<String> {
+        let mut templates = vec![self.template.clone()];
+        templates.extend(self.options.templates());
+        templates
+    }
+}
+
+impl<T, C, R> TaskRecipe<T> for WaitForTemplate
+where
+    C: Controller,
+    R: ResRoot,
+    T: Core<Controller = C, Resource = R>,
+{
+    type Res = Rect;
+    fn run(&self, aah: &T) -> anyhow::Result<Self::Res> {
+        let analyzer = template_analyzer(
+            &self.template,
+            &self.options,
+            aah.resource(),
+            aah.scale_memory(),
+        )?;
+        let timeout = secs(
+            "timeout_sec",
+            self.timeout_sec.unwrap_or(DEFAULT_TIMEOUT_SEC),
+        )?;
+        let interval = secs(
+            "poll_interval_sec",
+            self.poll_interval_sec.unwrap_or(DEFAULT_POLL_INTERVAL_SEC),
+        )?;
+
+        let mut last = None;
+        poll_screen(aah, timeout, interval, |screen| {
+            let output = analyzer.analyze_image(screen)?;
+            let found = output.res.rect.is_some();
+            last = Some(output);
+            Ok(found)
+        })?;
+        let output = last.unwrap();
+        if let Some(reporter) = aah.reporter() {
+            let recognition =
+                Recognition::new(&self.template, output.res.rect.clone(), output.res.score);
+            reporter.record_recognition(match output.res.rect {
+                Some(_) => recognition,
+                None => recognition.with_screenshot(&output.annotated_screen),
+            });
+        }
+        output.res.rect.ok_or_else(|| {
+            ActionError::new(
+                ErrorKind::TemplateNotFound,
+                format!(
+                    "timed out waiting for {} (best score: {})",
+                    self.template, output.res.score
+                ),
+            )
+            .into()
+        })
+    }
+}
+
+/// 等待模板从画面上消失
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WaitUntilGone {
+    /// 模板文件名（相对于 `templates/1920x1080`）
+    template: String,
+    /// 最长等待时间（秒），默认为 10 秒
+    timeout_sec: Option<f32>,
+    /// 轮询间隔（秒），默认为 0.5 秒
+    poll_interval_sec: Option<f32>,
+    /// 匹配选项，如匹配区域 `roi`、阈值 `threshold` 等
+    #[serde(flatten)]
+    options: MatchOptions,
+}
+
+impl From<WaitUntilGone> for ActionSet {
+    fn from(action: WaitUntilGone) -> Self {
+        ActionSet::WaitUntilGone(action)
+    }
+}
+
+impl WaitUntilGone {
+    pub fn new(template: impl AsRef<str>) -> Self {
+        Self {
+            template: template.as_ref().to_string(),
+            timeout_sec: None,
+            poll_interval_sec: None,
+            options: MatchOptions::default(),
+        }
+    }
+
+    pub fn with_timeout(mut self, sec: f32) -> Self {
+        self.timeout_sec = Some(sec);
+        self
+    }
+
+    pub fn with_poll_interval(mut self, sec: f32) -> Self {
+        self.poll_interval_sec = Some(sec);
+        self
+    }
+
+    pub fn with_options(mut self, options: MatchOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl ActionRefs for WaitUntilGone {
+    fn templates(&self) -> Vec<String> {
+        let mut templates = vec![self.template.clone()];
+        templates.extend(self.options.templates());
+        templates
+    }
+}
+
+impl<T, C, R> TaskRecipe<T> for WaitUntilGone
+where
+    C: Controller,
+    R: ResRoot,
+    T: Core<Controller = C, Resource = R>,
+{
+    type Res = ();
+    fn run(&self, aah: &T) -> anyhow::Result<Self::Res> {
+        let analyzer = template_analyzer(
+            &self.template,
+            &self.options,
+            aah.resource(),
+            aah.scale_memory(),
+        )?;
+        let timeout = secs(
+            "timeout_sec",
+            self.timeout_sec.unwrap_or(DEFAULT_TIMEOUT_SEC),
+        )?;
+        let interval = secs(
+            "poll_interval_sec",
+            self.poll_interval_sec.unwrap_or(DEFAULT_POLL_INTERVAL_SEC),
+        )?;
+
+        let gone = poll_screen(aah, timeout, interval, |screen| {
+            Ok(analyzer.analyze_image(screen)?.res.rect.is_none())
+        })?;
+        if !gone {
+            return Err(ActionError::new(
+                ErrorKind::Timeout,
+                format!("timed out waiting for {} to disappear", self.template),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// 等待画面（或画面中的某一区域）在一段时间内不再变化
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct WaitForStableScreen {
+    /// 检查的区域，为左上角与右下角在 `[0.0, 1.0]` 中的坐标，默认为整个画面
+    roi: Option<[(f32, f32); 2]>,
+    /// 画面需要保持不变的时间（秒），默认为 1 秒
+    stable_sec: Option<f32>,
+    /// 两次截图间平均每个像素的差异（`[0.0, 1.0]`）不超过此值即认为没有变化，默认为 0.02
+    threshold: Option<f32>,
+    /// 最长等待时间（秒），默认为 10 秒
+    timeout_sec: Option<f32>,
+    /// 轮询间隔（秒），默认为 0.5 秒
+    poll_interval_sec: Option<f32>,
+}
+
+impl From<WaitForStableScreen> for ActionSet {
+    fn from(action: WaitForStableScreen) -> Self {
+        ActionSet::WaitForStableScreen(action)
+    }
+}
+
+impl WaitForStableScreen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_roi(mut self, tl: (f32, f32), br: (f32, f32)) -> Self {
+        self.roi = Some([tl, br]);
+        self
+    }
+
+    pub fn with_stable(mut self, sec: f32) -> Self {
+        self.stable_sec = Some(sec);
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    pub fn with_timeout(mut self, sec: f32) -> Self {
+        self.timeout_sec = Some(sec);
+        self
+    }
+
+    pub fn with_poll_interval(mut self, sec: f32) -> Self {
+        self.poll_interval_sec = Some(sec);
+        self
+    }
+}
+
+impl ActionRefs for WaitForStableScreen {}
+
+/// 截取 `roi` 区域（默认为整个画面）并缩小为灰度图，以便快速比较画面是否变化
+pub(crate) fn thumbnail(screen: &DynamicImage, roi: Option<[(f32, f32); 2]>) -> image::GrayImage {
+    let [tl, br] = roi.unwrap_or([(0.0, 0.0), (1.0, 1.0)]);
+    let (width, height) = (screen.width() as f32, screen.height() as f32);
+    let (x1, y1) = ((tl.0 * width) as u32, (tl.1 * height) as u32);
+    let (x2, y2) = ((br.0 * width) as u32, (br.1 * height) as u32);
+    screen
+        .crop_imm(
+            x1,
+            y1,
+            x2.saturating_sub(x1).max(1),
+            y2.saturating_sub(y1).max(1),
+        )
+        .resize_exact(64, 36, image::imageops::FilterType::Triangle)
+        .to_luma8()
+}
+
+/// 两张同样大小的灰度图平均每个像素的差异，范围为 `[0.0, 1.0]`
+pub(crate) fn mean_diff(a: &image::GrayImage, b: &image::GrayImage) -> f32 {
+    let sum = a
+        .pixels()
+        .zip(b.pixels())
+        .map(|(a, b)| a.0[0].abs_diff(b.0[0]) as u64)
+        .sum::<u64>();
+    sum as f32 / (a.len() as f32 * 255.0)
+}
+
+impl<T, C> TaskRecipe<T> for WaitForStableScreen
+where
+    C: Controller,
+    T: Core<Controller = C>,
+{
+    type Res = ();
+    fn run(&self, aah: &T) -> anyhow::Result<Self::Res> {
+        if aah.dry_run() {
+            return Ok(());
+        }
+        let stable_for = secs("stable_sec", self.stable_sec.unwrap_or(1.0))?;
+        let threshold = self.threshold.unwrap_or(0.02);
+        let timeout = secs(
+            "timeout_sec",
+            self.timeout_sec.unwrap_or(DEFAULT_TIMEOUT_SEC),
+        )?;
+        let interval = secs(
+            "poll_interval_sec",
+            self.poll_interval_sec.unwrap_or(DEFAULT_POLL_INTERVAL_SEC),
+        )?;
+
+        // 上一张截图及画面开始保持不变的时间
+        let clock = aah.clock();
+        let mut last: Option<(image::GrayImage, Instant)> = None;
+        let stable = poll_screen(aah, timeout, interval, |screen| {
+            let thumbnail = thumbnail(screen, self.roi);
+            let since = match last.take() {
+                Some((prev, since)) if mean_diff(&prev, &thumbnail) <= threshold => since,
+                _ => clock.now(),
+            };
+            let stable = clock.elapsed(since) >= stable_for;
+            last = Some((thumbnail, since));
+            Ok(stable)
+        })?;
+        if !stable {
+            return Err(ActionError::new(
+                ErrorKind::Timeout,
+                "timed out waiting for a stable screen",
+            )
+            .into());
+        }
+        info!("[WaitForStableScreen]: screen is stable");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::VecDeque, sync::Mutex};
+
+    use image::{GenericImage, Rgba};
+
+    use crate::test_utils::{old_resources, resource, screenshot, TestCore};
+
+    use super::*;
+
+    /// 依次返回 `screens` 中的截图，最后一张会一直被返回
+    struct SequenceController {
+        screens: Mutex<VecDeque<DynamicImage>>,
+    }
+
+    impl SequenceController {
+        fn new(screens: Vec<DynamicImage>) -> Self {
+            Self {
+                screens: Mutex::new(screens.into()),
+            }
+        }
+    }
+
+    impl Controller for SequenceController {
+        fn screen_size(&self) -> (u32, u32) {
+            (640, 360)
+        }
+        fn click(&self, _x: u32, _y: u32) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn swipe(
+            &self,
+            _start: (u32, u32),
+            _end: (i32, i32),
+            _duration: Duration,
+            _slope_in: f32,
+            _slope_out: f32,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn raw_screencap(&self) -> anyhow::Result<Vec<u8>> {
+            anyhow::bail!("raw screencap is not supported")
+        }
+        fn screencap(&self) -> anyhow::Result<DynamicImage> {
+            let mut screens = self.screens.lock().unwrap();
+            if screens.len() > 1 {
+                Ok(screens.pop_front().unwrap())
+            } else {
+                Ok(screens[0].clone())
+            }
+        }
+        fn press_home(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn press_esc(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn core(screens: Vec<DynamicImage>) -> TestCore<SequenceController> {
+        TestCore::with_controller(
+            SequenceController::new(screens),
+            resource(old_resources(), vec![]),
+        )
+    }
+
+    #[test]
+    fn test_wait_for_template() {
+        let start_screen = screenshot("start.png");
+        let main_screen = screenshot("main.png");
+
+        // 第三次截图时出现
+        let aah = core(vec![
+            start_screen.clone(),
+            start_screen.clone(),
+            main_screen.clone(),
+        ]);
+        WaitForTemplate::new("main_base.png")
+            .with_poll_interval(0.1)
+            .run(&aah)
+            .unwrap();
+        assert_eq!(aah.clock.total_elapsed().as_millis(), 200);
+
+        let aah = core(vec![start_screen.clone()]);
+        let err = WaitForTemplate::new("main_base.png")
+            .with_timeout(0.3)
+            .with_poll_interval(0.1)
+            .run(&aah)
+            .unwrap_err();
+        assert_eq!(ActionError::kind_of(&err), ErrorKind::TemplateNotFound);
+        assert_eq!(aah.clock.total_elapsed().as_millis(), 300);
+
+        // 第二次截图时消失
+        let aah = core(vec![main_screen.clone(), start_screen]);
+        WaitUntilGone::new("main_base.png")
+            .with_poll_interval(0.1)
+            .run(&aah)
+            .unwrap();
+        let aah = core(vec![main_screen.clone()]);
+        let err = WaitUntilGone::new("main_base.png")
+            .with_timeout(0.3)
+            .with_poll_interval(0.1)
+            .run(&aah)
+            .unwrap_err();
+        assert_eq!(ActionError::kind_of(&err), ErrorKind::Timeout);
+
+        // 无效的时间会报错而不是 panic
+        let aah = core(vec![main_screen]);
+        for sec in [-1.0, f32::NAN, f32::INFINITY] {
+            assert!(WaitForTemplate::new("main_base.png")
+                .with_timeout(sec)
+                .run(&aah)
+                .is_err());
+            assert!(WaitUntilGone::new("main_base.png")
+                .with_poll_interval(sec)
+                .run(&aah)
+                .is_err());
+            assert!(WaitForStableScreen::new()
+                .with_stable(sec)
+                .run(&aah)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_wait_for_stable_screen() {
+        // 前几张截图不断变化，之后保持不变
+        let screens = (0..5)
+            .map(|i| {
+                let mut screen = DynamicImage::new_rgba8(640, 360);
+                for x in 0..640 {
+                    for y in 0..180 {
+                        screen.put_pixel(x, y, Rgba([i * 60, i * 60, i * 60, 255]));
+                    }
+                }
+                screen
+            })
+            .collect::<Vec<_>>();
+
+        let aah = core(screens.clone());
+        WaitForStableScreen::new()
+            .with_stable(0.25)
+            .with_poll_interval(0.1)
+            .run(&aah)
+            .unwrap();
+        // 4 次变化之后还需要保持 0.25 秒，即再截图 3 次
+        assert_eq!(aah.clock.total_elapsed().as_millis(), 700);
+
+        // 只检查下半部分，画面一开始就是稳定的
+        let aah = core(screens.clone());
+        WaitForStableScreen::new()
+            .with_roi((0.0, 0.5), (1.0, 1.0))
+            .with_stable(0.25)
+            .with_poll_interval(0.1)
+            .run(&aah)
+            .unwrap();
+        assert_eq!(aah.clock.total_elapsed().as_millis(), 300);
+
+        let aah = core(screens);
+        let err = WaitForStableScreen::new()
+            .with_stable(1.0)
+            .with_timeout(0.3)
+            .with_poll_interval(0.1)
+            .run(&aah)
+            .unwrap_err();
+        assert_eq!(ActionError::kind_of(&err), ErrorKind::Timeout);
+    }
+}
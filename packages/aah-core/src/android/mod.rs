@@ -89,7 +89,7 @@ impl GeneralAndroidCore {
             .get_task(name)
             .ok_or(anyhow::anyhow!("failed to get task"))?;
 
-        task.run(self)
+        task.run(self).map(|_| ())
     }
 
     /// Get screen cache or capture one. This is for internal analyzer use
@@ -1,22 +1,27 @@
-use std::{thread, time::Duration};
+use std::time::Duration;
 
 use aah_controller::Controller;
 use anyhow::Context;
 
-use crate::android::actions::ClickMatchTemplate;
-use crate::arknights::analyzer::levels::LevelAnalyzer;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::android::actions::{ClickMatchTemplate, Swipe, WaitForStableScreen, WaitForTemplate};
 use crate::arknights::AahCore;
+use crate::resource::validate::ActionRefs;
 use crate::vision::analyzer::matching::MatchOptions;
-use crate::vision::analyzer::single_match::SingleMatchAnalyzer;
-use crate::vision::analyzer::Analyzer;
 use crate::vision::utils::Rect;
 use crate::{Core, TaskRecipe};
 
-use crate::android::actions::Click;
+use super::click_text::ClickText;
+use super::swipe_until_found::SwipeUntilFound;
 
 /// A task to choose level from main
 ///
 /// the inner String is the level code
+///
+/// 在任务中使用：`action.ChooseLevel = "CE-5"`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ChooseLevel(String);
 
 impl ChooseLevel {
@@ -25,34 +30,29 @@ impl ChooseLevel {
     }
 }
 
-fn match_terminal_resource(aah: &AahCore) -> Result<Rect, anyhow::Error> {
-    let mut analyzer = SingleMatchAnalyzer::new(&aah.resource.root, "terminal-resource.png")
-        .with_options(MatchOptions::default().with_roi((0.0, 0.875), (1.0, 1.0)));
-    let res = analyzer.analyze(aah).context("match terminal-resource")?;
-    res.res
-        .rect
-        .ok_or(anyhow::anyhow!("failed to match terminal-resource"))
+impl ActionRefs for ChooseLevel {
+    fn templates(&self) -> Vec<String> {
+        let mut templates = vec!["main_terminal.png".to_string()];
+        if self.0.starts_with("CE") {
+            templates.push("terminal-resource.png".to_string());
+            templates.push("levels-resources-lmb.png".to_string());
+        }
+        templates
+    }
 }
 
-fn match_levels_resources_lmb(aah: &AahCore) -> Result<Rect, anyhow::Error> {
-    let mut analyzer = SingleMatchAnalyzer::new(&aah.resource.root, "levels-resources-lmb.png")
-        .with_options(MatchOptions::default().with_roi((0.0, 0.5), (1.0, 0.75)));
-    let res = analyzer
-        .analyze(aah)
-        .map_err(|err| anyhow::anyhow!(err))
-        .context("match levels-resources-lmb")?;
-    res.res.matched_img.save("matched.png").unwrap();
-    res.annotated_screen.save("test.png").unwrap();
-    res.res
-        .rect
-        .ok_or(anyhow::anyhow!("failed to match levels-resources-lmb"))
+fn match_terminal_resource(aah: &AahCore) -> Result<Rect, anyhow::Error> {
+    WaitForTemplate::new("terminal-resource.png")
+        .with_options(MatchOptions::default().with_roi((0.0, 0.875), (1.0, 1.0)))
+        .run(aah)
+        .context("match terminal-resource")
 }
 
-fn analyze_levels(aah: &AahCore) -> Result<Vec<(String, Rect)>, anyhow::Error> {
-    let mut analyzer = LevelAnalyzer::new();
-    let res = analyzer.analyze(aah).map_err(|err| anyhow::anyhow!(err))?;
-    println!("{:?}", res.levels);
-    Ok(res.levels)
+fn match_levels_resources_lmb(aah: &AahCore) -> Result<Rect, anyhow::Error> {
+    WaitForTemplate::new("levels-resources-lmb.png")
+        .with_options(MatchOptions::default().with_roi((0.0, 0.5), (1.0, 0.75)))
+        .run(aah)
+        .context("match levels-resources-lmb")
 }
 
 impl TaskRecipe<AahCore> for ChooseLevel {
@@ -63,26 +63,37 @@ impl TaskRecipe<AahCore> for ChooseLevel {
             .run(aah)
             .map_err(|err| anyhow::anyhow!(err))?;
 
-        thread::sleep(Duration::from_millis(800));
         if self.0.starts_with("CE") {
             // aah.emit_task_evt(super::TaskEvt::Log(
             //     "entering terminal-resource page".to_string(),
             // ));
             let rect = match_terminal_resource(aah)?.into();
             aah.controller().click_in_rect(rect)?;
-            thread::sleep(Duration::from_millis(800));
 
             // aah.emit_task_evt(super::TaskEvt::Log("entering levels-lmb page".to_string()));
             let rect = match_levels_resources_lmb(aah)?.into();
             aah.controller().click_in_rect(rect)?;
-            thread::sleep(Duration::from_millis(800));
-
-            let levels = analyze_levels(aah)?;
-            if let Some((_, rect)) = levels.iter().find(|(level, _)| level == &self.0) {
-                Click::new(rect.x + rect.width / 2, rect.y + rect.height / 2)
-                    .run(aah)
-                    .map_err(|err| anyhow::anyhow!(err))?;
-            }
+            // 等待关卡列表滑入
+            WaitForStableScreen::new()
+                .with_roi((0.0, 0.25), (1.0, 0.75))
+                .with_stable(0.5)
+                .run(aah)?;
+
+            // 关卡列表可能需要向左滑动才能看到目标关卡
+            let (width, height) = aah.controller().screen_size();
+            let swipe = Swipe::new(
+                (width * 4 / 5, height / 2),
+                ((width / 5) as i32, (height / 2) as i32),
+                Duration::from_secs_f32(1.0),
+                0.0,
+                1.0,
+            );
+            let pattern = format!("^{}$", regex::escape(&self.0));
+            SwipeUntilFound::text(ClickText::regex(pattern)?, swipe)
+                .with_max_swipes(5)
+                .with_click()
+                .run(aah)
+                .with_context(|| format!("find level {}", self.0))?;
         }
         Ok(())
     }
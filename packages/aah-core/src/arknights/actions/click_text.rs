@@ -0,0 +1,308 @@
+//! 通过文字点击
+//!
+//! 每期活动都会变化的菜单，以及不同语言的服务器，用文字比用各自的模板图片更容易定位：
+//!
+//! ```toml
+//! [[steps]]
+//! action.ClickText = { text = "开始行动", roi = [[0.5, 0.5], [1.0, 1.0]] }
+//!
+//! [[steps]]
+//! action.ClickText = { regex = "^CE-\\d$", index = 1 }
+//! ```
+
+use aah_controller::Controller;
+use image::DynamicImage;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::{
+    arknights::AahCore,
+    report::Recognition,
+    resource::validate::ActionRefs,
+    task::retry::{ActionError, ErrorKind},
+    vision::utils::Rect,
+    Core, TaskRecipe,
+};
+
+/// 要查找的文字
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TextPattern {
+    /// 包含此文字的行
+    Text(String),
+    /// 匹配此正则表达式的行，加载资源时编译，无效的正则表达式会使加载失败
+    Regex(
+        #[serde(with = "regex_serde")]
+        #[schemars(with = "String")]
+        Regex,
+    ),
+}
+
+mod regex_serde {
+    use regex::Regex;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(regex: &Regex, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(regex.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+        let regex = String::deserialize(deserializer)?;
+        Regex::new(&regex).map_err(serde::de::Error::custom)
+    }
+}
+
+/// 识别画面中的文字，点击匹配的一行文字
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ClickText {
+    #[serde(flatten)]
+    pattern: TextPattern,
+    /// 识别的区域，为左上角与右下角在 `[0.0, 1.0]` 中的坐标，默认为整个画面
+    roi: Option<[(f32, f32); 2]>,
+    /// 允许的编辑距离（仅对 `text` 有效），用于容忍 OCR 的识别错误，默认为 0
+    fuzzy: Option<usize>,
+    /// 有多个匹配时点击第几个（从上到下、从左到右，从 0 开始），默认为 0
+    index: Option<usize>,
+}
+
+impl ClickText {
+    pub fn new(text: impl AsRef<str>) -> Self {
+        Self {
+            pattern: TextPattern::Text(text.as_ref().to_string()),
+            roi: None,
+            fuzzy: None,
+            index: None,
+        }
+    }
+
+    pub fn regex(regex: impl AsRef<str>) -> anyhow::Result<Self> {
+        Ok(Self {
+            pattern: TextPattern::Regex(Regex::new(regex.as_ref())?),
+            ..Self::new("")
+        })
+    }
+
+    pub fn with_roi(mut self, tl: (f32, f32), br: (f32, f32)) -> Self {
+        self.roi = Some([tl, br]);
+        self
+    }
+
+    pub fn with_fuzzy(mut self, max_distance: usize) -> Self {
+        self.fuzzy = Some(max_distance);
+        self
+    }
+
+    pub fn with_index(mut self, index: usize) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// 在 OCR 的结果中找到要点击的一行，并给出 `[0.0, 1.0]` 之间的得分
+    ///
+    /// `text` 的得分为与该行的相似度，即 `1 - 编辑距离 / 文字长度`，正则表达式匹配时为 1.0，否则为 0.0。
+    /// 没有找到时（包括没有第 `index` 个）为得分最高的一行的得分
+    fn find<'a>(&self, lines: &'a [(String, Rect)]) -> (Option<&'a (String, Rect)>, f32) {
+        // 每一行与其得分
+        let scored = match &self.pattern {
+            TextPattern::Text(text) => {
+                let len = text.chars().count().max(1);
+                let fuzzy = self.fuzzy.unwrap_or(0);
+                lines
+                    .iter()
+                    .map(|line| {
+                        let distance = substring_distance(text, &line.0);
+                        let score = 1.0 - distance as f32 / len as f32;
+                        (line, score.max(0.0), distance <= fuzzy)
+                    })
+                    .collect::<Vec<_>>()
+            }
+            TextPattern::Regex(regex) => lines
+                .iter()
+                .map(|line| {
+                    let hit = regex.is_match(&line.0);
+                    (line, hit as u8 as f32, hit)
+                })
+                .collect(),
+        };
+        let mut hits = scored
+            .iter()
+            .filter(|(_, _, hit)| *hit)
+            .map(|(line, score, _)| (*line, *score))
+            .collect::<Vec<_>>();
+        hits.sort_by_key(|((_, rect), _)| (rect.y, rect.x));
+        match hits.get(self.index.unwrap_or(0)) {
+            Some((line, score)) => (Some(*line), *score),
+            None => {
+                let best = scored
+                    .iter()
+                    .map(|(_, score, _)| *score)
+                    .fold(0.0, f32::max);
+                (None, best)
+            }
+        }
+    }
+
+    /// 识别 `screen` 中的文字，返回要点击的一行的位置与得分，得分见 [`ClickText::find`]
+    pub(crate) fn locate(
+        &self,
+        aah: &AahCore,
+        screen: &DynamicImage,
+    ) -> anyhow::Result<(Option<Rect>, f32)> {
+        let lines = aah.ocr_in_roi(screen, self.roi)?;
+        let (hit, score) = self.find(&lines);
+        Ok((hit.map(|(_, rect)| rect.clone()), score))
+    }
+
+    /// 在运行报告中使用的名称
+    pub(crate) fn name(&self) -> String {
+        match &self.pattern {
+            TextPattern::Text(text) => format!("text {text:?}"),
+            TextPattern::Regex(regex) => format!("text /{}/", regex.as_str()),
+        }
+    }
+}
+
+/// `pattern` 与 `text` 中最相近的子串之间的编辑距离
+fn substring_distance(pattern: &str, text: &str) -> usize {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    // 第一行全为 0，即子串可以从 `text` 的任意位置开始
+    let mut row = vec![0; text.chars().count() + 1];
+    for (i, p) in pattern.iter().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, t) in text.chars().enumerate() {
+            let cur = (prev + (*p != t) as usize)
+                .min(row[j] + 1)
+                .min(row[j + 1] + 1);
+            prev = row[j + 1];
+            row[j + 1] = cur;
+        }
+    }
+    row.into_iter().min().unwrap()
+}
+
+impl ActionRefs for ClickText {}
+
+impl TaskRecipe<AahCore> for ClickText {
+    type Res = ();
+    fn run(&self, aah: &AahCore) -> anyhow::Result<Self::Res> {
+        let screen = aah.controller().screencap().map_err(|err| {
+            anyhow::Error::new(ActionError::new(
+                ErrorKind::ControllerError,
+                format!("controller error: {err:?}"),
+            ))
+        })?;
+        let lines = aah.ocr_in_roi(&screen, self.roi)?;
+        let (hit, score) = self.find(&lines);
+
+        let name = self.name();
+        if let Some(reporter) = aah.reporter() {
+            let recognition = Recognition::new(&name, hit.map(|(_, rect)| rect.clone()), score);
+            reporter.record_recognition(match hit {
+                Some(_) => recognition,
+                None => recognition.with_screenshot(&screen),
+            });
+        }
+
+        let (_, rect) = hit.ok_or_else(|| {
+            ActionError::new(
+                ErrorKind::TemplateNotFound,
+                format!(
+                    "failed to find {name} (recognized: {:?})",
+                    lines.iter().map(|(text, _)| text).collect::<Vec<_>>()
+                ),
+            )
+        })?;
+        aah.controller()
+            .click_in_rect(rect.clone().into())
+            .map_err(|err| {
+                ActionError::new(
+                    ErrorKind::ControllerError,
+                    format!("controller error: {err:?}"),
+                )
+            })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rect(x: u32, y: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width: 10,
+            height: 10,
+        }
+    }
+
+    #[test]
+    fn test_substring_distance() {
+        assert_eq!(substring_distance("开始行动", "开始行动"), 0);
+        assert_eq!(substring_distance("开始行动", "> 开始行动 <"), 0);
+        assert_eq!(substring_distance("开始行动", "开姶行动"), 1);
+        assert_eq!(substring_distance("CE-5", "CE5"), 1);
+        assert_eq!(substring_distance("CE-5", "LS-5"), 2);
+        assert_eq!(substring_distance("abc", ""), 3);
+    }
+
+    #[test]
+    fn test_find() {
+        let lines = vec![
+            ("CE-5".to_string(), rect(300, 100)),
+            ("CE-4".to_string(), rect(100, 100)),
+            ("CE 3".to_string(), rect(100, 50)),
+            ("LS-5".to_string(), rect(100, 200)),
+        ];
+        let find = |action: ClickText| action.find(&lines).0.map(|(text, _)| text.as_str());
+        let regex = |regex: &str| ClickText::regex(regex).unwrap();
+
+        assert_eq!(find(ClickText::new("CE-5")), Some("CE-5"));
+        assert_eq!(find(ClickText::new("CE-3")), None);
+        assert_eq!(find(ClickText::new("CE-3").with_fuzzy(1)), Some("CE 3"));
+
+        // 从上到下、从左到右
+        assert_eq!(find(regex(r"^CE.\d$")), Some("CE 3"));
+        assert_eq!(find(regex(r"^CE-\d$")), Some("CE-4"));
+        assert_eq!(find(regex(r"^CE-\d$").with_index(1)), Some("CE-5"));
+        assert_eq!(find(regex(r"^CE-\d$").with_index(2)), None);
+        assert!(ClickText::regex("(").is_err());
+
+        // 得分为与最相近的一行的相似度
+        assert_eq!(ClickText::new("CE-5").find(&lines).1, 1.0);
+        assert_eq!(ClickText::new("CE-3").with_fuzzy(1).find(&lines).1, 0.75);
+        assert_eq!(ClickText::new("CE-3").find(&lines).1, 0.75);
+        assert_eq!(ClickText::new("CE-3").find(&[]).1, 0.0);
+        assert_eq!(regex(r"^CE-\d$").find(&lines).1, 1.0);
+        assert_eq!(regex(r"^CE-\d$").with_index(2).find(&lines).1, 1.0);
+        assert_eq!(regex(r"^1-\d$").find(&lines).1, 0.0);
+    }
+
+    #[test]
+    fn test_serde_click_text() {
+        let action: ClickText = toml::from_str(
+            r#"regex = "^CE-\\d$"
+index = 1
+roi = [[0.0, 0.5], [1.0, 1.0]]"#,
+        )
+        .unwrap();
+        assert!(matches!(action.pattern, TextPattern::Regex(_)));
+        assert_eq!(action.index, Some(1));
+        let toml = toml::to_string(&action).unwrap();
+        let action: ClickText = toml::from_str(&toml).unwrap();
+        assert_eq!(action.name(), r"text /^CE-\d$/");
+        assert!(toml::from_str::<ClickText>(r#"regex = "(""#).is_err());
+
+        let action: ClickText = toml::from_str(r#"text = "开始行动""#).unwrap();
+        assert_eq!(
+            toml::to_string(&action).unwrap().trim(),
+            r#"text = "开始行动""#
+        );
+        assert!(toml::from_str::<ClickText>(r#"roi = [[0.0, 0.5], [1.0, 1.0]]"#).is_err());
+    }
+}
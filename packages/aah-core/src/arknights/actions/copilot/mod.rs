@@ -1,9 +1,9 @@
 use std::{
     collections::{HashMap, HashSet},
-    thread,
     time::Duration,
 };
 
+use aah_controller::{clock::Clock, DEFAULT_HEIGHT};
 use aah_cv::template_matching::{match_template, MatchTemplateMethod};
 use aah_resource::level::get_level;
 use color_print::{cformat, cprintln};
@@ -16,25 +16,35 @@ use crate::{
         analyzer::battle::{BattleAnalyzer, BattleAnalyzerOutput, BattleState},
         AahCore,
     },
-    utils::resource::get_template,
-    vision::analyzer::Analyzer,
-    CachedScreenCapper, TaskRecipe,
+    report::RunKind,
+    resource::validate::ActionRefs,
+    utils::resource::get_oper_avatars,
+    vision::{analyzer::Analyzer, utils::Rect},
+    CachedScreenCapper, Core, TaskRecipe,
 };
 use std::fmt::{self, Display, Formatter};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// 一个作业，对应 `resources/copilots` 中的一个 `xxx.toml` 文件
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct Copilot {
+    /// 作业名称
     pub name: String,
+    /// 关卡代号，如 `1-4`
     pub level_code: String,
+    /// 干员别名到干员内部名称（如 `char_151_myrtle`）的映射
     pub operators: HashMap<String, String>,
+    /// 作业的步骤
     pub steps: Vec<CopilotStep>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct CopilotStep {
+    /// 此步骤的执行时机
     pub time: CopilotStepTime,
+    /// 此步骤要执行的操作
     pub action: CopilotAction,
 }
 
@@ -59,7 +69,8 @@ impl CopilotStep {
 //     pub steps: Vec<CopilotAction>,
 // }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// 干员部署的朝向
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub enum Direction {
     Left,
     Up,
@@ -78,34 +89,230 @@ impl Display for Direction {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, JsonSchema)]
 pub enum CopilotStepTime {
+    /// 在上一步骤完成后等待指定秒数
     DeltaSec(f32),
     /// As Soon As Possible
     Asap,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub enum CopilotAction {
+    /// 将干员部署到指定格子上
     Deploy {
+        /// 干员别名（`operators` 中的键）
         operator: String,
+        /// 格子坐标
         position: (u32, u32),
+        /// 部署朝向
         direction: Direction,
     },
+    /// 在干员技能就绪时自动开启技能
     AutoSkill {
+        /// 干员别名（`operators` 中的键）
         operator: String,
     },
+    /// 停止自动开启干员的技能
     StopAutoSkill {
+        /// 干员别名（`operators` 中的键）
         operator: String,
     },
+    /// 撤退干员
     Retreat {
+        /// 干员别名（`operators` 中的键）
         operator: String,
     },
 }
 
+/// 作业步骤的时间线，每个步骤按其 [`CopilotStepTime`] 等待后执行，执行成功才进入下一步骤
+struct Timeline<'a> {
+    steps: &'a [CopilotStep],
+    cur: usize,
+}
+
+impl<'a> Timeline<'a> {
+    fn new(steps: &'a [CopilotStep]) -> Self {
+        Self { steps, cur: 0 }
+    }
+
+    /// 当前要执行的步骤，所有步骤都完成后为 `None`
+    fn current(&self) -> Option<(usize, &'a CopilotStep)> {
+        self.steps.get(self.cur).map(|step| (self.cur, step))
+    }
+
+    /// 按当前步骤的时机等待，重试同一步骤时也会重新等待
+    fn wait(&self, clock: &dyn Clock) {
+        if let Some((_, step)) = self.current() {
+            match step.time {
+                CopilotStepTime::DeltaSec(delta) => {
+                    clock.sleep(Duration::from_secs_f32(delta));
+                }
+                CopilotStepTime::Asap => (),
+            }
+        }
+    }
+
+    /// 当前步骤执行成功，进入下一步骤
+    fn advance(&mut self) {
+        self.cur = (self.cur + 1).min(self.steps.len());
+    }
+}
+
+/// 作业进行中使用的模板
+const COPILOT_TEMPLATES: [&str; 3] = [
+    "level_start-pre.png",
+    "formation_start.png",
+    "battle_skill-ready.png",
+];
+
+impl ActionRefs for Copilot {
+    fn templates(&self) -> Vec<String> {
+        COPILOT_TEMPLATES.map(str::to_string).to_vec()
+    }
+    fn avatars(&self) -> Vec<String> {
+        let mut opers = self.operators.values().cloned().collect::<Vec<_>>();
+        opers.sort();
+        opers.dedup();
+        opers
+    }
+}
+
 impl TaskRecipe<AahCore> for Copilot {
     type Res = ();
     fn run(&self, aah: &AahCore) -> anyhow::Result<Self::Res> {
+        if let Some(reporter) = aah.reporter() {
+            reporter.begin_task(RunKind::Copilot, &self.name);
+        }
+        let res = match aah.dry_run() {
+            true => self.plan(aah),
+            false => self.run_battle(aah),
+        };
+        if let Some(reporter) = aah.reporter() {
+            reporter.end_task(&res);
+        }
+        res
+    }
+}
+
+impl Copilot {
+    /// 在报告中将 `f` 记录为一个只有一次尝试的步骤
+    fn report_step<T>(
+        aah: &AahCore,
+        index: usize,
+        desc: impl AsRef<str>,
+        f: impl FnOnce() -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let Some(reporter) = aah.reporter() else {
+            return f();
+        };
+        reporter.begin_step(index, desc);
+        reporter.begin_attempt();
+        let res = f();
+        reporter.end_attempt(&res);
+        reporter.end_step();
+        res
+    }
+
+    /// dry-run 模式下的作业
+    ///
+    /// 检查关卡、干员头像与模板，在当前画面上识别开始行动的按钮与部署卡片，
+    /// 并打印作业中每一步将要进行的操作，而不会等待战斗开始
+    fn plan(&self, aah: &AahCore) -> anyhow::Result<()> {
+        let log_tag = cformat!("<strong>[CopilotPlan {}]: </strong>", self.level_code);
+        let copilot_task = aah
+            .resource
+            .get_copilot(&self.level_code)
+            .ok_or(anyhow::anyhow!(
+                "failed to get copilot task: {}",
+                self.level_code
+            ))?;
+        let level = get_level(
+            copilot_task.level_code.as_str(),
+            aah.resource.root.join("levels.json"),
+        )
+        .ok_or(anyhow::anyhow!(
+            "level not found: {}",
+            copilot_task.level_code
+        ))?;
+        for oper in copilot_task.operators.values() {
+            get_oper_avatars(oper, &aah.resource.root)
+                .map_err(|err| anyhow::anyhow!("failed to load avatars of {oper}: {err}"))?;
+        }
+        aah.resource.templates.get("battle_skill-ready.png")?;
+
+        for (idx, template) in ["level_start-pre.png", "formation_start.png"]
+            .into_iter()
+            .enumerate()
+        {
+            let action = ClickMatchTemplate::new(template);
+            if let Err(err) = Self::report_step(aah, idx, format!("{action:?}"), || action.run(aah))
+            {
+                cprintln!("{log_tag}<r>{err}</r>");
+            }
+        }
+
+        let mut battle_analyzer = BattleAnalyzer::new(
+            &aah.resource.templates,
+            &copilot_task.operators.values().collect::<Vec<_>>(),
+        )?;
+        let output = battle_analyzer.analyze(aah)?;
+        let mut deployed_operators = HashMap::<String, (u32, u32)>::new();
+        for (idx, step) in copilot_task.steps.iter().enumerate() {
+            cprintln!(
+                "{log_tag}command[{idx}] at {:?}: {:?}",
+                step.time,
+                step.action
+            );
+            let res = Self::report_step(aah, idx + 2, format!("{:?}", step.action), || match &step
+                .action
+            {
+                CopilotAction::Deploy {
+                    operator,
+                    position,
+                    direction,
+                } => {
+                    let oper_name = copilot_task
+                        .operators
+                        .get(operator)
+                        .ok_or(anyhow::anyhow!("unknown operator: {operator}"))?;
+                    let card_rect = match output
+                        .deploy_cards
+                        .iter()
+                        .find(|card| &card.oper_name == oper_name)
+                    {
+                        Some(card) => card.rect.clone(),
+                        None => {
+                            cprintln!("{log_tag}<y>deploy card of {operator} is not on the screen, using (0, 0)</y>");
+                            Rect {
+                                x: 0,
+                                y: 0,
+                                width: 0,
+                                height: 0,
+                            }
+                        }
+                    };
+                    deployed_operators.insert(operator.to_string(), *position);
+                    Deploy::new(&level, &card_rect, position, direction)
+                        .run(aah)
+                        .map(|_| ())
+                }
+                CopilotAction::Retreat { operator } => {
+                    let position = deployed_operators
+                        .remove(operator)
+                        .ok_or(anyhow::anyhow!("{operator} is not deployed"))?;
+                    Retreat::new(&level, &position).run(aah).map(|_| ())
+                }
+                CopilotAction::AutoSkill { .. } | CopilotAction::StopAutoSkill { .. } => Ok(()),
+            });
+            if let Err(err) = res {
+                cprintln!("{log_tag}<r>{err}</r>");
+            }
+        }
+        Ok(())
+    }
+
+    fn run_battle(&self, aah: &AahCore) -> anyhow::Result<()> {
         let log_tag = cformat!("<strong>[CopilotTask {}]: </strong>", self.level_code);
         let copilot_task = aah
             .resource
@@ -119,6 +326,7 @@ impl TaskRecipe<AahCore> for Copilot {
             aah.resource.root.join("levels.json"),
         )
         .unwrap();
+        let clock = aah.clock();
 
         // disable prts
         // TODO: fix it
@@ -133,7 +341,7 @@ impl TaskRecipe<AahCore> for Copilot {
         cprintln!("{log_tag}clicking start-pre...");
         // aah.emit_task_evt(TaskEvt::Log("[INFO]: 正在点击 start-pre...".to_string()));
         let start_pre = ClickMatchTemplate::new("level_start-pre.png");
-        match start_pre.run(aah) {
+        match Self::report_step(aah, 0, format!("{start_pre:?}"), || start_pre.run(aah)) {
             Ok(_) => {
                 // aah.emit_task_evt(TaskEvt::Log("[INFO]: 已点击 start-pre".to_string()));
                 cprintln!("{log_tag}<g>clicked start pre</g>")
@@ -146,13 +354,13 @@ impl TaskRecipe<AahCore> for Copilot {
             }
         }
 
-        thread::sleep(Duration::from_secs_f32(0.5));
+        clock.sleep(Duration::from_secs_f32(0.5));
         // TODO: formation
 
         cprintln!("{log_tag}clicking start...");
         // aah.emit_task_evt(TaskEvt::Log("[INFO]: 正在点击 start...".to_string()));
         let start_pre = ClickMatchTemplate::new("formation_start.png");
-        match start_pre.run(aah) {
+        match Self::report_step(aah, 1, format!("{start_pre:?}"), || start_pre.run(aah)) {
             Ok(_) => {
                 // aah.emit_task_evt(TaskEvt::Log("[INFO]: 已点击 start".to_string()));
                 cprintln!("{log_tag}<g>clicked start</g>")
@@ -166,21 +374,24 @@ impl TaskRecipe<AahCore> for Copilot {
         }
 
         let mut battle_analyzer = BattleAnalyzer::new(
-            &aah.resource.root,
+            &aah.resource.templates,
             &copilot_task.operators.values().collect::<Vec<_>>(),
-        );
+        )?
+        .with_clock(clock.clone());
         // wait for battle begins
         // aah.emit_task_evt(TaskEvt::Log("[INFO]: 正在等待关卡开始...".to_string()));
         cprintln!("{log_tag}waiting for battle to begin...");
         while battle_analyzer.battle_state == BattleState::Unknown {
-            thread::sleep(Duration::from_secs_f32(0.5));
+            clock.sleep(Duration::from_secs_f32(0.5));
             battle_analyzer.analyze(aah)?;
         }
         // Do battle things
         // aah.emit_task_evt(TaskEvt::Log("[INFO]: 关卡开始".to_string()));
         cprintln!("{log_tag}battle begins!");
         let skill_ready_template =
-            get_template("battle_skill-ready.png", &aah.resource.root)?.to_luma32f();
+            aah.resource
+                .templates
+                .luma("battle_skill-ready.png", DEFAULT_HEIGHT, 1.0)?;
         let mut battle_analyzer_output: BattleAnalyzerOutput;
         let mut deployed_operators = HashMap::<String, (u32, u32)>::new();
         let mut auto_skill_operators = HashSet::<String>::new();
@@ -225,7 +436,7 @@ impl TaskRecipe<AahCore> for Copilot {
                                     //     "[INFO]: {oper} 技能已使用"
                                     // )));
                                     cprintln!("{log_tag}auto_skill[{oper}]: skill clicked");
-                                    thread::sleep(Duration::from_secs_f32(0.2))
+                                    clock.sleep(Duration::from_secs_f32(0.2))
                                 }
                             }
                         }
@@ -233,11 +444,23 @@ impl TaskRecipe<AahCore> for Copilot {
                 }
             };
 
-        let mut iter = copilot_task.steps.iter().enumerate();
-        let mut cur = iter.next();
+        let mut timeline = Timeline::new(&copilot_task.steps);
+        let reporter = aah.reporter();
+        // 报告中当前正在记录的步骤，作业的步骤会被记录在开始行动前的两个步骤之后
+        let mut reported_step = None;
         while battle_analyzer.battle_state != BattleState::Completed {
             // Execute step
-            if let Some((idx, step)) = cur {
+            if let Some((idx, step)) = timeline.current() {
+                if let Some(reporter) = reporter {
+                    if reported_step != Some(idx) {
+                        if reported_step.is_some() {
+                            reporter.end_step();
+                        }
+                        reporter.begin_step(idx + 2, format!("{:?}", step.action));
+                        reported_step = Some(idx);
+                    }
+                    reporter.begin_attempt();
+                }
                 // aah.emit_task_evt(TaskEvt::Log(format!(
                 //     "[INFO]: 执行命令 [{}/{}]: {:?}",
                 //     idx,
@@ -247,17 +470,12 @@ impl TaskRecipe<AahCore> for Copilot {
                 cprintln!(
                     "{log_tag}executing command[{}/{}]: {:?}",
                     idx,
-                    iter.len(),
+                    copilot_task.steps.len(),
                     step
                 );
                 // aah.emit_task_evt(TaskEvt::Log(format!("[INFO]: 等待 {:?}...", step.time)));
                 cprintln!("{log_tag}waiting for time {:?}...", step.time);
-                match step.time {
-                    CopilotStepTime::DeltaSec(delta) => {
-                        thread::sleep(Duration::from_secs_f32(delta));
-                    }
-                    CopilotStepTime::Asap => (),
-                }
+                timeline.wait(clock.as_ref());
                 // aah.emit_task_evt(TaskEvt::Log(format!("[INFO]: 等待完成")));
                 cprintln!("{log_tag}is time!");
                 battle_analyzer_output = battle_analyzer.analyze(aah)?;
@@ -327,9 +545,15 @@ impl TaskRecipe<AahCore> for Copilot {
                         true
                     }
                 };
+                if let Some(reporter) = reporter {
+                    reporter.end_attempt(&match success {
+                        true => Ok(()),
+                        false => Err(anyhow::anyhow!("command failed")),
+                    });
+                }
                 if success {
                     cprintln!("{log_tag}<green>command success!</green>");
-                    cur = iter.next();
+                    timeline.advance();
                 }
             }
 
@@ -341,3 +565,69 @@ impl TaskRecipe<AahCore> for Copilot {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use aah_controller::clock::VirtualClock;
+
+    use super::*;
+
+    /// 只覆盖 [`Timeline`] 的等待与重试，`run_battle` 还需要真实的战斗画面
+    #[test]
+    fn test_timeline_only() {
+        let steps = vec![
+            CopilotStep::from_action(CopilotAction::Deploy {
+                operator: "A".to_string(),
+                position: (3, 4),
+                direction: Direction::Left,
+            })
+            .with_time(CopilotStepTime::DeltaSec(2.0)),
+            CopilotStep::from_action(CopilotAction::AutoSkill {
+                operator: "A".to_string(),
+            }),
+            CopilotStep::from_action(CopilotAction::Retreat {
+                operator: "A".to_string(),
+            })
+            .with_time(CopilotStepTime::DeltaSec(5.0)),
+        ];
+
+        // 模拟一场 60 秒的战斗，每轮识别耗时 0.5 秒，撤退第一次失败
+        let clock = VirtualClock::new();
+        let start = clock.now();
+        let real = Instant::now();
+        let mut timeline = Timeline::new(&steps);
+        let mut attempts = vec![];
+        while clock.elapsed(start) < Duration::from_secs(60) {
+            if let Some((idx, _)) = timeline.current() {
+                timeline.wait(&clock);
+                attempts.push((idx, clock.elapsed(start).as_secs_f32()));
+                if attempts.len() != 3 {
+                    timeline.advance();
+                }
+            }
+            clock.sleep(Duration::from_millis(500));
+        }
+
+        // 重试时重新等待
+        assert_eq!(attempts, vec![(0, 2.0), (1, 2.5), (2, 8.0), (2, 13.5)]);
+        assert!(timeline.current().is_none());
+        assert!(real.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_copilot_refs() {
+        let copilot = Copilot {
+            name: "test".to_string(),
+            level_code: "1-4".to_string(),
+            operators: HashMap::from([
+                ("米格鲁".to_string(), "char_149_scave".to_string()),
+                ("桃金娘".to_string(), "char_151_myrtle".to_string()),
+            ]),
+            steps: vec![],
+        };
+        assert_eq!(copilot.templates().len(), 3);
+        assert_eq!(copilot.avatars(), vec!["char_149_scave", "char_151_myrtle"]);
+    }
+}
@@ -1,15 +1,29 @@
 use aah_controller::Controller;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::{android, Core, TaskRecipe};
+use std::sync::{OnceLock, RwLock};
+
+use crate::{
+    android,
+    resource::validate::ActionRefs,
+    task::{
+        registry::{ActionRegistry, ActionRegistryProvider, RegisteredAction},
+        vars::Value,
+    },
+    Core, TaskRecipe,
+};
 
 use super::AahCore;
 
 pub mod battle;
 pub mod choose_level;
+pub mod click_text;
 pub mod copilot;
+pub mod script;
+pub mod swipe_until_found;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum ActionSet {
     /// General android actions
@@ -17,8 +31,29 @@ pub enum ActionSet {
     // BattleDeploy(battle::Deploy),
     // BattleRetreat(battle::Retreat),
     // BattleUseSkill(battle::UseSkill),
-    // Copilot(copilot::Copilot),
-    // ChooseLevel(choose_level::ChooseLevel),
+    /// 通过 [`ActionRegistry`] 注册的操作，如 `Copilot`、`ChooseLevel`、`Script`、`ClickText`
+    Registered(RegisteredAction<AahCore>),
+}
+
+impl ActionRegistryProvider for AahCore {
+    fn action_registry() -> &'static RwLock<ActionRegistry<Self>> {
+        static REGISTRY: OnceLock<RwLock<ActionRegistry<AahCore>>> = OnceLock::new();
+        REGISTRY.get_or_init(|| {
+            let mut registry = ActionRegistry::new();
+            registry.register::<copilot::Copilot>("Copilot").unwrap();
+            registry
+                .register::<choose_level::ChooseLevel>("ChooseLevel")
+                .unwrap();
+            registry.register::<script::Script>("Script").unwrap();
+            registry
+                .register::<click_text::ClickText>("ClickText")
+                .unwrap();
+            registry
+                .register::<swipe_until_found::SwipeUntilFound>("SwipeUntilFound")
+                .unwrap();
+            RwLock::new(registry)
+        })
+    }
 }
 
 impl From<android::actions::ActionSet> for ActionSet {
@@ -27,12 +62,101 @@ impl From<android::actions::ActionSet> for ActionSet {
     }
 }
 
+impl ActionRefs for ActionSet {
+    fn templates(&self) -> Vec<String> {
+        match self {
+            ActionSet::Genral(action) => action.templates(),
+            ActionSet::Registered(action) => action.templates(),
+        }
+    }
+    fn tasks(&self) -> Vec<String> {
+        match self {
+            ActionSet::Genral(action) => action.tasks(),
+            ActionSet::Registered(action) => action.tasks(),
+        }
+    }
+    fn scenes(&self) -> Vec<String> {
+        match self {
+            ActionSet::Genral(action) => action.scenes(),
+            ActionSet::Registered(action) => action.scenes(),
+        }
+    }
+    fn scripts(&self) -> Vec<String> {
+        match self {
+            ActionSet::Genral(action) => action.scripts(),
+            ActionSet::Registered(action) => action.scripts(),
+        }
+    }
+    fn avatars(&self) -> Vec<String> {
+        match self {
+            ActionSet::Genral(action) => action.avatars(),
+            ActionSet::Registered(action) => action.avatars(),
+        }
+    }
+}
+
 impl TaskRecipe<AahCore> for ActionSet {
-    type Res = ();
+    type Res = Value;
     fn run(&self, runner: &AahCore) -> anyhow::Result<Self::Res> {
         match self {
             ActionSet::Genral(action) => action.run(runner),
-            // ActionSet::Copilot(copilot) => copilot.run(runner),
+            ActionSet::Registered(action) => action.run(runner),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::task::{Action, Task};
+
+    use super::*;
+
+    #[test]
+    fn test_serde_registered_action() {
+        let task: Task<ActionSet> = toml::from_str(
+            r#"
+name = "test"
+
+[[steps]]
+action.Press = { key = "Esc" }
+
+[[steps]]
+action.ChooseLevel = "CE-5"
+"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            task.steps[0].action,
+            Action::Detailed(ActionSet::Genral(_))
+        ));
+        let Action::Detailed(ActionSet::Registered(action)) = &task.steps[1].action else {
+            panic!("expected registered action");
+        };
+        assert_eq!(action.name(), "ChooseLevel");
+        assert_eq!(
+            task.steps[1].action.templates(),
+            vec![
+                "main_terminal.png",
+                "terminal-resource.png",
+                "levels-resources-lmb.png"
+            ]
+        );
+
+        // 序列化后能够再被解析为同样的任务
+        let toml = toml::to_string_pretty(&task).unwrap();
+        let parsed: Task<ActionSet> = toml::from_str(&toml).unwrap();
+        assert_eq!(toml::to_string_pretty(&parsed).unwrap(), toml);
+        assert_eq!(
+            parsed.steps[1].action.templates(),
+            task.steps[1].action.templates()
+        );
+
+        assert!(toml::from_str::<Task<ActionSet>>(
+            r#"
+name = "test"
+steps = [{ action.NotExist = {} }]
+"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,636 @@
+//! 运行嵌入的 [Rhai](https://rhai.rs) 脚本
+//!
+//! 对于计数、根据 OCR 识别出的数字进行分支、计算坐标等难以用 toml 步骤表达的逻辑，可以使用 [`Script`]：
+//!
+//! ```toml
+//! [[steps]]
+//! action.Script = { source = '''
+//! let m = match_template("main_mission.png", #{ roi: [[0.5, 0.5], [1.0, 1.0]] });
+//! if m != () {
+//!     click(m.x + m.width / 2, m.y + m.height / 2);
+//! }
+//! ''' }
+//! ```
+//!
+//! 脚本中可以使用以下函数：
+//!
+//! - `click(x, y)`、`swipe(x1, y1, x2, y2, duration_ms)`、`press_esc()`、`press_home()`
+//! - `screencap()`：截图，返回 `#{ width, height }`
+//! - `match_template(template)`、`match_template(template, options)`：匹配模板，
+//!   `options` 与 [`MatchOptions`] 格式相同，返回 `#{ x, y, width, height, confidence }`，未匹配到时返回 `()`
+//! - `ocr()`、`ocr(roi)`：识别文字，返回 `[#{ text, x, y, width, height }]`
+//! - `run_task(name)`：运行任务
+//! - `sleep(sec)`、`log(msg)`、`warn(msg)`，`print` 与 `debug` 会被输出到日志
+//!
+//! `file` 引用的脚本位于资源目录下的 `scripts` 中，会在资源检查时确认其存在。
+//!
+//! 脚本最后一个表达式的值为此操作的结果，可以通过步骤的 `output` 保存为变量（见 [`crate::task::vars`]）。
+//! 与其他操作一样，`source` 中的 `${name}` 会被替换为任务变量，Rhai 的字符串插值需要写作 `` `$${x}` ``。
+//!
+//! 脚本运行在沙箱中：不能 `import` 外部模块，不能使用 `eval`，
+//! 且执行的操作数、调用深度、字符串/数组/表的大小以及运行时间都受到限制。
+//! 运行时间包括 `sleep` 与 `run_task` 等函数的耗时，`sleep` 最多睡眠到超时为止。
+
+use std::{
+    fs,
+    rc::Rc,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use aah_controller::{clock::Clock, Controller};
+use anyhow::Context;
+use log::{debug, info, warn};
+use rhai::{module_resolvers::DummyModuleResolver, Array, Dynamic, Engine, EvalAltResult, Map};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::{
+    arknights::AahCore,
+    report::Recognition,
+    resource::{validate::ActionRefs, GetTask},
+    task::{
+        retry::{ActionError, ErrorKind},
+        vars::Value,
+    },
+    utils::resource::template_path,
+    vision::{
+        analyzer::{matching::MatchOptions, single_match::SingleMatchAnalyzer},
+        utils::Rect,
+    },
+    Core, TaskRecipe,
+};
+
+/// 运行一段 Rhai 脚本，`source` 与 `file` 需要且只能指定一个
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Script {
+    /// 脚本内容
+    pub source: Option<String>,
+    /// 脚本文件（相对于 `scripts`）
+    pub file: Option<String>,
+    /// 最多执行的操作数，默认为 1000000
+    pub max_operations: Option<u64>,
+    /// 最长运行时间（秒），默认为 60 秒
+    pub timeout_sec: Option<f32>,
+}
+
+impl Script {
+    pub fn new(source: impl AsRef<str>) -> Self {
+        Self {
+            source: Some(source.as_ref().to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_file(file: impl AsRef<str>) -> Self {
+        Self {
+            file: Some(file.as_ref().to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_max_operations(mut self, max_operations: u64) -> Self {
+        self.max_operations = Some(max_operations);
+        self
+    }
+
+    pub fn with_timeout(mut self, sec: f32) -> Self {
+        self.timeout_sec = Some(sec);
+        self
+    }
+}
+
+impl ActionRefs for Script {
+    fn scripts(&self) -> Vec<String> {
+        self.file.iter().cloned().collect()
+    }
+}
+
+/// 脚本对 core 的请求
+#[derive(Debug)]
+enum Request {
+    Click(u32, u32),
+    Swipe((u32, u32), (i32, i32), Duration),
+    PressEsc,
+    PressHome,
+    Screencap,
+    MatchTemplate(String, MatchOptions),
+    Ocr(Option<[(f32, f32); 2]>),
+    RunTask(String),
+}
+
+/// core 对请求的回应
+#[derive(Debug)]
+enum Response {
+    Unit,
+    Size(u32, u32),
+    Match(Option<(Rect, f32)>),
+    Text(Vec<(String, Rect)>),
+}
+
+/// 脚本线程一侧的通道
+struct Bridge {
+    requests: Sender<Request>,
+    responses: Receiver<Result<Response, String>>,
+    /// 脚本的等待与计时使用的时钟
+    clock: Arc<dyn Clock>,
+    /// 脚本必须在此之前（按 `clock` 计）结束
+    deadline: Instant,
+}
+
+impl Bridge {
+    fn call(&self, request: Request) -> Result<Response, Box<EvalAltResult>> {
+        self.requests
+            .send(request)
+            .map_err(|_| "script host is gone".to_string())?;
+        let response = self
+            .responses
+            .recv()
+            .map_err(|_| "script host is gone".to_string())?;
+        // 请求（如 `run_task`）可能耗时很久
+        self.check_deadline()?;
+        Ok(response?)
+    }
+
+    /// 剩余的运行时间
+    fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(self.clock.now())
+    }
+
+    fn check_deadline(&self) -> Result<(), Box<EvalAltResult>> {
+        match self.remaining().is_zero() {
+            true => Err("script timeout".into()),
+            false => Ok(()),
+        }
+    }
+}
+
+fn from_script<T: DeserializeOwned>(value: &Dynamic) -> Result<T, Box<EvalAltResult>> {
+    let value: serde_json::Value = rhai::serde::from_dynamic(value)?;
+    serde_json::from_value(value).map_err(|err| err.to_string().into())
+}
+
+fn rect_map(rect: &Rect) -> Map {
+    let mut map = Map::new();
+    map.insert("x".into(), (rect.x as i64).into());
+    map.insert("y".into(), (rect.y as i64).into());
+    map.insert("width".into(), (rect.width as i64).into());
+    map.insert("height".into(), (rect.height as i64).into());
+    map
+}
+
+fn coord(value: i64) -> Result<u32, Box<EvalAltResult>> {
+    u32::try_from(value).map_err(|_| format!("invalid coordinate: {value}").into())
+}
+
+/// 沙箱限制
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    max_operations: u64,
+    timeout: Duration,
+}
+
+/// 创建一个带有沙箱限制与所有绑定的 [`Engine`]
+fn engine(bridge: Rc<Bridge>, limits: Limits, dry_run: bool) -> Engine {
+    let mut engine = Engine::new();
+
+    // 沙箱
+    engine
+        .set_max_operations(limits.max_operations)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(1 << 16)
+        .set_max_array_size(1 << 16)
+        .set_max_map_size(1 << 16)
+        .set_module_resolver(DummyModuleResolver::new())
+        .disable_symbol("eval");
+    let b = bridge.clone();
+    engine.on_progress(move |_| {
+        b.remaining()
+            .is_zero()
+            .then(|| Dynamic::from("script timeout".to_string()))
+    });
+
+    // 日志
+    engine
+        .on_print(|s| info!("[Script]: {s}"))
+        .on_debug(|s, _, pos| debug!("[Script] {pos:?}: {s}"));
+    engine
+        .register_fn("log", |msg: &str| info!("[Script]: {msg}"))
+        .register_fn("warn", |msg: &str| warn!("[Script]: {msg}"));
+
+    // sleep，不超过剩余的运行时间
+    let b = bridge.clone();
+    let sleep = Rc::new(move |sec: f64| -> Result<(), Box<EvalAltResult>> {
+        if !dry_run && sec > 0.0 {
+            let duration = Duration::try_from_secs_f64(sec).unwrap_or(Duration::MAX);
+            b.clock.sleep(duration.min(b.remaining()));
+        }
+        b.check_deadline()
+    });
+    let s = sleep.clone();
+    engine
+        .register_fn("sleep", move |sec: f64| s(sec))
+        .register_fn("sleep", move |sec: i64| sleep(sec as f64));
+
+    // controller
+    let b = bridge.clone();
+    engine.register_fn("click", move |x: i64, y: i64| {
+        b.call(Request::Click(coord(x)?, coord(y)?)).map(|_| ())
+    });
+    let b = bridge.clone();
+    engine.register_fn(
+        "swipe",
+        move |x1: i64, y1: i64, x2: i64, y2: i64, duration_ms: i64| {
+            let duration = Duration::from_millis(duration_ms.max(0) as u64);
+            b.call(Request::Swipe(
+                (coord(x1)?, coord(y1)?),
+                (x2 as i32, y2 as i32),
+                duration,
+            ))
+            .map(|_| ())
+        },
+    );
+    let b = bridge.clone();
+    engine.register_fn("press_esc", move || b.call(Request::PressEsc).map(|_| ()));
+    let b = bridge.clone();
+    engine.register_fn("press_home", move || b.call(Request::PressHome).map(|_| ()));
+    let b = bridge.clone();
+    engine.register_fn("screencap", move || -> Result<Map, Box<EvalAltResult>> {
+        match b.call(Request::Screencap)? {
+            Response::Size(width, height) => {
+                let mut map = Map::new();
+                map.insert("width".into(), (width as i64).into());
+                map.insert("height".into(), (height as i64).into());
+                Ok(map)
+            }
+            response => Err(format!("unexpected response: {response:?}").into()),
+        }
+    });
+
+    // analyzers
+    let match_template = {
+        let b = bridge.clone();
+        move |template: &str, options: MatchOptions| -> Result<Dynamic, Box<EvalAltResult>> {
+            match b.call(Request::MatchTemplate(template.to_string(), options))? {
+                Response::Match(Some((rect, confidence))) => {
+                    let mut map = rect_map(&rect);
+                    map.insert("confidence".into(), (confidence as f64).into());
+                    Ok(map.into())
+                }
+                Response::Match(None) => Ok(Dynamic::UNIT),
+                response => Err(format!("unexpected response: {response:?}").into()),
+            }
+        }
+    };
+    let f = match_template.clone();
+    engine.register_fn("match_template", move |template: &str| {
+        f(template, MatchOptions::default())
+    });
+    engine.register_fn("match_template", move |template: &str, options: Map| {
+        match_template(template, from_script(&options.into())?)
+    });
+    let ocr = {
+        let b = bridge.clone();
+        move |roi: Option<[(f32, f32); 2]>| -> Result<Array, Box<EvalAltResult>> {
+            match b.call(Request::Ocr(roi))? {
+                Response::Text(lines) => Ok(lines
+                    .into_iter()
+                    .map(|(text, rect)| {
+                        let mut map = rect_map(&rect);
+                        map.insert("text".into(), text.into());
+                        map.into()
+                    })
+                    .collect()),
+                response => Err(format!("unexpected response: {response:?}").into()),
+            }
+        }
+    };
+    let f = ocr.clone();
+    engine.register_fn("ocr", move || f(None));
+    engine.register_fn("ocr", move |roi: Array| {
+        ocr(Some(from_script(&roi.into())?))
+    });
+
+    // tasks
+    let b = bridge;
+    engine.register_fn("run_task", move |name: &str| {
+        b.call(Request::RunTask(name.to_string())).map(|_| ())
+    });
+
+    engine
+}
+
+/// 在单独的线程中运行脚本，脚本的请求由 `handle` 在当前线程中处理
+///
+/// 返回脚本的结果（若不为 `()`）
+fn run_script(
+    source: String,
+    limits: Limits,
+    dry_run: bool,
+    clock: Arc<dyn Clock>,
+    mut handle: impl FnMut(Request) -> anyhow::Result<Response>,
+) -> anyhow::Result<Value> {
+    let deadline = clock
+        .now()
+        .checked_add(limits.timeout)
+        .context("script timeout is too long")?;
+    let (request_tx, request_rx) = mpsc::channel();
+    let (response_tx, response_rx) = mpsc::channel();
+
+    let script = thread::spawn(move || {
+        let bridge = Rc::new(Bridge {
+            requests: request_tx,
+            responses: response_rx,
+            clock,
+            deadline,
+        });
+        let engine = engine(bridge, limits, dry_run);
+        engine
+            .eval::<Dynamic>(&source)
+            .map_err(|err| err.to_string())
+            .and_then(|res| rhai::serde::from_dynamic::<Value>(&res).map_err(|err| err.to_string()))
+    });
+
+    // 脚本线程结束后 engine 被释放，request_tx 随之关闭
+    for request in request_rx {
+        let response = handle(request).map_err(|err| format!("{err:#}"));
+        if response_tx.send(response).is_err() {
+            break;
+        }
+    }
+
+    match script.join() {
+        Ok(res) => res.map_err(|err| anyhow::anyhow!("script error: {err}")),
+        Err(_) => anyhow::bail!("script panicked"),
+    }
+}
+
+impl Script {
+    fn limits(&self) -> anyhow::Result<Limits> {
+        let timeout_sec = self.timeout_sec.unwrap_or(60.0);
+        Ok(Limits {
+            max_operations: self.max_operations.unwrap_or(1_000_000),
+            timeout: Duration::try_from_secs_f32(timeout_sec)
+                .with_context(|| format!("invalid timeout_sec: {timeout_sec}"))?,
+        })
+    }
+
+    fn source(&self, aah: &AahCore) -> anyhow::Result<String> {
+        match (&self.source, &self.file) {
+            (Some(source), None) => Ok(source.clone()),
+            (None, Some(file)) => {
+                let path = aah.resource.root.join("scripts").join(file);
+                if !path.is_file() {
+                    return Err(ActionError::new(
+                        ErrorKind::ResourceNotFound,
+                        format!("script not found: {file}"),
+                    )
+                    .into());
+                }
+                fs::read_to_string(&path).with_context(|| format!("failed to read {path:?}"))
+            }
+            _ => anyhow::bail!("script requires exactly one of `source` and `file`"),
+        }
+    }
+}
+
+/// 在 `aah` 上处理脚本的请求
+fn handle(aah: &AahCore, request: Request) -> anyhow::Result<Response> {
+    let controller_error = |err: anyhow::Error| {
+        anyhow::Error::new(ActionError::new(
+            ErrorKind::ControllerError,
+            format!("controller error: {err:?}"),
+        ))
+    };
+    match request {
+        Request::Click(x, y) => aah.controller().click(x, y).map_err(controller_error)?,
+        Request::Swipe(start, end, duration) => aah
+            .controller()
+            .swipe(start, end, duration, 0.0, 0.0)
+            .map_err(controller_error)?,
+        Request::PressEsc => aah.controller().press_esc().map_err(controller_error)?,
+        Request::PressHome => aah.controller().press_home().map_err(controller_error)?,
+        Request::Screencap => {
+            let screen = aah.controller().screencap().map_err(controller_error)?;
+            return Ok(Response::Size(screen.width(), screen.height()));
+        }
+        Request::MatchTemplate(template, options) => {
+            if !template_path(&template, &aah.resource.root).is_file() {
+                return Err(ActionError::new(
+                    ErrorKind::ResourceNotFound,
+                    format!("template not found: {template}"),
+                )
+                .into());
+            }
+            let screen = aah.controller().screencap().map_err(controller_error)?;
+            let analyzer = SingleMatchAnalyzer::from_resource(aah.resource.as_ref(), &template)?
+                .with_options(options);
+            let output = analyzer.analyze_image(&screen)?;
+            if let Some(reporter) = aah.reporter() {
+                reporter.record_recognition(Recognition::new(
+                    &template,
+                    output.res.rect.clone(),
+                    output.res.score,
+                ));
+            }
+            let confidence = output.res.confidence;
+            return Ok(Response::Match(
+                output.res.rect.map(|rect| (rect, confidence)),
+            ));
+        }
+        Request::Ocr(roi) => {
+            let screen = aah.controller().screencap().map_err(controller_error)?;
+            let lines = aah.ocr_in_roi(&screen, roi)?;
+            return Ok(Response::Text(lines));
+        }
+        Request::RunTask(name) => {
+            let task = aah.resource.get_task(&name).ok_or_else(|| {
+                ActionError::new(
+                    ErrorKind::ResourceNotFound,
+                    format!("failed to get task by name: {name:?}"),
+                )
+            })?;
+            task.run(aah)?;
+        }
+    }
+    Ok(Response::Unit)
+}
+
+impl TaskRecipe<AahCore> for Script {
+    type Res = Value;
+    fn run(&self, aah: &AahCore) -> anyhow::Result<Self::Res> {
+        let source = self.source(aah)?;
+        let limits = self.limits()?;
+        let res = run_script(source, limits, aah.dry_run(), aah.clock(), |request| {
+            handle(aah, request)
+        })?;
+        if !res.is_null() {
+            info!("[Script]: result: {res}");
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use aah_controller::clock::{SystemClock, VirtualClock};
+
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        max_operations: 10_000,
+        timeout: Duration::from_secs(5),
+    };
+
+    /// 运行脚本，记录所有请求，模板匹配总是匹配到 `(100, 200, 50, 20)`
+    fn run(source: &str, limits: Limits) -> (anyhow::Result<Value>, Vec<String>) {
+        let mut requests = vec![];
+        let res = run_script(
+            source.to_string(),
+            limits,
+            true,
+            Arc::new(SystemClock),
+            |request| {
+                requests.push(format!("{request:?}"));
+                Ok(match request {
+                    Request::Screencap => Response::Size(1920, 1080),
+                    Request::MatchTemplate(template, _) => {
+                        Response::Match((template == "found.png").then_some((
+                            Rect {
+                                x: 100,
+                                y: 200,
+                                width: 50,
+                                height: 20,
+                            },
+                            0.9,
+                        )))
+                    }
+                    Request::Ocr(_) => Response::Text(vec![(
+                        "x12".to_string(),
+                        Rect {
+                            x: 0,
+                            y: 0,
+                            width: 10,
+                            height: 10,
+                        },
+                    )]),
+                    Request::RunTask(name) if name == "not_exist" => anyhow::bail!("no such task"),
+                    _ => Response::Unit,
+                })
+            },
+        );
+        (res, requests)
+    }
+
+    #[test]
+    fn test_script() {
+        let (res, requests) = run(
+            r#"
+let size = screencap();
+let m = match_template("found.png", #{ roi: [[0.5, 0.5], [1, 1]], threshold: 30 });
+if m != () {
+    click(m.x + m.width / 2, m.y + m.height / 2);
+}
+if match_template("missing.png") == () {
+    press_esc();
+}
+let count = parse_int(ocr([[0, 0], [0.5, 0.5]])[0].text.sub_string(1));
+for i in 0..count / 4 {
+    swipe(size.width / 2, 500, size.width / 2, 100, 200);
+}
+sleep(10);
+run_task("inner");
+count
+"#,
+            LIMITS,
+        );
+        assert_eq!(res.unwrap(), Value::from(12));
+        assert_eq!(requests.len(), 10);
+        assert_eq!(requests[2], "Click(125, 210)");
+        assert_eq!(requests[4], "PressEsc");
+        assert_eq!(requests[6], "Swipe((960, 500), (960, 100), 200ms)");
+        assert_eq!(requests[9], "RunTask(\"inner\")");
+
+        // 请求失败会成为脚本的错误，可以被捕获
+        let (res, _) = run(r#"run_task("not_exist")"#, LIMITS);
+        assert!(res.unwrap_err().to_string().contains("no such task"));
+        let (res, _) = run(
+            r#"let caught = false; try { run_task("not_exist"); } catch { caught = true; } caught"#,
+            LIMITS,
+        );
+        assert_eq!(res.unwrap(), Value::Bool(true));
+
+        // 资源检查时确认脚本文件存在
+        assert_eq!(
+            Script::from_file("collect.rhai").scripts(),
+            vec!["collect.rhai"]
+        );
+        assert!(Script::new("1").scripts().is_empty());
+    }
+
+    #[test]
+    fn test_script_sandbox() {
+        let (res, _) = run("loop {}", LIMITS);
+        assert!(res.is_err());
+
+        let limits = Limits {
+            max_operations: 0,
+            timeout: Duration::from_millis(100),
+        };
+        let start = Instant::now();
+        let (res, _) = run("loop {}", limits);
+        assert!(res.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        for source in [r#"import "hello" as h;"#, r#"eval("1")"#, "click(-1, 0)"] {
+            let (res, requests) = run(source, LIMITS);
+            assert!(res.is_err());
+            assert!(requests.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_script_timeout() {
+        let limits = Limits {
+            max_operations: 10_000,
+            timeout: Duration::from_millis(200),
+        };
+        // sleep 不会超过剩余的运行时间，运行时间按脚本的时钟计
+        let clock = Arc::new(VirtualClock::new());
+        let start = Instant::now();
+        let res = run_script(
+            "sleep(100); 1".to_string(),
+            limits,
+            false,
+            clock.clone(),
+            |_| Ok(Response::Unit),
+        );
+        assert!(res.unwrap_err().to_string().contains("script timeout"));
+        assert_eq!(clock.total_elapsed(), limits.timeout);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // 请求的耗时也计入运行时间
+        let res = run_script(
+            r#"run_task("slow"); 1"#.to_string(),
+            limits,
+            false,
+            Arc::new(SystemClock),
+            |_| {
+                thread::sleep(Duration::from_millis(300));
+                Ok(Response::Unit)
+            },
+        );
+        assert!(res.unwrap_err().to_string().contains("script timeout"));
+
+        for sec in [-1.0, f32::NAN, f32::INFINITY] {
+            assert!(Script::new("1").with_timeout(sec).limits().is_err());
+        }
+    }
+}
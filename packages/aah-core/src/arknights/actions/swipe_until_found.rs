@@ -0,0 +1,451 @@
+//! 滑动直到找到目标
+//!
+//! 在章节地图中找关卡、在长列表中找干员时，需要反复滑动并在每次滑动后识别一次：
+//!
+//! ```toml
+//! [[steps]]
+//! action.SwipeUntilFound = { target = { regex = "^1-7$" }, swipe = { p1 = [1500, 540], p2 = [500, 540], duration = 1.0, slope_in = 0.0, slope_out = 1.0 }, click = true }
+//! ```
+//!
+//! 滑动后画面不再变化时，认为已经到达列表的末尾，不会再继续滑动。
+
+use aah_controller::Controller;
+use image::DynamicImage;
+use log::info;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::{
+    android::actions::{
+        wait::{mean_diff, secs, template_analyzer, thumbnail},
+        Swipe,
+    },
+    arknights::AahCore,
+    report::Recognition,
+    resource::validate::ActionRefs,
+    task::retry::{ActionError, ErrorKind},
+    vision::{analyzer::matching::MatchOptions, utils::Rect},
+    Core, TaskRecipe,
+};
+
+use super::click_text::ClickText;
+
+/// 要找的目标，模板或文字
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum SwipeTarget {
+    /// 匹配模板
+    Template {
+        /// 模板文件名（相对于 `templates/1920x1080`）
+        template: String,
+        /// 匹配选项，如匹配区域 `roi`、阈值 `threshold` 等
+        #[serde(flatten)]
+        options: MatchOptions,
+    },
+    /// 识别文字，见 [`ClickText`]
+    Text(ClickText),
+}
+
+impl SwipeTarget {
+    /// 在 `screen` 中查找目标，返回目标的位置与得分（模板的匹配值或文字的相似度）
+    fn locate(&self, aah: &AahCore, screen: &DynamicImage) -> anyhow::Result<(Option<Rect>, f32)> {
+        match self {
+            SwipeTarget::Template { template, options } => {
+                let analyzer = template_analyzer(
+                    template,
+                    options,
+                    aah.resource.as_ref(),
+                    aah.scale_memory(),
+                )?;
+                let res = analyzer.analyze_image(screen)?.res;
+                Ok((res.rect, res.score))
+            }
+            SwipeTarget::Text(text) => text.locate(aah, screen),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            SwipeTarget::Template { template, .. } => template.clone(),
+            SwipeTarget::Text(text) => text.name(),
+        }
+    }
+}
+
+/// 反复滑动，直到找到目标，返回目标的位置
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SwipeUntilFound {
+    /// 要找的目标，为 `{ template = "xxx.png", ... }` 或 `{ text = "xxx", ... }`、`{ regex = "xxx", ... }`
+    target: SwipeTarget,
+    /// 每次的滑动
+    swipe: Swipe,
+    /// 最多滑动的次数，默认为 10
+    max_swipes: Option<u32>,
+    /// 每次滑动后等待画面停止的时间（秒），默认为 0.5 秒
+    settle_sec: Option<f32>,
+    /// 滑动前后平均每个像素的差异（`[0.0, 1.0]`）不超过此值即认为到达了末尾，默认为 0.02
+    threshold: Option<f32>,
+    /// 找到后是否点击目标，默认为 `false`
+    click: Option<bool>,
+}
+
+impl SwipeUntilFound {
+    pub fn new(target: SwipeTarget, swipe: Swipe) -> Self {
+        Self {
+            target,
+            swipe,
+            max_swipes: None,
+            settle_sec: None,
+            threshold: None,
+            click: None,
+        }
+    }
+
+    pub fn template(template: impl AsRef<str>, swipe: Swipe) -> Self {
+        Self::new(
+            SwipeTarget::Template {
+                template: template.as_ref().to_string(),
+                options: MatchOptions::default(),
+            },
+            swipe,
+        )
+    }
+
+    pub fn text(text: ClickText, swipe: Swipe) -> Self {
+        Self::new(SwipeTarget::Text(text), swipe)
+    }
+
+    pub fn with_max_swipes(mut self, max_swipes: u32) -> Self {
+        self.max_swipes = Some(max_swipes);
+        self
+    }
+
+    pub fn with_settle(mut self, sec: f32) -> Self {
+        self.settle_sec = Some(sec);
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    pub fn with_click(mut self) -> Self {
+        self.click = Some(true);
+        self
+    }
+}
+
+impl ActionRefs for SwipeUntilFound {
+    fn templates(&self) -> Vec<String> {
+        match &self.target {
+            SwipeTarget::Template { template, options } => {
+                let mut templates = vec![template.clone()];
+                templates.extend(options.templates());
+                templates
+            }
+            SwipeTarget::Text(_) => vec![],
+        }
+    }
+}
+
+impl TaskRecipe<AahCore> for SwipeUntilFound {
+    type Res = Rect;
+    fn run(&self, aah: &AahCore) -> anyhow::Result<Self::Res> {
+        self.swipe_until(aah, |screen| self.target.locate(aah, screen))
+    }
+}
+
+impl SwipeUntilFound {
+    /// 反复截图并通过 `locate` 查找目标，找不到时滑动一次
+    ///
+    /// 找到时在报告中记录目标的得分，找不到时记录所有画面中最高的得分
+    fn swipe_until<T, C>(
+        &self,
+        aah: &T,
+        mut locate: impl FnMut(&DynamicImage) -> anyhow::Result<(Option<Rect>, f32)>,
+    ) -> anyhow::Result<Rect>
+    where
+        C: Controller,
+        T: Core<Controller = C>,
+    {
+        let max_swipes = self.max_swipes.unwrap_or(10);
+        let threshold = self.threshold.unwrap_or(0.02);
+        let settle = secs("settle_sec", self.settle_sec.unwrap_or(0.5))?;
+        let name = self.target.name();
+
+        let mut prev = None;
+        let mut swipes = 0;
+        let mut best = 0.0f32;
+        let screen = loop {
+            let screen = aah.controller().screencap().map_err(|err| {
+                anyhow::Error::new(ActionError::new(
+                    ErrorKind::ControllerError,
+                    format!("controller error: {err:?}"),
+                ))
+            })?;
+            let (rect, score) = locate(&screen)?;
+            if let Some(rect) = rect {
+                if let Some(reporter) = aah.reporter() {
+                    reporter.record_recognition(Recognition::new(&name, Some(rect.clone()), score));
+                }
+                if self.click.unwrap_or(false) {
+                    aah.controller()
+                        .click_in_rect(rect.clone().into())
+                        .map_err(|err| {
+                            ActionError::new(
+                                ErrorKind::ControllerError,
+                                format!("controller error: {err:?}"),
+                            )
+                        })?;
+                }
+                return Ok(rect);
+            }
+
+            best = best.max(score);
+
+            let current = thumbnail(&screen, None);
+            if prev.is_some_and(|prev| mean_diff(&prev, &current) <= threshold) {
+                info!("[SwipeUntilFound]: reached the end after {swipes} swipes");
+                break screen;
+            }
+            if swipes >= max_swipes {
+                break screen;
+            }
+            self.swipe.run(aah)?;
+            swipes += 1;
+            if !aah.dry_run() {
+                aah.clock().sleep(settle);
+            }
+            prev = Some(current);
+        };
+
+        if let Some(reporter) = aah.reporter() {
+            reporter
+                .record_recognition(Recognition::new(&name, None, best).with_screenshot(&screen));
+        }
+        Err(ActionError::new(
+            ErrorKind::TemplateNotFound,
+            format!("failed to find {name} after {swipes} swipes"),
+        )
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{path::PathBuf, sync::Mutex, time::Duration};
+
+    use image::{Rgb, RgbImage};
+
+    use crate::{
+        report::RunKind,
+        test_utils::{resource, TestCore},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_serde_swipe_until_found() {
+        let action: SwipeUntilFound = toml::from_str(
+            r#"
+target = { template = "levels_crystal.png", threshold = 0.9 }
+swipe = { p1 = [1500, 540], p2 = [500, 540], duration = 1.0, slope_in = 0.0, slope_out = 1.0 }
+max_swipes = 5
+"#,
+        )
+        .unwrap();
+        assert!(matches!(action.target, SwipeTarget::Template { .. }));
+        assert_eq!(action.templates(), vec!["levels_crystal.png"]);
+
+        let action: SwipeUntilFound = toml::from_str(
+            r#"
+target = { regex = "^1-7$", roi = [[0.0, 0.25], [1.0, 0.75]] }
+swipe = { p1 = [1500, 540], p2 = [500, 540], duration = 1.0, slope_in = 0.0, slope_out = 1.0 }
+click = true
+"#,
+        )
+        .unwrap();
+        assert!(matches!(action.target, SwipeTarget::Text(_)));
+        assert!(action.templates().is_empty());
+    }
+
+    /// 每次滑动后显示下一张截图，滑到最后一张后画面不再变化
+    struct ListController {
+        screens: Vec<DynamicImage>,
+        pos: Mutex<usize>,
+        ops: Mutex<Vec<String>>,
+    }
+
+    impl ListController {
+        fn new(screens: Vec<DynamicImage>) -> Self {
+            Self {
+                screens,
+                pos: Mutex::new(0),
+                ops: Mutex::new(vec![]),
+            }
+        }
+    }
+
+    impl Controller for ListController {
+        fn screen_size(&self) -> (u32, u32) {
+            (64, 36)
+        }
+        fn click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+            self.ops.lock().unwrap().push(format!("click ({x}, {y})"));
+            Ok(())
+        }
+        fn swipe(
+            &self,
+            _start: (u32, u32),
+            _end: (i32, i32),
+            _duration: Duration,
+            _slope_in: f32,
+            _slope_out: f32,
+        ) -> anyhow::Result<()> {
+            let mut pos = self.pos.lock().unwrap();
+            *pos = (*pos + 1).min(self.screens.len() - 1);
+            self.ops.lock().unwrap().push("swipe".to_string());
+            Ok(())
+        }
+        fn raw_screencap(&self) -> anyhow::Result<Vec<u8>> {
+            anyhow::bail!("raw screencap is not supported")
+        }
+        fn screencap(&self) -> anyhow::Result<DynamicImage> {
+            Ok(self.screens[*self.pos.lock().unwrap()].clone())
+        }
+        fn press_home(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn press_esc(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    const TARGET: Rect = Rect {
+        x: 10,
+        y: 20,
+        width: 5,
+        height: 4,
+    };
+
+    /// 依次滑过 `colors` 颜色的画面，红色的画面中有目标
+    fn run(
+        action: SwipeUntilFound,
+        colors: &[[u8; 3]],
+    ) -> (anyhow::Result<Rect>, Vec<String>, u128) {
+        let screens = colors
+            .iter()
+            .map(|color| DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 36, Rgb(*color))))
+            .collect();
+        let aah = TestCore::with_controller(
+            ListController::new(screens),
+            resource(PathBuf::new(), vec![]),
+        );
+        let res = action.swipe_until(&aah, locate);
+        let ops = aah.controller.ops.lock().unwrap().clone();
+        (res, ops, aah.clock.total_elapsed().as_millis())
+    }
+
+    /// 红色的画面中有目标，得分为 0.9，其余画面的得分为红色分量的比例
+    fn locate(screen: &DynamicImage) -> anyhow::Result<(Option<Rect>, f32)> {
+        let color = screen.to_rgb8().get_pixel(0, 0).0;
+        Ok(match color == RED {
+            true => (Some(TARGET), 0.9),
+            false => (None, color[0] as f32 / 255.0 * 0.5),
+        })
+    }
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const GRAY: [u8; 3] = [128, 128, 128];
+    const WHITE: [u8; 3] = [255, 255, 255];
+    const BLACK: [u8; 3] = [0, 0, 0];
+
+    #[test]
+    fn test_swipe_until_found() {
+        let action = || {
+            SwipeUntilFound::template(
+                "levels_crystal.png",
+                Swipe::new((1500, 540), (500, 540), Duration::from_secs(1), 0.0, 1.0),
+            )
+        };
+
+        // 第三个画面中找到，点击目标
+        let (res, ops, elapsed) = run(action().with_click(), &[GRAY, WHITE, RED]);
+        let rect = res.unwrap();
+        assert_eq!((rect.x, rect.y), (TARGET.x, TARGET.y));
+        assert_eq!(ops[..2], ["swipe", "swipe"]);
+        assert_eq!(ops.len(), 3);
+        let (x, y) = ops[2]
+            .strip_prefix("click (")
+            .and_then(|op| op.strip_suffix(')'))
+            .and_then(|op| op.split_once(", "))
+            .unwrap();
+        let (x, y) = (x.parse::<u32>().unwrap(), y.parse::<u32>().unwrap());
+        assert!((TARGET.x..TARGET.x + TARGET.width).contains(&x));
+        assert!((TARGET.y..TARGET.y + TARGET.height).contains(&y));
+        assert_eq!(elapsed, 1000);
+
+        // 不点击
+        let (res, ops, _) = run(action(), &[RED]);
+        let rect = res.unwrap();
+        assert_eq!((rect.x, rect.y), (TARGET.x, TARGET.y));
+        assert!(ops.is_empty());
+
+        // 滑到末尾后画面不再变化，不会继续滑动
+        let (res, ops, _) = run(action(), &[GRAY, WHITE]);
+        let err = res.unwrap_err();
+        assert_eq!(ActionError::kind_of(&err), ErrorKind::TemplateNotFound);
+        assert!(err.to_string().contains("after 2 swipes"));
+        assert_eq!(ops, ["swipe", "swipe"]);
+
+        // 超过最多滑动的次数
+        let (res, ops, _) = run(
+            action().with_max_swipes(2),
+            &[GRAY, WHITE, BLACK, WHITE, RED],
+        );
+        let err = res.unwrap_err();
+        assert_eq!(ActionError::kind_of(&err), ErrorKind::TemplateNotFound);
+        assert_eq!(ops, ["swipe", "swipe"]);
+    }
+
+    #[test]
+    fn test_swipe_until_found_report() {
+        // 报告中记录的得分
+        let scores = |colors: &[[u8; 3]]| {
+            let screens = colors
+                .iter()
+                .map(|color| DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 36, Rgb(*color))))
+                .collect();
+            let aah = TestCore::with_controller(
+                ListController::new(screens),
+                resource(PathBuf::new(), vec![]),
+            )
+            .with_reporter();
+            let reporter = aah.reporter().unwrap();
+            reporter.begin_task(RunKind::Task, "swipe");
+            reporter.begin_step(0, "SwipeUntilFound");
+            reporter.begin_attempt();
+            let action = SwipeUntilFound::template(
+                "levels_crystal.png",
+                Swipe::new((1500, 540), (500, 540), Duration::from_secs(1), 0.0, 1.0),
+            );
+            let res = action.swipe_until(&aah, locate);
+            reporter.end_attempt(&res);
+            reporter.end_step();
+            reporter.end_task(&res);
+            let report = reporter.take_reports().pop().unwrap();
+            report.steps[0].attempts[0]
+                .recognitions
+                .iter()
+                .map(|recognition| (recognition.rect.is_some(), recognition.score))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(scores(&[GRAY, RED]), vec![(true, 0.9)]);
+        // 找不到时为所有画面中最高的得分，即白色画面的 0.5
+        assert_eq!(scores(&[GRAY, WHITE]), vec![(false, 0.5)]);
+    }
+}
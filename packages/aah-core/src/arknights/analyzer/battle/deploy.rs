@@ -1,7 +1,4 @@
-use std::{
-    path::{Path, PathBuf},
-    time::Instant,
-};
+use std::{path::PathBuf, time::Instant};
 
 use color_print::{cformat, cprintln};
 use image::DynamicImage;
@@ -9,6 +6,7 @@ use serde::Serialize;
 
 use crate::{
     arknights::AahCore,
+    resource::template_store::TemplateStore,
     utils::resource::get_opers_avatars,
     vision::{
         analyzer::{matching::MatchOptions, multi_match::MultiMatchAnalyzer, Analyzer},
@@ -53,10 +51,11 @@ pub struct DeployAnalyzer {
 }
 
 impl DeployAnalyzer {
-    pub fn new<S: AsRef<str>>(res_dir: impl AsRef<Path>, opers: &[S]) -> Self {
-        let res_dir = res_dir.as_ref().to_path_buf();
+    /// 创建一个新的 [`DeployAnalyzer`]，部署卡片的模板从 `templates` 中获取，干员头像从其资源目录中加载
+    pub fn new<S: AsRef<str>>(templates: &TemplateStore, opers: &[S]) -> anyhow::Result<Self> {
+        let res_dir = templates.root().to_path_buf();
 
-        let opers_avatars = get_opers_avatars(opers, &res_dir).unwrap();
+        let opers_avatars = get_opers_avatars(opers, &res_dir).map_err(anyhow::Error::msg)?;
         let oper_names = opers_avatars
             .iter()
             .map(|(s, _)| s.to_string())
@@ -68,15 +67,15 @@ impl DeployAnalyzer {
 
         // ccorr_normed 0.9
         let multi_match_analyzer =
-            MultiMatchAnalyzer::new(&res_dir, "battle_deploy-card-cost1.png")
+            MultiMatchAnalyzer::from_store(templates, "battle_deploy-card-cost1.png")?
                 .with_options(MatchOptions::default().with_roi((0.0, 0.75), (1.0, 1.0)));
-        Self {
+        Ok(Self {
             res_dir,
             use_cache: false,
             oper_names,
             matcher: BestMatcher::new(images, None),
             multi_match_analyzer,
-        }
+        })
     }
 
     pub fn use_cache(mut self) -> Self {
@@ -182,7 +181,7 @@ mod test {
     fn test_deploy_analyzer() {
         // let mut core = AAH::connect("127.0.0.1:16384", "../../resources", |_| {}).unwrap();
         let mut analyzer = DeployAnalyzer::new(
-            "../../resources",
+            &TemplateStore::new("../../resources"),
             &[
                 // "char_1028_texas2",
                 // "char_4087_ines",
@@ -209,7 +208,8 @@ mod test {
                 "char_264_f12yin",
                 "char_4087_ines",
             ],
-        );
+        )
+        .unwrap();
         // let mut analyzer = DeployAnalyzer::new(&core.res_dir, core.default_oper_list.clone()); // self.default_oper_list.clone() cost 52s
         // let image = image::open("../../resources/templates/MUMU-1920x1080/1-4.png").unwrap();
         let image = image::open("./assets/5-10-resumed.png").unwrap();
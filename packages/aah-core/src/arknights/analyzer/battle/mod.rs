@@ -2,11 +2,9 @@
 
 pub mod deploy;
 
-use std::{
-    path::{Path, PathBuf},
-    time::Instant,
-};
+use std::{sync::Arc, time::Instant};
 
+use aah_controller::clock::{Clock, SystemClock};
 use color_print::{cformat, cprintln};
 use deploy::{DeployAnalyzer, DeployCard};
 use image::DynamicImage;
@@ -14,6 +12,7 @@ use serde::Serialize;
 
 use crate::{
     arknights::AahCore,
+    resource::template_store::TemplateStore,
     vision::analyzer::{matching::MatchOptions, single_match::SingleMatchAnalyzer, Analyzer},
     CachedScreenCapper,
 };
@@ -48,14 +47,17 @@ pub enum BattleState {
 /// 战场分析器，详情见输出分析结果 [`BattleAnalyzer`]
 ///
 /// # Example
-/// ```rust
-/// use aah_core::vision::analyzer::battle::BattleAnalyzer;
+/// ```rust,no_run
+/// use aah_core::{
+///     arknights::analyzer::battle::BattleAnalyzer, resource::template_store::TemplateStore,
+/// };
 /// use image;
 ///
 /// fn main() {
+///     let templates = TemplateStore::new("../../resources");
 ///     let mut analyzer = BattleAnalyzer::new(
-///         "../../resources",
-///         vec![
+///         &templates,
+///         &[
 ///             "char_1028_texas2",
 ///             "char_4087_ines",
 ///             "char_479_sleach",
@@ -69,34 +71,44 @@ pub enum BattleState {
 ///             "char_1035_wisdel",
 ///             "char_311_mudrok",
 ///         ],
-///     );
+///     )
+///     .unwrap();
 ///     let image = image::open("../../resources/templates/MUMU-1920x1080/1-4.png").unwrap();
 ///     let output = analyzer.analyze_image(&image).unwrap();
 ///     println!("{:?}", output.deploy_cards);
 /// }
 /// ```
 pub struct BattleAnalyzer {
-    res_dir: PathBuf,
+    templates: TemplateStore,
     pub battle_state: BattleState,
     deploy_cards: Vec<DeployCard>,
     start_time: Instant,
     deploy_analyzer: DeployAnalyzer,
+    clock: Arc<dyn Clock>,
 }
 
 impl BattleAnalyzer {
     /// 创建一个新的 [`BattleAnalyzer`]
     ///
-    /// - `res_dir`: 资源文件路径，会通过 [`crate::utils::resource::get_template`] 加载模板
+    /// - `templates`: 资源的模板缓存，一般为 [`ResRoot::template_store`](crate::resource::ResRoot::template_store)
     /// - `oper_names`: 内部的 [`DeployAnalyzer`] 识别的干员，为游戏内部资源文件的命名方式，如 `char_102_texas`, `char_1028_texas2`
-    pub fn new<P: AsRef<Path>, S: AsRef<str>>(res_dir: P, oper_names: &[S]) -> Self {
-        let deploy_analyzer = DeployAnalyzer::new(&res_dir, oper_names);
-        Self {
-            res_dir: res_dir.as_ref().to_path_buf(),
+    pub fn new<S: AsRef<str>>(templates: &TemplateStore, oper_names: &[S]) -> anyhow::Result<Self> {
+        let deploy_analyzer = DeployAnalyzer::new(templates, oper_names)?;
+        Ok(Self {
+            templates: templates.clone(),
             battle_state: BattleState::Unknown,
             deploy_cards: Vec::new(),
             start_time: Instant::now(),
             deploy_analyzer,
-        }
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// 使用 `clock` 记录战斗的开始与结束时间
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.start_time = clock.now();
+        self.clock = clock;
+        self
     }
 
     /// 分析传入的 `image`
@@ -119,14 +131,14 @@ impl BattleAnalyzer {
                     if self.battle_state == BattleState::Resumed
                         || self.battle_state == BattleState::Paused
                     {
-                        self.start_time = Instant::now();
+                        self.start_time = self.clock.now();
                         self.battle_state = BattleState::Completed;
                     } else {
                         self.battle_state = state;
                     }
                 }
                 Some(img) => {
-                    let output = SingleMatchAnalyzer::new(&self.res_dir, img.to_string())
+                    let output = SingleMatchAnalyzer::from_store(&self.templates, img)?
                         .with_options(
                             MatchOptions::default()
                                 .with_roi((0.875, 0.0), (1.0, 0.125))
@@ -138,7 +150,7 @@ impl BattleAnalyzer {
                         if self.battle_state == BattleState::Unknown
                             && state != BattleState::Unknown
                         {
-                            self.start_time = Instant::now();
+                            self.start_time = self.clock.now();
                         }
                         self.battle_state = state;
                         break;
@@ -171,12 +183,14 @@ impl Analyzer<AahCore> for BattleAnalyzer {
 
 #[cfg(test)]
 mod test {
+    use crate::resource::template_store::TemplateStore;
+
     use super::BattleAnalyzer;
 
     #[test]
     fn test_battle_analyzer() {
         let mut analyzer = BattleAnalyzer::new(
-            "../../resources",
+            &TemplateStore::new("../../resources"),
             &[
                 "char_1028_texas2",
                 "char_4087_ines",
@@ -191,7 +205,8 @@ mod test {
                 "char_1035_wisdel",
                 "char_311_mudrok",
             ],
-        );
+        )
+        .unwrap();
         let image = image::open("../../resources/templates/MUMU-1920x1080/1-4.png").unwrap();
         let output = analyzer.analyze_image(&image).unwrap();
         println!("{:?}", output.deploy_cards);
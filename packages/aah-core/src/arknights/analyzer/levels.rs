@@ -1,5 +1,4 @@
-use image::{DynamicImage, GenericImageView};
-use ocrs::ImageSource;
+use image::DynamicImage;
 use regex::Regex;
 
 use crate::{
@@ -41,6 +40,7 @@ impl Analyzer<AahCore> for LevelAnalyzer {
         // println!("matched, cost {:?}", t.elapsed()); // 1s
         // res.annotated_screen.save("./test.png").unwrap();
 
+        let level_code = Regex::new(r#"[a-zA-Z\d]+-[a-zA-Z\d]+(?:-[a-zA-Z\d])*"#).unwrap();
         let mut levels = vec![];
         let mut annotated_screen = res.annotated_screen;
         for level_crystal_rect in res.res.rects {
@@ -58,34 +58,14 @@ impl Analyzer<AahCore> for LevelAnalyzer {
                 [0xff, 0x00, 0x00, 0x00],
             );
             let level_code_img = res.screen.crop_imm(x, y, width, height);
-            let engine = &aah.ocr_engine;
-            let image_source =
-                ImageSource::from_bytes(level_code_img.as_bytes(), level_code_img.dimensions())
-                    .unwrap();
-            let ocr_input = engine.prepare_input(image_source).unwrap();
-
-            let word_rects = engine.detect_words(&ocr_input).unwrap();
-            let rects = engine.find_text_lines(&ocr_input, &word_rects);
-            let texts = engine.recognize_text(&ocr_input, &rects).unwrap();
-            let texts = texts
-                .iter()
-                .zip(rects.iter())
-                .filter_map(|(text, rect)| match text {
-                    Some(text) => {
-                        let text = text.to_string();
-                        if let Some(cap) = Regex::new(r#"[a-zA-Z\d]+-[a-zA-Z\d]+(?:-[a-zA-Z\d])*"#)
-                            .unwrap()
-                            .captures(&text)
-                        {
-                            let text = cap.get(0).unwrap().as_str().to_string();
-                            Some((text, rect))
-                        } else {
-                            None
-                        }
-                    }
-                    None => None,
+            let texts = aah
+                .ocr(&level_code_img)?
+                .into_iter()
+                .filter_map(|(text, rect)| {
+                    let text = level_code.find(&text)?.as_str().to_string();
+                    Some((text, rect))
                 })
-                .collect::<Vec<(String, _)>>();
+                .collect::<Vec<_>>();
 
             // for (text, rect) in &texts {
             //     println!("{} {:?}", text, rect)
@@ -1,21 +1,37 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
 pub mod actions;
 pub mod analyzer;
 pub mod resource;
+pub mod supervisor;
 
 use aah_controller::{
     android::{AahController, AdbController},
+    clock::{Clock, SystemClock},
+    dry_run::DryRunController,
     Controller,
 };
 pub use actions::ActionSet;
 use anyhow::Context;
 use anyhow::Result;
-use ocrs::{OcrEngine, OcrEngineParams};
+use image::DynamicImage;
+use ocrs::{ImageSource, OcrEngine, OcrEngineParams, TextItem};
 use resource::AahResource;
 use rten::Model;
 
-use crate::{resource::GetTask, CachedScreenCapper, Core, TaskRecipe};
+use crate::{
+    report::{Reporter, TaskReport},
+    resource::{GetNavigate, GetTask},
+    task::checkpoint::ResumeMode,
+    vision::{
+        analyzer::{matching::ScaleMemory, scene::SceneAnalyzerOutput},
+        utils::Rect,
+    },
+    CachedScreenCapper, Core, TaskRecipe,
+};
 
 pub struct AahCore {
     pub controller: Box<dyn Controller>,
@@ -24,6 +40,10 @@ pub struct AahCore {
     ocr_engine: OcrEngine,
 
     screen_cache: Mutex<Option<image::DynamicImage>>,
+    reporter: Option<Reporter>,
+    dry_run: bool,
+    clock: Arc<dyn Clock>,
+    scale_memory: ScaleMemory,
 }
 
 impl Core for AahCore {
@@ -35,6 +55,18 @@ impl Core for AahCore {
     fn resource(&self) -> &Self::Resource {
         &self.resource
     }
+    fn reporter(&self) -> Option<&Reporter> {
+        self.reporter.as_ref()
+    }
+    fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+    fn scale_memory(&self) -> Option<ScaleMemory> {
+        Some(self.scale_memory.clone())
+    }
 }
 
 impl AahCore {
@@ -66,6 +98,18 @@ impl AahCore {
         Self::new(controller, resource)
     }
 
+    /// 使用 [`DryRunController`] 创建一个 dry-run 模式的 [`AahCore`]
+    ///
+    /// 任务与作业会正常解析与识别，但所有的操作都只会被打印出来，不会发送到设备上
+    pub fn new_dry_run(
+        controller: DryRunController,
+        resource: Arc<AahResource>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut core = Self::new(Box::new(controller), resource)?;
+        core.dry_run = true;
+        Ok(core)
+    }
+
     fn new(
         controller: Box<dyn Controller + Sync + Send>,
         resource: Arc<AahResource>,
@@ -87,8 +131,31 @@ impl AahCore {
             ocr_engine,
             controller,
             screen_cache: Mutex::new(None),
+            reporter: None,
+            dry_run: false,
+            clock: Arc::new(SystemClock),
+            scale_memory: ScaleMemory::new(),
         })
     }
+
+    /// 开启运行报告的记录，之后每次运行任务或作业都会生成一个 [`TaskReport`]
+    pub fn enable_report(&mut self) {
+        self.reporter.get_or_insert_with(Reporter::new);
+    }
+
+    /// 替换所有等待与计时使用的时钟（包括控制器的），如使用 [`VirtualClock`](aah_controller::clock::VirtualClock) 在测试中模拟时间
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.controller.set_clock(clock.clone());
+        self.clock = clock;
+    }
+
+    /// 取出所有已完成的运行报告
+    pub fn take_reports(&self) -> Vec<TaskReport> {
+        self.reporter
+            .as_ref()
+            .map(|reporter| reporter.take_reports())
+            .unwrap_or_default()
+    }
     /// 运行名为 `name` 的任务
     ///
     /// - `name`: 任务名称
@@ -104,6 +171,28 @@ impl AahCore {
 
         Ok(())
     }
+    /// 运行名为 `name` 的任务，并将进度保存到 `path` 中的检查点，见 [`crate::task::checkpoint`]
+    ///
+    /// - `name`: 任务名称
+    /// - `path`: 检查点文件的路径
+    /// - `mode`: 是否从已有的检查点继续运行
+    pub fn run_task_with_checkpoint<S: AsRef<str>>(
+        &self,
+        name: S,
+        path: impl AsRef<Path>,
+        mode: ResumeMode,
+    ) -> anyhow::Result<()> {
+        let name = name.as_ref().to_string();
+
+        let task = self
+            .resource
+            .get_task(&name)
+            .ok_or(anyhow::anyhow!("failed to get task"))?;
+
+        task.run_with_checkpoint(self, path, mode)?;
+
+        Ok(())
+    }
     /// 运行名为 `name` 的作业
     ///
     /// - `name`: 作业名称
@@ -120,6 +209,72 @@ impl AahCore {
         Ok(())
     }
 
+    /// 识别 `image` 中的文字，返回每一行文字及其在 `image` 中的位置
+    pub fn ocr(&self, image: &DynamicImage) -> anyhow::Result<Vec<(String, Rect)>> {
+        let image = image.to_rgb8();
+        let source = ImageSource::from_bytes(image.as_raw(), image.dimensions())?;
+        let input = self.ocr_engine.prepare_input(source)?;
+        let word_rects = self.ocr_engine.detect_words(&input)?;
+        let line_rects = self.ocr_engine.find_text_lines(&input, &word_rects);
+        let lines = self.ocr_engine.recognize_text(&input, &line_rects)?;
+        Ok(lines
+            .into_iter()
+            .flatten()
+            .map(|line| {
+                let rect = line.bounding_rect();
+                let rect = Rect {
+                    x: rect.left().max(0) as u32,
+                    y: rect.top().max(0) as u32,
+                    width: rect.width().max(0) as u32,
+                    height: rect.height().max(0) as u32,
+                };
+                (line.to_string(), rect)
+            })
+            .collect())
+    }
+
+    /// 识别 `image` 中 `roi` 区域（左上角与右下角在 `[0.0, 1.0]` 中的坐标）内的文字，
+    /// 返回的位置为在 `image` 中的位置，区域为空时返回错误
+    pub fn ocr_in_roi(
+        &self,
+        image: &DynamicImage,
+        roi: Option<[(f32, f32); 2]>,
+    ) -> anyhow::Result<Vec<(String, Rect)>> {
+        let Some([tl, br]) = roi else {
+            return self.ocr(image);
+        };
+        let [(x1, y1), (x2, y2)] = [tl, br].map(|(x, y)| {
+            (
+                ((x * image.width() as f32) as u32).min(image.width()),
+                ((y * image.height() as f32) as u32).min(image.height()),
+            )
+        });
+        anyhow::ensure!(x2 > x1 && y2 > y1, "empty ocr roi: {:?}", [tl, br]);
+        let region = image.crop_imm(x1, y1, x2 - x1, y2 - y1);
+        Ok(self
+            .ocr(&region)?
+            .into_iter()
+            .map(|(text, rect)| {
+                let rect = Rect {
+                    x: rect.x + x1,
+                    y: rect.y + y1,
+                    ..rect
+                };
+                (text, rect)
+            })
+            .collect())
+    }
+
+    /// 识别当前所处的场景，场景定义在资源的 `navigates.toml` 中
+    pub fn recognize_scene(&self) -> anyhow::Result<SceneAnalyzerOutput> {
+        let analyzer = self
+            .resource
+            .navigate_config()
+            .analyzer(self.resource.as_ref())?;
+        let screen = self.controller.screencap()?;
+        analyzer.analyze_image(&screen, self.scale_memory().as_ref())
+    }
+
     // pub fn register_task_evt_handler<F: Fn(TaskEvt) + Send + Sync + 'static>(
     //     &mut self,
     //     handler: F,
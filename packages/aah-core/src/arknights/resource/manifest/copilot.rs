@@ -1,11 +1,23 @@
-use std::{collections::HashMap, fs, ops::Deref, path::Path};
+use std::{
+    collections::HashMap,
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 
 use crate::arknights::actions::copilot::{Copilot, CopilotAction, CopilotStep, Direction};
 
+/// 所有作业，以作业名为键
+///
+/// 第二个字段记录了每个作业所在的文件，仅在通过 [`CopilotConfig::load`] 加载时存在
 #[derive(Serialize, Deserialize, Debug)]
-pub struct CopilotConfig(pub HashMap<String, Copilot>);
+#[serde(transparent)]
+pub struct CopilotConfig(
+    pub HashMap<String, Copilot>,
+    #[serde(skip)] pub HashMap<String, PathBuf>,
+);
 
 impl Deref for CopilotConfig {
     type Target = HashMap<String, Copilot>;
@@ -15,9 +27,14 @@ impl Deref for CopilotConfig {
 }
 
 impl CopilotConfig {
+    /// 获取名为 `name` 的作业所在的文件
+    pub fn source(&self, name: impl AsRef<str>) -> Option<&Path> {
+        self.1.get(name.as_ref()).map(|path| path.as_path())
+    }
+
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
         let path = path.as_ref();
-        let mut config = CopilotConfig(HashMap::new());
+        let mut config = CopilotConfig(HashMap::new(), HashMap::new());
 
         if let Ok(read_dir) = fs::read_dir(path) {
             for entry in read_dir {
@@ -28,6 +45,7 @@ impl CopilotConfig {
                 if let Ok(task) = fs::read_to_string(entry.path()) {
                     let task = toml::from_str::<Copilot>(&task)?;
 
+                    config.1.insert(task.name.clone(), entry.path());
                     config.0.insert(task.name.clone(), task);
                 }
             }
@@ -43,7 +61,7 @@ impl Default for CopilotConfig {
         for (name, v) in test_copilots {
             map.insert(name.to_string(), v);
         }
-        Self(map)
+        Self(map, HashMap::new())
     }
 }
 
@@ -3,13 +3,22 @@ use std::{ops::Deref, path::Path};
 use manifest::copilot::CopilotConfig;
 
 use crate::{
-    resource::{GeneralAahResource, GetTask, Load, ResRoot},
+    resource::{
+        manifest::{interrupt::InterruptConfig, navigate::NavigateConfig},
+        template_store::TemplateStore,
+        validate::{IssueKind, IssueLocation, Validate, ValidationIssue},
+        GeneralAahResource, GetInterrupts, GetNavigate, GetTask, Load, ResRoot,
+    },
     task::Task,
 };
 
 pub mod manifest;
+pub mod schema;
 
-use super::{actions::copilot::Copilot, ActionSet};
+use super::{
+    actions::copilot::{Copilot, CopilotAction},
+    ActionSet,
+};
 
 // MARK: AahResource
 
@@ -33,10 +42,25 @@ impl GetTask<ActionSet> for AahResource {
     }
 }
 
+impl GetNavigate<ActionSet> for AahResource {
+    fn navigate_config(&self) -> &NavigateConfig<ActionSet> {
+        self.inner.navigate_config()
+    }
+}
+
+impl GetInterrupts<ActionSet> for AahResource {
+    fn interrupt_config(&self) -> &InterruptConfig<ActionSet> {
+        self.inner.interrupt_config()
+    }
+}
+
 impl ResRoot for AahResource {
     fn res_root(&self) -> &Path {
         self.inner.res_root()
     }
+    fn template_store(&self) -> Option<&TemplateStore> {
+        self.inner.template_store()
+    }
 }
 
 impl Load for AahResource {
@@ -52,6 +76,38 @@ impl Load for AahResource {
     }
 }
 
+impl Validate for AahResource {
+    fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = self.inner.validate();
+
+        let mut names = self.copilot_config.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            let copilot = &self.copilot_config[name];
+            for (idx, step) in copilot.steps.iter().enumerate() {
+                let operator = match &step.action {
+                    CopilotAction::Deploy { operator, .. }
+                    | CopilotAction::AutoSkill { operator }
+                    | CopilotAction::StopAutoSkill { operator }
+                    | CopilotAction::Retreat { operator } => operator,
+                };
+                if !copilot.operators.contains_key(operator) {
+                    issues.push(ValidationIssue {
+                        file: self.copilot_config.source(name).map(Path::to_path_buf),
+                        location: IssueLocation::Copilot {
+                            name: name.clone(),
+                            step: idx,
+                        },
+                        kind: IssueKind::UnknownOperator(operator.clone()),
+                    });
+                }
+            }
+        }
+
+        issues
+    }
+}
+
 // MARK: impl AahResource
 
 impl AahResource {
@@ -0,0 +1,64 @@
+//! 明日方舟资源文件的 JSON Schema
+//!
+//! 生成的 Schema 被提交在 `packages/aah-core/schemas` 下，测试会检查其与 Rust 类型是否一致，
+//! 修改相关类型后可以通过 `AAH_UPDATE_SCHEMAS=1 cargo test -p aah-core schema` 或 `aah schema <dir>` 重新生成。
+
+use schemars::schema::RootSchema;
+
+use crate::{
+    arknights::{actions::copilot::Copilot, ActionSet},
+    resource::{
+        manifest::{interrupt::InterruptConfig, navigate::NavigateConfig},
+        schema::schema,
+    },
+    task::Task,
+};
+
+/// 任务文件（`tasks/*.toml`）的 Schema 文件名
+pub const TASK_SCHEMA: &str = "task.schema.json";
+/// 作业文件（`copilots/*.toml`）的 Schema 文件名
+pub const COPILOT_SCHEMA: &str = "copilot.schema.json";
+/// 导航配置（`navigates.toml`）的 Schema 文件名
+pub const NAVIGATE_SCHEMA: &str = "navigate.schema.json";
+/// 全局中断配置（`interrupts.toml`）的 Schema 文件名
+pub const INTERRUPT_SCHEMA: &str = "interrupt.schema.json";
+
+/// 所有资源文件的 `(文件名, Schema)`
+pub fn schemas() -> Vec<(&'static str, RootSchema)> {
+    vec![
+        (TASK_SCHEMA, schema::<Task<ActionSet>>("Task")),
+        (COPILOT_SCHEMA, schema::<Copilot>("Copilot")),
+        (
+            NAVIGATE_SCHEMA,
+            schema::<NavigateConfig<ActionSet>>("NavigateConfig"),
+        ),
+        (
+            INTERRUPT_SCHEMA,
+            schema::<InterruptConfig<ActionSet>>("InterruptConfig"),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use crate::resource::schema::{to_json_string, write_schemas};
+
+    use super::*;
+
+    #[test]
+    fn test_schema_up_to_date() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("schemas");
+        if std::env::var("AAH_UPDATE_SCHEMAS").is_ok() {
+            write_schemas(&dir, &schemas()).unwrap();
+        }
+        for (name, schema) in schemas() {
+            let committed = fs::read_to_string(dir.join(name)).unwrap_or_default();
+            assert!(
+                committed == to_json_string(&schema),
+                "{name} is out of date, run `AAH_UPDATE_SCHEMAS=1 cargo test -p aah-core schema` to regenerate it"
+            );
+        }
+    }
+}
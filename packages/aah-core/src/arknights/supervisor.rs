@@ -0,0 +1,341 @@
+//! 多设备运行
+//!
+//! 同时在多个模拟器上运行多个账号时，由 [`Supervisor`] 为每台设备创建一个 [`AahCore`]，
+//! 所有设备共享同一份 [`AahResource`]，各自在独立的线程中按顺序运行自己的任务队列。
+//! 一台设备连接失败、任务失败甚至 panic 都不会影响其他设备。
+//!
+//! 设备组配置为一个 toml 文件，对应 [`DeviceGroupConfig`]：
+//!
+//! ```toml
+//! [[devices]]
+//! name = "main"
+//! serial = "127.0.0.1:16384"
+//! tasks = ["award", "farm"]
+//!
+//! [[devices]]
+//! name = "alt"
+//! serial = "127.0.0.1:16416"
+//! tasks = ["award"]
+//! ```
+
+use std::{
+    any::Any,
+    fmt::Display,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use anyhow::Context;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::resource::Load;
+
+use super::{resource::AahResource, AahCore};
+
+/// 设备组配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceGroupConfig {
+    /// 组中的设备
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+}
+
+impl Load for DeviceGroupConfig {
+    fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let config = fs::read_to_string(path).context(format!("failed to read {path:?}"))?;
+        toml::from_str(&config).context(format!("failed to parse {path:?}"))
+    }
+}
+
+/// 一台设备
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    /// 设备的名称，默认为序列号
+    pub name: Option<String>,
+    /// 设备的序列号（`adb devices` 里的序列号）
+    pub serial: String,
+    /// 依次运行的任务
+    #[serde(default)]
+    pub tasks: Vec<String>,
+}
+
+impl DeviceConfig {
+    pub fn new(serial: impl AsRef<str>, tasks: Vec<String>) -> Self {
+        Self {
+            name: None,
+            serial: serial.as_ref().to_string(),
+            tasks,
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.serial)
+    }
+}
+
+/// 设备当前的状态
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum DeviceState {
+    /// 尚未开始
+    Pending,
+    /// 正在连接
+    Connecting,
+    /// 正在运行任务
+    Running(String),
+    /// 任务队列已运行完毕（其中可能有失败的任务）
+    Finished,
+    /// 连接失败或 panic，没有运行完任务队列
+    Failed(String),
+}
+
+/// 一台设备的运行状态
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceStatus {
+    pub name: String,
+    pub serial: String,
+    pub state: DeviceState,
+    /// 成功完成的任务
+    pub completed: Vec<String>,
+    /// 失败的任务及错误信息
+    pub failed: Vec<(String, String)>,
+}
+
+impl DeviceStatus {
+    fn new(device: &DeviceConfig) -> Self {
+        Self {
+            name: device.name().to_string(),
+            serial: device.serial.clone(),
+            state: DeviceState::Pending,
+            completed: vec![],
+            failed: vec![],
+        }
+    }
+
+    /// 是否运行完了任务队列，且所有任务都成功
+    pub fn succeeded(&self) -> bool {
+        self.state == DeviceState::Finished && self.failed.is_empty()
+    }
+}
+
+impl Display for DeviceStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] ", self.name)?;
+        match &self.state {
+            DeviceState::Pending => write!(f, "pending")?,
+            DeviceState::Connecting => write!(f, "connecting")?,
+            DeviceState::Running(task) => write!(f, "running {task}")?,
+            DeviceState::Finished => write!(f, "finished")?,
+            DeviceState::Failed(err) => write!(f, "failed: {err}")?,
+        }
+        write!(f, ", {} completed", self.completed.len())?;
+        for (task, err) in &self.failed {
+            write!(f, ", {task} failed: {err}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 多设备运行的管理者
+pub struct Supervisor {
+    devices: Vec<DeviceConfig>,
+    status: Mutex<Vec<DeviceStatus>>,
+}
+
+impl Supervisor {
+    /// 组中没有任何设备时返回错误
+    pub fn new(config: DeviceGroupConfig) -> anyhow::Result<Self> {
+        if config.devices.is_empty() {
+            anyhow::bail!("no device in the device group");
+        }
+        let status = config.devices.iter().map(DeviceStatus::new).collect();
+        Ok(Self {
+            devices: config.devices,
+            status: Mutex::new(status),
+        })
+    }
+
+    /// 所有设备当前的状态，可以在运行期间从其他线程获取
+    pub fn status(&self) -> Vec<DeviceStatus> {
+        self.lock_status().clone()
+    }
+
+    /// 连接所有设备并行运行各自的任务队列，等待全部结束后返回所有设备的状态
+    ///
+    /// 所有设备的 [`AahCore`] 共享同一份 `resource`
+    pub fn run(&self, resource: Arc<AahResource>) -> Vec<DeviceStatus> {
+        self.run_with(
+            |serial| AahCore::connect(serial, resource.clone()),
+            |aah, task| aah.run_task(task),
+        )
+    }
+
+    /// 以 `connect` 连接设备、`run_task` 运行任务，见 [`Supervisor::run`]
+    pub fn run_with<T>(
+        &self,
+        connect: impl Fn(&str) -> anyhow::Result<T> + Sync,
+        run_task: impl Fn(&T, &str) -> anyhow::Result<()> + Sync,
+    ) -> Vec<DeviceStatus> {
+        info!("[Supervisor]: running {} devices", self.devices.len());
+        thread::scope(|s| {
+            let handles = self
+                .devices
+                .iter()
+                .enumerate()
+                .map(|(i, device)| {
+                    let (connect, run_task) = (&connect, &run_task);
+                    let handle = thread::Builder::new()
+                        .name(device.name().to_string())
+                        .spawn_scoped(s, move || self.run_device(i, device, connect, run_task));
+                    (i, handle)
+                })
+                .collect::<Vec<_>>();
+            for (i, handle) in handles {
+                let err = match handle {
+                    Ok(handle) => match handle.join() {
+                        Ok(()) => continue,
+                        Err(payload) => format!("panicked: {}", panic_message(payload.as_ref())),
+                    },
+                    Err(err) => format!("failed to spawn thread: {err}"),
+                };
+                self.update(i, |status| status.state = DeviceState::Failed(err));
+            }
+        });
+        let status = self.status();
+        for status in &status {
+            info!("[Supervisor]: {status}");
+        }
+        status
+    }
+
+    /// 在当前线程运行第 `i` 台设备的任务队列，任务失败后继续运行之后的任务
+    fn run_device<T>(
+        &self,
+        i: usize,
+        device: &DeviceConfig,
+        connect: impl Fn(&str) -> anyhow::Result<T>,
+        run_task: impl Fn(&T, &str) -> anyhow::Result<()>,
+    ) {
+        let name = device.name();
+        self.update(i, |status| status.state = DeviceState::Connecting);
+        let core = match connect(&device.serial) {
+            Ok(core) => core,
+            Err(err) => {
+                warn!("[Supervisor]: [{name}] failed to connect: {err:#}");
+                let err = format!("failed to connect: {err:#}");
+                self.update(i, |status| status.state = DeviceState::Failed(err));
+                return;
+            }
+        };
+        for task in &device.tasks {
+            info!("[Supervisor]: [{name}] running {task}");
+            self.update(i, |status| {
+                status.state = DeviceState::Running(task.clone())
+            });
+            match run_task(&core, task) {
+                Ok(()) => self.update(i, |status| status.completed.push(task.clone())),
+                Err(err) => {
+                    warn!("[Supervisor]: [{name}] {task} failed: {err:#}");
+                    self.update(i, |status| {
+                        status.failed.push((task.clone(), format!("{err:#}")))
+                    });
+                }
+            }
+        }
+        self.update(i, |status| status.state = DeviceState::Finished);
+    }
+
+    // 某台设备 panic 时不应影响其他设备更新状态
+    fn lock_status(&self) -> std::sync::MutexGuard<'_, Vec<DeviceStatus>> {
+        self.status.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn update(&self, i: usize, f: impl FnOnce(&mut DeviceStatus)) {
+        f(&mut self.lock_status()[i]);
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config: DeviceGroupConfig = toml::from_str(
+            r#"
+[[devices]]
+name = "main"
+serial = "127.0.0.1:16384"
+tasks = ["award", "farm"]
+
+[[devices]]
+serial = "127.0.0.1:16416"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.devices.len(), 2);
+        assert_eq!(config.devices[0].name(), "main");
+        assert_eq!(config.devices[1].name(), "127.0.0.1:16416");
+        assert!(config.devices[1].tasks.is_empty());
+    }
+
+    #[test]
+    fn test_run_isolated() {
+        let tasks = || vec!["award".to_string(), "fail".to_string(), "farm".to_string()];
+        let config = DeviceGroupConfig {
+            devices: vec![
+                DeviceConfig::new("a", tasks()).with_name("main"),
+                DeviceConfig::new("bad", tasks()),
+                DeviceConfig::new("b", vec!["award".to_string(), "panic".to_string()]),
+            ],
+        };
+        let supervisor = Supervisor::new(config).unwrap();
+
+        let status = supervisor.run_with(
+            |serial| match serial {
+                "bad" => anyhow::bail!("device offline"),
+                serial => Ok(serial.to_string()),
+            },
+            |serial, task| match task {
+                "fail" => anyhow::bail!("{serial}: not found"),
+                "panic" => panic!("{serial}: boom"),
+                _ => Ok(()),
+            },
+        );
+
+        assert_eq!(status[0].name, "main");
+        assert_eq!(status[0].state, DeviceState::Finished);
+        assert_eq!(status[0].completed, vec!["award", "farm"]);
+        assert_eq!(status[0].failed[0].0, "fail");
+        assert!(!status[0].succeeded());
+
+        assert_eq!(status[1].name, "bad");
+        assert!(
+            matches!(&status[1].state, DeviceState::Failed(err) if err.contains("device offline"))
+        );
+        assert!(status[1].completed.is_empty());
+
+        assert_eq!(status[2].completed, vec!["award"]);
+        assert!(matches!(&status[2].state, DeviceState::Failed(err) if err.contains("b: boom")));
+
+        assert!(Supervisor::new(DeviceGroupConfig::default()).is_err());
+    }
+}
@@ -4,11 +4,22 @@ pub mod android;
 pub mod arknights;
 #[cfg(feature = "desktop")]
 pub mod desktop;
+pub mod report;
 pub mod resource;
+pub mod schedule;
 pub mod task;
 pub mod utils;
 pub mod vision;
 
+#[cfg(test)]
+mod test_utils;
+
+use std::sync::Arc;
+
+use aah_controller::clock::{Clock, SystemClock};
+use report::Reporter;
+use vision::analyzer::matching::ScaleMemory;
+
 pub trait CachedScreenCapper {
     fn screen_cache_or_cap(&self) -> anyhow::Result<image::DynamicImage>;
     fn screen_cap_and_cache(&self) -> anyhow::Result<image::DynamicImage>;
@@ -36,6 +47,25 @@ pub trait Core {
     type Resource;
     fn resource(&self) -> &Self::Resource;
     fn controller(&self) -> &Self::Controller;
+    /// 运行报告的记录器，返回 `None` 时不进行记录
+    fn reporter(&self) -> Option<&Reporter> {
+        None
+    }
+    /// 是否处于 dry-run 模式
+    ///
+    /// dry-run 模式下不会有任何延迟与重试，步骤失败后也会继续执行后续的步骤，
+    /// 一般与 [`aah_controller::dry_run::DryRunController`] 一同使用
+    fn dry_run(&self) -> bool {
+        false
+    }
+    /// 所有等待与计时使用的时钟，测试时可以使用 [`VirtualClock`](aah_controller::clock::VirtualClock)
+    fn clock(&self) -> Arc<dyn Clock> {
+        Arc::new(SystemClock)
+    }
+    /// 多尺度匹配记住的尺度，返回 `None` 时每次匹配都重新搜索
+    fn scale_memory(&self) -> Option<ScaleMemory> {
+        None
+    }
 }
 
 /// [`TaskRecipe<T>`] 是一个可以由 `T` 运行的任务。
@@ -0,0 +1,448 @@
+//! 运行报告
+//!
+//! 一次任务（或作业）的运行会被记录为一个 [`TaskReport`]，其结构与任务的结构一致：
+//!
+//! - [`TaskReport`] 由若干 [`StepReport`] 组成
+//! - [`StepReport`] 由若干次尝试 [`AttemptReport`] 组成（重试与重复都会产生新的尝试）
+//! - [`AttemptReport`] 中包含此次尝试中的识别结果 [`Recognition`]，以及通过 `ByName` 嵌套运行的任务
+//!
+//! 记录由 [`Reporter`] 完成，[`crate::Core::reporter`] 返回 `Some` 时，任务运行过程中会自动进行记录。
+//! 报告可以导出为 json（[`TaskReport::to_json`]）或是一个自包含的 html 页面（[`TaskReport::to_html`]）。
+
+use std::{fmt::Write, io::Cursor, sync::Mutex, time::Instant};
+
+use base64::Engine;
+use chrono::{DateTime, Local};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use crate::vision::utils::Rect;
+
+/// 报告中截图的最大高度，超过此高度的截图会被缩小以控制报告的大小
+const SCREENSHOT_HEIGHT: u32 = 540;
+
+/// 运行的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RunKind {
+    Task,
+    Copilot,
+}
+
+/// 运行的结果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    Success,
+    Failure(String),
+}
+
+impl Outcome {
+    fn from_result<T>(res: &anyhow::Result<T>) -> Self {
+        match res {
+            Ok(_) => Outcome::Success,
+            Err(err) => Outcome::Failure(format!("{err:#}")),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self, Outcome::Success)
+    }
+}
+
+/// 一次任务或作业的运行
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskReport {
+    pub kind: RunKind,
+    pub name: String,
+    pub started_at: DateTime<Local>,
+    pub duration_sec: f32,
+    pub outcome: Outcome,
+    pub steps: Vec<StepReport>,
+}
+
+/// 任务中的一个步骤
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepReport {
+    /// 步骤在任务中的下标
+    ///
+    /// 对于作业，0 和 1 为开始行动前的点击，之后为作业中的各个步骤
+    pub index: usize,
+    /// 步骤的描述（一般为 Action 的 Debug 输出）
+    pub desc: String,
+    pub attempts: Vec<AttemptReport>,
+}
+
+/// 步骤的一次尝试
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttemptReport {
+    pub duration_sec: f32,
+    pub outcome: Outcome,
+    pub recognitions: Vec<Recognition>,
+    /// 在此次尝试中嵌套运行的任务
+    pub tasks: Vec<TaskReport>,
+}
+
+/// 一次识别的结果
+///
+/// - `target`: 识别的目标，如模板文件名
+/// - `rect`: 识别出的矩形框，识别失败时为 `None`
+/// - `score`: 匹配值
+/// - `screenshot`: 识别失败时标注过的截图，为 png 格式的 data url
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recognition {
+    pub target: String,
+    pub rect: Option<Rect>,
+    pub score: f32,
+    pub screenshot: Option<String>,
+}
+
+impl Recognition {
+    pub fn new(target: impl AsRef<str>, rect: Option<Rect>, score: f32) -> Self {
+        Self {
+            target: target.as_ref().to_string(),
+            rect,
+            score,
+            screenshot: None,
+        }
+    }
+
+    pub fn with_screenshot(mut self, image: &DynamicImage) -> Self {
+        self.screenshot = Some(image_data_url(image));
+        self
+    }
+}
+
+/// 将图片编码为 png 格式的 data url
+fn image_data_url(image: &DynamicImage) -> String {
+    let image = if image.height() > SCREENSHOT_HEIGHT {
+        image.resize(
+            u32::MAX,
+            SCREENSHOT_HEIGHT,
+            image::imageops::FilterType::Triangle,
+        )
+    } else {
+        image.clone()
+    };
+    let mut png = Cursor::new(vec![]);
+    image
+        .write_to(&mut png, image::ImageFormat::Png)
+        .expect("failed to encode screenshot");
+    format!(
+        "data:image/png;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(png.into_inner())
+    )
+}
+
+enum Frame {
+    Task(TaskReport, Instant),
+    Step(StepReport),
+    Attempt(AttemptReport, Instant),
+}
+
+/// 运行报告的记录器
+///
+/// 内部维护一个由 任务/步骤/尝试 组成的栈，`begin_xxx` 入栈，`end_xxx` 出栈并挂到新的栈顶上。
+/// 最外层的任务结束后会被放入已完成的报告中，通过 [`Reporter::take_reports`] 取出。
+#[derive(Default)]
+pub struct Reporter {
+    stack: Mutex<Vec<Frame>>,
+    finished: Mutex<Vec<TaskReport>>,
+}
+
+impl Reporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin_task(&self, kind: RunKind, name: impl AsRef<str>) {
+        let report = TaskReport {
+            kind,
+            name: name.as_ref().to_string(),
+            started_at: Local::now(),
+            duration_sec: 0.0,
+            outcome: Outcome::Success,
+            steps: vec![],
+        };
+        self.stack
+            .lock()
+            .unwrap()
+            .push(Frame::Task(report, Instant::now()));
+    }
+
+    /// 结束当前任务，尚未结束的步骤与尝试会以 `res` 作为结果一并结束
+    pub fn end_task<T>(&self, res: &anyhow::Result<T>) {
+        let outcome = Outcome::from_result(res);
+        let mut stack = self.stack.lock().unwrap();
+        while let Some(frame) = stack.pop() {
+            match frame {
+                Frame::Task(mut report, start) => {
+                    report.duration_sec = start.elapsed().as_secs_f32();
+                    report.outcome = outcome;
+                    match stack.last_mut() {
+                        Some(Frame::Attempt(attempt, _)) => attempt.tasks.push(report),
+                        _ => self.finished.lock().unwrap().push(report),
+                    }
+                    return;
+                }
+                frame => close(&mut stack, frame, &outcome),
+            }
+        }
+    }
+
+    pub fn begin_step(&self, index: usize, desc: impl AsRef<str>) {
+        let report = StepReport {
+            index,
+            desc: desc.as_ref().to_string(),
+            attempts: vec![],
+        };
+        self.stack.lock().unwrap().push(Frame::Step(report));
+    }
+
+    pub fn end_step(&self) {
+        let mut stack = self.stack.lock().unwrap();
+        if let Some(Frame::Step(_)) = stack.last() {
+            let frame = stack.pop().unwrap();
+            close(&mut stack, frame, &Outcome::Success);
+        }
+    }
+
+    pub fn begin_attempt(&self) {
+        let report = AttemptReport {
+            duration_sec: 0.0,
+            outcome: Outcome::Success,
+            recognitions: vec![],
+            tasks: vec![],
+        };
+        self.stack
+            .lock()
+            .unwrap()
+            .push(Frame::Attempt(report, Instant::now()));
+    }
+
+    pub fn end_attempt<T>(&self, res: &anyhow::Result<T>) {
+        let mut stack = self.stack.lock().unwrap();
+        if let Some(Frame::Attempt(..)) = stack.last() {
+            let frame = stack.pop().unwrap();
+            close(&mut stack, frame, &Outcome::from_result(res));
+        }
+    }
+
+    /// 记录当前尝试中的一次识别
+    pub fn record_recognition(&self, recognition: Recognition) {
+        if let Some(Frame::Attempt(attempt, _)) = self.stack.lock().unwrap().last_mut() {
+            attempt.recognitions.push(recognition);
+        }
+    }
+
+    /// 取出所有已完成的报告
+    pub fn take_reports(&self) -> Vec<TaskReport> {
+        std::mem::take(&mut self.finished.lock().unwrap())
+    }
+}
+
+/// 结束一个步骤或尝试，并将其挂到栈顶上
+fn close(stack: &mut [Frame], frame: Frame, outcome: &Outcome) {
+    match frame {
+        Frame::Step(step) => {
+            if let Some(Frame::Task(task, _)) = stack.last_mut() {
+                task.steps.push(step);
+            }
+        }
+        Frame::Attempt(mut attempt, start) => {
+            attempt.duration_sec = start.elapsed().as_secs_f32();
+            attempt.outcome = outcome.clone();
+            if let Some(Frame::Step(step)) = stack.last_mut() {
+                step.attempts.push(attempt);
+            }
+        }
+        Frame::Task(..) => unreachable!(),
+    }
+}
+
+// MARK: Export
+
+impl TaskReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// 导出为一个自包含的 html 页面（截图以 data url 的形式内嵌）
+    pub fn to_html(&self) -> String {
+        let mut body = String::new();
+        self.write_html(&mut body);
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; font-size: 14px; margin: 2em; }}
+details {{ margin-left: 1.5em; }}
+summary {{ cursor: pointer; padding: 2px 0; }}
+.success {{ color: #1a7f37; }}
+.failure {{ color: #cf222e; }}
+.dim {{ color: #777; }}
+code {{ background: #f3f3f3; padding: 0 4px; }}
+img {{ display: block; max-width: 100%; margin: 4px 0 8px 1.5em; border: 1px solid #ccc; }}
+ul {{ margin: 2px 0; }}
+</style>
+</head>
+<body>
+{body}</body>
+</html>
+"#,
+            title = escape(&format!("{:?} {}", self.kind, self.name)),
+        )
+    }
+
+    fn write_html(&self, out: &mut String) {
+        writeln!(
+            out,
+            r#"<details open><summary><strong>{:?} {}</strong> {} <span class="dim">{} · {:.2}s</span></summary>"#,
+            self.kind,
+            escape(&self.name),
+            outcome_html(&self.outcome),
+            self.started_at.format("%Y-%m-%d %H:%M:%S"),
+            self.duration_sec
+        )
+        .unwrap();
+        for step in &self.steps {
+            let outcome = step.attempts.last().map(|attempt| &attempt.outcome);
+            writeln!(
+                out,
+                r#"<details{}><summary>step {}: <code>{}</code> {} <span class="dim">{} attempt(s)</span></summary>"#,
+                if outcome.is_some_and(|o| !o.is_success()) { " open" } else { "" },
+                step.index,
+                escape(&step.desc),
+                outcome.map(outcome_html).unwrap_or_default(),
+                step.attempts.len()
+            )
+            .unwrap();
+            for (i, attempt) in step.attempts.iter().enumerate() {
+                writeln!(
+                    out,
+                    r#"<details{}><summary>attempt {}: {} <span class="dim">{:.2}s</span></summary>"#,
+                    if attempt.outcome.is_success() { "" } else { " open" },
+                    i,
+                    outcome_html(&attempt.outcome),
+                    attempt.duration_sec
+                )
+                .unwrap();
+                if !attempt.recognitions.is_empty() {
+                    out.push_str("<ul>\n");
+                    for rec in &attempt.recognitions {
+                        let rect = match &rec.rect {
+                            Some(r) => format!("({}, {}) {}x{}", r.x, r.y, r.width, r.height),
+                            None => "not found".to_string(),
+                        };
+                        writeln!(
+                            out,
+                            "<li><code>{}</code>: {} <span class=\"dim\">score {:.4}</span>",
+                            escape(&rec.target),
+                            rect,
+                            rec.score
+                        )
+                        .unwrap();
+                        if let Some(screenshot) = &rec.screenshot {
+                            writeln!(out, r#"<img src="{screenshot}">"#).unwrap();
+                        }
+                        out.push_str("</li>\n");
+                    }
+                    out.push_str("</ul>\n");
+                }
+                for task in &attempt.tasks {
+                    task.write_html(out);
+                }
+                out.push_str("</details>\n");
+            }
+            out.push_str("</details>\n");
+        }
+        out.push_str("</details>\n");
+    }
+}
+
+fn outcome_html(outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::Success => r#"<span class="success">✔</span>"#.to_string(),
+        Outcome::Failure(err) => format!(r#"<span class="failure">✘ {}</span>"#, escape(err)),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reporter() {
+        let reporter = Reporter::new();
+        reporter.begin_task(RunKind::Task, "outer");
+
+        reporter.begin_step(0, "ByName(inner)");
+        reporter.begin_attempt();
+        {
+            reporter.begin_task(RunKind::Task, "inner");
+            reporter.begin_step(0, "ClickMatchTemplate(a.png)");
+            reporter.begin_attempt();
+            reporter.record_recognition(
+                Recognition::new("a.png", None, 0.3).with_screenshot(&DynamicImage::new_rgb8(4, 4)),
+            );
+            reporter.end_attempt(&Err::<(), _>(anyhow::anyhow!("failed to match a.png")));
+            reporter.begin_attempt();
+            let rect = Rect {
+                x: 1,
+                y: 2,
+                width: 3,
+                height: 4,
+            };
+            reporter.record_recognition(Recognition::new("a.png", Some(rect), 0.9));
+            reporter.end_attempt(&Ok(()));
+            reporter.end_step();
+            reporter.end_task(&Ok(()));
+        }
+        reporter.end_attempt(&Ok(()));
+        reporter.end_step();
+
+        // 未结束的步骤与尝试随任务一并结束
+        reporter.begin_step(1, "Press(Esc)");
+        reporter.begin_attempt();
+        reporter.end_task(&Err::<(), _>(anyhow::anyhow!("controller error")));
+
+        let reports = reporter.take_reports();
+        assert_eq!(reports.len(), 1);
+        let outer = &reports[0];
+        assert_eq!(
+            outer.outcome,
+            Outcome::Failure("controller error".to_string())
+        );
+        assert_eq!(outer.steps.len(), 2);
+        assert_eq!(
+            outer.steps[1].attempts[0].outcome,
+            Outcome::Failure("controller error".to_string())
+        );
+
+        let inner = &outer.steps[0].attempts[0].tasks[0];
+        assert_eq!(inner.name, "inner");
+        assert_eq!(inner.outcome, Outcome::Success);
+        let attempts = &inner.steps[0].attempts;
+        assert_eq!(attempts.len(), 2);
+        assert!(!attempts[0].outcome.is_success());
+        assert!(attempts[0].recognitions[0].screenshot.is_some());
+        assert_eq!(attempts[1].recognitions[0].rect.as_ref().unwrap().x, 1);
+
+        let json = outer.to_json();
+        let parsed: TaskReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.steps.len(), 2);
+        let html = outer.to_html();
+        assert!(html.contains("ClickMatchTemplate(a.png)"));
+        assert!(html.contains("data:image/png;base64,"));
+
+        assert!(reporter.take_reports().is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+//! 全局中断配置，对应资源目录下的 `interrupts.toml`
+//!
+//! 游戏中随时可能弹出的对话框（如断线重连、签到、活动公告、升级提示等）会打断任务的执行，
+//! 每一个 [`Interrupt`] 由识别它的特征与处理它的操作组成：
+//!
+//! ```toml
+//! [[interrupts]]
+//! name = "notice"
+//! signatures = [{ template = "notice_close.png" }]
+//! action.ClickMatchTemplate = { template = "notice_close.png" }
+//! max_consecutive = 3
+//! ```
+//!
+//! 中断的处理见 [`crate::task::interrupt`]。
+
+use std::{
+    fmt::{self, Debug},
+    fs,
+    path::Path,
+    sync::OnceLock,
+};
+
+use anyhow::Context;
+use log::warn;
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::{
+    resource::ResRoot, task::Action, utils::resource::template_path,
+    vision::analyzer::scene::SceneAnalyzer,
+};
+
+use super::scene::{Scene, SceneSignature};
+
+/// 一个全局中断
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Interrupt<ActionSet: Debug + Clone> {
+    /// 中断名称
+    pub name: String,
+    /// 识别中断的特征，所有特征都满足时即认为出现了此中断
+    pub signatures: Vec<SceneSignature>,
+    /// 处理中断的操作
+    pub action: Action<ActionSet>,
+    /// 在同一个步骤中最多连续处理的次数，超过后任务失败，默认为 3
+    pub max_consecutive: Option<u32>,
+}
+
+impl<ActionSet: Debug + Clone> Interrupt<ActionSet> {
+    pub fn new(
+        name: impl AsRef<str>,
+        signatures: Vec<SceneSignature>,
+        action: Action<ActionSet>,
+    ) -> Self {
+        Self {
+            name: name.as_ref().to_string(),
+            signatures,
+            action,
+            max_consecutive: None,
+        }
+    }
+
+    /// 将此中断作为一个 [`Scene`] 进行识别
+    pub fn scene(&self) -> Scene {
+        Scene::new(&self.name, self.signatures.clone())
+    }
+}
+
+/// 所有全局中断
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(bound(deserialize = "ActionSet: Deserialize<'de>"))]
+pub struct InterruptConfig<ActionSet: Debug + Clone> {
+    #[serde(default)]
+    pub interrupts: Vec<Interrupt<ActionSet>>,
+    /// 识别中断的场景识别器，见 [`InterruptConfig::analyzer`]
+    #[serde(skip)]
+    analyzer: OnceLock<Option<SceneAnalyzer>>,
+}
+
+impl<ActionSet: Debug + Clone> Debug for InterruptConfig<ActionSet> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InterruptConfig")
+            .field("interrupts", &self.interrupts)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<ActionSet: Debug + Clone> Default for InterruptConfig<ActionSet> {
+    fn default() -> Self {
+        Self::new(vec![])
+    }
+}
+
+impl<ActionSet: Debug + Clone> InterruptConfig<ActionSet> {
+    pub fn new(interrupts: Vec<Interrupt<ActionSet>>) -> Self {
+        Self {
+            interrupts,
+            analyzer: OnceLock::new(),
+        }
+    }
+
+    /// 识别所有中断的场景识别器，第一次调用时加载 `res` 中的模板，之后的任务（包括 `ByName` 子任务）都复用它
+    ///
+    /// 没有中断时为 `None`。模板缺失的中断会被忽略并记录警告，而不是使每一个任务都失败，资源校验会报告这些中断
+    pub fn analyzer(&self, res: &impl ResRoot) -> Option<&SceneAnalyzer> {
+        self.analyzer
+            .get_or_init(|| {
+                let res_dir = res.res_root();
+                let scenes = self
+                    .interrupts
+                    .iter()
+                    .filter(|interrupt| {
+                        let missing =
+                            interrupt
+                                .signatures
+                                .iter()
+                                .find_map(|signature| match signature {
+                                    SceneSignature::Template(signature)
+                                        if !template_path(&signature.template, res_dir)
+                                            .is_file() =>
+                                    {
+                                        Some(&signature.template)
+                                    }
+                                    _ => None,
+                                });
+                        if let Some(template) = missing {
+                            warn!(
+                                "interrupt {} is disabled: template not found: {template}",
+                                interrupt.name
+                            );
+                        }
+                        missing.is_none()
+                    })
+                    .map(|interrupt| interrupt.scene())
+                    .collect::<Vec<_>>();
+                if scenes.is_empty() {
+                    return None;
+                }
+                SceneAnalyzer::new(res, &scenes)
+                    .inspect_err(|err| warn!("failed to load interrupts: {err:#}"))
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    /// 获取名为 `name` 的中断
+    pub fn interrupt(&self, name: impl AsRef<str>) -> Option<&Interrupt<ActionSet>> {
+        let name = name.as_ref();
+        self.interrupts
+            .iter()
+            .find(|interrupt| interrupt.name == name)
+    }
+}
+
+impl<ActionSet: Debug + Clone + DeserializeOwned> InterruptConfig<ActionSet> {
+    /// 从 `path` 加载，文件不存在时为空配置
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let config = fs::read_to_string(path)?;
+        toml::from_str(&config).with_context(|| format!("failed to parse {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::android::actions::ActionSet;
+
+    use super::*;
+
+    #[test]
+    fn test_serde_interrupt_config() {
+        let config: InterruptConfig<ActionSet> = toml::from_str(
+            r#"
+[[interrupts]]
+name = "notice"
+signatures = [{ template = "notice_close.png" }]
+action.ClickMatchTemplate = { template = "notice_close.png" }
+max_consecutive = 3
+
+[[interrupts]]
+name = "reconnect"
+signatures = [{ template = "confirm.png", roi = [[0.3, 0.5], [0.7, 0.8]] }]
+action.ByName = { name = "reconnect" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.interrupts.len(), 2);
+        assert_eq!(config.interrupt("notice").unwrap().max_consecutive, Some(3));
+        assert!(matches!(
+            config.interrupt("reconnect").unwrap().action,
+            Action::ByName(_)
+        ));
+    }
+}
@@ -1,3 +1,6 @@
+pub mod interrupt;
+pub mod navigate;
+pub mod scene;
 pub mod task;
 
 use serde::Deserialize;
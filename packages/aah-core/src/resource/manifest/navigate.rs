@@ -0,0 +1,313 @@
+//! 场景导航配置，对应资源目录下的 `navigates.toml`
+//!
+//! 游戏中的每一个界面都是一个 [`Scene`]，由若干特征识别（见 [`super::scene`]）；
+//! 界面之间的跳转为 [`Edge`]，由一个 [`Action`] 完成：
+//!
+//! ```toml
+//! [[scenes]]
+//! name = "main"
+//! signatures = [{ template = "main_mission.png", roi = [[0.5, 0.5], [1.0, 1.0]] }]
+//!
+//! [[scenes]]
+//! name = "mission"
+//! signatures = [{ template = "mission-week_collect-all.png" }]
+//!
+//! [[edges]]
+//! from = "main"
+//! to = "mission"
+//! action.ClickMatchTemplate = { template = "main_mission.png" }
+//!
+//! [[edges]]
+//! from = "mission"
+//! to = "main"
+//! action.ByName = { name = "back" }
+//! ```
+//!
+//! 实际的导航由 [`crate::task::navigate::NavigateTo`] 完成，识别场景的 [`SceneAnalyzer`] 由每个资源的
+//! [`NavigateConfig::analyzer`] 创建一次，所有导航与场景识别共用。
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fmt::{self, Debug},
+    fs,
+    path::Path,
+    sync::OnceLock,
+};
+
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::{resource::ResRoot, task::Action, vision::analyzer::scene::SceneAnalyzer};
+
+use super::scene::Scene;
+
+/// 从场景 `from` 到场景 `to` 的跳转
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Edge<ActionSet: Debug + Clone> {
+    /// 起始场景
+    pub from: String,
+    /// 目标场景
+    pub to: String,
+    /// 完成跳转的操作
+    pub action: Action<ActionSet>,
+    /// 寻路时的代价，默认为 1
+    pub cost: Option<u32>,
+    /// 执行操作后等待到达目标场景的最长时间，默认为 5 秒
+    pub timeout_sec: Option<f32>,
+}
+
+impl<ActionSet: Debug + Clone> Edge<ActionSet> {
+    pub fn new(from: impl AsRef<str>, to: impl AsRef<str>, action: Action<ActionSet>) -> Self {
+        Self {
+            from: from.as_ref().to_string(),
+            to: to.as_ref().to_string(),
+            action,
+            cost: None,
+            timeout_sec: None,
+        }
+    }
+
+    pub fn with_cost(mut self, cost: u32) -> Self {
+        self.cost = Some(cost);
+        self
+    }
+}
+
+/// 所有场景与场景之间的跳转
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(
+    bound(deserialize = "ActionSet: Deserialize<'de>"),
+    deny_unknown_fields
+)]
+pub struct NavigateConfig<ActionSet: Debug + Clone> {
+    #[serde(default)]
+    pub scenes: Vec<Scene>,
+    #[serde(default)]
+    pub edges: Vec<Edge<ActionSet>>,
+    /// 识别场景的场景识别器，见 [`NavigateConfig::analyzer`]
+    #[serde(skip)]
+    analyzer: OnceLock<SceneAnalyzer>,
+}
+
+impl<ActionSet: Debug + Clone> Debug for NavigateConfig<ActionSet> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NavigateConfig")
+            .field("scenes", &self.scenes)
+            .field("edges", &self.edges)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<ActionSet: Debug + Clone> Default for NavigateConfig<ActionSet> {
+    fn default() -> Self {
+        Self::new(vec![], vec![])
+    }
+}
+
+impl<ActionSet: Debug + Clone> NavigateConfig<ActionSet> {
+    pub fn new(scenes: Vec<Scene>, edges: Vec<Edge<ActionSet>>) -> Self {
+        Self {
+            scenes,
+            edges,
+            analyzer: OnceLock::new(),
+        }
+    }
+
+    /// 识别所有场景的场景识别器，第一次调用时加载 `res` 中的模板，之后的导航与场景识别都复用它
+    ///
+    /// 加载失败（如模板缺失）时返回错误，下一次调用时重新加载
+    pub fn analyzer(&self, res: &impl ResRoot) -> anyhow::Result<&SceneAnalyzer> {
+        if let Some(analyzer) = self.analyzer.get() {
+            return Ok(analyzer);
+        }
+        let analyzer = SceneAnalyzer::new(res, &self.scenes)?;
+        Ok(self.analyzer.get_or_init(|| analyzer))
+    }
+
+    /// 获取名为 `name` 的场景
+    pub fn scene(&self, name: impl AsRef<str>) -> Option<&Scene> {
+        let name = name.as_ref();
+        self.scenes.iter().find(|scene| scene.name == name)
+    }
+
+    /// 以 [`Edge::cost`] 为权重，找出从 `from` 到 `to` 的最短路径
+    ///
+    /// `from` 与 `to` 相同时返回空路径，不可达时返回 `None`
+    pub fn shortest_path(
+        &self,
+        from: impl AsRef<str>,
+        to: impl AsRef<str>,
+    ) -> Option<Vec<&Edge<ActionSet>>> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+
+        let mut dist: HashMap<&str, u32> = HashMap::from([(from, 0)]);
+        // 到达每个场景的最短路径上的最后一条边
+        let mut prev: HashMap<&str, usize> = HashMap::new();
+        let mut heap = BinaryHeap::from([Reverse((0, from))]);
+        while let Some(Reverse((d, scene))) = heap.pop() {
+            if scene == to {
+                break;
+            }
+            if dist.get(scene).is_some_and(|&best| d > best) {
+                continue;
+            }
+            for (idx, edge) in self.edges.iter().enumerate() {
+                if edge.from != scene {
+                    continue;
+                }
+                let next = d + edge.cost.unwrap_or(1);
+                if dist.get(edge.to.as_str()).is_none_or(|&best| next < best) {
+                    dist.insert(&edge.to, next);
+                    prev.insert(&edge.to, idx);
+                    heap.push(Reverse((next, &edge.to)));
+                }
+            }
+        }
+
+        if !dist.contains_key(to) {
+            return None;
+        }
+        let mut path = vec![];
+        let mut scene = to;
+        while scene != from {
+            let edge = &self.edges[prev[scene]];
+            path.push(edge);
+            scene = &edge.from;
+        }
+        path.reverse();
+        Some(path)
+    }
+}
+
+impl<ActionSet: Debug + Clone + DeserializeOwned> NavigateConfig<ActionSet> {
+    /// 从 `path` 加载，文件不存在时为空配置
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let config = fs::read_to_string(path)?;
+        toml::from_str(&config).with_context(|| format!("failed to parse {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        android::actions::ActionSet,
+        resource::{manifest::scene::TemplateSignature, validate::ActionRefs},
+        test_utils::{old_resources, resource},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_serde_navigate_config() {
+        let config: NavigateConfig<ActionSet> = toml::from_str(
+            r#"
+[[scenes]]
+name = "main"
+signatures = [{ template = "main_mission.png", roi = [[0.5, 0.5], [1.0, 1.0]] }]
+
+[[scenes]]
+name = "mission"
+signatures = [{ template = "mission-week_collect-all.png" }]
+
+[[edges]]
+from = "main"
+to = "mission"
+action.ClickMatchTemplate = { template = "main_mission.png" }
+
+[[edges]]
+from = "mission"
+to = "main"
+action.ByName = { name = "back" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.scenes.len(), 2);
+        assert_eq!(
+            config.scene("main").unwrap().signatures[0].template(),
+            Some("main_mission.png")
+        );
+        assert!(matches!(config.edges[1].action, Action::ByName(_)));
+    }
+
+    #[test]
+    fn test_load_navigate_config() {
+        let config =
+            NavigateConfig::<ActionSet>::load(old_resources().join("navigates.toml")).unwrap();
+        let path = config.shortest_path("main", "mission").unwrap();
+        assert_eq!(path.len(), 1);
+        assert_eq!(path[0].action.tasks(), vec!["enter_mission"]);
+
+        // 旧格式的 `[mission] enter.ByName = "..."` 不再被接受
+        assert!(toml::from_str::<NavigateConfig<ActionSet>>(
+            r#"
+[mission]
+enter.ByName = "enter_mission"
+exit.ByName = "back"
+"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_navigate_analyzer() {
+        let res = resource(old_resources(), vec![]);
+        let config =
+            NavigateConfig::<ActionSet>::load(old_resources().join("navigates.toml")).unwrap();
+
+        // 模板从资源的模板缓存中获取，识别器只创建一次
+        let analyzer = config.analyzer(&res).unwrap();
+        assert!(res.templates.is_loaded("main_mission.png"));
+        assert!(std::ptr::eq(analyzer, config.analyzer(&res).unwrap()));
+
+        let config = NavigateConfig::<ActionSet>::new(
+            vec![Scene::new(
+                "missing",
+                vec![TemplateSignature::new("missing.png").into()],
+            )],
+            vec![],
+        );
+        assert!(config.analyzer(&res).is_err());
+    }
+
+    #[test]
+    fn test_shortest_path() {
+        let edge =
+            |from: &str, to: &str| Edge::new(from, to, Action::detailed(ActionSet::press_esc()));
+        let config = NavigateConfig::<ActionSet>::new(
+            vec![],
+            vec![
+                edge("main", "terminal"),
+                edge("terminal", "level"),
+                edge("main", "level").with_cost(3),
+                edge("level", "main"),
+                edge("level", "formation"),
+            ],
+        );
+        let path = |from, to| {
+            config.shortest_path(from, to).map(|path| {
+                path.iter()
+                    .map(|edge| format!("{}->{}", edge.from, edge.to))
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(
+            path("main", "formation").unwrap(),
+            vec!["main->terminal", "terminal->level", "level->formation"]
+        );
+        assert_eq!(
+            path("level", "terminal").unwrap(),
+            vec!["level->main", "main->terminal"]
+        );
+        assert_eq!(path("main", "main").unwrap(), Vec::<String>::new());
+        assert_eq!(path("formation", "main"), None);
+    }
+}
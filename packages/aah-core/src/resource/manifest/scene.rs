@@ -0,0 +1,164 @@
+//! 场景定义
+//!
+//! 一个 [`Scene`] 对应游戏中的一个界面（如主界面、终端、关卡选择、编队、战斗、结算、加载、弹窗等），
+//! 由若干特征（[`SceneSignature`]）识别，目前支持模板特征与颜色特征：
+//!
+//! ```toml
+//! [[scenes]]
+//! name = "main"
+//! signatures = [
+//!     { template = "main_mission.png", roi = [[0.5, 0.5], [1.0, 1.0]], threshold = 30.0 },
+//!     { roi = [[0.0, 0.0], [0.1, 0.1]], color = [30, 30, 30], tolerance = 40 },
+//! ]
+//! ```
+//!
+//! 场景目前定义在 `navigates.toml` 中（见 [`super::navigate`]），识别由 [`crate::vision::analyzer::scene::SceneAnalyzer`] 完成。
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::vision::analyzer::matching::MatchOptions;
+
+/// 一个游戏界面
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Scene {
+    /// 场景名称
+    pub name: String,
+    /// 场景的描述
+    pub desc: Option<String>,
+    /// 场景的特征，所有特征都满足时即认为处于此场景
+    pub signatures: Vec<SceneSignature>,
+}
+
+impl Scene {
+    pub fn new(name: impl AsRef<str>, signatures: Vec<SceneSignature>) -> Self {
+        Self {
+            name: name.as_ref().to_string(),
+            desc: None,
+            signatures,
+        }
+    }
+}
+
+/// 场景的特征
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum SceneSignature {
+    /// 模板特征，见 [`TemplateSignature`]
+    Template(TemplateSignature),
+    /// 颜色特征，见 [`ColorSignature`]
+    Color(ColorSignature),
+}
+
+impl From<TemplateSignature> for SceneSignature {
+    fn from(signature: TemplateSignature) -> Self {
+        Self::Template(signature)
+    }
+}
+
+impl From<ColorSignature> for SceneSignature {
+    fn from(signature: ColorSignature) -> Self {
+        Self::Color(signature)
+    }
+}
+
+impl SceneSignature {
+    /// 此特征引用的模板文件
+    pub fn template(&self) -> Option<&str> {
+        match self {
+            SceneSignature::Template(signature) => Some(&signature.template),
+            SceneSignature::Color(_) => None,
+        }
+    }
+}
+
+/// 模板在画面中能够匹配到
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TemplateSignature {
+    /// 模板文件（相对于 `templates/1920x1080`）
+    pub template: String,
+    /// 匹配选项，如匹配区域 `roi`、阈值 `threshold`、匹配方法 `method` 等
+    #[serde(flatten)]
+    pub options: MatchOptions,
+}
+
+impl TemplateSignature {
+    pub fn new(template: impl AsRef<str>) -> Self {
+        Self {
+            template: template.as_ref().to_string(),
+            options: MatchOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: MatchOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// 画面中某一区域的平均颜色与给定颜色接近
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ColorSignature {
+    /// 区域，为左上角与右下角在 `[0.0, 1.0]` 中的坐标
+    pub roi: [(f32, f32); 2],
+    /// RGB 颜色
+    pub color: [u8; 3],
+    /// 每个通道允许的最大误差，默认为 20
+    pub tolerance: Option<u8>,
+}
+
+impl ColorSignature {
+    pub fn new(tl: (f32, f32), br: (f32, f32), color: [u8; 3]) -> Self {
+        Self {
+            roi: [tl, br],
+            color,
+            tolerance: None,
+        }
+    }
+
+    pub fn with_tolerance(mut self, tolerance: u8) -> Self {
+        self.tolerance = Some(tolerance);
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Scenes {
+        scenes: Vec<Scene>,
+    }
+
+    #[test]
+    fn test_serde_scene() {
+        let Scenes { scenes } = toml::from_str(
+            r#"
+[[scenes]]
+name = "main"
+signatures = [
+    { template = "main_mission.png", roi = [[0.5, 0.5], [1.0, 1.0]], threshold = 30.0 },
+    { roi = [[0.0, 0.0], [0.1, 0.1]], color = [30, 30, 30], tolerance = 40 },
+]
+"#,
+        )
+        .unwrap();
+        let signatures = &scenes[0].signatures;
+        let SceneSignature::Template(template) = &signatures[0] else {
+            panic!("expected template signature");
+        };
+        assert_eq!(template.template, "main_mission.png");
+        assert_eq!(template.options.roi, [(0.5, 0.5), (1.0, 1.0)]);
+        assert_eq!(template.options.threshold, Some(30.0));
+        assert_eq!(template.options.method, None);
+        let SceneSignature::Color(color) = &signatures[1] else {
+            panic!("expected color signature");
+        };
+        assert_eq!(color.color, [30, 30, 30]);
+        assert_eq!(color.tolerance, Some(40));
+    }
+}
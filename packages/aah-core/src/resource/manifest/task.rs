@@ -26,8 +26,15 @@ fn get_task_files(path: impl AsRef<Path>) -> Vec<PathBuf> {
     task_files
 }
 
+/// 所有任务，以任务名为键
+///
+/// 第二个字段记录了每个任务所在的文件，仅在通过 [`TaskConfig::load`] 加载时存在
 #[derive(Serialize, Deserialize, Debug)]
-pub struct TaskConfig<ActionSet: Debug + Clone>(pub HashMap<String, Task<ActionSet>>);
+#[serde(transparent)]
+pub struct TaskConfig<ActionSet: Debug + Clone>(
+    pub HashMap<String, Task<ActionSet>>,
+    #[serde(skip)] pub HashMap<String, PathBuf>,
+);
 
 impl<ActionSet: Debug + Clone> Deref for TaskConfig<ActionSet> {
     type Target = HashMap<String, Task<ActionSet>>;
@@ -36,14 +43,22 @@ impl<ActionSet: Debug + Clone> Deref for TaskConfig<ActionSet> {
     }
 }
 
+impl<ActionSet: Debug + Clone> TaskConfig<ActionSet> {
+    /// 获取名为 `name` 的任务所在的文件
+    pub fn source(&self, name: impl AsRef<str>) -> Option<&Path> {
+        self.1.get(name.as_ref()).map(|path| path.as_path())
+    }
+}
+
 impl<ActionSet: Debug + Clone + DeserializeOwned> TaskConfig<ActionSet> {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
         let path = path.as_ref();
-        let mut task_config = TaskConfig(HashMap::new());
+        let mut task_config = TaskConfig(HashMap::new(), HashMap::new());
         for task_file in get_task_files(path) {
-            if let Ok(task) = fs::read_to_string(task_file) {
+            if let Ok(task) = fs::read_to_string(&task_file) {
                 let task = toml::from_str::<Task<ActionSet>>(&task)?;
 
+                task_config.1.insert(task.name.to_string(), task_file);
                 task_config.0.insert(task.name.to_string(), task);
             }
         }
@@ -58,17 +73,19 @@ impl Default for TaskConfig<ActionSet> {
         for task in test_tasks {
             map.insert(task.name.clone(), task);
         }
-        Self(map)
+        Self(map, HashMap::new())
     }
 }
 
-use android::actions::ClickMatchTemplate;
+use android::actions::{ClickMatchTemplate, WaitForTemplate};
 
 #[allow(unused)]
 fn startup_task() -> Task<android::actions::ActionSet> {
     Task {
         name: "start_up".to_string(),
         desc: Some("start up to the main screen".to_string()),
+        handle_interrupts: None,
+        output: None,
         steps: vec![
             TaskStep::from_action(Action::detailed(ClickMatchTemplate::new("start_start.png")))
                 .with_retry(-1),
@@ -76,8 +93,11 @@ fn startup_task() -> Task<android::actions::ActionSet> {
                 "wakeup_wakeup.png",
             )))
             .with_retry(-1),
+            TaskStep::from_action(Action::detailed(
+                WaitForTemplate::new("confirm.png").with_timeout(10.0),
+            ))
+            .skip_if_failed(),
             TaskStep::from_action(Action::detailed(ClickMatchTemplate::new("confirm.png")))
-                .with_delay(6.0)
                 .with_retry(3)
                 .skip_if_failed(),
             TaskStep::from_action(Action::detailed(ClickMatchTemplate::new(
@@ -101,6 +121,8 @@ fn award_task() -> Task<android::actions::ActionSet> {
     Task {
         name: "award".to_string(),
         desc: None,
+        handle_interrupts: None,
+        output: None,
         steps: vec![
             TaskStep::from_action(Action::by_name("enter_mission")),
             TaskStep::from_action(Action::detailed(ClickMatchTemplate::new(
@@ -138,11 +160,15 @@ pub fn default_tasks() -> Vec<Task<android::actions::ActionSet>> {
         Task {
             name: "press_esc".to_string(),
             desc: None,
+            handle_interrupts: None,
+            output: None,
             steps: vec![TaskStep::from_action(Action::detailed(Press::esc()))],
         },
         Task {
             name: "press_home".to_string(),
             desc: None,
+            handle_interrupts: None,
+            output: None,
             steps: vec![TaskStep::from_action(Action::detailed(Press::home()))],
         },
     ]
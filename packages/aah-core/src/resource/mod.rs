@@ -7,13 +7,16 @@ use std::{
 };
 
 pub mod manifest;
+pub mod schema;
+pub mod template_store;
+pub mod validate;
 
 use anyhow::Context;
 use bytes::Bytes;
-use image::DynamicImage;
 use log::info;
-use manifest::{task::TaskConfig, Manifest};
+use manifest::{interrupt::InterruptConfig, navigate::NavigateConfig, task::TaskConfig, Manifest};
 use serde::de::DeserializeOwned;
+use template_store::TemplateStore;
 
 use crate::task::Task;
 
@@ -28,15 +31,24 @@ pub trait GetTask<ActionSet: Debug + Clone> {
     fn get_task(&self, name: impl AsRef<str>) -> Option<&Task<ActionSet>>;
 }
 
+pub trait GetNavigate<ActionSet: Debug + Clone> {
+    /// 获取 `resources-root/navigates.toml` 的导航配置
+    fn navigate_config(&self) -> &NavigateConfig<ActionSet>;
+}
+
+pub trait GetInterrupts<ActionSet: Debug + Clone> {
+    /// 获取 `resources-root/interrupts.toml` 的全局中断配置
+    fn interrupt_config(&self) -> &InterruptConfig<ActionSet>;
+}
+
 pub trait ResRoot {
     fn res_root(&self) -> &Path;
+    /// 模板缓存，返回 `None` 时每次都从磁盘读取模板
+    fn template_store(&self) -> Option<&TemplateStore> {
+        None
+    }
 }
 
-// pub trait GetTemplate {
-//     /// 获取 `resources-root/templates/<path>` 的图片
-//     fn get_template(&self, path: impl AsRef<Path>) -> anyhow::Result<DynamicImage>;
-// }
-
 /// 一个通用的基础 resources 目录应当具备以下目录结构：
 /// ```
 /// /resource-repo
@@ -49,12 +61,18 @@ pub trait ResRoot {
 /// │   ├── template1.png
 /// │   ├── template2.png
 /// │   └── ...
+/// ├── navigates.toml (可选)
+/// ├── interrupts.toml (可选)
 /// └── ...
 #[derive(Debug)]
 pub struct GeneralAahResource<ActionSet: Debug + Clone> {
     pub root: PathBuf,
     pub manifest: Manifest,
     pub task_config: TaskConfig<ActionSet>,
+    pub navigate_config: NavigateConfig<ActionSet>,
+    pub interrupt_config: InterruptConfig<ActionSet>,
+    /// `templates` 目录下模板的缓存
+    pub templates: TemplateStore,
 }
 
 impl<ActionSet: Debug + Clone + DeserializeOwned> Load for GeneralAahResource<ActionSet> {
@@ -74,15 +92,19 @@ impl<ActionSet: Debug + Clone + DeserializeOwned> Load for GeneralAahResource<Ac
             TaskConfig::load(root.join("tasks")).context("failed to load task config")?;
         // let copilot_config =
         //     CopilotConfig::load(root.join("copilot")).context("failed to load copilot config")?;
-        // let navigate_config = NavigateConfig::load(root.join("navigates.toml"))
-        //     .context("failed to load navigate config")?;
+        let navigate_config = NavigateConfig::load(root.join("navigates.toml"))
+            .context("failed to load navigate config")?;
+        let interrupt_config = InterruptConfig::load(root.join("interrupts.toml"))
+            .context("failed to load interrupt config")?;
 
         Ok(Self {
+            templates: TemplateStore::new(&root),
             root,
             manifest,
             task_config,
             // copilot_config,
-            // navigate_config,
+            navigate_config,
+            interrupt_config,
         })
     }
 }
@@ -94,20 +116,27 @@ impl<ActionSet: Debug + Clone> GetTask<ActionSet> for GeneralAahResource<ActionS
     }
 }
 
+impl<ActionSet: Debug + Clone> GetNavigate<ActionSet> for GeneralAahResource<ActionSet> {
+    fn navigate_config(&self) -> &NavigateConfig<ActionSet> {
+        &self.navigate_config
+    }
+}
+
+impl<ActionSet: Debug + Clone> GetInterrupts<ActionSet> for GeneralAahResource<ActionSet> {
+    fn interrupt_config(&self) -> &InterruptConfig<ActionSet> {
+        &self.interrupt_config
+    }
+}
+
 impl<ActionSet: Debug + Clone> ResRoot for GeneralAahResource<ActionSet> {
     fn res_root(&self) -> &Path {
         &self.root
     }
+    fn template_store(&self) -> Option<&TemplateStore> {
+        Some(&self.templates)
+    }
 }
 
-// impl<ActionSet: Debug + Clone> GetTemplate for GeneralAahResource<ActionSet> {
-//     fn get_template(&self, path: impl AsRef<Path>) -> anyhow::Result<DynamicImage> {
-//         let path = path.as_ref();
-//         let img = image::open(self.root.join("templates").join(path))?;
-//         Ok(img)
-//     }
-// }
-
 // MARK: GitRepoResource
 
 /// 一个实现了 [`Load`] 的结构的 Wrapper，用于便捷地从 Git 仓库初始化/更新资源。
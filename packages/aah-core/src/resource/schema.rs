@@ -0,0 +1,34 @@
+//! 资源文件格式的 JSON Schema
+//!
+//! 任务、作业等资源文件的格式均由对应的 Rust 类型通过 serde 定义，
+//! 这里借助 [`schemars`] 从同样的类型生成 JSON Schema，以便 taplo、VS Code 等编辑器对资源文件进行补全与校验。
+
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+
+/// 生成 `T` 的 JSON Schema，`title` 为 Schema 的标题
+pub fn schema<T: JsonSchema>(title: impl AsRef<str>) -> RootSchema {
+    let mut schema = schema_for!(T);
+    schema.schema.metadata().title = Some(title.as_ref().to_string());
+    schema
+}
+
+/// 将 Schema 序列化为格式化后的 json 字符串（以换行结尾）
+pub fn to_json_string(schema: &RootSchema) -> String {
+    let mut json = serde_json::to_string_pretty(schema).unwrap();
+    json.push('\n');
+    json
+}
+
+/// 将若干 `(文件名, Schema)` 写入到 `dir` 目录下
+pub fn write_schemas(dir: impl AsRef<Path>, schemas: &[(&str, RootSchema)]) -> anyhow::Result<()> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir).context(format!("failed to create dir {dir:?}"))?;
+    for (name, schema) in schemas {
+        let path = dir.join(name);
+        fs::write(&path, to_json_string(schema)).context(format!("failed to write {path:?}"))?;
+    }
+    Ok(())
+}
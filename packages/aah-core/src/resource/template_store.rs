@@ -0,0 +1,186 @@
+//! 模板缓存
+//!
+//! 每次创建分析器都从磁盘读取并解码模板的开销很大，战斗中每一帧都会创建多个分析器。
+//! [`TemplateStore`] 在第一次使用模板时才读取并解码，之后复用解码后的模板，
+//! 并按照截图的高度与缩放尺度缓存缩放后的模板与灰度图，同一份资源的所有设备共享这些缓存。
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use image::{DynamicImage, ImageBuffer, Luma};
+
+use crate::{utils::resource::get_template, vision::analyzer::matching::scale_template};
+
+/// 灰度模板
+pub type LumaTemplate = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// 模板名称、截图高度与缩放尺度
+type VariantKey = (String, u32, u32);
+
+#[derive(Default)]
+struct Templates {
+    decoded: HashMap<String, Arc<DynamicImage>>,
+    scaled: HashMap<VariantKey, Arc<DynamicImage>>,
+    luma: HashMap<VariantKey, Arc<LumaTemplate>>,
+}
+
+/// `{root}/templates/1920x1080` 下模板的缓存，可以廉价地 clone，clone 之间共享缓存
+#[derive(Clone)]
+pub struct TemplateStore {
+    root: PathBuf,
+    templates: Arc<Mutex<Templates>>,
+}
+
+impl Debug for TemplateStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let templates = self.lock();
+        f.debug_struct("TemplateStore")
+            .field("root", &self.root)
+            .field("decoded", &templates.decoded.len())
+            .field("scaled", &templates.scaled.len())
+            .field("luma", &templates.luma.len())
+            .finish()
+    }
+}
+
+impl TemplateStore {
+    /// 创建资源目录 `root` 的模板缓存，模板会在第一次使用时加载
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            templates: Arc::new(Mutex::new(Templates::default())),
+        }
+    }
+
+    /// 资源目录
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Templates> {
+        self.templates.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// 获取 1920x1080 下的模板，未加载时从磁盘读取
+    pub fn get(&self, name: &str) -> anyhow::Result<Arc<DynamicImage>> {
+        if let Some(template) = self.lock().decoded.get(name) {
+            return Ok(template.clone());
+        }
+        // 解码时不持有锁，避免阻塞其他设备
+        let template = Arc::new(get_template(name, &self.root)?);
+        Ok(self
+            .lock()
+            .decoded
+            .entry(name.to_string())
+            .or_insert(template)
+            .clone())
+    }
+
+    /// 获取按截图高度 `height` 缩放后再缩放 `scale` 倍的模板，见 [`scale_template`]
+    pub fn scaled(&self, name: &str, height: u32, scale: f32) -> anyhow::Result<Arc<DynamicImage>> {
+        let key = (name.to_string(), height, scale.to_bits());
+        if let Some(template) = self.lock().scaled.get(&key) {
+            return Ok(template.clone());
+        }
+        let template = self.get(name)?;
+        let template = Arc::new(scale_template(&template, height, scale));
+        Ok(self.lock().scaled.entry(key).or_insert(template).clone())
+    }
+
+    /// 获取缩放后的模板的灰度图，见 [`TemplateStore::scaled`]
+    pub fn luma(&self, name: &str, height: u32, scale: f32) -> anyhow::Result<Arc<LumaTemplate>> {
+        let key = (name.to_string(), height, scale.to_bits());
+        if let Some(template) = self.lock().luma.get(&key) {
+            return Ok(template.clone());
+        }
+        let template = Arc::new(self.scaled(name, height, scale)?.to_luma32f());
+        Ok(self.lock().luma.entry(key).or_insert(template).clone())
+    }
+
+    /// 提前加载 `names` 中的模板
+    pub fn preload<S: AsRef<str>>(&self, names: &[S]) -> anyhow::Result<()> {
+        for name in names {
+            self.get(name.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// 是否已经加载了模板 `name`
+    pub fn is_loaded(&self, name: &str) -> bool {
+        self.lock().decoded.contains_key(name)
+    }
+
+    /// 移除模板 `name` 及其所有缩放后的模板，下次使用时重新从磁盘读取
+    pub fn evict(&self, name: &str) {
+        let mut templates = self.lock();
+        templates.decoded.remove(name);
+        templates
+            .scaled
+            .retain(|(template, ..), _| template != name);
+        templates.luma.retain(|(template, ..), _| template != name);
+    }
+
+    /// 移除截图高度不为 `height` 的缩放后的模板，如设备的分辨率改变后
+    pub fn retain_height(&self, height: u32) {
+        let mut templates = self.lock();
+        templates.scaled.retain(|(_, h, _), _| *h == height);
+        templates.luma.retain(|(_, h, _), _| *h == height);
+    }
+
+    /// 移除所有模板
+    pub fn clear(&self) {
+        *self.lock() = Templates::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_utils::old_resources;
+
+    use super::*;
+
+    #[test]
+    fn test_template_store() {
+        let store = TemplateStore::new(old_resources());
+        assert!(!store.is_loaded("main_base.png"));
+
+        let template = store.get("main_base.png").unwrap();
+        assert!(store.is_loaded("main_base.png"));
+        assert!(Arc::ptr_eq(&template, &store.get("main_base.png").unwrap()));
+
+        let scaled = store.scaled("main_base.png", 360, 1.0).unwrap();
+        assert!(scaled.height() < template.height());
+        assert!(Arc::ptr_eq(
+            &scaled,
+            &store.clone().scaled("main_base.png", 360, 1.0).unwrap()
+        ));
+        let luma = store.luma("main_base.png", 360, 1.0).unwrap();
+        assert_eq!(luma.dimensions(), (scaled.width(), scaled.height()));
+        assert!(Arc::ptr_eq(
+            &luma,
+            &store.luma("main_base.png", 360, 1.0).unwrap()
+        ));
+
+        store.retain_height(720);
+        assert!(store.is_loaded("main_base.png"));
+        assert!(!Arc::ptr_eq(
+            &scaled,
+            &store.scaled("main_base.png", 360, 1.0).unwrap()
+        ));
+
+        store.evict("main_base.png");
+        assert!(!store.is_loaded("main_base.png"));
+        assert!(!Arc::ptr_eq(
+            &template,
+            &store.get("main_base.png").unwrap()
+        ));
+
+        assert!(store.get("not_exist.png").is_err());
+        store.clear();
+        assert!(!store.is_loaded("main_base.png"));
+    }
+}
@@ -0,0 +1,560 @@
+//! 对资源进行静态检查
+//!
+//! [`super::manifest::task::TaskConfig::load`] 只检查 toml 能否被解析，
+//! 而 `ByName` 引用的任务是否存在、是否存在循环引用、模板文件是否存在等问题只有在运行到对应的步骤时才会暴露。
+//!
+//! [`Validate::validate`] 会在资源加载完成后对其进行一次完整的检查，并返回所有发现的问题（[`ValidationIssue`]）。
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Debug, Display, Formatter},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    task::{navigate::NavigateActionSet, Action, ByNameAction, ByNameActionSet, Task},
+    utils::resource::{oper_avatar_dir, template_path},
+};
+
+use super::GeneralAahResource;
+
+/// 由 ActionSet 实现，用于声明一个 Action 所引用的资源，以便进行静态检查
+pub trait ActionRefs {
+    /// 此 Action 引用的模板文件（相对于 `templates/1920x1080`）
+    fn templates(&self) -> Vec<String> {
+        vec![]
+    }
+    /// 此 Action 通过名称引用的任务
+    fn tasks(&self) -> Vec<String> {
+        vec![]
+    }
+    /// 此 Action 导航到的场景
+    fn scenes(&self) -> Vec<String> {
+        vec![]
+    }
+    /// 此 Action 运行的脚本文件（相对于 `scripts`）
+    fn scripts(&self) -> Vec<String> {
+        vec![]
+    }
+    /// 此 Action 需要头像的干员（内部名称，如 `char_151_myrtle`）
+    fn avatars(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+impl<ActionSet: ActionRefs + Debug + Clone> ActionRefs for Action<ActionSet> {
+    fn templates(&self) -> Vec<String> {
+        match self {
+            Action::ByName(_) | Action::Navigate(_) => vec![],
+            Action::Detailed(action) => action.templates(),
+            Action::Deferred(action) => without_vars(action.shape().templates()),
+        }
+    }
+    fn tasks(&self) -> Vec<String> {
+        match self {
+            Action::ByName(ByNameActionSet::ByName(ByNameAction { name })) => vec![name.clone()],
+            Action::Navigate(_) => vec![],
+            Action::Detailed(action) => action.tasks(),
+            Action::Deferred(action) => without_vars(action.shape().tasks()),
+        }
+    }
+    fn scenes(&self) -> Vec<String> {
+        match self {
+            Action::ByName(_) => vec![],
+            Action::Navigate(NavigateActionSet::NavigateTo(action)) => vec![action.scene.clone()],
+            Action::Detailed(action) => action.scenes(),
+            Action::Deferred(action) => without_vars(action.shape().scenes()),
+        }
+    }
+    fn scripts(&self) -> Vec<String> {
+        match self {
+            Action::ByName(_) | Action::Navigate(_) => vec![],
+            Action::Detailed(action) => action.scripts(),
+            Action::Deferred(action) => without_vars(action.shape().scripts()),
+        }
+    }
+    fn avatars(&self) -> Vec<String> {
+        match self {
+            Action::ByName(_) | Action::Navigate(_) => vec![],
+            Action::Detailed(action) => action.avatars(),
+            Action::Deferred(action) => without_vars(action.shape().avatars()),
+        }
+    }
+}
+
+/// 引用了变量的资源名称只有运行时才能确定，不进行检查
+fn without_vars(names: Vec<String>) -> Vec<String> {
+    names
+        .into_iter()
+        .filter(|name| !name.contains("${"))
+        .collect()
+}
+
+/// 资源静态检查
+pub trait Validate {
+    /// 检查资源，返回所有发现的问题，若资源没有问题则返回空列表
+    fn validate(&self) -> Vec<ValidationIssue>;
+}
+
+/// 问题所在的位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IssueLocation {
+    /// 任务 `name` 的第 `step` 步
+    Task { name: String, step: usize },
+    /// 作业 `name` 的第 `step` 步
+    Copilot { name: String, step: usize },
+    /// 导航配置中的场景 `name`
+    Scene { name: String },
+    /// 导航配置中从 `from` 到 `to` 的边
+    Edge { from: String, to: String },
+    /// 全局中断 `name`
+    Interrupt { name: String },
+}
+
+impl Display for IssueLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueLocation::Task { name, step } => write!(f, "task `{name}` step {step}"),
+            IssueLocation::Copilot { name, step } => write!(f, "copilot `{name}` step {step}"),
+            IssueLocation::Scene { name } => write!(f, "scene `{name}`"),
+            IssueLocation::Edge { from, to } => write!(f, "edge `{from}` -> `{to}`"),
+            IssueLocation::Interrupt { name } => write!(f, "interrupt `{name}`"),
+        }
+    }
+}
+
+/// 问题的种类
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IssueKind {
+    /// 通过 `ByName` 引用了不存在的任务
+    UnknownTask(String),
+    /// 任务之间存在循环引用，内容为环上的任务名（首尾相同）
+    RecursiveTask(Vec<String>),
+    /// 引用的模板在 `templates/1920x1080` 下不存在
+    TemplateNotFound(String),
+    /// 作业步骤中使用的干员不在 `operators` 中
+    UnknownOperator(String),
+    /// 导航到了不存在的场景
+    UnknownScene(String),
+    /// 运行的脚本在 `scripts` 下不存在
+    ScriptNotFound(String),
+    /// 干员在 `avatars/char` 下没有头像
+    AvatarNotFound(String),
+}
+
+impl Display for IssueKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueKind::UnknownTask(name) => write!(f, "unknown task `{name}`"),
+            IssueKind::RecursiveTask(cycle) => {
+                write!(f, "recursive task reference: {}", cycle.join(" -> "))
+            }
+            IssueKind::TemplateNotFound(template) => write!(f, "template not found: {template}"),
+            IssueKind::UnknownOperator(operator) => write!(f, "unknown operator `{operator}`"),
+            IssueKind::UnknownScene(scene) => write!(f, "unknown scene `{scene}`"),
+            IssueKind::ScriptNotFound(script) => write!(f, "script not found: {script}"),
+            IssueKind::AvatarNotFound(oper) => write!(f, "avatar not found: {oper}"),
+        }
+    }
+}
+
+/// 一个静态检查发现的问题
+///
+/// - `file`: 问题所在的文件，若资源不是从文件加载的则为 `None`
+/// - `location`: 问题所在的任务/作业及步骤
+/// - `kind`: 问题的种类
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub file: Option<PathBuf>,
+    pub location: IssueLocation,
+    pub kind: IssueKind,
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file.display())?;
+        }
+        write!(f, "{}: {}", self.location, self.kind)
+    }
+}
+
+impl<ActionSet: ActionRefs + Debug + Clone> GeneralAahResource<ActionSet> {
+    /// 检查 `action` 引用的模板、任务、场景、脚本与头像是否存在，发现的问题都位于 `file` 的 `location`
+    fn check_refs(
+        &self,
+        action: &impl ActionRefs,
+        file: Option<&Path>,
+        location: IssueLocation,
+    ) -> Vec<ValidationIssue> {
+        let templates = action
+            .templates()
+            .into_iter()
+            .filter(|template| !template_path(template, &self.root).is_file())
+            .map(IssueKind::TemplateNotFound);
+        let tasks = action
+            .tasks()
+            .into_iter()
+            .filter(|task| !self.task_config.contains_key(task))
+            .map(IssueKind::UnknownTask);
+        let scenes = action
+            .scenes()
+            .into_iter()
+            .filter(|scene| self.navigate_config.scene(scene).is_none())
+            .map(IssueKind::UnknownScene);
+        let scripts = action
+            .scripts()
+            .into_iter()
+            .filter(|script| !self.root.join("scripts").join(script).is_file())
+            .map(IssueKind::ScriptNotFound);
+        let avatars = action
+            .avatars()
+            .into_iter()
+            .filter(|oper| !has_avatars(oper, &self.root))
+            .map(IssueKind::AvatarNotFound);
+        templates
+            .chain(tasks)
+            .chain(scenes)
+            .chain(scripts)
+            .chain(avatars)
+            .map(|kind| ValidationIssue {
+                file: file.map(Path::to_path_buf),
+                location: location.clone(),
+                kind,
+            })
+            .collect()
+    }
+}
+
+impl<ActionSet: ActionRefs + Debug + Clone> Validate for GeneralAahResource<ActionSet> {
+    fn validate(&self) -> Vec<ValidationIssue> {
+        let tasks = &self.task_config;
+        let navigate = &self.navigate_config;
+        let location = |task: &str, step: usize| IssueLocation::Task {
+            name: task.to_string(),
+            step,
+        };
+
+        let mut names = tasks.keys().collect::<Vec<_>>();
+        names.sort();
+
+        let mut issues = vec![];
+        for name in names.iter() {
+            for (idx, step) in tasks[name.as_str()].steps.iter().enumerate() {
+                issues.extend(self.check_refs(
+                    &step.action,
+                    tasks.source(name),
+                    location(name, idx),
+                ));
+            }
+        }
+
+        for (name, step, cycle) in find_cycles(tasks) {
+            issues.push(ValidationIssue {
+                file: tasks.source(&name).map(Path::to_path_buf),
+                location: location(&name, step),
+                kind: IssueKind::RecursiveTask(cycle),
+            });
+        }
+
+        // 导航配置
+        let file = self.root.join("navigates.toml");
+        let issue = |location: IssueLocation, kind: IssueKind| ValidationIssue {
+            file: Some(file.clone()),
+            location,
+            kind,
+        };
+        for scene in navigate.scenes.iter() {
+            for template in scene.signatures.iter().filter_map(|s| s.template()) {
+                if !template_path(template, &self.root).is_file() {
+                    issues.push(issue(
+                        IssueLocation::Scene {
+                            name: scene.name.clone(),
+                        },
+                        IssueKind::TemplateNotFound(template.to_string()),
+                    ));
+                }
+            }
+        }
+        for edge in navigate.edges.iter() {
+            let location = IssueLocation::Edge {
+                from: edge.from.clone(),
+                to: edge.to.clone(),
+            };
+            for scene in [&edge.from, &edge.to] {
+                if navigate.scene(scene).is_none() {
+                    issues.push(issue(
+                        location.clone(),
+                        IssueKind::UnknownScene(scene.clone()),
+                    ));
+                }
+            }
+            issues.extend(self.check_refs(&edge.action, Some(&file), location));
+        }
+
+        // 全局中断
+        let file = self.root.join("interrupts.toml");
+        for interrupt in self.interrupt_config.interrupts.iter() {
+            let location = IssueLocation::Interrupt {
+                name: interrupt.name.clone(),
+            };
+            for template in interrupt.signatures.iter().filter_map(|s| s.template()) {
+                if !template_path(template, &self.root).is_file() {
+                    issues.push(ValidationIssue {
+                        file: Some(file.clone()),
+                        location: location.clone(),
+                        kind: IssueKind::TemplateNotFound(template.to_string()),
+                    });
+                }
+            }
+            issues.extend(self.check_refs(&interrupt.action, Some(&file), location));
+        }
+
+        issues
+    }
+}
+
+/// 干员 `oper` 在 `root` 下是否有头像
+fn has_avatars(oper: &str, root: &Path) -> bool {
+    oper_avatar_dir(oper, root).is_some_and(|dir| dir.is_dir())
+}
+
+/// 找出任务之间的所有循环引用
+///
+/// 返回 `(task, step, cycle)`，其中 `task` 的第 `step` 步是闭合这个环的引用
+fn find_cycles<ActionSet: ActionRefs + Debug + Clone>(
+    tasks: &HashMap<String, Task<ActionSet>>,
+) -> Vec<(String, usize, Vec<String>)> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum State {
+        Visiting,
+        Visited,
+    }
+
+    fn visit<ActionSet: ActionRefs + Debug + Clone>(
+        name: &str,
+        tasks: &HashMap<String, Task<ActionSet>>,
+        states: &mut HashMap<String, State>,
+        stack: &mut Vec<String>,
+        found: &mut HashSet<Vec<String>>,
+        cycles: &mut Vec<(String, usize, Vec<String>)>,
+    ) {
+        let Some(task) = tasks.get(name) else {
+            return;
+        };
+        states.insert(name.to_string(), State::Visiting);
+        stack.push(name.to_string());
+
+        for (idx, step) in task.steps.iter().enumerate() {
+            for next in step.action.tasks() {
+                match states.get(&next) {
+                    Some(State::Visiting) => {
+                        let start = stack.iter().position(|n| n == &next).unwrap();
+                        let mut cycle = stack[start..].to_vec();
+                        // 以字典序最小的任务为起点，避免同一个环被报告多次
+                        let min = (0..cycle.len()).min_by_key(|&i| &cycle[i]).unwrap();
+                        cycle.rotate_left(min);
+                        if found.insert(cycle.clone()) {
+                            cycle.push(cycle[0].clone());
+                            cycles.push((name.to_string(), idx, cycle));
+                        }
+                    }
+                    Some(State::Visited) => {}
+                    None => visit(&next, tasks, states, stack, found, cycles),
+                }
+            }
+        }
+
+        stack.pop();
+        states.insert(name.to_string(), State::Visited);
+    }
+
+    let mut names = tasks.keys().collect::<Vec<_>>();
+    names.sort();
+
+    let mut states = HashMap::new();
+    let mut found = HashSet::new();
+    let mut cycles = vec![];
+    for name in names {
+        if !states.contains_key(name) {
+            visit(
+                name,
+                tasks,
+                &mut states,
+                &mut vec![],
+                &mut found,
+                &mut cycles,
+            );
+        }
+    }
+    cycles
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        android::actions::{ClickMatchTemplate, Press},
+        resource::manifest::{
+            interrupt::{Interrupt, InterruptConfig},
+            navigate::{Edge, NavigateConfig},
+            scene::{Scene, TemplateSignature},
+        },
+        task::TaskStep,
+        test_utils::{old_resources, resource},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let resource = resource(
+            old_resources(),
+            vec![
+                Task::from_steps(vec![
+                    TaskStep::from_action(Action::detailed(ClickMatchTemplate::new("confirm.png"))),
+                    TaskStep::from_action(Action::detailed(ClickMatchTemplate::new("missing.png"))),
+                    TaskStep::from_action(Action::by_name("not_exist")),
+                    TaskStep::from_action(Action::by_name("b")),
+                ])
+                .with_name("a"),
+                Task::from_steps(vec![
+                    TaskStep::from_action(Action::detailed(Press::esc())),
+                    TaskStep::from_action(Action::by_name("a")),
+                ])
+                .with_name("b"),
+                Task::from_steps(vec![TaskStep::from_action(Action::by_name("c"))]).with_name("c"),
+            ],
+        );
+        let issues = resource.validate();
+
+        let kinds = issues
+            .iter()
+            .map(|issue| (issue.location.clone(), issue.kind.clone()))
+            .collect::<Vec<_>>();
+        let location = |name: &str, step| IssueLocation::Task {
+            name: name.to_string(),
+            step,
+        };
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    location("a", 1),
+                    IssueKind::TemplateNotFound("missing.png".to_string())
+                ),
+                (
+                    location("a", 2),
+                    IssueKind::UnknownTask("not_exist".to_string())
+                ),
+                (
+                    location("b", 1),
+                    IssueKind::RecursiveTask(vec!["a".into(), "b".into(), "a".into()])
+                ),
+                (
+                    location("c", 0),
+                    IssueKind::RecursiveTask(vec!["c".into(), "c".into()])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_navigate() {
+        let mut resource = resource(
+            old_resources(),
+            vec![Task::from_steps(vec![
+                TaskStep::from_action(Action::navigate_to("main")),
+                TaskStep::from_action(Action::navigate_to("not_exist")),
+            ])
+            .with_name("a")],
+        );
+        resource.navigate_config = NavigateConfig::new(
+            vec![
+                Scene::new(
+                    "main",
+                    vec![TemplateSignature::new("main_mission.png").into()],
+                ),
+                Scene::new(
+                    "mission",
+                    vec![TemplateSignature::new("missing.png").into()],
+                ),
+            ],
+            vec![
+                Edge::new("main", "mission", Action::by_name("a")),
+                Edge::new("mission", "unknown", Action::by_name("not_exist")),
+            ],
+        );
+        let issues = resource.validate();
+
+        let kinds = issues
+            .iter()
+            .map(|issue| (issue.location.clone(), issue.kind.clone()))
+            .collect::<Vec<_>>();
+        let edge = IssueLocation::Edge {
+            from: "mission".to_string(),
+            to: "unknown".to_string(),
+        };
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    IssueLocation::Task {
+                        name: "a".to_string(),
+                        step: 1
+                    },
+                    IssueKind::UnknownScene("not_exist".to_string())
+                ),
+                (
+                    IssueLocation::Scene {
+                        name: "mission".to_string()
+                    },
+                    IssueKind::TemplateNotFound("missing.png".to_string())
+                ),
+                (edge.clone(), IssueKind::UnknownScene("unknown".to_string())),
+                (edge, IssueKind::UnknownTask("not_exist".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_interrupts() {
+        let mut resource = resource(
+            old_resources(),
+            vec![
+                Task::from_steps(vec![TaskStep::from_action(Action::detailed(Press::esc()))])
+                    .with_name("a"),
+            ],
+        );
+        resource.interrupt_config = InterruptConfig::new(vec![
+            Interrupt::new(
+                "notice",
+                vec![TemplateSignature::new("notice_close.png").into()],
+                Action::detailed(ClickMatchTemplate::new("notice_close.png")),
+            ),
+            Interrupt::new(
+                "broken",
+                vec![TemplateSignature::new("missing.png").into()],
+                Action::by_name("not_exist"),
+            ),
+        ]);
+        let issues = resource.validate();
+
+        let kinds = issues
+            .iter()
+            .map(|issue| (issue.location.clone(), issue.kind.clone()))
+            .collect::<Vec<_>>();
+        let location = IssueLocation::Interrupt {
+            name: "broken".to_string(),
+        };
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    location.clone(),
+                    IssueKind::TemplateNotFound("missing.png".to_string())
+                ),
+                (location, IssueKind::UnknownTask("not_exist".to_string())),
+            ]
+        );
+    }
+}
@@ -0,0 +1,568 @@
+//! 定时任务调度
+//!
+//! 调度配置为一个 toml 文件，对应 [`ScheduleConfig`]，其中每一个 [`ScheduleEntry`] 描述了一个任务在什么时候运行：
+//!
+//! ```toml
+//! # 配置中的时间所在的时区（相对 UTC 的小时数），默认为 8（国服）
+//! utc_offset = 8
+//! # 服务器每日重置的时间，不设置则不考虑服务器重置
+//! server_reset = "04:00"
+//!
+//! [[entries]]
+//! name = "award"
+//! task = "award"
+//! rule = { Daily = "04:30" }
+//!
+//! [[entries]]
+//! name = "farm"
+//! task = "farm"
+//! # 秒 分 时 日 月 星期 [年]
+//! rule = { Cron = "0 0 */8 * * *" }
+//! catch_up = false
+//! ```
+//!
+//! [`Scheduler`] 会持久化每个条目上一次运行的时间，在重新启动后对停机期间错过的运行进行补跑（错过多次也只补跑一次）。
+//! 若设置了 `server_reset`，则只有与当前处于同一个服务器日（两次重置之间）的错过的运行才会被补跑。
+//!
+//! 到期的条目会按到期时间依次进入队列，由 [`Scheduler::run`] 逐个运行，因此同一台设备上的任务不会重叠运行。
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use aah_controller::clock::{Clock, SystemClock};
+use anyhow::Context;
+use chrono::{DateTime, Days, FixedOffset, NaiveTime, TimeDelta, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::resource::Load;
+
+fn default_utc_offset() -> i32 {
+    8
+}
+
+/// 调度配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleConfig {
+    /// 配置中的时间所在的时区（相对 UTC 的小时数）
+    #[serde(default = "default_utc_offset")]
+    pub utc_offset: i32,
+    /// 服务器每日重置的时间（`HH:MM`）
+    pub server_reset: Option<String>,
+    /// 调度条目
+    #[serde(default)]
+    pub entries: Vec<ScheduleEntry>,
+}
+
+impl Load for ScheduleConfig {
+    fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let config = fs::read_to_string(path).context(format!("failed to read {path:?}"))?;
+        toml::from_str(&config).context(format!("failed to parse {path:?}"))
+    }
+}
+
+/// 一个调度条目
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+    /// 条目名称，用于记录上一次运行的时间，需唯一
+    pub name: String,
+    /// 要运行的任务名称
+    pub task: String,
+    /// 运行规则
+    pub rule: Rule,
+    /// 是否补跑停机期间错过的运行，默认为 `true`
+    pub catch_up: Option<bool>,
+}
+
+/// 运行规则
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Rule {
+    /// 每天在指定时间（`HH:MM`）运行
+    Daily(String),
+    /// cron 表达式（秒 分 时 日 月 星期 [年]）
+    Cron(String),
+}
+
+/// 解析后的 [`Rule`]
+enum Trigger {
+    Daily(NaiveTime),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Trigger {
+    fn parse(rule: &Rule) -> anyhow::Result<Self> {
+        Ok(match rule {
+            Rule::Daily(time) => Trigger::Daily(parse_time(time)?),
+            Rule::Cron(expr) => {
+                Trigger::Cron(Box::new(cron::Schedule::from_str(expr).map_err(|err| {
+                    anyhow::anyhow!("invalid cron expression {expr:?}: {err}")
+                })?))
+            }
+        })
+    }
+
+    /// `after` 之后（不含）的第一个触发时间
+    fn next_after(&self, after: DateTime<FixedOffset>) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Trigger::Daily(time) => {
+                let today = after.date_naive().and_time(*time);
+                let today = today.and_local_timezone(after.timezone()).single()?;
+                if today > after {
+                    Some(today)
+                } else {
+                    today.checked_add_days(Days::new(1))
+                }
+            }
+            Trigger::Cron(schedule) => schedule.after(&after).next(),
+        }
+    }
+
+    /// `(after, until]` 中的最后一个触发时间
+    fn last_between(
+        &self,
+        after: DateTime<FixedOffset>,
+        until: DateTime<FixedOffset>,
+    ) -> Option<DateTime<FixedOffset>> {
+        let mut last = None;
+        let mut cur = after;
+        while let Some(next) = self.next_after(cur).filter(|next| *next <= until) {
+            last = Some(next);
+            cur = next;
+        }
+        last
+    }
+}
+
+fn parse_time(time: &str) -> anyhow::Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").context(format!("invalid time {time:?}, expect HH:MM"))
+}
+
+/// 持久化的调度状态：每个条目上一次运行的时间
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ScheduleState {
+    pub last_run: HashMap<String, DateTime<FixedOffset>>,
+}
+
+/// 调度器
+pub struct Scheduler {
+    entries: Vec<(ScheduleEntry, Trigger)>,
+    timezone: FixedOffset,
+    server_reset: Option<NaiveTime>,
+    /// 持久化的状态，以及其保存的路径
+    state: ScheduleState,
+    state_path: Option<PathBuf>,
+    /// 每个条目从何时之后开始检查是否到期
+    ///
+    /// 运行后会被更新为运行的时间，被跳过的补跑会被更新为跳过的时间
+    bookmarks: HashMap<String, DateTime<FixedOffset>>,
+    /// 调度器启动的时间，在此之前的触发时间均视为停机期间错过的运行
+    started_at: DateTime<FixedOffset>,
+    /// 启动时 `clock` 的时间，当前时间为 `started_at` 加上 `clock` 自此经过的时间
+    started_instant: Instant,
+    /// 等待运行的条目（`entries` 中的下标）
+    queue: VecDeque<usize>,
+    /// 计时与等待下一次触发时使用的时钟
+    clock: Arc<dyn Clock>,
+}
+
+impl Scheduler {
+    /// 创建调度器，若 `state_path` 存在则从中读取上一次运行的时间，并在每次运行后写回
+    pub fn new(config: ScheduleConfig, state_path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let timezone = FixedOffset::east_opt(config.utc_offset * 3600)
+            .ok_or(anyhow::anyhow!("invalid utc_offset: {}", config.utc_offset))?;
+        let now = Utc::now().with_timezone(&timezone);
+        Self::new_at(config, state_path, now)
+    }
+
+    /// 以 `now` 作为启动时间创建调度器
+    fn new_at(
+        config: ScheduleConfig,
+        state_path: Option<PathBuf>,
+        now: DateTime<FixedOffset>,
+    ) -> anyhow::Result<Self> {
+        let server_reset = config.server_reset.as_deref().map(parse_time).transpose()?;
+
+        let mut entries: Vec<(ScheduleEntry, Trigger)> = vec![];
+        for entry in config.entries {
+            if entries.iter().any(|(e, _)| e.name == entry.name) {
+                anyhow::bail!("duplicated schedule entry: {}", entry.name);
+            }
+            let trigger = Trigger::parse(&entry.rule)
+                .context(format!("invalid rule of schedule entry {}", entry.name))?;
+            entries.push((entry, trigger));
+        }
+
+        let state = match &state_path {
+            Some(path) if path.exists() => {
+                let state = fs::read_to_string(path).context(format!("failed to read {path:?}"))?;
+                toml::from_str(&state).context(format!("failed to parse {path:?}"))?
+            }
+            _ => ScheduleState::default(),
+        };
+        let bookmarks = state.last_run.clone();
+
+        Ok(Self {
+            entries,
+            timezone: now.timezone(),
+            server_reset,
+            state,
+            state_path,
+            bookmarks,
+            started_at: now,
+            started_instant: SystemClock.now(),
+            queue: VecDeque::new(),
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// 替换计时与等待下一次触发时使用的时钟，如使用 [`VirtualClock`](aah_controller::clock::VirtualClock) 模拟时间的流逝
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.started_instant = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    /// 当前时间（位于配置的时区），即启动的时间加上 `clock` 自启动以来经过的时间
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        let elapsed = self.clock.elapsed(self.started_instant);
+        self.started_at + TimeDelta::from_std(elapsed).unwrap_or(TimeDelta::MAX)
+    }
+
+    /// 包含 `now` 的服务器日的开始时间
+    fn server_day_start(&self, now: DateTime<FixedOffset>) -> Option<DateTime<FixedOffset>> {
+        let reset = now
+            .date_naive()
+            .and_time(self.server_reset?)
+            .and_local_timezone(self.timezone)
+            .single()?;
+        if reset > now {
+            reset.checked_sub_days(Days::new(1))
+        } else {
+            Some(reset)
+        }
+    }
+
+    /// 条目从何时之后开始检查是否到期
+    fn bookmark(&self, name: &str) -> DateTime<FixedOffset> {
+        self.bookmarks.get(name).copied().unwrap_or(self.started_at)
+    }
+
+    /// 将 `now` 时所有到期的条目按到期时间加入队列，返回新加入队列的条目名称
+    pub fn enqueue_due(&mut self, now: DateTime<FixedOffset>) -> Vec<String> {
+        let mut due = vec![];
+        for (idx, (entry, trigger)) in self.entries.iter().enumerate() {
+            if self.queue.contains(&idx) {
+                continue;
+            }
+            let Some(last) = trigger.last_between(self.bookmark(&entry.name), now) else {
+                continue;
+            };
+            if last < self.started_at {
+                let catch_up = entry.catch_up.unwrap_or(true)
+                    && self.server_day_start(now).is_none_or(|start| last >= start);
+                if !catch_up {
+                    info!(
+                        "[Scheduler]: skipping missed run of {} scheduled at {last}",
+                        entry.name
+                    );
+                    self.bookmarks.insert(entry.name.clone(), now);
+                    continue;
+                }
+                info!(
+                    "[Scheduler]: catching up missed run of {} scheduled at {last}",
+                    entry.name
+                );
+            }
+            due.push((last, idx));
+        }
+        due.sort();
+
+        due.into_iter()
+            .map(|(_, idx)| {
+                self.queue.push_back(idx);
+                self.entries[idx].0.name.clone()
+            })
+            .collect()
+    }
+
+    /// 取出队首的条目
+    pub fn pop(&mut self) -> Option<ScheduleEntry> {
+        self.queue
+            .pop_front()
+            .map(|idx| self.entries[idx].0.clone())
+    }
+
+    /// 记录条目 `name` 在 `at` 运行过，并保存状态
+    pub fn mark_run(
+        &mut self,
+        name: impl AsRef<str>,
+        at: DateTime<FixedOffset>,
+    ) -> anyhow::Result<()> {
+        let name = name.as_ref().to_string();
+        self.bookmarks.insert(name.clone(), at);
+        self.state.last_run.insert(name, at);
+        if let Some(path) = &self.state_path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).context(format!("failed to create dir {dir:?}"))?;
+            }
+            let state = toml::to_string_pretty(&self.state)?;
+            fs::write(path, state).context(format!("failed to write {path:?}"))?;
+        }
+        Ok(())
+    }
+
+    /// `now` 之后最早的触发时间
+    pub fn next_wakeup(&self, now: DateTime<FixedOffset>) -> Option<DateTime<FixedOffset>> {
+        self.entries
+            .iter()
+            .filter_map(|(entry, trigger)| trigger.next_after(self.bookmark(&entry.name).max(now)))
+            .min()
+    }
+
+    /// 持续运行调度，通过 `runner` 运行到期的任务，不会返回
+    pub fn run(&mut self, mut runner: impl FnMut(&str) -> anyhow::Result<()>) -> ! {
+        info!("[Scheduler]: started with {} entries", self.entries.len());
+        loop {
+            self.enqueue_due(self.now());
+
+            if let Some(entry) = self.pop() {
+                let start = self.now();
+                info!("[Scheduler]: running {} (task {})", entry.name, entry.task);
+                match runner(&entry.task) {
+                    Ok(()) => info!("[Scheduler]: {} finished", entry.name),
+                    Err(err) => warn!("[Scheduler]: {} failed: {err}", entry.name),
+                }
+                if let Err(err) = self.mark_run(&entry.name, start) {
+                    warn!("[Scheduler]: failed to save schedule state: {err}");
+                }
+                continue;
+            }
+
+            let now = self.now();
+            let wait = self
+                .next_wakeup(now)
+                .and_then(|next| (next - now).to_std().ok())
+                .unwrap_or(Duration::MAX);
+            // 最多等待一分钟，之后重新检查到期的条目
+            self.clock
+                .sleep(wait.clamp(Duration::from_secs(1), Duration::from_secs(60)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use aah_controller::clock::VirtualClock;
+
+    use super::*;
+
+    fn time(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    fn config(server_reset: Option<&str>, entries: Vec<(&str, Rule, bool)>) -> ScheduleConfig {
+        ScheduleConfig {
+            utc_offset: 8,
+            server_reset: server_reset.map(|s| s.to_string()),
+            entries: entries
+                .into_iter()
+                .map(|(name, rule, catch_up)| ScheduleEntry {
+                    name: name.to_string(),
+                    task: name.to_string(),
+                    rule,
+                    catch_up: Some(catch_up),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config: ScheduleConfig = toml::from_str(
+            r#"
+server_reset = "04:00"
+
+[[entries]]
+name = "award"
+task = "award"
+rule = { Daily = "04:30" }
+
+[[entries]]
+name = "farm"
+task = "farm"
+rule = { Cron = "0 0 */8 * * *" }
+catch_up = false
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.utc_offset, 8);
+        assert_eq!(config.entries.len(), 2);
+        assert!(Scheduler::new(config, None).is_ok());
+
+        let invalid = config_with_rule(Rule::Daily("25:00".to_string()));
+        assert!(Scheduler::new(invalid, None).is_err());
+        let invalid = config_with_rule(Rule::Cron("not a cron".to_string()));
+        assert!(Scheduler::new(invalid, None).is_err());
+    }
+
+    fn config_with_rule(rule: Rule) -> ScheduleConfig {
+        config(None, vec![("a", rule, true)])
+    }
+
+    #[test]
+    fn test_daily() {
+        let config = config(
+            None,
+            vec![("award", Rule::Daily("04:30".to_string()), true)],
+        );
+        let mut scheduler =
+            Scheduler::new_at(config, None, time("2024-10-01T03:00:00+08:00")).unwrap();
+
+        assert!(scheduler
+            .enqueue_due(time("2024-10-01T04:00:00+08:00"))
+            .is_empty());
+        assert_eq!(
+            scheduler.next_wakeup(time("2024-10-01T04:00:00+08:00")),
+            Some(time("2024-10-01T04:30:00+08:00"))
+        );
+
+        let now = time("2024-10-01T04:30:01+08:00");
+        assert_eq!(scheduler.enqueue_due(now), vec!["award"]);
+        // 已经在队列中的条目不会被重复加入
+        assert!(scheduler.enqueue_due(now).is_empty());
+        assert_eq!(scheduler.pop().unwrap().name, "award");
+        scheduler.mark_run("award", now).unwrap();
+
+        assert!(scheduler
+            .enqueue_due(time("2024-10-02T04:00:00+08:00"))
+            .is_empty());
+        assert_eq!(
+            scheduler.enqueue_due(time("2024-10-02T04:31:00+08:00")),
+            vec!["award"]
+        );
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let config = config(
+            None,
+            vec![
+                ("award", Rule::Daily("04:30".to_string()), true),
+                ("farm", Rule::Cron("0 0 */8 * * *".to_string()), false),
+            ],
+        );
+        let last_run = time("2024-10-01T04:30:00+08:00");
+        let mut scheduler =
+            Scheduler::new_at(config, None, time("2024-10-03T12:00:00+08:00")).unwrap();
+        scheduler.bookmarks.insert("award".to_string(), last_run);
+        scheduler.bookmarks.insert("farm".to_string(), last_run);
+
+        // 停机期间错过了多次，只补跑一次；不允许补跑的条目被跳过
+        let now = time("2024-10-03T12:00:01+08:00");
+        assert_eq!(scheduler.enqueue_due(now), vec!["award"]);
+        assert_eq!(scheduler.pop().unwrap().name, "award");
+        scheduler.mark_run("award", now).unwrap();
+        assert!(scheduler.enqueue_due(now).is_empty());
+
+        assert_eq!(
+            scheduler.enqueue_due(time("2024-10-03T16:00:00+08:00")),
+            vec!["farm"]
+        );
+    }
+
+    #[test]
+    fn test_server_reset() {
+        let config = config(
+            Some("04:00"),
+            vec![
+                ("before_reset", Rule::Daily("03:00".to_string()), true),
+                ("after_reset", Rule::Daily("05:00".to_string()), true),
+            ],
+        );
+        let last_run = time("2024-10-01T06:00:00+08:00");
+        let mut scheduler =
+            Scheduler::new_at(config, None, time("2024-10-02T08:00:00+08:00")).unwrap();
+        scheduler
+            .bookmarks
+            .insert("before_reset".to_string(), last_run);
+        scheduler
+            .bookmarks
+            .insert("after_reset".to_string(), last_run);
+
+        // 03:00 的运行属于上一个服务器日，不再补跑
+        assert_eq!(
+            scheduler.enqueue_due(time("2024-10-02T08:00:01+08:00")),
+            vec!["after_reset"]
+        );
+    }
+
+    #[test]
+    fn test_simulated_day() {
+        let config = config(
+            None,
+            vec![
+                ("award", Rule::Daily("04:30".to_string()), true),
+                ("farm", Rule::Cron("0 0 */8 * * *".to_string()), true),
+            ],
+        );
+        let clock = Arc::new(VirtualClock::new());
+        let start = time("2024-10-01T03:00:00+08:00");
+        let mut scheduler = Scheduler::new_at(config, None, start)
+            .unwrap()
+            .with_clock(clock.clone());
+        assert_eq!(scheduler.now(), start);
+
+        // 与 `Scheduler::run` 一样运行到期的条目，再等待到下一次触发
+        let real = std::time::Instant::now();
+        let mut runs = vec![];
+        while scheduler.now() < time("2024-10-02T03:00:00+08:00") {
+            let now = scheduler.now();
+            scheduler.enqueue_due(now);
+            while let Some(entry) = scheduler.pop() {
+                runs.push((entry.name.clone(), now));
+                scheduler.mark_run(&entry.name, now).unwrap();
+                // 运行任务耗时十分钟
+                clock.sleep(Duration::from_secs(600));
+            }
+            let now = scheduler.now();
+            let next = scheduler.next_wakeup(now).unwrap();
+            clock.sleep((next - now).to_std().unwrap());
+        }
+
+        assert_eq!(
+            runs,
+            vec![
+                ("award".to_string(), time("2024-10-01T04:30:00+08:00")),
+                ("farm".to_string(), time("2024-10-01T08:00:00+08:00")),
+                ("farm".to_string(), time("2024-10-01T16:00:00+08:00")),
+                ("farm".to_string(), time("2024-10-02T00:00:00+08:00")),
+            ]
+        );
+        assert_eq!(clock.total_elapsed(), Duration::from_secs(25 * 3600 + 1800));
+        assert!(real.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let config = config(
+            None,
+            vec![
+                ("b", Rule::Daily("04:40".to_string()), true),
+                ("a", Rule::Daily("04:30".to_string()), true),
+            ],
+        );
+        let mut scheduler =
+            Scheduler::new_at(config, None, time("2024-10-01T03:00:00+08:00")).unwrap();
+        assert_eq!(
+            scheduler.enqueue_due(time("2024-10-01T05:00:00+08:00")),
+            vec!["a", "b"]
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! 任务的检查点
+//!
+//! 长时间运行的任务（如刷图 + 基建）中途因为崩溃、重启、ADB 断开而中断后，可以从上一个检查点继续运行，
+//! 而不必从头开始（也不会重复消耗资源）。
+//!
+//! 通过 [`Task::run_with_checkpoint`](super::Task::run_with_checkpoint) 运行任务时，每完成一个步骤
+//! （以及 `repeat` 的每一次执行）都会将进度与变量保存为一个 [`Checkpoint`]，任务成功完成后删除检查点。
+//! 通过 `ByName` 运行的子任务的进度保存在 [`Progress::nested`] 中，恢复时从子任务中断的步骤继续。
+//!
+//! 会消耗资源的步骤（如使用理智药、购买物品）应设置 `idempotent = false`：此类步骤开始执行前会先记录下来，
+//! 若在其完成前中断，恢复时不会盲目地重新执行，而是报错，由用户确认后选择 [`ResumeMode::SkipInterrupted`]
+//! 跳过或重新开始。
+
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::vars::Vars;
+
+/// 如何处理已有的检查点
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResumeMode {
+    /// 从头开始运行，覆盖已有的检查点
+    #[default]
+    Restart,
+    /// 从检查点继续运行，被中断的非幂等步骤会导致报错
+    Resume,
+    /// 从检查点继续运行，跳过被中断的非幂等步骤的那一次执行
+    SkipInterrupted,
+}
+
+/// 任务运行到的位置
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Progress {
+    /// 下一个要执行的步骤
+    pub step: usize,
+    /// 此步骤已经完成的执行次数（见 `repeat`）
+    pub repeat: u32,
+    /// 此步骤的下一次执行是否已经开始（仅对非幂等步骤记录）
+    pub pending: bool,
+    /// 此步骤正在运行的 `ByName` 子任务的进度
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nested: Option<Box<Checkpoint>>,
+}
+
+/// 保存的任务进度
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// 任务名称
+    pub task: String,
+    #[serde(flatten)]
+    pub progress: Progress,
+    /// 运行到此处时的变量
+    pub vars: Vars,
+}
+
+impl Checkpoint {
+    /// 读取 `path` 中的检查点，文件不存在时返回 `None`
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Option<Self>> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let checkpoint = fs::read_to_string(path).context(format!("failed to read {path:?}"))?;
+        serde_json::from_str(&checkpoint)
+            .map(Some)
+            .context(format!("failed to parse {path:?}"))
+    }
+
+    /// 将检查点写入 `path`
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(format!("failed to create dir {dir:?}"))?;
+        }
+        // 先写入临时文件再重命名，避免写入过程中中断导致检查点损坏
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .context(format!("failed to write {tmp:?}"))?;
+        fs::rename(&tmp, path).context(format!("failed to write {path:?}"))
+    }
+
+    /// 删除 `path` 中的检查点
+    pub fn clear(path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if path.exists() {
+            fs::remove_file(path).context(format!("failed to remove {path:?}"))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+//! 全局中断的处理
+//!
+//! [`Task`](super::Task) 的每一步开始前，以及每一次尝试因识别失败（[`ErrorKind::TemplateNotFound`]）而失败后，
+//! 都会检查 [`InterruptConfig`] 中的中断是否出现在画面上，若出现则执行其处理操作。
+//! 识别失败后若处理了中断，会立即重新尝试该步骤，且不计入重试次数。
+//!
+//! 同一个中断在一个步骤中连续处理的次数超过 [`Interrupt::max_consecutive`] 时，该步骤失败。
+//! 处理中断的操作运行时不会再处理中断，任务可以通过 `handle_interrupts = false` 关闭中断处理。
+//! 识别中断的 [`SceneAnalyzer`] 由每个资源的 [`InterruptConfig::analyzer`] 创建一次，所有任务共用。
+//!
+//! [`Interrupt::max_consecutive`]: crate::resource::manifest::interrupt::Interrupt::max_consecutive
+
+use std::{cell::Cell, fmt::Debug};
+
+use aah_controller::Controller;
+use color_print::cprintln;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    report::Recognition,
+    resource::{
+        manifest::interrupt::InterruptConfig, GetInterrupts, GetNavigate, GetTask, ResRoot,
+    },
+    vision::analyzer::scene::SceneAnalyzer,
+    Core, TaskRecipe,
+};
+
+use super::{
+    retry::{ActionError, ErrorKind},
+    vars::Value,
+};
+
+thread_local! {
+    /// 当前线程是否正在处理中断
+    static HANDLING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// 一个任务运行期间的中断处理器
+pub(crate) struct InterruptHandler<'a> {
+    /// 没有中断或处于 dry-run 模式时为 `None`
+    analyzer: Option<&'a SceneAnalyzer>,
+    /// 上一次处理的中断及其连续处理的次数
+    last: Option<(String, u32)>,
+}
+
+impl<'a> InterruptHandler<'a> {
+    pub fn new<T, C, R, ActionSet>(runner: &'a T) -> Self
+    where
+        C: Controller,
+        R: GetInterrupts<ActionSet> + ResRoot + 'a,
+        T: Core<Controller = C, Resource = R>,
+        ActionSet: Debug + Clone + 'a,
+    {
+        let config: &InterruptConfig<ActionSet> = runner.resource().interrupt_config();
+        // dry-run 模式下画面不会变化，出现的中断永远无法被处理
+        let analyzer = match runner.dry_run() {
+            true => None,
+            false => config.analyzer(runner.resource()),
+        };
+        Self {
+            analyzer,
+            last: None,
+        }
+    }
+
+    /// 开始一个新的步骤，重置连续处理的次数
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    /// 处理画面上出现的所有中断，直到没有中断出现为止，返回是否处理了中断
+    pub fn handle<T, C, R, ActionSet>(&mut self, runner: &T) -> anyhow::Result<bool>
+    where
+        C: Controller,
+        R: GetTask<ActionSet> + GetNavigate<ActionSet> + GetInterrupts<ActionSet> + ResRoot,
+        T: Core<Controller = C, Resource = R>,
+        ActionSet: TaskRecipe<T, Res = Value> + Serialize + DeserializeOwned + Debug + Clone,
+    {
+        let Some(analyzer) = self.analyzer else {
+            return Ok(false);
+        };
+        if HANDLING.get() {
+            return Ok(false);
+        }
+
+        let config: &InterruptConfig<ActionSet> = runner.resource().interrupt_config();
+        let mut handled = false;
+        loop {
+            let screen = runner.controller().screencap().map_err(|err| {
+                anyhow::Error::new(ActionError::new(
+                    ErrorKind::ControllerError,
+                    format!("controller error: {err:?}"),
+                ))
+            })?;
+            let output = analyzer.analyze_image(&screen, runner.scale_memory().as_ref())?;
+            let Some(interrupt) = output.scene.and_then(|name| config.interrupt(name)) else {
+                return Ok(handled);
+            };
+
+            let count = match &mut self.last {
+                Some((name, count)) if *name == interrupt.name => {
+                    *count += 1;
+                    *count
+                }
+                last => {
+                    *last = Some((interrupt.name.clone(), 1));
+                    1
+                }
+            };
+            if count > interrupt.max_consecutive.unwrap_or(3) {
+                anyhow::bail!(
+                    "interrupt {} fired {} times in a row",
+                    interrupt.name,
+                    count
+                );
+            }
+
+            cprintln!(
+                "<y><strong>[Interrupt]</strong></y>: handling {} ({})",
+                interrupt.name,
+                count
+            );
+            if let Some(reporter) = runner.reporter() {
+                reporter.record_recognition(Recognition::new(
+                    format!("interrupt {}", interrupt.name),
+                    None,
+                    output.confidence,
+                ));
+            }
+            HANDLING.set(true);
+            let res = interrupt.action.run(runner);
+            HANDLING.set(false);
+            res?;
+            handled = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use image::DynamicImage;
+
+    use crate::{
+        android::actions::ActionSet,
+        resource::manifest::{
+            interrupt::Interrupt,
+            scene::{ColorSignature, TemplateSignature},
+        },
+        task::{Action, Task, TaskStep},
+        test_utils::{resource, TestCore},
+    };
+
+    use super::*;
+
+    fn run(task: Task<ActionSet>, color: [u8; 3]) -> (anyhow::Result<()>, Vec<String>) {
+        let mut interrupt = Interrupt::new(
+            "popup",
+            vec![ColorSignature::new((0.0, 0.0), (1.0, 1.0), color).into()],
+            Action::detailed(ActionSet::click(1, 2)),
+        );
+        interrupt.max_consecutive = Some(2);
+        // 模板缺失的中断被忽略，不影响其他中断与任务
+        let broken = Interrupt::new(
+            "broken",
+            vec![TemplateSignature::new("missing.png").into()],
+            Action::detailed(ActionSet::click(3, 4)),
+        );
+        let mut resource = resource(PathBuf::new(), vec![]);
+        resource.interrupt_config = InterruptConfig::new(vec![broken, interrupt]);
+        // 截图是纯黑的
+        let core = TestCore::new(DynamicImage::new_rgb8(64, 36), resource);
+        let res = task.run(&core).map(|_| ());
+        (res, core.ops())
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let task = Task::from_steps(vec![TaskStep::from_action(Action::detailed(
+            ActionSet::press_esc(),
+        ))]);
+
+        // 中断没有出现
+        let (res, ops) = run(task.clone(), [255, 255, 255]);
+        res.unwrap();
+        assert_eq!(ops, vec!["press esc"]);
+
+        // 画面不会变化，中断会一直出现，直到超过连续处理的次数
+        let (res, ops) = run(task.clone(), [0, 0, 0]);
+        let err = res.unwrap_err();
+        assert!(err.to_string().contains("popup"));
+        assert_eq!(ops, vec!["click (1, 2)", "click (1, 2)"]);
+
+        // 关闭中断处理
+        let (res, ops) = run(task.without_interrupts(), [0, 0, 0]);
+        res.unwrap();
+        assert_eq!(ops, vec!["press esc"]);
+    }
+}
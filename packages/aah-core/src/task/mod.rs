@@ -0,0 +1,1250 @@
+//! 此模块包含对任务的定义
+//!
+//! 每一个任务对应一个 `resources/tasks` 中的 `xxx.toml` 文件，其中内容对应 [`Task`] 结构。
+//!
+//! 每一个 [`Task`] 由若干 [`TaskStep`] 组成，每一个 [`TaskStep`] 包含一个 [`Action`] 和一些额外的属性。
+//!
+//! [`Action`]` 即为 [`super::Task`] 中每一个 [`super::TaskStep`] 中的实际操作。[`Action`] 本身只是对操作的数据表示，实际的实现在 [`Runnable`] 中。
+//!
+//! 除了 ActionSet 中的操作与通过名称引用的任务外，[`Action`] 还可以是导航到某一场景（见 [`navigate`]）。
+//!
+//! 步骤失败时的重试行为由 [`TaskStep::retry`] 和 [`retry::RetryPolicy`] 控制。
+//!
+//! 任务运行过程中出现的弹窗等全局中断由 [`interrupt`] 处理。
+//!
+//! ActionSet 之外的操作可以通过 [`registry`] 以名称注册后在任务中使用。
+//!
+//! 步骤之间通过 [`vars`] 中的变量传递数据。
+//!
+//! 长时间运行的任务可以通过 [`checkpoint`] 保存进度，中断后从检查点继续运行。
+//!
+//!
+
+use std::{borrow::Cow, fmt::Debug, path::Path, time::Duration};
+
+use aah_controller::Controller;
+use anyhow::Context;
+use color_print::cprintln;
+use image::DynamicImage;
+use log::{info, warn};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::{
+    report::RunKind,
+    resource::{GetInterrupts, GetNavigate, GetTask, ResRoot},
+    Core, TaskRecipe,
+};
+
+pub mod checkpoint;
+pub mod interrupt;
+pub mod navigate;
+pub mod registry;
+pub mod retry;
+pub mod vars;
+
+use checkpoint::{Checkpoint, Progress, ResumeMode};
+use interrupt::InterruptHandler;
+use navigate::{NavigateActionSet, NavigateTo};
+use retry::{ActionError, ErrorKind, RetryPolicy};
+use vars::{Value, Vars};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ByNameAction {
+    /// 要运行的任务名称
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum ByNameActionSet {
+    /// 运行通过名称引用的任务
+    ByName(ByNameAction),
+}
+
+/// 参数中引用了变量（如 `x = "${rect.x}"`）的 Action，运行时替换变量后才能确定具体的 Action
+///
+/// 加载时会将整个为 `${name}` 的参数替换为占位值来检查 Action 的结构，得到 [`DeferredAction::shape`]，
+/// 因此 Action 名称拼写错误等问题在加载时就会暴露，静态检查也能看到其中引用的资源
+#[derive(Debug, Clone, Serialize)]
+#[serde(transparent)]
+pub struct DeferredAction<ActionSet: Debug + Clone> {
+    value: Value,
+    #[serde(skip)]
+    shape: Box<Action<ActionSet>>,
+}
+
+/// 不含 [`Action::Deferred`] 的 [`Action`]，用于解析替换变量后的 Action
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ActionShape<ActionSet> {
+    ByName(ByNameActionSet),
+    Navigate(NavigateActionSet),
+    Detailed(ActionSet),
+}
+
+impl<ActionSet: Debug + Clone> From<ActionShape<ActionSet>> for Action<ActionSet> {
+    fn from(shape: ActionShape<ActionSet>) -> Self {
+        match shape {
+            ActionShape::ByName(action) => Action::ByName(action),
+            ActionShape::Navigate(action) => Action::Navigate(action),
+            ActionShape::Detailed(action) => Action::Detailed(action),
+        }
+    }
+}
+
+impl<'de, ActionSet: Deserialize<'de> + Debug + Clone> Deserialize<'de>
+    for DeferredAction<ActionSet>
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        if !vars::has_templates(&value) {
+            return Err(serde::de::Error::custom("no variables in action"));
+        }
+        let shape = Self::parse_shape(&value).map_err(serde::de::Error::custom)?;
+        Ok(Self {
+            value,
+            shape: Box::new(shape),
+        })
+    }
+}
+
+impl<ActionSet: Debug + Clone> DeferredAction<ActionSet> {
+    /// 以占位值替换变量后的 Action，其中的字符串参数可能仍含有 `${name}`
+    pub fn shape(&self) -> &Action<ActionSet> {
+        &self.shape
+    }
+
+    /// 依次尝试以原字符串、数字、布尔值与二元数组作为整个为 `${name}` 的参数的占位值
+    fn parse_shape<'de>(value: &Value) -> Result<Action<ActionSet>, String>
+    where
+        ActionSet: Deserialize<'de>,
+    {
+        let mut leaves = vec![];
+        collect_template_leaves(value, String::new(), &mut leaves);
+        let placeholders = |leaf: &str| {
+            [
+                Value::String(leaf.to_string()),
+                Value::from(0),
+                Value::from(false),
+                Value::from(vec![0, 0]),
+            ]
+        };
+        let parse = |choice: &[usize]| {
+            let mut value = value.clone();
+            for ((pointer, leaf), i) in leaves.iter().zip(choice) {
+                *value.pointer_mut(pointer).unwrap() = placeholders(leaf)[*i].clone();
+            }
+            ActionShape::<ActionSet>::deserialize(value).map(Action::from)
+        };
+
+        // 参数较少时尝试所有组合，否则每个参数使用相同类型的占位值
+        let mut choices: Vec<Vec<usize>> = (0..4).map(|i| vec![i; leaves.len()]).collect();
+        if leaves.len() <= 4 {
+            choices = (0..4usize.pow(leaves.len() as u32))
+                .map(|n| {
+                    (0..leaves.len())
+                        .map(|k| n / 4usize.pow(k as u32) % 4)
+                        .collect()
+                })
+                .collect();
+        }
+        for choice in &choices {
+            if let Ok(action) = parse(choice) {
+                return Ok(action);
+            }
+        }
+        Err(format!("invalid action with variables: {value}"))
+    }
+
+    /// 替换变量，得到具体的 Action
+    fn resolve(&self, vars: &Vars) -> anyhow::Result<Action<ActionSet>>
+    where
+        ActionSet: DeserializeOwned,
+    {
+        let mut value = self.value.clone();
+        vars.substitute(&mut value)?;
+        serde_json::from_value::<ActionShape<ActionSet>>(value.clone())
+            .map(Action::from)
+            .map_err(|_| anyhow::anyhow!("invalid action after substituting variables: {value}"))
+    }
+}
+
+/// 收集 `value` 中整个为 `${name}` 的字符串的 JSON Pointer 及其内容
+fn collect_template_leaves(value: &Value, pointer: String, leaves: &mut Vec<(String, String)>) {
+    match value {
+        Value::String(s) if vars::whole_template(s).is_some() => leaves.push((pointer, s.clone())),
+        Value::Array(array) => {
+            for (i, value) in array.iter().enumerate() {
+                collect_template_leaves(value, format!("{pointer}/{i}"), leaves);
+            }
+        }
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = key.replace('~', "~0").replace('/', "~1");
+                collect_template_leaves(value, format!("{pointer}/{key}"), leaves);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Action are the tasks you can use in the configuration file
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Action<ActionSet: Debug + Clone> {
+    /// An action with variables to be substituted at runtime
+    ///
+    /// 放在最前面，使参数中含有变量的 Action 在加载时就确定为此变体
+    #[schemars(skip)]
+    Deferred(DeferredAction<ActionSet>),
+    /// Run a task referenced by the name
+    ByName(ByNameActionSet),
+    /// Navigate to a scene
+    Navigate(NavigateActionSet),
+    /// Run an action in the action set
+    Detailed(ActionSet),
+}
+
+impl<ActionSet: Debug + Clone> Action<ActionSet> {
+    pub fn by_name(name: impl AsRef<str>) -> Self {
+        let name = name.as_ref().to_string();
+        // 这一坨莫名其妙的一层包一层其实是为了统一 toml 格式（）
+        Self::ByName(ByNameActionSet::ByName(ByNameAction { name }))
+    }
+    pub fn navigate_to(scene: impl AsRef<str>) -> Self {
+        Self::Navigate(NavigateActionSet::NavigateTo(NavigateTo::new(scene)))
+    }
+    pub fn detailed(action: impl Into<ActionSet>) -> Self {
+        Self::Detailed(action.into())
+    }
+}
+
+impl<C, R, T, ActionSet> TaskRecipe<T> for Action<ActionSet>
+where
+    C: Controller,
+    R: GetTask<ActionSet> + GetNavigate<ActionSet> + GetInterrupts<ActionSet> + ResRoot,
+    T: Core<Controller = C, Resource = R>,
+    ActionSet: TaskRecipe<T, Res = Value> + Serialize + DeserializeOwned + Debug + Clone,
+{
+    type Res = Value;
+    fn run(&self, runner: &T) -> anyhow::Result<Self::Res> {
+        match self {
+            Action::ByName(ByNameActionSet::ByName(ByNameAction { name })) => {
+                get_task_by_name(runner.resource(), name)?.run(runner)
+            }
+            Action::Navigate(NavigateActionSet::NavigateTo(action)) => {
+                action.run(runner).map(|_| Value::Null)
+            }
+            Action::Detailed(action) => action.run(runner),
+            Action::Deferred(DeferredAction { value, .. }) => {
+                anyhow::bail!("unresolved variables in action: {value}")
+            }
+        }
+    }
+}
+
+/// 获取 `ByName` 引用的任务
+fn get_task_by_name<'a, ActionSet: Debug + Clone>(
+    resource: &'a impl GetTask<ActionSet>,
+    name: &str,
+) -> anyhow::Result<&'a Task<ActionSet>> {
+    resource.get_task(name).ok_or_else(|| {
+        ActionError::new(
+            ErrorKind::ResourceNotFound,
+            format!("failed to get task by name: {:?}", name),
+        )
+        .into()
+    })
+}
+
+#[skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+/// 一个完整的 [`Task`] 由若干 [`TaskStep`] 组成
+pub struct Task<ActionSet: Debug + Clone> {
+    /// Task 的名称
+    pub name: String,
+    /// Task 的描述
+    pub desc: Option<String>,
+    /// 是否处理全局中断（见 [`interrupt`]），默认为 `true`
+    pub handle_interrupts: Option<bool>,
+    /// 作为任务结果的变量名（见 [`vars`]），通过 `ByName` 运行此任务时会得到此变量的值
+    pub output: Option<String>,
+    /// Task 的步骤
+    pub steps: Vec<TaskStep<ActionSet>>,
+}
+
+impl<ActionSet: Debug + Clone> Task<ActionSet> {
+    pub fn from_steps(steps: Vec<TaskStep<ActionSet>>) -> Self {
+        Self {
+            name: "unnamed".to_string(),
+            desc: None,
+            handle_interrupts: None,
+            output: None,
+            steps,
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn with_desc(mut self, desc: &str) -> Self {
+        self.desc = Some(desc.to_string());
+        self
+    }
+
+    pub fn without_interrupts(mut self) -> Self {
+        self.handle_interrupts = Some(false);
+        self
+    }
+
+    pub fn with_output(mut self, var: &str) -> Self {
+        self.output = Some(var.to_string());
+        self
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+/// TaskStep 是对 [`Action`] 的封装，可以设置一些额外的属性
+pub struct TaskStep<ActionSet: Debug + Clone> {
+    /// 在此 Step 开始前的延迟
+    pub delay_sec: Option<f32>,
+    /// 如果此 Step 失败，是否跳过（否则会直接中断退出）
+    pub skip_if_failed: Option<bool>,
+    /// 重复次数
+    pub repeat: Option<u32>,
+    /// 每次重试次数（小于 0 表示无限重试）
+    pub retry: Option<i32>,
+    /// 重试策略，默认为立即重试所有错误，见 [`RetryPolicy`]
+    pub retry_policy: Option<RetryPolicy>,
+    /// 执行此 Step 的条件，为以变量为作用域的 Rhai 表达式（如 `count > 3`），为 `false` 时跳过此 Step
+    pub when: Option<String>,
+    /// 将 Action 的结果保存为此名称的变量（见 [`vars`]）
+    pub output: Option<String>,
+    /// 此 Step 是否可以安全地重复执行，默认为 `true`
+    ///
+    /// 会消耗资源的 Step（如使用理智药）应设为 `false`，从检查点恢复时不会盲目地重新执行被中断的此 Step（见 [`checkpoint`]）
+    pub idempotent: Option<bool>,
+    /// 在此 Step 中要执行的 Action，参数中可以通过 `${name}` 引用变量
+    pub action: Action<ActionSet>,
+}
+
+impl<ActionSet: Debug + Clone> TaskStep<ActionSet> {
+    pub fn from_action(action: Action<ActionSet>) -> Self {
+        Self {
+            delay_sec: None,
+            skip_if_failed: None,
+            repeat: None,
+            retry: None,
+            retry_policy: None,
+            when: None,
+            output: None,
+            idempotent: None,
+            action,
+        }
+    }
+
+    pub fn with_delay(mut self, sec: f32) -> Self {
+        self.delay_sec = Some(sec);
+        self
+    }
+
+    pub fn skip_if_failed(mut self) -> Self {
+        self.skip_if_failed = Some(true);
+        self
+    }
+
+    pub fn with_repeat(mut self, times: u32) -> Self {
+        self.repeat = Some(times);
+        self
+    }
+
+    pub fn with_retry(mut self, times: i32) -> Self {
+        self.retry = Some(times);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    pub fn when(mut self, condition: &str) -> Self {
+        self.when = Some(condition.to_string());
+        self
+    }
+
+    pub fn with_output(mut self, var: &str) -> Self {
+        self.output = Some(var.to_string());
+        self
+    }
+
+    pub fn non_idempotent(mut self) -> Self {
+        self.idempotent = Some(false);
+        self
+    }
+}
+
+// /// 任务事件
+// ///
+// /// - `Log(String)`: log 信息
+// /// - `Img(DynamicImage)`: 标记过的图片
+// #[derive(Clone)]
+// #[non_exhaustive]
+// pub enum TaskEvt<T: Debug + Clone> {
+//     ExecStat {
+//         step: TaskStep<T>,
+//         cur: usize,
+//         total: usize,
+//     },
+//     MatchTaskRes {},
+//     Log(String),
+//     AnnotatedImg(DynamicImage),
+//     BattleAnalyzerRes(BattleAnalyzerOutput),
+// }
+
+// impl<T: Debug + Clone> Debug for TaskEvt<T> {
+//     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+//         match self {
+//             TaskEvt::ExecStat { step, cur, total } => {
+//                 write!(f, "TaskEvt::ExecStat({:?}, {}/{})", step, cur, total)
+//             }
+//             TaskEvt::Log(log) => write!(f, "TaskEvt::Log({})", log),
+//             TaskEvt::AnnotatedImg(_img) => write!(f, "TaskEvt::AnnotatedImg"),
+//             TaskEvt::BattleAnalyzerRes(res) => write!(f, "TaskEvt::BattleAnalyzerRes({:?})", res),
+//             TaskEvt::MatchTaskRes { .. } => write!(f, "TaskEvt::MatchTaskRes"),
+//         }
+//     }
+// }
+
+impl<C, R, T, ActionSet> TaskRecipe<T> for Task<ActionSet>
+where
+    C: Controller,
+    R: GetTask<ActionSet> + GetNavigate<ActionSet> + GetInterrupts<ActionSet> + ResRoot,
+    T: Core<Controller = C, Resource = R>,
+    ActionSet: TaskRecipe<T, Res = Value> + Serialize + DeserializeOwned + Debug + Clone,
+{
+    type Res = Value;
+    fn run(&self, runner: &T) -> anyhow::Result<Self::Res> {
+        self.run_with_vars(runner, &mut Vars::new())
+    }
+}
+
+impl<ActionSet: Debug + Clone> Task<ActionSet> {
+    /// 以 `vars` 作为变量运行任务，返回 `output` 变量的值（未设置 `output` 时为 `null`）
+    ///
+    /// 可以通过 `vars` 预先设置变量，或在运行后读取步骤保存的变量
+    pub fn run_with_vars<C, R, T>(&self, runner: &T, vars: &mut Vars) -> anyhow::Result<Value>
+    where
+        C: Controller,
+        R: GetTask<ActionSet> + GetNavigate<ActionSet> + GetInterrupts<ActionSet> + ResRoot,
+        T: Core<Controller = C, Resource = R>,
+        ActionSet: TaskRecipe<T, Res = Value> + Serialize + DeserializeOwned,
+    {
+        self.run_from(runner, vars, Progress::default(), &mut |_, _| Ok(()))
+    }
+
+    /// 运行任务，每完成一个步骤后将进度保存到 `path` 中的检查点（见 [`checkpoint`]），任务成功完成后删除检查点
+    ///
+    /// `mode` 不为 [`ResumeMode::Restart`] 时从 `path` 中的检查点继续运行，不存在检查点时从头开始
+    pub fn run_with_checkpoint<C, R, T>(
+        &self,
+        runner: &T,
+        path: impl AsRef<Path>,
+        mode: ResumeMode,
+    ) -> anyhow::Result<Value>
+    where
+        C: Controller,
+        R: GetTask<ActionSet> + GetNavigate<ActionSet> + GetInterrupts<ActionSet> + ResRoot,
+        T: Core<Controller = C, Resource = R>,
+        ActionSet: TaskRecipe<T, Res = Value> + Serialize + DeserializeOwned,
+    {
+        let path = path.as_ref();
+        let (progress, mut vars) = match mode {
+            ResumeMode::Restart => (Progress::default(), Vars::new()),
+            mode => self.resume_from(runner, Checkpoint::load(path)?, mode)?,
+        };
+        // dry-run 模式下不保存进度
+        let mut save = |progress, vars: &Vars| match runner.dry_run() {
+            true => Ok(()),
+            false => Checkpoint {
+                task: self.name.clone(),
+                progress,
+                vars: vars.clone(),
+            }
+            .save(path),
+        };
+        let res = self.run_from(runner, &mut vars, progress, &mut save)?;
+        if !runner.dry_run() {
+            Checkpoint::clear(path)?;
+        }
+        Ok(res)
+    }
+
+    /// 根据 `mode` 确定从 `checkpoint` 的何处继续运行，`ByName` 子任务的进度会递归地处理
+    fn resume_from<C, R, T>(
+        &self,
+        runner: &T,
+        checkpoint: Option<Checkpoint>,
+        mode: ResumeMode,
+    ) -> anyhow::Result<(Progress, Vars)>
+    where
+        C: Controller,
+        R: GetTask<ActionSet> + GetNavigate<ActionSet> + GetInterrupts<ActionSet> + ResRoot,
+        T: Core<Controller = C, Resource = R>,
+        ActionSet: DeserializeOwned,
+    {
+        let Some(Checkpoint {
+            task,
+            mut progress,
+            vars,
+        }) = checkpoint
+        else {
+            warn!("[Task<{}>] no checkpoint found, starting over", self.name);
+            return Ok((Progress::default(), Vars::new()));
+        };
+        if task != self.name {
+            anyhow::bail!(
+                "the checkpoint belongs to task {task:?}, not {:?}",
+                self.name
+            );
+        }
+        if progress.step > self.steps.len() {
+            anyhow::bail!(
+                "the checkpoint is at step {} but task {:?} only has {} steps",
+                progress.step,
+                self.name,
+                self.steps.len()
+            );
+        }
+        if let Some(nested) = progress.nested.take() {
+            // 子任务的进度已经精确到其中的步骤，由子任务决定如何处理被中断的非幂等步骤
+            let step = self.steps.get(progress.step).with_context(|| {
+                format!(
+                    "the checkpoint has a subtask at step {} but task {:?} only has {} steps",
+                    progress.step,
+                    self.name,
+                    self.steps.len()
+                )
+            })?;
+            let Action::ByName(ByNameActionSet::ByName(ByNameAction { name })) =
+                &*step.resolve_action(&vars)?
+            else {
+                anyhow::bail!(
+                    "the checkpoint has a subtask at step {} of task {:?}, which is not `ByName`",
+                    progress.step,
+                    self.name
+                );
+            };
+            let task = get_task_by_name(runner.resource(), name)?;
+            let (sub, sub_vars) = task.resume_from(runner, Some(*nested), mode)?;
+            progress.pending = false;
+            progress.nested = Some(Box::new(Checkpoint {
+                task: task.name.clone(),
+                progress: sub,
+                vars: sub_vars,
+            }));
+        } else if progress.pending {
+            let Some(step) = self.steps.get(progress.step) else {
+                anyhow::bail!(
+                    "the checkpoint is pending at step {} but task {:?} only has {} steps",
+                    progress.step,
+                    self.name,
+                    self.steps.len()
+                );
+            };
+            if mode != ResumeMode::SkipInterrupted {
+                anyhow::bail!(
+                    "step {} of task {:?} is not idempotent and was interrupted, \
+                    check the game state and skip it or restart the task",
+                    progress.step,
+                    self.name
+                );
+            }
+            warn!(
+                "[Task<{}>] skipping the interrupted step {}",
+                self.name, progress.step
+            );
+            progress.pending = false;
+            progress.repeat += 1;
+            if progress.repeat > step.repeat.unwrap_or(0) {
+                progress.step += 1;
+                progress.repeat = 0;
+            }
+        }
+        info!(
+            "[Task<{}>] resuming from step {} (repeat {})",
+            self.name, progress.step, progress.repeat
+        );
+        Ok((progress, vars))
+    }
+
+    /// 从 `start` 开始运行任务，每次保存进度时调用 `save`
+    fn run_from<C, R, T>(
+        &self,
+        runner: &T,
+        vars: &mut Vars,
+        start: Progress,
+        save: &mut dyn FnMut(Progress, &Vars) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Value>
+    where
+        C: Controller,
+        R: GetTask<ActionSet> + GetNavigate<ActionSet> + GetInterrupts<ActionSet> + ResRoot,
+        T: Core<Controller = C, Resource = R>,
+        ActionSet: TaskRecipe<T, Res = Value> + Serialize + DeserializeOwned,
+    {
+        if let Some(reporter) = runner.reporter() {
+            reporter.begin_task(RunKind::Task, &self.name);
+        }
+        let res = self.run_steps(runner, vars, start, save);
+        if let Some(reporter) = runner.reporter() {
+            reporter.end_task(&res);
+        }
+        res?;
+        Ok(match &self.output {
+            Some(var) => vars.value(var).cloned().unwrap_or_default(),
+            None => Value::Null,
+        })
+    }
+
+    fn run_steps<C, R, T>(
+        &self,
+        runner: &T,
+        vars: &mut Vars,
+        mut start: Progress,
+        save: &mut dyn FnMut(Progress, &Vars) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()>
+    where
+        C: Controller,
+        R: GetTask<ActionSet> + GetNavigate<ActionSet> + GetInterrupts<ActionSet> + ResRoot,
+        T: Core<Controller = C, Resource = R>,
+        ActionSet: TaskRecipe<T, Res = Value> + Serialize + DeserializeOwned,
+    {
+        info!("[Task<{}>] running...", self.name);
+        let mut interrupts = if self.handle_interrupts.unwrap_or(true) {
+            Some(InterruptHandler::new(runner))
+        } else {
+            None
+        };
+        for (i, step) in self.steps.iter().enumerate().skip(start.step) {
+            // 从检查点恢复时，此步骤已经完成的执行次数
+            let (done, nested) = match i == start.step {
+                true => (start.repeat, start.nested.take()),
+                false => (0, None),
+            };
+            info!(
+                "[Task<{}>] running step {}/{}: {:?}",
+                self.name,
+                i,
+                self.steps.len(),
+                step
+            );
+            // runner.emit_task_evt(TaskEvt::ExecStat {
+            //     step: step.clone(),
+            //     cur: i,
+            //     total: self.steps.len(),
+            // });
+            if let (0, Some(condition)) = (done, &step.when) {
+                if !vars.eval_condition(condition)? {
+                    info!(
+                        "[Task<{}>] skipping step {i}: `{condition}` is false",
+                        self.name
+                    );
+                    continue;
+                }
+            }
+            cprintln!(
+                "<m><strong>[Task]</strong></m>: executing task {}({}/{}): {:?}",
+                self.name,
+                i,
+                self.steps.len(),
+                step
+            );
+            if let Some(reporter) = runner.reporter() {
+                reporter.begin_step(i, format!("{:?}", step.action));
+            }
+            let res = step.run_with_interrupts(
+                runner,
+                interrupts.as_mut(),
+                vars,
+                done,
+                nested.map(|nested| *nested),
+                |repeat, pending, nested| {
+                    save(
+                        Progress {
+                            step: i,
+                            repeat,
+                            pending,
+                            nested,
+                        },
+                        vars,
+                    )
+                },
+            );
+            if let Some(reporter) = runner.reporter() {
+                reporter.end_step();
+            }
+            match res {
+                Ok(value) => {
+                    if let Some(var) = &step.output {
+                        vars.set(var, value)?;
+                    }
+                }
+                // dry-run 模式下继续执行后续步骤，以便检查整个任务
+                Err(err) if runner.dry_run() => {
+                    cprintln!("<r><strong>[Task]</strong></r>: step {i} failed: {err}");
+                }
+                Err(err) => {
+                    if !step.skip_if_failed.unwrap_or(false) {
+                        return Err(err);
+                    }
+                }
+            }
+            save(
+                Progress {
+                    step: i + 1,
+                    ..Default::default()
+                },
+                vars,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<C, R, T, ActionSet> TaskRecipe<T> for TaskStep<ActionSet>
+where
+    C: Controller,
+    R: GetTask<ActionSet> + GetNavigate<ActionSet> + GetInterrupts<ActionSet> + ResRoot,
+    T: Core<Controller = C, Resource = R>,
+    ActionSet: TaskRecipe<T, Res = Value> + Serialize + DeserializeOwned + Debug + Clone,
+{
+    type Res = Value;
+    fn run(&self, runner: &T) -> anyhow::Result<Self::Res> {
+        self.run_with_interrupts(runner, None, &Vars::new(), 0, None, |_, _, _| Ok(()))
+    }
+}
+
+impl<ActionSet: Debug + Clone> TaskStep<ActionSet> {
+    /// 将 Action 参数中的 `${name}` 替换为 `vars` 中的变量，只有 [`Action::Deferred`] 需要替换
+    fn resolve_action(&self, vars: &Vars) -> anyhow::Result<Cow<'_, Action<ActionSet>>>
+    where
+        ActionSet: DeserializeOwned,
+    {
+        match &self.action {
+            Action::Deferred(action) => action.resolve(vars).map(Cow::Owned),
+            action => Ok(Cow::Borrowed(action)),
+        }
+    }
+
+    /// 运行此步骤，若 `interrupts` 不为 `None`，则在步骤开始前及每次识别失败后处理全局中断
+    ///
+    /// 跳过已经完成的 `done` 次执行，每次执行后（非幂等的步骤还有执行前）以已完成的次数及是否正在执行调用 `on_exec`，
+    /// `ByName` 子任务每次保存进度时也会调用 `on_exec`，并带上子任务的进度。
+    /// `nested` 为从检查点恢复的子任务进度，下一次执行会从此处继续
+    fn run_with_interrupts<C, R, T>(
+        &self,
+        runner: &T,
+        mut interrupts: Option<&mut InterruptHandler>,
+        vars: &Vars,
+        done: u32,
+        mut nested: Option<Checkpoint>,
+        mut on_exec: impl FnMut(u32, bool, Option<Box<Checkpoint>>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Value>
+    where
+        C: Controller,
+        R: GetTask<ActionSet> + GetNavigate<ActionSet> + GetInterrupts<ActionSet> + ResRoot,
+        T: Core<Controller = C, Resource = R>,
+        ActionSet: TaskRecipe<T, Res = Value> + Serialize + DeserializeOwned,
+    {
+        let action = self.resolve_action(vars)?;
+        if !runner.dry_run() {
+            runner
+                .clock()
+                .sleep(Duration::from_secs_f32(self.delay_sec.unwrap_or(0.0)));
+        }
+        if let Some(interrupts) = interrupts.as_mut() {
+            interrupts.reset();
+            interrupts.handle(runner)?;
+        }
+
+        let idempotent = self.idempotent.unwrap_or(true);
+        // 从检查点恢复子任务时，检查点中已经记录了这一次执行，不要覆盖子任务的进度
+        let resumed = nested.is_some().then_some(done);
+        // 每一次运行 Action 都是一次尝试
+        let mut attempt = |done: u32,
+                           on_exec: &mut dyn FnMut(
+            u32,
+            bool,
+            Option<Box<Checkpoint>>,
+        ) -> anyhow::Result<()>| {
+            if let Some(reporter) = runner.reporter() {
+                reporter.begin_attempt();
+            }
+            let res = match &*action {
+                // 子任务的进度作为此步骤进度的一部分保存
+                Action::ByName(ByNameActionSet::ByName(ByNameAction { name })) => {
+                    get_task_by_name(runner.resource(), name).and_then(|task| {
+                        let (start, mut vars) = match nested.take() {
+                            Some(Checkpoint { progress, vars, .. }) => (progress, vars),
+                            None => (Progress::default(), Vars::new()),
+                        };
+                        task.run_from(runner, &mut vars, start, &mut |progress, vars| {
+                            on_exec(
+                                done,
+                                !idempotent,
+                                Some(Box::new(Checkpoint {
+                                    task: task.name.clone(),
+                                    progress,
+                                    vars: vars.clone(),
+                                })),
+                            )
+                        })
+                    })
+                }
+                action => action.run(runner),
+            };
+            if let Some(reporter) = runner.reporter() {
+                reporter.end_attempt(&res);
+            }
+            res
+        };
+        // dry-run 模式下只运行一次
+        if runner.dry_run() {
+            return attempt(done, &mut on_exec);
+        }
+
+        let policy = self.retry_policy.clone().unwrap_or_default();
+        let clock = runner.clock();
+        let mut exec = |done: u32,
+                        on_exec: &mut dyn FnMut(
+            u32,
+            bool,
+            Option<Box<Checkpoint>>,
+        ) -> anyhow::Result<()>| {
+            let start = clock.now();
+            let mut retried = 0;
+            loop {
+                let res = attempt(done, on_exec);
+                // debug!("TaskStep::run: {:?}", res);
+                let Err(err) = &res else {
+                    return res;
+                };
+                // 识别失败可能是被弹窗挡住了，处理中断后立即重试
+                if ActionError::kind_of(err) == ErrorKind::TemplateNotFound {
+                    if let Some(interrupts) = interrupts.as_mut() {
+                        if interrupts.handle(runner)? {
+                            continue;
+                        }
+                    }
+                }
+                let can_retry = match self.retry {
+                    None => false,
+                    Some(retry) => retry < 0 || retried < retry as u32,
+                };
+                if !can_retry || !policy.should_retry(err) {
+                    return res;
+                }
+                let delay = policy.backoff.delay(retried);
+                if !policy.within_duration(clock.elapsed(start), delay) {
+                    warn!(
+                        "[TaskStep]: attempt {} failed: {err:#}, retry timeout",
+                        retried + 1
+                    );
+                    return res;
+                }
+                warn!(
+                    "[TaskStep]: attempt {} failed: {err:#}, retrying in {:.2}s",
+                    retried + 1,
+                    delay.as_secs_f32()
+                );
+                clock.sleep(delay);
+                retried += 1;
+            }
+        };
+
+        // 执行一次，再重复 `repeat` 次
+        let times = self.repeat.unwrap_or(0) + 1;
+        let mut res = Ok(Value::Null);
+        for done in done..times {
+            if !idempotent && resumed != Some(done) {
+                on_exec(done, true, None)?;
+            }
+            res = exec(done, &mut on_exec);
+            // Fail fast for repeat
+            if res.is_err() {
+                break;
+            }
+            if done + 1 < times {
+                on_exec(done + 1, false, None)?;
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::{
+        android::{
+            self,
+            actions::{ClickMatchTemplate, Press},
+        },
+        resource::validate::ActionRefs,
+        test_utils::{old_resources, resource, screenshot, TestCore},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_serde_action() {
+        let action = Action::<android::actions::ActionSet>::by_name("test".to_string());
+        let toml = toml::to_string_pretty(&action).unwrap();
+        println!("{toml}");
+
+        let action = Action::<android::actions::ActionSet>::detailed(Press::esc());
+        let toml = toml::to_string_pretty(&action).unwrap();
+        println!("{toml}");
+
+        let action = Action::<android::actions::ActionSet>::detailed(ClickMatchTemplate::new(
+            "template.png",
+        ));
+        let toml = toml::to_string_pretty(&action).unwrap();
+        println!("{toml}");
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let tasks = vec![
+            Task::from_steps(vec![
+                TaskStep::from_action(Action::detailed(Press::esc())).with_delay(10.0),
+                // 失败的步骤不会中断任务，也不会被重试
+                TaskStep::from_action(Action::by_name("not_exist")).with_retry(-1),
+                TaskStep::from_action(Action::by_name("inner")).with_repeat(3),
+            ])
+            .with_name("outer"),
+            Task::from_steps(vec![TaskStep::from_action(Action::detailed(
+                android::actions::ActionSet::click(100, 200),
+            ))])
+            .with_name("inner"),
+        ];
+        let core = TestCore::new(
+            DynamicImage::new_rgb8(1920, 1080),
+            resource(PathBuf::new(), tasks),
+        )
+        .with_dry_run();
+
+        core.resource.get_task("outer").unwrap().run(&core).unwrap();
+        assert_eq!(core.clock.total_elapsed(), Duration::ZERO);
+        assert_eq!(core.ops(), vec!["press esc", "click (100, 200)"]);
+    }
+
+    #[test]
+    fn test_vars() {
+        let outer: Task<android::actions::ActionSet> = toml::from_str(
+            r#"
+name = "outer"
+output = "base"
+
+[[steps]]
+output = "base"
+action.ByName = { name = "inner" }
+
+[[steps]]
+action.Click = { x = "${base.x}", y = "${ base.y }" }
+
+[[steps]]
+when = "base.x > 10000"
+action.Press = { key = "Esc" }
+
+[[steps]]
+when = "base.x < 10000"
+action.Click = { x = "${offset}", y = 0 }
+"#,
+        )
+        .unwrap();
+        assert!(matches!(outer.steps[1].action, Action::Deferred(_)));
+        // 加载时就检查 Action 的结构
+        let typo = toml::from_str::<TaskStep<android::actions::ActionSet>>(
+            r#"action.Clik = { x = "${base.x}", y = 0 }"#,
+        );
+        assert!(typo.is_err());
+        let step: TaskStep<android::actions::ActionSet> = toml::from_str(
+            r#"action.ClickMatchTemplate = { template = "${name}.png", index = "${i}" }"#,
+        )
+        .unwrap();
+        let Action::Deferred(deferred) = &step.action else {
+            panic!("{:?}", step.action);
+        };
+        assert!(matches!(deferred.shape(), Action::Detailed(_)));
+        assert_eq!(step.action.templates(), Vec::<String>::new());
+        let step: TaskStep<android::actions::ActionSet> = toml::from_str(
+            r#"action.WaitForTemplate = { template = "main_base.png", timeout_sec = "${t}" }"#,
+        )
+        .unwrap();
+        assert_eq!(step.action.templates(), vec!["main_base.png".to_string()]);
+        // 不含变量的 Action 不需要替换
+        assert!(matches!(
+            TaskStep::from_action(Action::<android::actions::ActionSet>::by_name("x"))
+                .resolve_action(&Vars::new())
+                .unwrap(),
+            Cow::Borrowed(_)
+        ));
+        let inner = Task::from_steps(vec![TaskStep::from_action(Action::detailed(
+            android::actions::WaitForTemplate::new("main_base.png"),
+        ))
+        .with_output("rect")])
+        .with_name("inner")
+        .with_output("rect");
+
+        let core = TestCore::new(
+            screenshot("main.png"),
+            resource(old_resources(), vec![inner]),
+        )
+        .with_dry_run();
+
+        let mut vars = Vars::new();
+        vars.set("offset", 42).unwrap();
+        let res = outer.run_with_vars(&core, &mut vars).unwrap();
+        let rect: crate::vision::utils::Rect = vars.get("base").unwrap().unwrap();
+        assert_eq!(res, vars::to_value(&rect).unwrap());
+
+        assert_eq!(
+            core.ops(),
+            vec![
+                format!("click ({}, {})", rect.x, rect.y),
+                "click (42, 0)".to_string()
+            ]
+        );
+
+        // 没有设置变量时，引用变量的步骤失败
+        assert!(outer.steps[3].run(&core).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let task = Task::from_steps(vec![
+            TaskStep::from_action(Action::detailed(Press::esc())).with_repeat(2),
+            TaskStep::from_action(Action::detailed(android::actions::ActionSet::click(1, 1)))
+                .non_idempotent(),
+            TaskStep::from_action(Action::detailed(Press::home())).when("ready"),
+        ])
+        .with_name("farm")
+        .without_interrupts();
+        // 不处于 dry-run 模式才会保存检查点
+        let core = TestCore::new(
+            DynamicImage::new_rgb8(1920, 1080),
+            resource(PathBuf::new(), vec![]),
+        );
+        let ops = |from: usize| core.ops()[from..].to_vec();
+        let path = std::env::temp_dir().join(format!("aah-checkpoint-{}.json", std::process::id()));
+        let checkpoint = |step, repeat, pending, ready: Option<bool>| {
+            let mut vars = Vars::new();
+            if let Some(ready) = ready {
+                vars.set("ready", ready).unwrap();
+            }
+            Checkpoint {
+                task: "farm".to_string(),
+                progress: Progress {
+                    step,
+                    repeat,
+                    pending,
+                    ..Default::default()
+                },
+                vars,
+            }
+            .save(&path)
+            .unwrap();
+        };
+
+        // 未定义 `ready`，在最后一步失败
+        assert!(task
+            .run_with_checkpoint(&core, &path, ResumeMode::Restart)
+            .is_err());
+        assert_eq!(
+            ops(0),
+            vec!["press esc", "press esc", "press esc", "click (1, 1)"]
+        );
+        let saved = Checkpoint::load(&path).unwrap().unwrap();
+        assert_eq!(saved.task, "farm");
+        assert_eq!(
+            saved.progress,
+            Progress {
+                step: 2,
+                ..Default::default()
+            }
+        );
+
+        // 从检查点继续，变量也会恢复
+        checkpoint(2, 0, false, Some(true));
+        let n = core.ops().len();
+        task.run_with_checkpoint(&core, &path, ResumeMode::Resume)
+            .unwrap();
+        assert_eq!(ops(n), vec!["press home"]);
+        assert!(Checkpoint::load(&path).unwrap().is_none());
+
+        // 从 `repeat` 的中间继续
+        checkpoint(0, 2, false, Some(true));
+        let n = core.ops().len();
+        task.run_with_checkpoint(&core, &path, ResumeMode::Resume)
+            .unwrap();
+        assert_eq!(ops(n), vec!["press esc", "click (1, 1)", "press home"]);
+
+        // 被中断的非幂等步骤不会被重新执行
+        checkpoint(1, 0, true, Some(true));
+        let n = core.ops().len();
+        let err = task
+            .run_with_checkpoint(&core, &path, ResumeMode::Resume)
+            .unwrap_err();
+        assert!(err.to_string().contains("not idempotent"));
+        assert!(ops(n).is_empty());
+        task.run_with_checkpoint(&core, &path, ResumeMode::SkipInterrupted)
+            .unwrap();
+        assert_eq!(ops(n), vec!["press home"]);
+
+        // 检查点中正在执行的步骤超出范围
+        checkpoint(3, 0, true, Some(true));
+        assert!(task
+            .run_with_checkpoint(&core, &path, ResumeMode::SkipInterrupted)
+            .is_err());
+
+        // 其他任务的检查点
+        checkpoint(1, 0, false, None);
+        let other = task.clone().with_name("other");
+        assert!(other
+            .run_with_checkpoint(&core, &path, ResumeMode::Resume)
+            .is_err());
+        Checkpoint::clear(&path).unwrap();
+    }
+
+    #[test]
+    fn test_nested_checkpoint() {
+        let inner = Task::from_steps(vec![
+            TaskStep::from_action(Action::detailed(Press::esc())),
+            TaskStep::from_action(Action::detailed(android::actions::ActionSet::click(1, 1)))
+                .non_idempotent(),
+            TaskStep::from_action(Action::detailed(Press::home())).when("ready"),
+        ])
+        .with_name("inner")
+        .without_interrupts();
+        let outer = Task::from_steps(vec![
+            TaskStep::from_action(Action::by_name("inner")),
+            TaskStep::from_action(Action::detailed(android::actions::ActionSet::click(2, 2))),
+        ])
+        .with_name("outer")
+        .without_interrupts();
+        let core = TestCore::new(
+            DynamicImage::new_rgb8(1920, 1080),
+            resource(PathBuf::new(), vec![inner]),
+        );
+        let ops = |from: usize| core.ops()[from..].to_vec();
+        let path =
+            std::env::temp_dir().join(format!("aah-nested-checkpoint-{}.json", std::process::id()));
+
+        // 子任务中未定义 `ready`，检查点中记录了子任务的进度
+        assert!(outer
+            .run_with_checkpoint(&core, &path, ResumeMode::Restart)
+            .is_err());
+        assert_eq!(ops(0), vec!["press esc", "click (1, 1)"]);
+        let mut saved = Checkpoint::load(&path).unwrap().unwrap();
+        assert_eq!(saved.progress.step, 0);
+        let nested = saved.progress.nested.as_mut().unwrap();
+        assert_eq!(nested.task, "inner");
+        assert_eq!(nested.progress.step, 2);
+
+        // 从子任务中断的步骤继续
+        nested.vars.set("ready", true).unwrap();
+        saved.save(&path).unwrap();
+        let n = core.ops().len();
+        outer
+            .run_with_checkpoint(&core, &path, ResumeMode::Resume)
+            .unwrap();
+        assert_eq!(ops(n), vec!["press home", "click (2, 2)"]);
+        assert!(Checkpoint::load(&path).unwrap().is_none());
+
+        // 子任务中被中断的非幂等步骤不会被重新执行
+        let nested = saved.progress.nested.as_mut().unwrap();
+        nested.progress = Progress {
+            step: 1,
+            pending: true,
+            ..Default::default()
+        };
+        saved.save(&path).unwrap();
+        let n = core.ops().len();
+        let err = outer
+            .run_with_checkpoint(&core, &path, ResumeMode::Resume)
+            .unwrap_err();
+        assert!(err.to_string().contains("not idempotent"));
+        assert!(ops(n).is_empty());
+        outer
+            .run_with_checkpoint(&core, &path, ResumeMode::SkipInterrupted)
+            .unwrap();
+        assert_eq!(ops(n), vec!["press home", "click (2, 2)"]);
+    }
+
+    #[test]
+    fn test_virtual_clock() {
+        let core = TestCore::new(
+            DynamicImage::new_rgb8(1920, 1080),
+            resource(PathBuf::new(), vec![]),
+        );
+        let task = Task::from_steps(vec![
+            TaskStep::from_action(Action::detailed(Press::esc())).with_delay(30.0),
+            TaskStep::from_action(Action::by_name("not_exist"))
+                .with_retry(3)
+                .with_retry_policy(RetryPolicy::default().with_backoff(retry::Backoff::Fixed(10.0)))
+                .skip_if_failed(),
+        ])
+        .without_interrupts();
+
+        let start = std::time::Instant::now();
+        task.run(&core).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(core.clock.total_elapsed(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_serde_task() {
+        let task = Task::<android::actions::ActionSet> {
+            name: "test".to_string(),
+            desc: Some("test".to_string()),
+            handle_interrupts: Some(false),
+            output: None,
+            steps: vec![
+                TaskStep {
+                    delay_sec: Some(1.0),
+                    skip_if_failed: Some(true),
+                    repeat: Some(2),
+                    retry: Some(3),
+                    retry_policy: None,
+                    when: Some("count > 3".to_string()),
+                    output: Some("res".to_string()),
+                    idempotent: None,
+                    action: Action::by_name("test"),
+                },
+                TaskStep {
+                    delay_sec: Some(1.0),
+                    skip_if_failed: Some(true),
+                    repeat: Some(2),
+                    retry: Some(3),
+                    retry_policy: Some(
+                        RetryPolicy::default()
+                            .with_backoff(retry::Backoff::Fixed(1.0))
+                            .retry_on(vec![ErrorKind::TemplateNotFound]),
+                    ),
+                    when: None,
+                    output: None,
+                    idempotent: Some(false),
+                    action: Action::detailed(Press::esc()),
+                },
+            ],
+        };
+        let toml = toml::to_string_pretty(&task).unwrap();
+        println!("{toml}");
+    }
+}
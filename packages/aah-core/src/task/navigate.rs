@@ -0,0 +1,232 @@
+//! 场景导航
+//!
+//! [`NavigateTo`] 会先识别当前所在的场景，然后按照 [`NavigateConfig`] 找出到目标场景的最短路径并依次执行每一条边。
+//! 每执行一条边后都会确认是否到达了预期的场景，若到达了其他场景，则从该场景重新规划路径：
+//!
+//! ```toml
+//! [[steps]]
+//! action.NavigateTo = { scene = "mission" }
+//! ```
+
+use std::{fmt::Debug, time::Duration};
+
+use aah_controller::Controller;
+use color_print::cprintln;
+use log::{info, warn};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::{
+    report::Recognition,
+    resource::{
+        manifest::navigate::{Edge, NavigateConfig},
+        GetInterrupts, GetNavigate, GetTask, ResRoot,
+    },
+    vision::analyzer::scene::SceneAnalyzer,
+    Core, TaskRecipe,
+};
+
+use super::{
+    retry::{ActionError, ErrorKind},
+    vars::Value,
+};
+
+/// 两次识别之间的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum NavigateActionSet {
+    /// 导航到指定的场景，见 [`NavigateTo`]
+    NavigateTo(NavigateTo),
+}
+
+/// 导航到 `scene` 场景
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NavigateTo {
+    /// 目标场景名称
+    pub scene: String,
+    /// 到达非预期场景后重新规划路径的最大次数，默认为 3
+    pub max_replan: Option<u32>,
+}
+
+impl NavigateTo {
+    pub fn new(scene: impl AsRef<str>) -> Self {
+        Self {
+            scene: scene.as_ref().to_string(),
+            max_replan: None,
+        }
+    }
+}
+
+/// 识别当前所处的场景
+fn current_scene<T, C>(runner: &T, analyzer: &SceneAnalyzer) -> anyhow::Result<Option<String>>
+where
+    C: Controller,
+    T: Core<Controller = C>,
+{
+    let screen = runner.controller().screencap().map_err(|err| {
+        anyhow::Error::new(ActionError::new(
+            ErrorKind::ControllerError,
+            format!("controller error: {err:?}"),
+        ))
+    })?;
+    let output = analyzer.analyze_image(&screen, runner.scale_memory().as_ref())?;
+    if let Some(reporter) = runner.reporter() {
+        let target = format!("scene {}", output.scene.as_deref().unwrap_or("unknown"));
+        let recognition = Recognition::new(target, None, output.confidence);
+        reporter.record_recognition(match output.scene {
+            Some(_) => recognition,
+            None => recognition.with_screenshot(&screen),
+        });
+    }
+    Ok(output.scene)
+}
+
+/// 执行 `edge` 后等待到达 [`Edge::to`]，超时则返回最后一次识别到的场景
+fn wait_for_scene<T, C, ActionSet>(
+    runner: &T,
+    analyzer: &SceneAnalyzer,
+    edge: &Edge<ActionSet>,
+) -> anyhow::Result<Option<String>>
+where
+    C: Controller,
+    T: Core<Controller = C>,
+    ActionSet: Debug + Clone,
+{
+    let timeout = Duration::from_secs_f32(edge.timeout_sec.unwrap_or(5.0));
+    let clock = runner.clock();
+    let start = clock.now();
+    loop {
+        let scene = current_scene(runner, analyzer)?;
+        if scene.as_deref() == Some(edge.to.as_str()) || clock.elapsed(start) >= timeout {
+            return Ok(scene);
+        }
+        clock.sleep(POLL_INTERVAL);
+    }
+}
+
+impl NavigateTo {
+    /// 使用 `runner` 的资源中 `ActionSet` 的导航配置进行导航
+    ///
+    /// 由于 [`NavigateTo`] 本身与 `ActionSet` 无关，这里没有实现 [`TaskRecipe`]，
+    /// 而是由 [`super::Action`] 在运行时指定 `ActionSet`
+    pub fn run<T, C, R, ActionSet>(&self, runner: &T) -> anyhow::Result<()>
+    where
+        C: Controller,
+        R: GetTask<ActionSet> + GetNavigate<ActionSet> + GetInterrupts<ActionSet> + ResRoot,
+        T: Core<Controller = C, Resource = R>,
+        ActionSet: TaskRecipe<T, Res = Value> + Serialize + DeserializeOwned + Debug + Clone,
+    {
+        let config: &NavigateConfig<ActionSet> = runner.resource().navigate_config();
+        if config.scene(&self.scene).is_none() {
+            return Err(ActionError::new(
+                ErrorKind::ResourceNotFound,
+                format!("unknown scene: {}", self.scene),
+            )
+            .into());
+        }
+        let unrecognized = || {
+            anyhow::Error::new(ActionError::new(
+                ErrorKind::TemplateNotFound,
+                "failed to recognize current scene",
+            ))
+        };
+
+        let analyzer = config.analyzer(runner.resource())?;
+        let mut current = current_scene(runner, analyzer)?.ok_or_else(unrecognized)?;
+        let mut replanned = 0;
+        while current != self.scene {
+            let path = config
+                .shortest_path(&current, &self.scene)
+                .ok_or_else(|| anyhow::anyhow!("no path from {} to {}", current, self.scene))?;
+            cprintln!(
+                "<c><strong>[Navigate]</strong></c>: {} -> {}",
+                current,
+                path.iter()
+                    .map(|edge| edge.to.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            );
+
+            for edge in path {
+                edge.action.run(runner)?;
+                // dry-run 模式下画面不会变化，无法确认是否到达
+                if runner.dry_run() {
+                    current = edge.to.clone();
+                    continue;
+                }
+                let scene = wait_for_scene(runner, analyzer, edge)?;
+                if scene.as_deref() == Some(edge.to.as_str()) {
+                    info!("[Navigate]: arrived at {}", edge.to);
+                    current = edge.to.clone();
+                    continue;
+                }
+
+                replanned += 1;
+                if replanned > self.max_replan.unwrap_or(3) {
+                    anyhow::bail!(
+                        "failed to navigate to {}: expected {}, got {:?}",
+                        self.scene,
+                        edge.to,
+                        scene
+                    );
+                }
+                current = scene.ok_or_else(unrecognized)?;
+                warn!(
+                    "[Navigate]: expected {}, got {}, replanning ({replanned})",
+                    edge.to, current
+                );
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        android::actions::ActionSet,
+        resource::manifest::scene::{ColorSignature, Scene, TemplateSignature},
+        task::Action,
+        test_utils::{old_resources, resource, screenshot, TestCore},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_navigate_to() {
+        let mut resource = resource(old_resources(), vec![]);
+        resource.navigate_config = NavigateConfig::new(
+            vec![
+                Scene::new(
+                    "main",
+                    vec![TemplateSignature::new("main_mission.png").into()],
+                ),
+                // 截图不是纯白的，不会被识别为 mission
+                Scene::new(
+                    "mission",
+                    vec![ColorSignature::new((0.0, 0.0), (1.0, 1.0), [255, 255, 255]).into()],
+                ),
+            ],
+            vec![
+                Edge::new(
+                    "main",
+                    "mission",
+                    Action::detailed(ActionSet::click(10, 20)),
+                ),
+                Edge::new("mission", "main", Action::detailed(ActionSet::press_esc())),
+            ],
+        );
+        let core = TestCore::new(screenshot("main.png"), resource).with_dry_run();
+
+        let navigate_to = |scene| Action::<ActionSet>::navigate_to(scene).run(&core);
+        navigate_to("mission").unwrap();
+        navigate_to("main").unwrap();
+        assert!(navigate_to("not_exist").is_err());
+
+        assert_eq!(core.ops(), vec!["click (10, 20)"]);
+    }
+}
@@ -0,0 +1,354 @@
+//! 可扩展的 Action 注册表
+//!
+//! ActionSet 是一个封闭的 enum，无法在不修改 `aah-core` 的情况下添加新的操作。
+//! [`ActionRegistry`] 允许以名称注册任意实现了 [`TaskRecipe`] 的操作类型，
+//! 注册后即可在任务文件中以与 ActionSet 中的操作相同的格式使用：
+//!
+//! ```toml
+//! [[steps]]
+//! action.ChooseLevel = "CE-5"
+//! ```
+//!
+//! 每一种 [`Core`](crate::Core) 通过 [`ActionRegistryProvider`] 提供一个全局的注册表，
+//! ActionSet 中的 [`RegisteredAction`] 在反序列化时会从中查找对应名称的操作类型。
+//! 注册需要在加载资源之前完成。
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+    sync::RwLock,
+};
+
+use anyhow::Context;
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject, SubschemaValidation},
+    JsonSchema,
+};
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    ser::{Error as _, SerializeMap},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{resource::validate::ActionRefs, TaskRecipe};
+
+use super::vars::{to_value, Value};
+
+/// 可以被注册的操作类型
+pub trait RegistrableAction<T>:
+    TaskRecipe<T, Res: Serialize>
+    + ActionRefs
+    + Serialize
+    + DeserializeOwned
+    + JsonSchema
+    + Debug
+    + Clone
+    + Send
+    + Sync
+    + 'static
+{
+}
+
+impl<T, A> RegistrableAction<T> for A where
+    A: TaskRecipe<T, Res: Serialize>
+        + ActionRefs
+        + Serialize
+        + DeserializeOwned
+        + JsonSchema
+        + Debug
+        + Clone
+        + Send
+        + Sync
+        + 'static
+{
+}
+
+/// 类型擦除后的 [`RegistrableAction`]
+trait ErasedAction<T>: Debug + Send + Sync {
+    fn run(&self, runner: &T) -> anyhow::Result<Value>;
+    fn to_value(&self) -> Result<toml::Value, toml::ser::Error>;
+    fn clone_box(&self) -> Box<dyn ErasedAction<T>>;
+    fn refs(&self) -> &dyn ActionRefs;
+}
+
+impl<T, A: RegistrableAction<T>> ErasedAction<T> for A {
+    fn run(&self, runner: &T) -> anyhow::Result<Value> {
+        TaskRecipe::run(self, runner).and_then(to_value)
+    }
+    fn to_value(&self) -> Result<toml::Value, toml::ser::Error> {
+        toml::Value::try_from(self)
+    }
+    fn clone_box(&self) -> Box<dyn ErasedAction<T>> {
+        Box::new(self.clone())
+    }
+    fn refs(&self) -> &dyn ActionRefs {
+        self
+    }
+}
+
+struct Entry<T> {
+    deserialize: fn(toml::Value) -> anyhow::Result<Box<dyn ErasedAction<T>>>,
+    schema: fn(&mut SchemaGenerator) -> Schema,
+}
+
+/// 以名称注册的操作类型，`T` 为运行这些操作的 [`Core`](crate::Core)
+pub struct ActionRegistry<T> {
+    entries: BTreeMap<String, Entry<T>>,
+}
+
+impl<T> Default for ActionRegistry<T> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> ActionRegistry<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 以 `name` 注册操作类型 `A`，名称已被注册时返回错误
+    pub fn register<A: RegistrableAction<T>>(
+        &mut self,
+        name: impl AsRef<str>,
+    ) -> anyhow::Result<()> {
+        let name = name.as_ref();
+        if self.entries.contains_key(name) {
+            anyhow::bail!("action `{name}` is already registered");
+        }
+        self.entries.insert(
+            name.to_string(),
+            Entry {
+                deserialize: |value| {
+                    let action: A = value.try_into()?;
+                    Ok(Box::new(action))
+                },
+                schema: |gen| gen.subschema_for::<A>(),
+            },
+        );
+        Ok(())
+    }
+
+    /// 所有已注册的名称
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// 将 `value` 反序列化为以 `name` 注册的操作
+    pub fn deserialize(
+        &self,
+        name: impl AsRef<str>,
+        value: toml::Value,
+    ) -> anyhow::Result<RegisteredAction<T>> {
+        let name = name.as_ref();
+        let entry = self
+            .entries
+            .get(name)
+            .with_context(|| format!("unknown action `{name}`"))?;
+        let action =
+            (entry.deserialize)(value).with_context(|| format!("invalid action `{name}`"))?;
+        Ok(RegisteredAction {
+            name: name.to_string(),
+            action,
+        })
+    }
+}
+
+/// 提供 `Self` 的全局 [`ActionRegistry`]
+pub trait ActionRegistryProvider: Sized + 'static {
+    fn action_registry() -> &'static RwLock<ActionRegistry<Self>>;
+
+    /// 以 `name` 注册操作类型 `A`
+    fn register_action<A: RegistrableAction<Self>>(name: impl AsRef<str>) -> anyhow::Result<()> {
+        Self::action_registry().write().unwrap().register::<A>(name)
+    }
+}
+
+/// 一个通过 [`ActionRegistry`] 注册的操作
+///
+/// 序列化格式为 `{ <name> = <action> }`，与 ActionSet 中的其他操作一致
+pub struct RegisteredAction<T> {
+    name: String,
+    action: Box<dyn ErasedAction<T>>,
+}
+
+impl<T> RegisteredAction<T> {
+    pub fn new<A: RegistrableAction<T>>(name: impl AsRef<str>, action: A) -> Self {
+        Self {
+            name: name.as_ref().to_string(),
+            action: Box::new(action),
+        }
+    }
+
+    /// 注册时使用的名称
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<T> Debug for RegisteredAction<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({:?})", self.name, self.action)
+    }
+}
+
+impl<T> Clone for RegisteredAction<T> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            action: self.action.clone_box(),
+        }
+    }
+}
+
+impl<T> TaskRecipe<T> for RegisteredAction<T> {
+    type Res = Value;
+    fn run(&self, runner: &T) -> anyhow::Result<Self::Res> {
+        self.action.run(runner)
+    }
+}
+
+impl<T> ActionRefs for RegisteredAction<T> {
+    fn templates(&self) -> Vec<String> {
+        self.action.refs().templates()
+    }
+    fn tasks(&self) -> Vec<String> {
+        self.action.refs().tasks()
+    }
+    fn scenes(&self) -> Vec<String> {
+        self.action.refs().scenes()
+    }
+    fn scripts(&self) -> Vec<String> {
+        self.action.refs().scripts()
+    }
+    fn avatars(&self) -> Vec<String> {
+        self.action.refs().avatars()
+    }
+}
+
+impl<T> Serialize for RegisteredAction<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = self.action.to_value().map_err(S::Error::custom)?;
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(&self.name, &value)?;
+        map.end()
+    }
+}
+
+impl<'de, T: ActionRegistryProvider> Deserialize<'de> for RegisteredAction<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = toml::Table::deserialize(deserializer)?;
+        if table.len() != 1 {
+            return Err(D::Error::custom("expected exactly one action"));
+        }
+        let (name, value) = table.into_iter().next().unwrap();
+        T::action_registry()
+            .read()
+            .unwrap()
+            .deserialize(name, value)
+            .map_err(|err| D::Error::custom(format!("{err:#}")))
+    }
+}
+
+impl<T: ActionRegistryProvider> JsonSchema for RegisteredAction<T> {
+    fn schema_name() -> String {
+        "RegisteredAction".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let registry = T::action_registry().read().unwrap();
+        let variants = registry
+            .entries
+            .iter()
+            .map(|(name, entry)| {
+                let mut schema = SchemaObject {
+                    instance_type: Some(InstanceType::Object.into()),
+                    ..Default::default()
+                };
+                let object = schema.object();
+                object.properties.insert(name.clone(), (entry.schema)(gen));
+                object.required.insert(name.clone());
+                object.additional_properties = Some(Box::new(false.into()));
+                schema.into()
+            })
+            .collect();
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                one_of: Some(variants),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::OnceLock;
+
+    use schemars::schema_for;
+
+    use super::*;
+
+    struct TestCore {
+        log: RwLock<Vec<String>>,
+    }
+
+    impl ActionRegistryProvider for TestCore {
+        fn action_registry() -> &'static RwLock<ActionRegistry<Self>> {
+            static REGISTRY: OnceLock<RwLock<ActionRegistry<TestCore>>> = OnceLock::new();
+            REGISTRY.get_or_init(|| {
+                let mut registry = ActionRegistry::new();
+                registry.register::<Echo>("Echo").unwrap();
+                RwLock::new(registry)
+            })
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+    struct Echo {
+        msg: String,
+    }
+
+    impl ActionRefs for Echo {
+        fn templates(&self) -> Vec<String> {
+            vec![format!("{}.png", self.msg)]
+        }
+    }
+
+    impl TaskRecipe<TestCore> for Echo {
+        type Res = ();
+        fn run(&self, runner: &TestCore) -> anyhow::Result<Self::Res> {
+            runner.log.write().unwrap().push(self.msg.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        assert!(TestCore::register_action::<Echo>("Echo").is_err());
+
+        let action: RegisteredAction<TestCore> =
+            toml::from_str(r#"Echo = { msg = "hello" }"#).unwrap();
+        assert_eq!(action.name(), "Echo");
+        assert_eq!(action.templates(), vec!["hello.png"]);
+        let core = TestCore {
+            log: RwLock::new(vec![]),
+        };
+        TaskRecipe::run(&action.clone(), &core).unwrap();
+        assert_eq!(*core.log.read().unwrap(), vec!["hello"]);
+
+        let toml = toml::to_string(&action).unwrap();
+        assert_eq!(toml.trim(), "[Echo]\nmsg = \"hello\"");
+
+        assert!(toml::from_str::<RegisteredAction<TestCore>>(r#"Unknown = {}"#).is_err());
+        assert!(toml::from_str::<RegisteredAction<TestCore>>(r#"Echo = { msg = 1 }"#).is_err());
+
+        let schema = serde_json::to_string(&schema_for!(RegisteredAction<TestCore>)).unwrap();
+        assert!(schema.contains("Echo"));
+    }
+}
@@ -0,0 +1,244 @@
+//! 重试策略
+//!
+//! [`super::TaskStep::retry`] 决定了一个步骤最多重试几次，而 [`RetryPolicy`] 决定了如何重试：
+//!
+//! ```toml
+//! [[steps]]
+//! retry = -1
+//!
+//! [steps.retry_policy]
+//! backoff = { Exponential = { initial_sec = 0.5, factor = 2.0, max_sec = 8.0, jitter = 0.2 } }
+//! max_duration_sec = 60.0
+//! on = ["TemplateNotFound"]
+//!
+//! [steps.action.ClickMatchTemplate]
+//! template = "confirm.png"
+//! ```
+//!
+//! Action 失败时可以通过 [`ActionError`] 标注失败的种类（[`ErrorKind`]），以便只对特定种类的错误进行重试。
+
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+/// Action 失败的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum ErrorKind {
+    /// 模板没有在画面中匹配到
+    TemplateNotFound,
+    /// 设备操作失败，如截图、点击失败
+    ControllerError,
+    /// 引用的任务、模板等资源不存在
+    ResourceNotFound,
+    /// 等待画面满足条件时超时，如模板一直没有消失、画面一直在变化
+    Timeout,
+    /// 其他错误
+    Other,
+}
+
+/// 带有 [`ErrorKind`] 的错误
+#[derive(Debug)]
+pub struct ActionError {
+    pub kind: ErrorKind,
+    pub msg: String,
+}
+
+impl ActionError {
+    pub fn new(kind: ErrorKind, msg: impl AsRef<str>) -> Self {
+        Self {
+            kind,
+            msg: msg.as_ref().to_string(),
+        }
+    }
+
+    /// 获取 `err` 的种类，若错误链中没有 [`ActionError`] 则为 [`ErrorKind::Other`]
+    pub fn kind_of(err: &anyhow::Error) -> ErrorKind {
+        err.chain()
+            .find_map(|err| err.downcast_ref::<ActionError>())
+            .map(|err| err.kind)
+            .unwrap_or(ErrorKind::Other)
+    }
+}
+
+impl Display for ActionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl Error for ActionError {}
+
+/// 两次尝试之间的等待时间
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum Backoff {
+    /// 固定等待指定秒数
+    Fixed(f32),
+    /// 指数退避，第 n 次重试前等待 `initial_sec * factor^n` 秒（不超过 `max_sec`），
+    /// 并在此基础上随机增减至多 `jitter` 比例的时间
+    Exponential {
+        initial_sec: f32,
+        factor: f32,
+        max_sec: Option<f32>,
+        jitter: Option<f32>,
+    },
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::Fixed(0.0)
+    }
+}
+
+impl Backoff {
+    /// 等待时间的上限（一天），未设置 `max_sec` 的指数退避很快就会超出 [`Duration`] 的范围
+    pub const MAX_DELAY_SEC: f32 = 86400.0;
+
+    /// 第 `n` 次重试（从 0 开始）前的等待时间，不超过 [`Backoff::MAX_DELAY_SEC`]
+    pub fn delay(&self, n: u32) -> Duration {
+        let sec = match self {
+            Backoff::Fixed(sec) => *sec,
+            Backoff::Exponential {
+                initial_sec,
+                factor,
+                max_sec,
+                jitter,
+            } => {
+                let sec = initial_sec * factor.powi(n as i32);
+                let sec = max_sec.map_or(sec, |max| sec.min(max));
+                let jitter = jitter.unwrap_or(0.0);
+                sec * (1.0 + jitter * (rand::random::<f32>() * 2.0 - 1.0))
+            }
+        };
+        if sec.is_nan() {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f32(sec.clamp(0.0, Self::MAX_DELAY_SEC))
+    }
+}
+
+/// 重试策略
+///
+/// - `backoff`: 两次尝试之间的等待时间，默认为不等待
+/// - `max_duration_sec`: 从第一次尝试开始的最长重试时间，超过后不再重试
+/// - `on`: 只对这些种类的错误进行重试，默认为所有错误
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct RetryPolicy {
+    #[serde(default)]
+    pub backoff: Backoff,
+    pub max_duration_sec: Option<f32>,
+    pub on: Option<Vec<ErrorKind>>,
+}
+
+impl RetryPolicy {
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn with_max_duration(mut self, sec: f32) -> Self {
+        self.max_duration_sec = Some(sec);
+        self
+    }
+
+    pub fn retry_on(mut self, kinds: Vec<ErrorKind>) -> Self {
+        self.on = Some(kinds);
+        self
+    }
+
+    /// 是否应对 `err` 进行重试
+    pub fn should_retry(&self, err: &anyhow::Error) -> bool {
+        self.on
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&ActionError::kind_of(err)))
+    }
+
+    /// 已经重试了 `elapsed` 时间，下一次重试需要等待 `delay` 时，是否还能重试
+    pub fn within_duration(&self, elapsed: Duration, delay: Duration) -> bool {
+        self.max_duration_sec
+            .is_none_or(|max| (elapsed + delay).as_secs_f32() <= max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff::Exponential {
+            initial_sec: 0.5,
+            factor: 2.0,
+            max_sec: Some(3.0),
+            jitter: None,
+        };
+        let delays = (0..4)
+            .map(|n| backoff.delay(n).as_secs_f32())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, vec![0.5, 1.0, 2.0, 3.0]);
+
+        let backoff = Backoff::Exponential {
+            initial_sec: 1.0,
+            factor: 1.0,
+            max_sec: None,
+            jitter: Some(0.5),
+        };
+        for n in 0..100 {
+            let delay = backoff.delay(n).as_secs_f32();
+            assert!((0.5..=1.5).contains(&delay));
+        }
+
+        // 没有上限的指数退避不会溢出
+        let backoff = Backoff::Exponential {
+            initial_sec: 1.0,
+            factor: 10.0,
+            max_sec: None,
+            jitter: None,
+        };
+        let max = Duration::from_secs_f32(Backoff::MAX_DELAY_SEC);
+        assert_eq!(backoff.delay(100), max);
+        assert_eq!(Backoff::Fixed(f32::INFINITY).delay(0), max);
+        assert_eq!(Backoff::Fixed(f32::NAN).delay(0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_should_retry() {
+        let policy = RetryPolicy::default().retry_on(vec![ErrorKind::TemplateNotFound]);
+        let not_found = anyhow::Error::new(ActionError::new(
+            ErrorKind::TemplateNotFound,
+            "failed to match a.png",
+        ));
+        let controller = anyhow::Error::new(ActionError::new(
+            ErrorKind::ControllerError,
+            "controller error",
+        ));
+        assert!(policy.should_retry(&not_found));
+        assert!(policy.should_retry(&not_found.context("running task a")));
+        assert!(!policy.should_retry(&controller));
+        assert!(!policy.should_retry(&anyhow::anyhow!("other")));
+        assert!(RetryPolicy::default().should_retry(&anyhow::anyhow!("other")));
+    }
+
+    #[test]
+    fn test_serde_retry_policy() {
+        let policy: RetryPolicy = toml::from_str(
+            r#"
+backoff = { Exponential = { initial_sec = 0.5, factor = 2.0, max_sec = 8.0, jitter = 0.2 } }
+max_duration_sec = 60.0
+on = ["TemplateNotFound"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(policy.max_duration_sec, Some(60.0));
+        assert_eq!(policy.on, Some(vec![ErrorKind::TemplateNotFound]));
+
+        let policy: RetryPolicy = toml::from_str("backoff = { Fixed = 1.0 }").unwrap();
+        assert_eq!(policy.backoff.delay(3), Duration::from_secs(1));
+    }
+}
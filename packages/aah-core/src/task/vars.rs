@@ -0,0 +1,219 @@
+//! 任务运行期间的变量
+//!
+//! 每一次运行 [`Task`](super::Task) 都有一个独立的 [`Vars`]。设置了 `output` 的步骤会将其操作的结果
+//! （如 `WaitForTemplate` 匹配到的位置）保存为同名变量，之后的步骤可以：
+//!
+//! - 在 `when` 中通过 Rhai 表达式判断是否执行此步骤，如 `when = "count > 3"`
+//! - 在操作的参数中通过 `${name}` 引用变量，如 `action.ClickText = { text = "${level}" }`，
+//!   可以通过 `${rect.x}` 访问变量的字段或数组元素，`$${` 表示字面的 `${`（如 Rhai 脚本中的字符串插值）
+//!
+//! 任务的 `output` 为作为任务结果的变量名，通过 `ByName` 运行此任务的步骤会得到这个结果。
+//!
+//! ```toml
+//! name = "count_sanity"
+//! output = "sanity"
+//!
+//! [[steps]]
+//! output = "sanity"
+//! action.Script = { source = "parse_int(ocr([[0.8, 0], [1, 0.1]])[0].text)" }
+//! ```
+
+use std::{collections::BTreeMap, sync::LazyLock};
+
+use anyhow::Context;
+use regex::{Captures, Regex};
+use rhai::{Engine, Scope};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+pub use serde_json::Value;
+
+/// `${name}` 或转义的 `$${name}`
+static TEMPLATE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\$(\$?)\{([^}]+)\}").unwrap());
+
+/// `value` 的字符串中是否有 `${name}` 或 `$${name}`，即是否需要 [`Vars::substitute`]
+pub fn has_templates(value: &Value) -> bool {
+    match value {
+        Value::String(s) => TEMPLATE.is_match(s),
+        Value::Array(array) => array.iter().any(has_templates),
+        Value::Object(map) => map.values().any(has_templates),
+        _ => false,
+    }
+}
+
+/// 整个字符串是否就是一个 `${name}`，若是则返回 `name`
+pub fn whole_template(s: &str) -> Option<&str> {
+    let caps = TEMPLATE.captures(s)?;
+    let whole = caps.get(0).unwrap().len() == s.len() && caps[1].is_empty();
+    whole.then(|| caps.get(2).unwrap().as_str().trim())
+}
+
+/// 一次任务运行中的变量
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Vars {
+    vars: BTreeMap<String, Value>,
+}
+
+impl Vars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 将 `value` 保存为变量 `name`
+    pub fn set(&mut self, name: impl AsRef<str>, value: impl Serialize) -> anyhow::Result<()> {
+        let value = to_value(value)?;
+        self.vars.insert(name.as_ref().to_string(), value);
+        Ok(())
+    }
+
+    /// 获取变量 `name` 并转换为 `V`，变量不存在时返回 `None`
+    pub fn get<V: DeserializeOwned>(&self, name: impl AsRef<str>) -> anyhow::Result<Option<V>> {
+        let name = name.as_ref();
+        self.vars
+            .get(name)
+            .map(|value| {
+                serde_json::from_value(value.clone())
+                    .with_context(|| format!("variable `{name}` has an unexpected type"))
+            })
+            .transpose()
+    }
+
+    /// 获取变量 `name` 的原始值
+    pub fn value(&self, name: impl AsRef<str>) -> Option<&Value> {
+        self.vars.get(name.as_ref())
+    }
+
+    /// 获取 `a.b.0` 形式的路径对应的值
+    fn lookup(&self, path: &str) -> anyhow::Result<&Value> {
+        let mut parts = path.split('.');
+        let name = parts.next().unwrap_or_default();
+        let mut value = self
+            .vars
+            .get(name)
+            .with_context(|| format!("undefined variable `{name}`"))?;
+        for part in parts {
+            value = match value {
+                Value::Object(map) => map.get(part),
+                Value::Array(array) => part.parse::<usize>().ok().and_then(|i| array.get(i)),
+                _ => None,
+            }
+            .with_context(|| format!("`{path}` does not exist"))?;
+        }
+        Ok(value)
+    }
+
+    /// 替换 `value` 中所有字符串里的 `${name}`，并将 `$${` 还原为 `${`
+    ///
+    /// 整个字符串就是一个 `${name}` 时替换为变量的值本身（保留数字、数组等类型），否则将变量插入字符串中
+    pub fn substitute(&self, value: &mut Value) -> anyhow::Result<()> {
+        match value {
+            Value::String(s) => {
+                if let Some(name) = whole_template(s) {
+                    *value = self.lookup(name)?.clone();
+                    return Ok(());
+                }
+                let mut err = None;
+                let replaced = TEMPLATE.replace_all(s, |caps: &Captures| {
+                    if !caps[1].is_empty() {
+                        return format!("${{{}}}", &caps[2]);
+                    }
+                    match self.lookup(caps[2].trim()) {
+                        Ok(Value::String(s)) => s.clone(),
+                        Ok(value) => value.to_string(),
+                        Err(e) => {
+                            err.get_or_insert(e);
+                            String::new()
+                        }
+                    }
+                });
+                if let Some(err) = err {
+                    return Err(err);
+                }
+                *s = replaced.into_owned();
+            }
+            Value::Array(array) => {
+                for value in array {
+                    self.substitute(value)?;
+                }
+            }
+            Value::Object(map) => {
+                for value in map.values_mut() {
+                    self.substitute(value)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// 以所有变量为作用域求值 Rhai 表达式 `expr`，结果需要为 `bool`
+    pub fn eval_condition(&self, expr: &str) -> anyhow::Result<bool> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(100_000);
+        let mut scope = Scope::new();
+        for (name, value) in &self.vars {
+            let value = rhai::serde::to_dynamic(value)
+                .map_err(|err| anyhow::anyhow!("failed to convert variable `{name}`: {err}"))?;
+            scope.push_dynamic(name.as_str(), value);
+        }
+        engine
+            .eval_expression_with_scope::<bool>(&mut scope, expr)
+            .map_err(|err| anyhow::anyhow!("failed to evaluate `{expr}`: {err}"))
+    }
+}
+
+/// 将操作的结果转换为变量的值
+pub fn to_value(res: impl Serialize) -> anyhow::Result<Value> {
+    serde_json::to_value(res).context("failed to convert the result to a variable")
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_vars() {
+        let mut vars = Vars::new();
+        vars.set("count", 5).unwrap();
+        vars.set("level", "CE-5").unwrap();
+        vars.set("rect", json!({ "x": 10, "y": 20 })).unwrap();
+        vars.set("none", ()).unwrap();
+
+        assert_eq!(vars.get::<u32>("count").unwrap(), Some(5));
+        assert_eq!(vars.get::<u32>("missing").unwrap(), None);
+        assert!(vars.get::<u32>("level").is_err());
+
+        let mut value = json!({
+            "text": "${level}",
+            "p1": ["${rect.x}", "${ rect.y }"],
+            "desc": "clicked ${level} ${count} times",
+            "plain": "$level {count}",
+            "escaped": "`$${count}` is ${count}",
+            "whole": "$${count}",
+        });
+        assert!(has_templates(&value));
+        assert!(!has_templates(&json!({ "plain": "$level {count}" })));
+        vars.substitute(&mut value).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "text": "CE-5",
+                "p1": [10, 20],
+                "desc": "clicked CE-5 5 times",
+                "plain": "$level {count}",
+                "escaped": "`${count}` is 5",
+                "whole": "${count}",
+            })
+        );
+        assert!(vars.substitute(&mut json!("${missing}")).is_err());
+        assert!(vars.substitute(&mut json!("a ${rect.z}")).is_err());
+
+        assert!(vars.eval_condition("count > 3").unwrap());
+        assert!(!vars.eval_condition(r#"level == "CE-6""#).unwrap());
+        assert!(vars.eval_condition("rect.x + rect.y == 30").unwrap());
+        assert!(vars.eval_condition("none == ()").unwrap());
+        assert!(vars.eval_condition("missing > 3").is_err());
+        assert!(vars.eval_condition("count").is_err());
+    }
+}
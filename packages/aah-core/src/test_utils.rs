@@ -0,0 +1,144 @@
+//! 测试共用的资源、截图与 [`Core`]
+
+use std::path::PathBuf;
+
+use image::DynamicImage;
+
+#[cfg(feature = "android")]
+pub use android_core::*;
+
+/// 仓库中的 `old-resources` 目录，测试使用的模板与截图都在这里
+pub fn old_resources() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../old-resources")
+}
+
+/// `old-resources` 中 MUMU 模拟器的截图 `name`
+///
+/// 缩小为 640x360 以加快匹配，模板会按照截图的高度缩放
+pub fn screenshot(name: &str) -> DynamicImage {
+    image::open(old_resources().join("templates/MUMU-1920x1080").join(name))
+        .unwrap()
+        .resize_exact(640, 360, image::imageops::FilterType::Triangle)
+}
+
+/// 确定的噪声（线性同余生成器），值在 `[0.0, 1.0)` 之间
+pub fn noise(mut seed: u32) -> impl FnMut() -> f32 {
+    move || {
+        seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+        (seed >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+#[cfg(feature = "android")]
+mod android_core {
+    use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+    use aah_controller::{
+        clock::{Clock, VirtualClock},
+        dry_run::DryRunController,
+    };
+    use image::DynamicImage;
+    use time::OffsetDateTime;
+
+    use crate::{
+        android::actions::ActionSet,
+        report::Reporter,
+        resource::{
+            manifest::{
+                interrupt::InterruptConfig, navigate::NavigateConfig, task::TaskConfig, Manifest,
+            },
+            template_store::TemplateStore,
+            GeneralAahResource,
+        },
+        task::Task,
+        Core,
+    };
+
+    /// 以 `root` 为资源目录，只包含任务 `tasks` 的资源
+    pub fn resource(root: PathBuf, tasks: Vec<Task<ActionSet>>) -> GeneralAahResource<ActionSet> {
+        GeneralAahResource {
+            templates: TemplateStore::new(&root),
+            root,
+            manifest: Manifest {
+                last_updated: OffsetDateTime::UNIX_EPOCH,
+            },
+            task_config: TaskConfig(
+                tasks
+                    .into_iter()
+                    .map(|task| (task.name.clone(), task))
+                    .collect(),
+                HashMap::new(),
+            ),
+            navigate_config: NavigateConfig::default(),
+            interrupt_config: InterruptConfig::default(),
+        }
+    }
+
+    /// 使用虚拟时钟的 [`Core`]，默认不处于 dry-run 模式
+    pub struct TestCore<C = DryRunController> {
+        pub controller: C,
+        pub resource: GeneralAahResource<ActionSet>,
+        pub clock: Arc<VirtualClock>,
+        dry_run: bool,
+        reporter: Option<Reporter>,
+    }
+
+    impl TestCore {
+        /// 使用 [`DryRunController`] 记录操作，截图总是 `screen`
+        pub fn new(screen: DynamicImage, resource: GeneralAahResource<ActionSet>) -> Self {
+            Self::with_controller(DryRunController::with_screenshot(screen), resource)
+        }
+
+        /// 记录下的所有操作
+        pub fn ops(&self) -> Vec<String> {
+            self.controller
+                .planned_ops()
+                .iter()
+                .map(|op| op.to_string())
+                .collect()
+        }
+    }
+
+    impl<C> TestCore<C> {
+        pub fn with_controller(controller: C, resource: GeneralAahResource<ActionSet>) -> Self {
+            Self {
+                controller,
+                resource,
+                clock: Arc::new(VirtualClock::new()),
+                dry_run: false,
+                reporter: None,
+            }
+        }
+
+        pub fn with_dry_run(mut self) -> Self {
+            self.dry_run = true;
+            self
+        }
+
+        /// 记录运行报告
+        pub fn with_reporter(mut self) -> Self {
+            self.reporter = Some(Reporter::new());
+            self
+        }
+    }
+
+    impl<C> Core for TestCore<C> {
+        type Controller = C;
+        type Resource = GeneralAahResource<ActionSet>;
+        fn resource(&self) -> &Self::Resource {
+            &self.resource
+        }
+        fn controller(&self) -> &Self::Controller {
+            &self.controller
+        }
+        fn dry_run(&self) -> bool {
+            self.dry_run
+        }
+        fn reporter(&self) -> Option<&Reporter> {
+            self.reporter.as_ref()
+        }
+        fn clock(&self) -> Arc<dyn Clock> {
+            self.clock.clone()
+        }
+    }
+}
@@ -57,19 +57,28 @@ pub fn init_logger() {
 
 pub mod resource {
     //! resource related utils
-    use std::{fs, path::Path};
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
 
     use image::DynamicImage;
 
+    /// 获取模板在 `{res_path}/resources/templates/1920x1080` 目录中的路径
+    pub fn template_path(template_path: impl AsRef<Path>, res_dir: impl AsRef<Path>) -> PathBuf {
+        res_dir
+            .as_ref()
+            .join("templates")
+            .join("1920x1080")
+            .join(template_path)
+    }
+
     /// 从 `{res_path}/resources/templates/1920x1080` 目录中根据文件名称获取模板
     pub fn get_template(
         template_path: impl AsRef<Path>,
         res_dir: impl AsRef<Path>,
     ) -> anyhow::Result<DynamicImage> {
-        let filename = template_path.as_ref();
-        let res_dir = res_dir.as_ref();
-
-        let path = res_dir.join("templates").join("1920x1080").join(filename);
+        let path = self::template_path(template_path, res_dir);
         let image =
             image::open(path).map_err(|err| anyhow::anyhow!("template not found: {err}"))?;
         Ok(image)
@@ -123,6 +132,12 @@ pub mod resource {
         Ok(oper_images)
     }
 
+    /// 干员 `oper`（内部名称，如 `char_151_myrtle`）的头像所在的目录 `<res_dir>/avatars/char/<编号>`
+    pub fn oper_avatar_dir(oper: &str, res_dir: &Path) -> Option<PathBuf> {
+        let num = oper.split('_').nth(1)?;
+        Some(res_dir.join("avatars").join("char").join(num))
+    }
+
     /// 从 `<res_dir>/avatars/` 获取指定角色的所有头像图片
     pub fn get_oper_avatars<S: AsRef<str>, P: AsRef<Path>>(
         oper: S,
@@ -132,8 +147,7 @@ pub mod resource {
         let res_dir = res_dir.as_ref();
 
         // println!("{:?}", oper);
-        let components = oper.split("_").collect::<Vec<&str>>();
-        let path = res_dir.join("avatars").join("char").join(components[1]);
+        let path = oper_avatar_dir(oper, res_dir).ok_or(format!("invalid operator: {oper}"))?;
         // println!("{:?}", path);
 
         let mut images = vec![];
@@ -1,29 +1,303 @@
-use std::ops::RangeInclusive;
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+};
 
-use aah_cv::template_matching::MatchTemplateMethod;
-use image::DynamicImage;
+use aah_controller::DEFAULT_HEIGHT;
+use aah_cv::template_matching::{MatchTemplateMethod, NmsOptions};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Luma};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 
-use crate::vision::utils::{binarize_image, mask_image};
+use crate::{
+    resource::template_store::{LumaTemplate, TemplateStore},
+    vision::{
+        matcher::{
+            single_matcher::{SingleMatcher, SingleMatcherResult},
+            Channels,
+        },
+        utils::{binarize_image, mask_image},
+    },
+};
 
 /// The generic options for matching
-#[derive(Serialize, Deserialize)]
+///
+/// 所有字段都有默认值，在资源文件中只需要写出需要修改的字段
 #[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
 pub struct MatchOptions {
     /// The matching method
+    #[schemars(with = "Option<MatchTemplateMethodDef>")]
     pub(crate) method: Option<MatchTemplateMethod>,
     /// The matching threshold
     pub(crate) threshold: Option<f32>,
     /// Whether should use cached img first
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
     pub(crate) use_cache: bool,
 
     /// Color mask
+    #[serde(skip_serializing_if = "is_full_color_mask")]
     pub(crate) color_mask: (RangeInclusive<u8>, RangeInclusive<u8>, RangeInclusive<u8>),
     /// Binarization threshold
     pub(crate) binarize_threshold: Option<u8>,
     /// Region of interest represented by top-left and bottom-right pos in [0.0, 1.0]
+    #[serde(skip_serializing_if = "is_full_roi")]
     pub(crate) roi: [(f32, f32); 2], // topleft and bottomright
+    /// 多尺度匹配，不设置时只按照截图的高度缩放模板，见 [`ScaleSearch`]
+    pub(crate) scale: Option<ScaleSearch>,
+    /// 模板的遮罩，不设置时模板的所有像素都参与匹配，见 [`TemplateMask`]
+    pub(crate) mask: Option<TemplateMask>,
+    /// 彩色匹配，不设置时只匹配灰度图，见 [`ColorMatch`]
+    pub(crate) color: Option<ColorMatch>,
+    /// 多目标匹配的非极大值抑制，不设置时匹配结果互不重叠，见 [`NmsOptions`]
+    ///
+    /// 只用于多目标匹配，单目标匹配时设置会返回错误
+    #[schemars(with = "Option<NmsOptionsDef>")]
+    #[serde(deserialize_with = "deserialize_nms")]
+    pub(crate) nms: Option<NmsOptions>,
+}
+
+/// 彩色匹配的选项
+///
+/// 默认只匹配灰度图，只有颜色不同的模板会相互匹配，如技能就绪的高亮、红色与蓝色的按钮、可用与不可用的部署卡片。
+/// 设置后在各个通道上分别匹配，结果为各个通道的匹配值的加权平均，阈值的含义与灰度匹配相同。
+/// 每个通道都是一次单独的模板匹配（GPU 上也是逐个通道提交与读回），耗时约为灰度匹配的通道数倍
+///
+/// ```toml
+/// color = { space = "hsv", weights = [1.0, 1.0, 0.5] }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ColorMatch {
+    /// 颜色空间，默认为 `rgb`
+    pub space: ColorSpace,
+    /// 各个通道的权重，默认为 `[1.0, 1.0, 1.0]`，为 0 的通道不参与匹配，不能为负数且不能都为 0
+    #[serde(deserialize_with = "deserialize_weights")]
+    pub weights: [f32; 3],
+}
+
+impl ColorMatch {
+    /// 检查权重，见 [`ColorMatch::weights`]
+    pub fn check_weights(weights: &[f32; 3]) -> Result<(), String> {
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+            return Err(format!(
+                "color weights should be non-negative numbers: {weights:?}"
+            ));
+        }
+        if weights.iter().all(|w| *w == 0.0) {
+            return Err("at least one color weight should be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// [`ColorSpace::split`] 得到的各个通道的权重，色相的权重平分给其 `cos`、`sin` 两个通道
+    pub fn channel_weights(&self) -> Vec<f32> {
+        let [a, b, c] = self.weights;
+        match self.space {
+            ColorSpace::Rgb => vec![a, b, c],
+            ColorSpace::Hsv => vec![a / 2.0, a / 2.0, b, c],
+        }
+    }
+}
+
+fn deserialize_weights<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<[f32; 3], D::Error> {
+    let weights = <[f32; 3]>::deserialize(deserializer)?;
+    ColorMatch::check_weights(&weights).map_err(serde::de::Error::custom)?;
+    Ok(weights)
+}
+
+/// 检查 [`NmsOptions::iou_threshold`]，应在 `[0.0, 1.0]` 之间
+fn check_nms(nms: &NmsOptions) -> Result<(), String> {
+    if !(0.0..=1.0).contains(&nms.iou_threshold) {
+        return Err(format!(
+            "nms iou_threshold should be in [0.0, 1.0]: {}",
+            nms.iou_threshold
+        ));
+    }
+    Ok(())
+}
+
+fn deserialize_nms<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<NmsOptions>, D::Error> {
+    let nms = Option::<NmsOptions>::deserialize(deserializer)?;
+    if let Some(nms) = &nms {
+        check_nms(nms).map_err(serde::de::Error::custom)?;
+    }
+    Ok(nms)
+}
+
+impl Default for ColorMatch {
+    fn default() -> Self {
+        Self {
+            space: ColorSpace::Rgb,
+            weights: [1.0, 1.0, 1.0],
+        }
+    }
+}
+
+/// 彩色匹配的颜色空间，各个通道的值都在 `[0.0, 1.0]` 之间
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    /// R、G、B 三个通道
+    #[default]
+    Rgb,
+    /// H、S、V 三个通道
+    ///
+    /// 色相是环形的（0° 与 360° 都是红色），因此拆分为 `cos`、`sin` 两个通道，并乘以饱和度，
+    /// 使饱和度很低（色相没有意义）的像素在这两个通道上都接近 0.5
+    Hsv,
+}
+
+impl ColorSpace {
+    /// 将 `image` 拆分为各个通道，RGB 为 3 个，HSV 为 4 个（见 [`ColorSpace::Hsv`]）
+    pub fn split(&self, image: &DynamicImage) -> Vec<ImageBuffer<Luma<f32>, Vec<f32>>> {
+        let image = image.to_rgb32f();
+        let pixels = image
+            .pixels()
+            .map(|pixel| {
+                let [r, g, b] = pixel.0;
+                match self {
+                    ColorSpace::Rgb => vec![r, g, b],
+                    ColorSpace::Hsv => {
+                        let [h, s, v] = rgb_to_hsv(r, g, b);
+                        let (sin, cos) = (h * std::f32::consts::TAU).sin_cos();
+                        vec![(1.0 + s * cos) / 2.0, (1.0 + s * sin) / 2.0, s, v]
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        let channels = pixels.first().map_or(0, Vec::len);
+        (0..channels)
+            .map(|c| {
+                let raw = pixels.iter().map(|pixel| pixel[c]).collect();
+                ImageBuffer::from_raw(image.width(), image.height(), raw).unwrap()
+            })
+            .collect()
+    }
+}
+
+/// `[0.0, 1.0]` 的 RGB 转换为 `[0.0, 1.0]` 的 HSV
+fn rgb_to_hsv(r: f32, g: f32, b: f32) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    [hue / 6.0, saturation, max]
+}
+
+/// 模板的遮罩，遮罩为 0 的像素不参与匹配
+///
+/// 用于不规则的图标等模板，使其透明的背景不影响匹配结果，
+/// 支持 SumOfSquaredDifference(Normed)、CrossCorrelation(Normed) 与 CorrelationCoefficient(Normed)
+///
+/// ```toml
+/// mask = "alpha"
+/// # 或
+/// mask = { image = "battle_deploy-card-cost-icon_mask.png" }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TemplateMask {
+    /// 使用模板的 alpha 通道
+    Alpha,
+    /// 使用 `templates` 下与模板大小相同的遮罩图片，按灰度计算权重，白色的像素参与匹配
+    Image(String),
+}
+
+/// 多尺度匹配的搜索范围
+///
+/// 平板、带鱼屏等设备上的界面元素与截图高度的比例可能不同，或存在少许的缩放，
+/// 此时在按截图高度缩放的基础上，先以 `step` 在 `range` 中粗搜索，再以 `step / 4` 在最优的尺度附近细搜索。
+/// 粗搜索在缩小的截图上进行，见 [`MatchOptions::search_scale`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ScaleSearch {
+    /// 相对于按截图高度缩放后的模板的缩放范围，默认为 `[0.8, 1.2]`
+    pub range: (f32, f32),
+    /// 粗搜索的步长，默认为 0.05
+    pub step: f32,
+}
+
+impl Default for ScaleSearch {
+    fn default() -> Self {
+        Self {
+            range: (0.8, 1.2),
+            step: 0.05,
+        }
+    }
+}
+
+/// 多尺度匹配时粗搜索的截图缩小的倍数
+const COARSE_LEVEL: f32 = 0.5;
+/// 缩小后模板的短边小于此值时，粗搜索不缩小截图
+const COARSE_MIN_SIZE: f32 = 16.0;
+
+/// 每台设备上各个模板多尺度匹配成功的尺度
+///
+/// 之后的匹配会先尝试记住的尺度，匹配失败时才重新搜索
+#[derive(Debug, Clone, Default)]
+pub struct ScaleMemory(Arc<Mutex<HashMap<String, f32>>>);
+
+impl ScaleMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, template: &str) -> Option<f32> {
+        self.0.lock().unwrap().get(template).copied()
+    }
+
+    pub fn remember(&self, template: &str, scale: f32) {
+        self.0.lock().unwrap().insert(template.to_string(), scale);
+    }
+}
+
+// 默认值不序列化，使任务文件中只出现修改过的选项
+fn is_full_color_mask(mask: &(RangeInclusive<u8>, RangeInclusive<u8>, RangeInclusive<u8>)) -> bool {
+    *mask == (0..=255, 0..=255, 0..=255)
+}
+
+fn is_full_roi(roi: &[(f32, f32); 2]) -> bool {
+    *roi == [(0.0, 0.0), (1.0, 1.0)]
+}
+
+/// [`MatchTemplateMethod`] 的 Schema
+#[allow(unused)]
+#[derive(JsonSchema)]
+#[schemars(rename = "MatchTemplateMethod")]
+enum MatchTemplateMethodDef {
+    SumOfSquaredDifference,
+    SumOfSquaredDifferenceNormed,
+    CrossCorrelation,
+    CrossCorrelationNormed,
+    CorrelationCoefficient,
+    CorrelationCoefficientNormed,
+}
+
+/// [`NmsOptions`] 的 Schema
+#[allow(unused)]
+#[derive(JsonSchema)]
+#[schemars(rename = "NmsOptions")]
+struct NmsOptionsDef {
+    /// 与得分更优的结果的 IoU 大于此值的结果会被抑制，在 `[0.0, 1.0]` 之间，默认为 0.0，即结果互不重叠
+    iou_threshold: Option<f32>,
+    /// 最多保留的结果数量，默认保留所有结果
+    top_k: Option<usize>,
 }
 
 impl MatchOptions {
@@ -61,6 +335,29 @@ impl MatchOptions {
         self.roi = [tl, br];
         self
     }
+
+    pub fn with_scale_search(mut self, range: (f32, f32), step: f32) -> Self {
+        self.scale = Some(ScaleSearch { range, step });
+        self
+    }
+
+    pub fn with_mask(mut self, mask: TemplateMask) -> Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    pub fn with_color(mut self, space: ColorSpace, weights: [f32; 3]) -> Self {
+        self.color = Some(ColorMatch { space, weights });
+        self
+    }
+
+    pub fn with_nms(mut self, iou_threshold: f32, top_k: Option<usize>) -> Self {
+        self.nms = Some(NmsOptions {
+            iou_threshold,
+            top_k,
+        });
+        self
+    }
 }
 
 impl Default for MatchOptions {
@@ -72,6 +369,10 @@ impl Default for MatchOptions {
             color_mask: (0..=255, 0..=255, 0..=255),
             binarize_threshold: None,
             roi: [(0.0, 0.0), (1.0, 1.0)],
+            scale: None,
+            mask: None,
+            color: None,
+            nms: None,
         }
     }
 }
@@ -121,4 +422,245 @@ impl MatchOptions {
         // template.save("./binarized_template.png").unwrap();
         (image, template)
     }
+
+    /// [`MatchOptions::preprocess`] 是否会改变模板，不会时可以直接使用 [`TemplateStore::luma`] 缓存的灰度图
+    pub(crate) fn preprocesses_template(&self) -> bool {
+        !is_full_color_mask(&self.color_mask) || self.binarize_threshold.is_some()
+    }
+}
+
+/// 将 1920x1080 下的 `template` 按截图的高度 `image_height` 缩放，再缩放 `scale` 倍
+pub(crate) fn scale_template(
+    template: &DynamicImage,
+    image_height: u32,
+    scale: f32,
+) -> DynamicImage {
+    let scale_factor = image_height as f32 / DEFAULT_HEIGHT as f32 * scale;
+    if scale_factor == 1.0 {
+        return template.clone();
+    }
+    let new_width = (template.width() as f32 * scale_factor) as u32;
+    let new_height = (template.height() as f32 * scale_factor) as u32;
+    DynamicImage::ImageRgba8(image::imageops::resize(
+        template,
+        new_width,
+        new_height,
+        image::imageops::FilterType::Lanczos3,
+    ))
+}
+
+/// 按尺度获取缩放后的 [`TemplateMask::Image`] 遮罩图片，见 [`MatchOptions::scaled_mask_image`]
+pub type MaskImage<'a> = dyn Fn(f32) -> anyhow::Result<Option<Arc<LumaTemplate>>> + 'a;
+
+/// 模板的 alpha 通道，在 `[0.0, 1.0]` 之间
+pub(crate) fn alpha_mask(template: &DynamicImage) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    ImageBuffer::from_fn(template.width(), template.height(), |x, y| {
+        Luma([template.get_pixel(x, y).0[3] as f32 / 255.0])
+    })
+}
+
+impl MatchOptions {
+    /// 检查通过代码（而不是反序列化）设置的选项
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        if let Some(color) = &self.color {
+            ColorMatch::check_weights(&color.weights).map_err(anyhow::Error::msg)?;
+        }
+        if let Some(nms) = &self.nms {
+            check_nms(nms).map_err(anyhow::Error::msg)?;
+        }
+        Ok(())
+    }
+
+    /// 选项引用的模板文件，即 [`TemplateMask::Image`] 的遮罩图片
+    pub(crate) fn templates(&self) -> Vec<String> {
+        match &self.mask {
+            Some(TemplateMask::Image(name)) => vec![name.clone()],
+            _ => vec![],
+        }
+    }
+
+    /// [`TemplateMask::Image`] 的遮罩图片按截图高度 `height` 缩放后再缩放 `scale` 倍的灰度图
+    ///
+    /// 优先使用已经加载好的 `mask`，否则从 `store` 中获取，缩放后的遮罩与缩放后的模板一同缓存在 `store` 中
+    pub(crate) fn scaled_mask_image(
+        &self,
+        template: &DynamicImage,
+        mask: Option<&DynamicImage>,
+        store: Option<&TemplateStore>,
+        height: u32,
+        scale: f32,
+    ) -> anyhow::Result<Option<Arc<LumaTemplate>>> {
+        let Some(TemplateMask::Image(name)) = &self.mask else {
+            return Ok(None);
+        };
+        let check = |size: (u32, u32)| {
+            anyhow::ensure!(
+                size == template.dimensions(),
+                "the size of mask {name} {size:?} differs from the template {:?}",
+                template.dimensions()
+            );
+            Ok(())
+        };
+        match (mask, store) {
+            (Some(mask), _) => {
+                check(mask.dimensions())?;
+                let mask = scale_template(mask, height, scale).to_luma32f();
+                Ok(Some(Arc::new(mask)))
+            }
+            (None, Some(store)) => {
+                check(store.get(name)?.dimensions())?;
+                store.luma(name, height, scale).map(Some)
+            }
+            (None, None) => anyhow::bail!("mask image {name} is not loaded"),
+        }
+    }
+
+    /// 缩放后的模板 `template` 的遮罩，`mask_image` 为 [`MatchOptions::scaled_mask_image`] 缩放后的遮罩图片
+    pub(crate) fn template_mask(
+        &self,
+        template: &DynamicImage,
+        mask_image: Option<&LumaTemplate>,
+    ) -> Option<LumaTemplate> {
+        match (&self.mask, mask_image) {
+            (Some(TemplateMask::Alpha), _) => Some(alpha_mask(template)),
+            (Some(TemplateMask::Image(_)), Some(mask)) => Some(mask.clone()),
+            _ => None,
+        }
+    }
+
+    /// 预处理后的 `image` 与 `template` 的各个通道，未设置 `color` 时为 `None`
+    pub(crate) fn channels(
+        &self,
+        image: &DynamicImage,
+        template: &DynamicImage,
+    ) -> Option<Channels> {
+        let color = self.color.as_ref()?;
+        Some(Channels {
+            image: color.space.split(image),
+            template: color.space.split(template),
+            weights: color.channel_weights(),
+        })
+    }
+
+    /// 以 `scale` 缩放模板，在缩小为原截图 `level` 倍的 `image` 中匹配，模板比匹配区域大时返回 `None`
+    ///
+    /// 模板与遮罩都按原截图的高度 `height` 缩放 `scale * level` 倍，使其与缩小后的截图一致
+    fn match_at(
+        &self,
+        image: &DynamicImage,
+        height: u32,
+        level: f32,
+        template: &DynamicImage,
+        mask_image: &MaskImage,
+        scale: f32,
+    ) -> anyhow::Result<Option<SingleMatcherResult>> {
+        let template = scale_template(template, height, scale * level);
+        let [tl, br] = self.calc_roi(image);
+        if template.width() == 0
+            || template.height() == 0
+            || template.width() > br.0 - tl.0
+            || template.height() > br.1 - tl.1
+        {
+            return Ok(None);
+        }
+        let mask = self.template_mask(&template, mask_image(scale * level)?.as_deref());
+        let (image, template) = self.preprocess(image, &template);
+        let channels = self.channels(&image, &template);
+        SingleMatcher::Template {
+            image: image.to_luma32f(),
+            template: template.to_luma32f(),
+            mask,
+            channels,
+            method: self.method,
+            threshold: self.threshold,
+        }
+        .result()
+        .map(Some)
+    }
+
+    /// 在 `image` 中搜索 `template` 最优的尺度（相对于按截图高度缩放后的模板），
+    /// 返回该尺度与其在原截图上的匹配结果，未设置 `scale` 时为 `(1.0, None)`
+    ///
+    /// 若 `remembered` 的尺度可以匹配成功，则直接使用它，`mask_image` 按尺度获取缩放后的遮罩图片，
+    /// 见 [`MatchOptions::scaled_mask_image`]。
+    /// 粗搜索在缩小一半的截图上进行（缩小后模板过小时除外），细搜索在原截图上进行
+    pub fn search_scale(
+        &self,
+        image: &DynamicImage,
+        template: &DynamicImage,
+        mask_image: &MaskImage,
+        remembered: Option<f32>,
+    ) -> anyhow::Result<(f32, Option<SingleMatcherResult>)> {
+        let Some(search) = &self.scale else {
+            return Ok((1.0, None));
+        };
+        let height = image.height();
+        if let Some(scale) = remembered {
+            let res = self.match_at(image, height, 1.0, template, mask_image, scale)?;
+            if let Some(res) = res.filter(|res| res.rect.is_some()) {
+                return Ok((scale, Some(res)));
+            }
+        }
+
+        // 不同尺度的模板大小不同，未归一化的方法需要按面积换算后才能比较
+        let method = self
+            .method
+            .unwrap_or(MatchTemplateMethod::SumOfSquaredDifference);
+        let rank = |res: &SingleMatcherResult| {
+            let area = (res.best_rect.width * res.best_rect.height) as f32;
+            match method {
+                MatchTemplateMethod::SumOfSquaredDifference => -res.score / area,
+                MatchTemplateMethod::SumOfSquaredDifferenceNormed => -res.score,
+                MatchTemplateMethod::CrossCorrelationNormed
+                | MatchTemplateMethod::CorrelationCoefficientNormed => res.score,
+                MatchTemplateMethod::CrossCorrelation
+                | MatchTemplateMethod::CorrelationCoefficient => res.score / area,
+            }
+        };
+        let best = |image: &DynamicImage, level: f32, scales: Vec<f32>| {
+            let mut best: Option<(f32, f32, SingleMatcherResult)> = None;
+            for scale in scales {
+                let Some(res) = self.match_at(image, height, level, template, mask_image, scale)?
+                else {
+                    continue;
+                };
+                let rank = rank(&res);
+                if best.as_ref().is_none_or(|(_, best, _)| rank > *best) {
+                    best = Some((scale, rank, res));
+                }
+            }
+            anyhow::Ok(best)
+        };
+
+        let (min, max) = search.range;
+        let step = search.step.max(0.001);
+        let steps = ((max - min) / step).round() as u32;
+        let coarse = (0..=steps)
+            .map(|i| min + step * i as f32)
+            .collect::<Vec<_>>();
+        let short_side = template.width().min(template.height()) as f32 * height as f32
+            / DEFAULT_HEIGHT as f32
+            * min;
+        let coarse = if short_side * COARSE_LEVEL >= COARSE_MIN_SIZE {
+            let small = image.resize_exact(
+                (image.width() as f32 * COARSE_LEVEL) as u32,
+                (height as f32 * COARSE_LEVEL) as u32,
+                image::imageops::FilterType::Triangle,
+            );
+            best(&small, COARSE_LEVEL, coarse)?
+        } else {
+            best(image, 1.0, coarse)?
+        };
+        let Some((scale, _, _)) = coarse else {
+            return Ok((1.0, None));
+        };
+        let fine = (-3..=3)
+            .map(|i| scale + step / 4.0 * i as f32)
+            .filter(|scale| (min..=max).contains(scale))
+            .collect::<Vec<_>>();
+        Ok(match best(image, 1.0, fine)? {
+            Some((scale, _, res)) => (scale, Some(res)),
+            None => (1.0, None),
+        })
+    }
 }
@@ -1,9 +1,10 @@
-use std::path::Path;
+use std::{path::Path, sync::Arc};
 
 use aah_cv::template_matching::MatchTemplateMethod;
 use image::DynamicImage;
 
 use crate::{
+    resource::{template_store::TemplateStore, ResRoot},
     utils::{resource::get_template, LazyImage},
     vision::{
         matcher::multi_matcher::{MultiMatcher, MultiMatcherResult},
@@ -11,27 +12,67 @@ use crate::{
     },
     CachedScreenCapper,
 };
-use aah_controller::DEFAULT_HEIGHT;
 
-use super::{matching::MatchOptions, Analyzer};
+use super::{
+    matching::{scale_template, MatchOptions, ScaleMemory},
+    Analyzer,
+};
 
 pub struct MultiMatchAnalyzerOutput {
     pub screen: Box<DynamicImage>,
     pub res: MultiMatcherResult,
+    /// 匹配时模板相对于按截图高度缩放后的尺度，见 [`ScaleSearch`](super::matching::ScaleSearch)
+    pub scale: f32,
     pub annotated_screen: Box<DynamicImage>,
 }
 
 pub struct MultiMatchAnalyzer {
-    template: DynamicImage,
+    template: Arc<DynamicImage>,
     options: MatchOptions,
+    memory: Option<(ScaleMemory, String)>,
+    store: Option<(TemplateStore, String)>,
+    mask: Option<Arc<DynamicImage>>,
 }
 
 impl MultiMatchAnalyzer {
     pub fn new(res_dir: impl AsRef<Path>, template_path: impl AsRef<Path>) -> Self {
         let template = get_template(template_path, res_dir).unwrap();
         Self {
-            template,
+            template: Arc::new(template),
+            options: Default::default(),
+            memory: None,
+            store: None,
+            mask: None,
+        }
+    }
+
+    /// 使用已经加载好的模板
+    pub fn from_template(template: DynamicImage) -> Self {
+        Self {
+            template: Arc::new(template),
             options: Default::default(),
+            memory: None,
+            store: None,
+            mask: None,
+        }
+    }
+
+    /// 使用 `store` 中的模板 `template`，匹配时复用缓存的缩放后的模板
+    pub fn from_store(store: &TemplateStore, template: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            template: store.get(template)?,
+            options: Default::default(),
+            memory: None,
+            store: Some((store.clone(), template.to_string())),
+            mask: None,
+        })
+    }
+
+    /// 加载 `res` 中的模板 `template`，`res` 有模板缓存时使用缓存，见 [`ResRoot::template_store`]
+    pub fn from_resource(res: &impl ResRoot, template: &str) -> anyhow::Result<Self> {
+        match res.template_store() {
+            Some(store) => Self::from_store(store, template),
+            None => Ok(Self::from_template(get_template(template, res.res_root())?)),
         }
     }
 
@@ -40,34 +81,66 @@ impl MultiMatchAnalyzer {
         self
     }
 
-    pub fn analyze_image(&self, image: &DynamicImage) -> anyhow::Result<MultiMatchAnalyzerOutput> {
-        // Scaling
-        let template = if image.height() != DEFAULT_HEIGHT {
-            let scale_factor = image.height() as f32 / DEFAULT_HEIGHT as f32;
+    /// 使用已经加载好的 [`TemplateMask::Image`](super::matching::TemplateMask::Image) 遮罩图片，而不是从模板缓存中加载
+    pub fn with_mask_image(mut self, mask: DynamicImage) -> Self {
+        self.mask = Some(Arc::new(mask));
+        self
+    }
+
+    /// 多尺度匹配时先尝试 `memory` 中以 `key` 记住的尺度，匹配成功后记住此次的尺度
+    pub fn remember_scale(mut self, memory: Option<ScaleMemory>, key: impl AsRef<str>) -> Self {
+        self.memory = memory.map(|memory| (memory, key.as_ref().to_string()));
+        self
+    }
 
-            let new_width = (self.template.width() as f32 * scale_factor) as u32;
-            let new_height = (self.template.height() as f32 * scale_factor) as u32;
+    pub fn analyze_image(&self, image: &DynamicImage) -> anyhow::Result<MultiMatchAnalyzerOutput> {
+        self.options.validate()?;
 
-            DynamicImage::ImageRgba8(image::imageops::resize(
+        // Scaling
+        let remembered = self
+            .memory
+            .as_ref()
+            .and_then(|(memory, key)| memory.get(key));
+        let mask_image = |scale| {
+            self.options.scaled_mask_image(
                 &self.template,
-                new_width,
-                new_height,
-                image::imageops::FilterType::Lanczos3,
-            ))
-        } else {
-            self.template.clone()
+                self.mask.as_deref(),
+                self.store.as_ref().map(|(store, _)| store),
+                image.height(),
+                scale,
+            )
+        };
+        let (scale, _) =
+            self.options
+                .search_scale(image, &self.template, &mask_image, remembered)?;
+        let template = match &self.store {
+            Some((store, name)) => store.scaled(name, image.height(), scale)?,
+            None => Arc::new(scale_template(&self.template, image.height(), scale)),
         };
 
         // Preprocess and match
         let res = {
-            let (image, template) = self.options.preprocess(image, &template);
+            let mask = self
+                .options
+                .template_mask(&template, mask_image(scale)?.as_deref());
+            let (cropped, preprocessed) = self.options.preprocess(image, &template);
+            let channels = self.options.channels(&cropped, &preprocessed);
+            let template = match &self.store {
+                Some((store, name)) if !self.options.preprocesses_template() => {
+                    (*store.luma(name, image.height(), scale)?).clone()
+                }
+                _ => preprocessed.to_luma32f(),
+            };
             MultiMatcher::Template {
-                image: image.to_luma32f(), // use cropped
-                template: template.to_luma32f(),
+                image: cropped.to_luma32f(),
+                template,
+                mask,
+                channels,
                 method: MatchTemplateMethod::CrossCorrelationNormed,
                 threshold: self.options.threshold,
+                nms: self.options.nms.unwrap_or_default(),
             }
-            .result()
+            .result()?
         };
 
         let [tl, _] = self.options.calc_roi(image);
@@ -81,8 +154,16 @@ impl MultiMatchAnalyzer {
                     ..rect
                 })
                 .collect(),
+            positions: res
+                .positions
+                .into_iter()
+                .map(|(x, y)| (x + tl.0 as f32, y + tl.1 as f32))
+                .collect(),
             ..res
         };
+        if let (Some((memory, key)), false) = (&self.memory, res.rects.is_empty()) {
+            memory.remember(key, scale);
+        }
 
         // Annotate
         let mut annotated_screen = image.clone();
@@ -103,6 +184,7 @@ impl MultiMatchAnalyzer {
         Ok(MultiMatchAnalyzerOutput {
             screen,
             res,
+            scale,
             annotated_screen,
         })
     }
@@ -126,7 +208,15 @@ impl<T: CachedScreenCapper> Analyzer<T> for MultiMatchAnalyzer {
 mod test {
     use std::path::Path;
 
-    use crate::vision::analyzer::multi_match::MultiMatchAnalyzer;
+    use image::{DynamicImage, GenericImage, GrayImage, Luma};
+
+    use crate::{
+        test_utils::noise,
+        vision::analyzer::{
+            matching::MatchOptions, multi_match::MultiMatchAnalyzer,
+            single_match::SingleMatchAnalyzer,
+        },
+    };
 
     #[test]
     fn test_multi_template_match_analyzer() {
@@ -142,4 +232,59 @@ mod test {
         output.annotated_screen.save("./assets/output.png").unwrap();
         println!("{:?}", output.res.rects);
     }
+
+    #[test]
+    fn test_nms_match() {
+        let mut noise = noise(5);
+        let template = GrayImage::from_fn(40, 30, |_, _| Luma([(noise() * 256.0) as u8]));
+        let mut image = GrayImage::from_pixel(300, 1080, Luma([128]));
+        for (x, y) in [(20, 100), (100, 100), (200, 600)] {
+            image.copy_from(&template, x, y).unwrap();
+        }
+        let template = DynamicImage::ImageLuma8(template);
+        let image = DynamicImage::ImageLuma8(image);
+        let analyze = |options: MatchOptions| {
+            MultiMatchAnalyzer::from_template(template.clone())
+                .with_options(options)
+                .analyze_image(&image)
+                .unwrap()
+                .res
+        };
+
+        let res = analyze(MatchOptions::default());
+        let mut locations = res.rects.iter().map(|r| (r.x, r.y)).collect::<Vec<_>>();
+        locations.sort();
+        assert_eq!(locations, vec![(20, 100), (100, 100), (200, 600)]);
+        assert_eq!(res.scores.len(), 3);
+        assert!(res.scores.windows(2).all(|s| s[0] >= s[1]));
+        // 模板与截图完全一致，亚像素位置即为匹配位置
+        for (rect, (x, y)) in res.rects.iter().zip(&res.positions) {
+            assert!((x - rect.x as f32).abs() < 0.5 && (y - rect.y as f32).abs() < 0.5);
+        }
+
+        let top = analyze(MatchOptions::default().with_nms(0.3, Some(2)));
+        assert_eq!(top.rects.len(), 2);
+        assert_eq!(top.scores, res.scores[..2]);
+
+        for nms in [
+            "{ iou_threshold = -0.1 }",
+            "{ iou_threshold = 1.5 }",
+            "{ iou_threshold = nan }",
+        ] {
+            let toml = format!("nms = {nms}");
+            assert!(toml::from_str::<MatchOptions>(&toml).is_err(), "{toml}");
+        }
+        let options: MatchOptions = toml::from_str("nms = { iou_threshold = 0.5 }").unwrap();
+        assert_eq!(options.nms.unwrap().iou_threshold, 0.5);
+        // 通过代码设置的选项在匹配时检查
+        let res = MultiMatchAnalyzer::from_template(template.clone())
+            .with_options(MatchOptions::default().with_nms(f32::NAN, None))
+            .analyze_image(&image);
+        assert!(res.is_err());
+        // 单目标匹配不支持非极大值抑制
+        let res = SingleMatchAnalyzer::from_template(template)
+            .with_options(MatchOptions::default().with_nms(0.5, None))
+            .analyze_image(&image);
+        assert!(res.is_err());
+    }
 }
@@ -0,0 +1,286 @@
+//! 场景识别，回答「当前处于哪个界面」
+//!
+//! [`SceneAnalyzer`] 对每个 [`Scene`] 依次检查其特征，所有特征都满足的场景中置信度最高的即为结果。
+//! 颜色特征的计算量远小于模板特征，因此总是会先于模板特征被检查，且一旦有特征不满足就不再检查该场景的其余特征。
+//! 多尺度匹配的模板特征与 `ClickMatchTemplate` 等操作一样，
+//! 以模板名称在 [`ScaleMemory`] 中记住匹配成功的尺度。
+
+use aah_controller::Controller;
+use image::{DynamicImage, GenericImageView};
+
+use crate::{
+    resource::{
+        manifest::scene::{ColorSignature, Scene, SceneSignature},
+        ResRoot,
+    },
+    task::retry::{ActionError, ErrorKind},
+    utils::resource::template_path,
+    Core,
+};
+
+use super::{matching::ScaleMemory, single_match::SingleMatchAnalyzer, Analyzer};
+
+/// 一个场景的识别结果
+///
+/// - `name`: 场景名称
+/// - `matched`: 是否所有特征都满足
+/// - `confidence`: 已检查的特征的置信度的平均值
+#[derive(Debug, Clone)]
+pub struct SceneCandidate {
+    pub name: String,
+    pub matched: bool,
+    pub confidence: f32,
+}
+
+/// [`SceneAnalyzer`] 的结果
+///
+/// - `scene`: 识别出的场景，没有任何场景满足时为 `None`
+/// - `confidence`: `scene` 的置信度，`scene` 为 `None` 时为 0
+/// - `candidates`: 所有场景的识别结果，按置信度从高到低排列
+#[derive(Debug, Clone)]
+pub struct SceneAnalyzerOutput {
+    pub scene: Option<String>,
+    pub confidence: f32,
+    pub candidates: Vec<SceneCandidate>,
+}
+
+enum Signature {
+    /// 模板名称与其匹配器
+    Template(String, Box<SingleMatchAnalyzer>),
+    Color(ColorSignature),
+}
+
+impl Signature {
+    /// 返回 `(是否满足, 置信度)`
+    fn check(
+        &self,
+        image: &DynamicImage,
+        memory: Option<&ScaleMemory>,
+    ) -> anyhow::Result<(bool, f32)> {
+        match self {
+            Signature::Template(template, analyzer) => {
+                let memory = memory.map(|memory| (memory, template.as_str()));
+                let output = analyzer.analyze_image_remembering(image, memory)?;
+                Ok((output.res.rect.is_some(), output.res.confidence))
+            }
+            Signature::Color(signature) => {
+                let [r, g, b] = average_color(image, signature.roi);
+                let diff = [r, g, b]
+                    .iter()
+                    .zip(signature.color)
+                    .map(|(a, b)| a.abs_diff(b))
+                    .max()
+                    .unwrap();
+                let tolerance = signature.tolerance.unwrap_or(20);
+                let confidence = if tolerance == 0 {
+                    if diff == 0 {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                } else {
+                    (1.0 - diff as f32 / (2.0 * tolerance as f32)).clamp(0.0, 1.0)
+                };
+                Ok((diff <= tolerance, confidence))
+            }
+        }
+    }
+}
+
+/// `roi` 区域内的平均 RGB 颜色
+fn average_color(image: &DynamicImage, roi: [(f32, f32); 2]) -> [u8; 3] {
+    let [tl, br] = roi.map(|(x, y)| {
+        (
+            ((x * image.width() as f32) as u32).min(image.width()),
+            ((y * image.height() as f32) as u32).min(image.height()),
+        )
+    });
+    let (width, height) = (br.0.saturating_sub(tl.0), br.1.saturating_sub(tl.1));
+    if width == 0 || height == 0 {
+        return [0, 0, 0];
+    }
+    let region = image.view(tl.0, tl.1, width, height).to_image();
+    let mut sum = [0u64; 3];
+    for pixel in region.pixels() {
+        for (s, c) in sum.iter_mut().zip(pixel.0) {
+            *s += c as u64;
+        }
+    }
+    let count = (width * height) as u64;
+    sum.map(|s| (s / count) as u8)
+}
+
+/// 场景识别器
+pub struct SceneAnalyzer {
+    scenes: Vec<(String, Vec<Signature>)>,
+}
+
+impl SceneAnalyzer {
+    /// 加载 `scenes` 中所有特征引用的模板，`res` 有模板缓存时使用缓存，见 [`ResRoot::template_store`]
+    pub fn new(res: &impl ResRoot, scenes: &[Scene]) -> anyhow::Result<Self> {
+        let res_dir = res.res_root();
+        let mut analyzer_scenes = vec![];
+        for scene in scenes {
+            let mut signatures = vec![];
+            for signature in &scene.signatures {
+                match signature {
+                    SceneSignature::Template(signature) => {
+                        if !template_path(&signature.template, res_dir).is_file() {
+                            return Err(ActionError::new(
+                                ErrorKind::ResourceNotFound,
+                                format!("template not found: {}", signature.template),
+                            )
+                            .into());
+                        }
+                        let analyzer =
+                            SingleMatchAnalyzer::from_resource(res, &signature.template)?
+                                .with_options(signature.options.clone());
+                        signatures.push(Signature::Template(
+                            signature.template.clone(),
+                            Box::new(analyzer),
+                        ));
+                    }
+                    SceneSignature::Color(signature) => {
+                        signatures.push(Signature::Color(signature.clone()))
+                    }
+                }
+            }
+            // 先检查颜色特征
+            signatures.sort_by_key(|signature| matches!(signature, Signature::Template(..)));
+            analyzer_scenes.push((scene.name.clone(), signatures));
+        }
+        Ok(Self {
+            scenes: analyzer_scenes,
+        })
+    }
+
+    /// 识别 `image` 所处的场景，多尺度匹配的尺度记在 `memory` 中（一般为 [`Core::scale_memory`]）
+    pub fn analyze_image(
+        &self,
+        image: &DynamicImage,
+        memory: Option<&ScaleMemory>,
+    ) -> anyhow::Result<SceneAnalyzerOutput> {
+        let mut candidates = vec![];
+        for (name, signatures) in &self.scenes {
+            let mut matched = true;
+            let mut confidences = vec![];
+            for signature in signatures {
+                let (ok, confidence) = signature.check(image, memory)?;
+                confidences.push(confidence);
+                if !ok {
+                    matched = false;
+                    break;
+                }
+            }
+            let confidence = if confidences.is_empty() {
+                0.0
+            } else {
+                confidences.iter().sum::<f32>() / confidences.len() as f32
+            };
+            candidates.push(SceneCandidate {
+                name: name.clone(),
+                matched: matched && !signatures.is_empty(),
+                confidence,
+            });
+        }
+        candidates.sort_by(|a, b| {
+            b.matched
+                .cmp(&a.matched)
+                .then(b.confidence.total_cmp(&a.confidence))
+        });
+
+        let best = candidates.first().filter(|candidate| candidate.matched);
+        Ok(SceneAnalyzerOutput {
+            scene: best.map(|candidate| candidate.name.clone()),
+            confidence: best.map(|candidate| candidate.confidence).unwrap_or(0.0),
+            candidates,
+        })
+    }
+}
+
+impl<T, C: Controller> Analyzer<T> for SceneAnalyzer
+where
+    T: Core<Controller = C>,
+{
+    type Res = SceneAnalyzerOutput;
+    fn analyze(&mut self, core: &T) -> anyhow::Result<Self::Res> {
+        let screen = core.controller().screencap()?;
+        self.analyze_image(&screen, core.scale_memory().as_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        resource::manifest::scene::TemplateSignature,
+        test_utils::{old_resources, resource, screenshot},
+        vision::analyzer::matching::MatchOptions,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_scene_analyzer() {
+        let root = old_resources();
+        let res = resource(root.clone(), vec![]);
+        let screen = screenshot("main.png");
+        let [r, g, b] = average_color(&screen, [(0.0, 0.0), (1.0, 1.0)]);
+
+        let scenes = vec![
+            // 颜色不满足，不会进行模板匹配
+            Scene::new(
+                "loading",
+                vec![
+                    TemplateSignature::new("main_mission.png").into(),
+                    ColorSignature::new((0.0, 0.0), (1.0, 1.0), [r, g, b.wrapping_add(128)]).into(),
+                ],
+            ),
+            Scene::new(
+                "main",
+                vec![
+                    ColorSignature::new((0.0, 0.0), (1.0, 1.0), [r, g, b])
+                        .with_tolerance(5)
+                        .into(),
+                    TemplateSignature::new("main_mission.png").into(),
+                ],
+            ),
+        ];
+        let analyzer = SceneAnalyzer::new(&res, &scenes).unwrap();
+        let output = analyzer.analyze_image(&screen, None).unwrap();
+        assert_eq!(output.scene.as_deref(), Some("main"));
+        assert!(output.confidence > 0.5);
+        assert_eq!(output.candidates[1].name, "loading");
+        assert!(!output.candidates[1].matched);
+        assert_eq!(output.candidates[1].confidence, 0.0);
+
+        assert!(SceneAnalyzer::new(
+            &res,
+            &[Scene::new(
+                "missing",
+                vec![TemplateSignature::new("missing.png").into()]
+            )]
+        )
+        .is_err());
+
+        // 多尺度匹配的尺度以模板名称记住
+        let screen = image::open(root.join("templates/MUMU-1920x1080/main.png"))
+            .unwrap()
+            .resize_exact(704, 396, image::imageops::FilterType::Triangle)
+            .crop_imm(32, 18, 640, 360);
+        let options = MatchOptions::default().with_scale_search((0.9, 1.2), 0.05);
+        let scenes = [Scene::new(
+            "main",
+            vec![TemplateSignature::new("main_base.png")
+                .with_options(options)
+                .into()],
+        )];
+        let memory = ScaleMemory::new();
+        let output = SceneAnalyzer::new(&res, &scenes)
+            .unwrap()
+            .analyze_image(&screen, Some(&memory))
+            .unwrap();
+        assert_eq!(output.scene.as_deref(), Some("main"));
+        let scale = memory.get("main_base.png").unwrap();
+        assert!((scale - 1.1).abs() <= 0.05, "{scale}");
+    }
+}
@@ -1,8 +1,9 @@
-use std::path::Path;
+use std::{path::Path, sync::Arc};
 
 use image::DynamicImage;
 
 use crate::{
+    resource::{template_store::TemplateStore, ResRoot},
     utils::resource::get_template,
     vision::{
         matcher::single_matcher::{SingleMatcher, SingleMatcherResult},
@@ -10,30 +11,71 @@ use crate::{
     },
     Core,
 };
-use aah_controller::{Controller, DEFAULT_HEIGHT};
+use aah_controller::Controller;
 
-use super::{matching::MatchOptions, Analyzer};
+use super::{
+    matching::{scale_template, MatchOptions, ScaleMemory},
+    Analyzer,
+};
 
 pub struct SingleMatchAnalyzerOutput {
     pub screen: Box<DynamicImage>,
     pub res: SingleMatcherResult,
+    /// 匹配时模板相对于按截图高度缩放后的尺度，见 [`ScaleSearch`](super::matching::ScaleSearch)
+    pub scale: f32,
     pub annotated_screen: Box<DynamicImage>,
 }
 
 /// To find the best result where the template fits in the screen
 pub struct SingleMatchAnalyzer {
-    template: DynamicImage,
+    template: Arc<DynamicImage>,
     // res_dir: PathBuf,
     options: MatchOptions,
+    memory: Option<(ScaleMemory, String)>,
+    store: Option<(TemplateStore, String)>,
+    mask: Option<Arc<DynamicImage>>,
 }
 
 impl SingleMatchAnalyzer {
     pub fn new(res_dir: impl AsRef<Path>, template_path: impl AsRef<Path>) -> Self {
         let template = get_template(template_path, res_dir).unwrap();
         Self {
-            template,
+            template: Arc::new(template),
             // res_dir,
             options: Default::default(),
+            memory: None,
+            store: None,
+            mask: None,
+        }
+    }
+
+    /// 使用已经加载好的模板
+    pub fn from_template(template: DynamicImage) -> Self {
+        Self {
+            template: Arc::new(template),
+            options: Default::default(),
+            memory: None,
+            store: None,
+            mask: None,
+        }
+    }
+
+    /// 使用 `store` 中的模板 `template`，匹配时复用缓存的缩放后的模板
+    pub fn from_store(store: &TemplateStore, template: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            template: store.get(template)?,
+            options: Default::default(),
+            memory: None,
+            store: Some((store.clone(), template.to_string())),
+            mask: None,
+        })
+    }
+
+    /// 加载 `res` 中的模板 `template`，`res` 有模板缓存时使用缓存，见 [`ResRoot::template_store`]
+    pub fn from_resource(res: &impl ResRoot, template: &str) -> anyhow::Result<Self> {
+        match res.template_store() {
+            Some(store) => Self::from_store(store, template),
+            None => Ok(Self::from_template(get_template(template, res.res_root())?)),
         }
     }
 
@@ -42,60 +84,116 @@ impl SingleMatchAnalyzer {
         self
     }
 
+    /// 使用已经加载好的 [`TemplateMask::Image`](super::matching::TemplateMask::Image) 遮罩图片，而不是从模板缓存中加载
+    pub fn with_mask_image(mut self, mask: DynamicImage) -> Self {
+        self.mask = Some(Arc::new(mask));
+        self
+    }
+
+    /// 多尺度匹配时先尝试 `memory` 中以 `key` 记住的尺度，匹配成功后记住此次的尺度
+    pub fn remember_scale(mut self, memory: Option<ScaleMemory>, key: impl AsRef<str>) -> Self {
+        self.memory = memory.map(|memory| (memory, key.as_ref().to_string()));
+        self
+    }
+
     pub fn analyze_image(&self, image: &DynamicImage) -> anyhow::Result<SingleMatchAnalyzerOutput> {
+        let memory = self
+            .memory
+            .as_ref()
+            .map(|(memory, key)| (memory, key.as_str()));
+        self.analyze_image_remembering(image, memory)
+    }
+
+    /// 同 [`SingleMatchAnalyzer::analyze_image`]，但使用 `memory` 中以 `key` 记住的尺度，
+    /// 而不是 [`SingleMatchAnalyzer::remember_scale`] 设置的
+    pub(crate) fn analyze_image_remembering(
+        &self,
+        image: &DynamicImage,
+        memory: Option<(&ScaleMemory, &str)>,
+    ) -> anyhow::Result<SingleMatchAnalyzerOutput> {
         // let template = self.template.get_or_load()?;
+        self.options.validate()?;
+        anyhow::ensure!(
+            self.options.nms.is_none(),
+            "nms only applies to multi-target matching"
+        );
 
         // Scaling
-        let template = if image.height() != DEFAULT_HEIGHT {
-            let scale_factor = image.height() as f32 / DEFAULT_HEIGHT as f32;
-
-            let new_width = (self.template.width() as f32 * scale_factor) as u32;
-            let new_height = (self.template.height() as f32 * scale_factor) as u32;
-
-            DynamicImage::ImageRgba8(image::imageops::resize(
+        let remembered = memory.and_then(|(memory, key)| memory.get(key));
+        let mask_image = |scale| {
+            self.options.scaled_mask_image(
                 &self.template,
-                new_width,
-                new_height,
-                image::imageops::FilterType::Lanczos3,
-            ))
-        } else {
-            self.template.clone()
+                self.mask.as_deref(),
+                self.store.as_ref().map(|(store, _)| store),
+                image.height(),
+                scale,
+            )
         };
+        let (scale, searched) =
+            self.options
+                .search_scale(image, &self.template, &mask_image, remembered)?;
 
-        // Preprocess and match
-        let res = {
-            let (image, template) = self.options.preprocess(image, &template);
-            SingleMatcher::Template {
-                image: image.to_luma32f(), // use cropped
-                template: template.to_luma32f(),
-                method: self.options.method,
-                threshold: self.options.threshold,
+        // Preprocess and match, the result of the scale search is reused
+        let res = match searched {
+            Some(res) => res,
+            None => {
+                let template = match &self.store {
+                    Some((store, name)) => store.scaled(name, image.height(), scale)?,
+                    None => Arc::new(scale_template(&self.template, image.height(), scale)),
+                };
+                let mask = self
+                    .options
+                    .template_mask(&template, mask_image(scale)?.as_deref());
+                let (cropped, preprocessed) = self.options.preprocess(image, &template);
+                let channels = self.options.channels(&cropped, &preprocessed);
+                let template = match &self.store {
+                    Some((store, name)) if !self.options.preprocesses_template() => {
+                        (*store.luma(name, image.height(), scale)?).clone()
+                    }
+                    _ => preprocessed.to_luma32f(),
+                };
+                SingleMatcher::Template {
+                    image: cropped.to_luma32f(),
+                    template,
+                    mask,
+                    channels,
+                    method: self.options.method,
+                    threshold: self.options.threshold,
+                }
+                .result()?
             }
-            .result()
         };
 
         let [tl, _] = self.options.calc_roi(image);
+        let offset = |rect: Rect| Rect {
+            x: rect.x + tl.0,
+            y: rect.y + tl.1,
+            ..rect
+        };
         let res = SingleMatcherResult {
-            rect: res.rect.map(|rect| Rect {
-                x: rect.x + tl.0,
-                y: rect.y + tl.1,
-                ..rect
-            }),
+            rect: res.rect.map(offset),
+            best_rect: offset(res.best_rect),
             ..res
         };
+        if let (Some((memory, key)), Some(_)) = (memory, &res.rect) {
+            memory.remember(key, scale);
+        }
 
         // Annotated
+        // 匹配成功时用红框标出结果，失败时用黄框标出得分最优的位置
         let mut annotated_screen = image.clone();
-        if let Some(rect) = &res.rect {
-            draw_box(
-                &mut annotated_screen,
-                rect.x as i32,
-                rect.y as i32,
-                rect.width,
-                rect.height,
-                [255, 0, 0, 255],
-            );
-        }
+        let (rect, color) = match &res.rect {
+            Some(rect) => (rect, [255, 0, 0, 255]),
+            None => (&res.best_rect, [255, 255, 0, 255]),
+        };
+        draw_box(
+            &mut annotated_screen,
+            rect.x as i32,
+            rect.y as i32,
+            rect.width,
+            rect.height,
+            color,
+        );
 
         // println!("cost: {:?}", t.elapsed());
         let screen = Box::new(image.clone());
@@ -103,6 +201,7 @@ impl SingleMatchAnalyzer {
         Ok(SingleMatchAnalyzerOutput {
             screen,
             res,
+            scale,
             annotated_screen,
         })
     }
@@ -133,6 +232,12 @@ mod test {
     use std::env;
 
     use super::*;
+    use image::{Rgb, Rgb32FImage};
+
+    use crate::{
+        test_utils::{old_resources, screenshot},
+        vision::analyzer::matching::{ColorSpace, TemplateMask},
+    };
 
     #[test]
     fn test_single_match_analyzer() {
@@ -147,4 +252,213 @@ mod test {
         let output = analyzer.analyze_image(&image).unwrap();
         println!("{:?}", output.res.rect);
     }
+
+    #[test]
+    fn test_multi_scale_match() {
+        let root = old_resources();
+        // 界面相对于截图高度放大了 1.1 倍
+        let image = image::open(root.join("templates/MUMU-1920x1080/main.png"))
+            .unwrap()
+            .resize_exact(704, 396, image::imageops::FilterType::Triangle)
+            .crop_imm(32, 18, 640, 360);
+        let memory = ScaleMemory::new();
+        let analyzer = SingleMatchAnalyzer::new(&root, "main_base.png")
+            .with_options(MatchOptions::default().with_scale_search((0.9, 1.2), 0.05))
+            .remember_scale(Some(memory.clone()), "main_base.png");
+
+        let output = analyzer.analyze_image(&image).unwrap();
+        assert!(output.res.rect.is_some());
+        assert!((output.scale - 1.1).abs() <= 0.05, "{}", output.scale);
+        assert_eq!(memory.get("main_base.png"), Some(output.scale));
+
+        // 之后的匹配直接使用记住的尺度
+        let output = analyzer.analyze_image(&image).unwrap();
+        assert_eq!(output.scale, memory.get("main_base.png").unwrap());
+
+        // 不进行多尺度匹配时无法匹配
+        let output = SingleMatchAnalyzer::new(&root, "main_base.png")
+            .analyze_image(&image)
+            .unwrap();
+        assert!(output.res.rect.is_none());
+        assert_eq!(output.scale, 1.0);
+    }
+
+    #[test]
+    fn test_store_match() {
+        let root = old_resources();
+        let image = screenshot("main.png");
+        let expected = SingleMatchAnalyzer::new(&root, "main_base.png")
+            .analyze_image(&image)
+            .unwrap()
+            .res;
+
+        // 没有预处理时使用缓存的灰度模板，结果与直接加载模板一致
+        let store = TemplateStore::new(&root);
+        let res = SingleMatchAnalyzer::from_store(&store, "main_base.png")
+            .unwrap()
+            .analyze_image(&image)
+            .unwrap()
+            .res;
+        let position = |rect: &Option<Rect>| rect.as_ref().map(|rect| (rect.x, rect.y));
+        assert_eq!(position(&res.rect), position(&expected.rect));
+        assert_eq!(res.score, expected.score);
+        assert!(format!("{store:?}").contains("luma: 1"), "{store:?}");
+
+        // 二值化会改变模板，不使用缓存的灰度模板
+        let store = TemplateStore::new(&root);
+        SingleMatchAnalyzer::from_store(&store, "main_base.png")
+            .unwrap()
+            .with_options(MatchOptions::default().with_binarize_threshold(128))
+            .analyze_image(&image)
+            .unwrap();
+        assert!(format!("{store:?}").contains("luma: 0"), "{store:?}");
+    }
+
+    #[test]
+    fn test_masked_match() {
+        let root = old_resources();
+        let image = screenshot("main.png");
+        let expected = SingleMatchAnalyzer::new(&root, "main_base.png")
+            .analyze_image(&image)
+            .unwrap()
+            .res
+            .rect
+            .unwrap();
+
+        // 模板的右半部分被涂花，但是透明
+        let mut template = get_template("main_base.png", &root).unwrap().to_rgba8();
+        let (width, height) = template.dimensions();
+        let mut mask = image::GrayImage::new(width, height);
+        for (x, y, pixel) in template.enumerate_pixels_mut() {
+            if x >= width / 2 {
+                *pixel = image::Rgba([(x * 7 % 256) as u8, (y * 13 % 256) as u8, 0, 0]);
+            } else {
+                pixel.0[3] = 255;
+                mask.put_pixel(x, y, image::Luma([255]));
+            }
+        }
+        let template = DynamicImage::ImageRgba8(template);
+
+        let output = SingleMatchAnalyzer::from_template(template.clone())
+            .analyze_image(&image)
+            .unwrap();
+        assert!(output.res.rect.is_none());
+
+        let output = SingleMatchAnalyzer::from_template(template.clone())
+            .with_options(MatchOptions::default().with_mask(TemplateMask::Alpha))
+            .analyze_image(&image)
+            .unwrap();
+        let rect = output.res.rect.unwrap();
+        assert_eq!((rect.x, rect.y), (expected.x, expected.y));
+
+        let options = MatchOptions::default()
+            .with_mask(TemplateMask::Image("main_base_mask.png".to_string()));
+        assert!(SingleMatchAnalyzer::from_template(template.clone())
+            .with_options(options.clone())
+            .analyze_image(&image)
+            .is_err());
+        // 遮罩与模板大小不同
+        assert!(SingleMatchAnalyzer::from_template(template.clone())
+            .with_options(options.clone())
+            .with_mask_image(DynamicImage::ImageLuma8(image::GrayImage::new(
+                width + 1,
+                height
+            )))
+            .analyze_image(&image)
+            .is_err());
+        let output = SingleMatchAnalyzer::from_template(template.clone())
+            .with_options(options)
+            .with_mask_image(DynamicImage::ImageLuma8(mask))
+            .analyze_image(&image)
+            .unwrap();
+        let rect = output.res.rect.unwrap();
+        assert_eq!((rect.x, rect.y), (expected.x, expected.y));
+
+        // 完全透明的模板没有可以匹配的像素
+        let mut transparent = template.to_rgba8();
+        transparent.pixels_mut().for_each(|pixel| pixel.0[3] = 0);
+        assert!(
+            SingleMatchAnalyzer::from_template(DynamicImage::ImageRgba8(transparent))
+                .with_options(MatchOptions::default().with_mask(TemplateMask::Alpha))
+                .analyze_image(&image)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_color_match() {
+        // 红色的棋盘格模板，以及形状相同的蓝色棋盘格
+        let checker = |x: u32, y: u32| {
+            if (x / 5 + y / 5).is_multiple_of(2) {
+                0.6
+            } else {
+                0.0
+            }
+        };
+        let template = Rgb32FImage::from_fn(40, 30, |x, y| Rgb([0.2 + checker(x, y), 0.2, 0.2]));
+        let mut image = Rgb32FImage::from_pixel(200, 1080, Rgb([0.2, 0.2, 0.2]));
+        for (x, y, pixel) in template.enumerate_pixels() {
+            image.put_pixel(x + 20, y + 100, *pixel);
+            image.put_pixel(x + 100, y + 600, Rgb([0.2, 0.2, 0.2 + checker(x, y)]));
+        }
+        let template = DynamicImage::ImageRgb32F(template);
+        let image = DynamicImage::ImageRgb32F(image);
+        let analyze = |template: &DynamicImage, options: MatchOptions| {
+            SingleMatchAnalyzer::from_template(template.clone())
+                .with_options(options)
+                .analyze_image(&image)
+                .unwrap()
+                .res
+        };
+
+        // 灰度图上蓝色的棋盘格也能匹配
+        let decoy = MatchOptions::default().with_roi((0.4, 0.4), (1.0, 1.0));
+        assert!(analyze(&template, decoy.clone()).rect.is_some());
+
+        for space in [ColorSpace::Rgb, ColorSpace::Hsv] {
+            let options = MatchOptions::default().with_color(space, [1.0, 1.0, 1.0]);
+            let rect = analyze(&template, options.clone()).rect.unwrap();
+            assert_eq!((rect.x, rect.y), (20, 100));
+            let options = decoy.clone().with_color(space, [1.0, 1.0, 1.0]);
+            assert!(analyze(&template, options).rect.is_none());
+        }
+    }
+
+    #[test]
+    fn test_color_options() {
+        let hsv = |rgb: [u8; 3]| {
+            let pixel = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1, 1, Rgb(rgb)));
+            ColorSpace::Hsv
+                .split(&pixel)
+                .iter()
+                .map(|channel| channel.as_raw()[0])
+                .collect::<Vec<_>>()
+        };
+        // 色相 350° 与 10° 都是红色，通道的值相近
+        let (a, b) = (hsv([255, 0, 43]), hsv([255, 43, 0]));
+        assert!(
+            a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 0.2),
+            "{a:?} {b:?}"
+        );
+        // 灰色没有色相
+        assert_eq!(hsv([128, 128, 128])[..3], [0.5, 0.5, 0.0]);
+
+        for weights in ["[0.0, 0.0, 0.0]", "[-1.0, 1.0, 1.0]", "[nan, 1.0, 1.0]"] {
+            let toml = format!("color = {{ weights = {weights} }}");
+            assert!(toml::from_str::<MatchOptions>(&toml).is_err(), "{toml}");
+        }
+        let options: MatchOptions =
+            toml::from_str(r#"color = { space = "hsv", weights = [0.0, 1.0, 1.0] }"#).unwrap();
+        assert_eq!(
+            options.color.unwrap().channel_weights(),
+            [0.0, 0.0, 1.0, 1.0]
+        );
+
+        // 通过代码设置的权重在匹配时检查
+        let image = DynamicImage::new_rgb8(40, 40);
+        let res = SingleMatchAnalyzer::from_template(DynamicImage::new_rgb8(4, 4))
+            .with_options(MatchOptions::default().with_color(ColorSpace::Rgb, [0.0; 3]))
+            .analyze_image(&image);
+        assert!(res.is_err());
+    }
 }
@@ -2,6 +2,19 @@ pub mod best_matcher;
 pub mod multi_matcher;
 pub mod single_matcher;
 
+use image::{ImageBuffer, Luma};
+
+/// 彩色匹配时截图与模板的各个通道，见 [`aah_cv::template_matching::match_template_channels`]
+///
+/// - `image`: 截图的各个通道
+/// - `template`: 模板的各个通道
+/// - `weights`: 各个通道的权重
+pub struct Channels {
+    pub image: Vec<ImageBuffer<Luma<f32>, Vec<f32>>>,
+    pub template: Vec<ImageBuffer<Luma<f32>, Vec<f32>>>,
+    pub weights: Vec<f32>,
+}
+
 const CCORR_THRESHOLD: f32 = 30.0;
 const CCORR_NORMED_THRESHOLD: f32 = 0.9;
 const CCOEFF_THRESHOLD: f32 = 30.0;
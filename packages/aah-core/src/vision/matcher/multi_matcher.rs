@@ -1,7 +1,10 @@
 use std::time::Instant;
 
 use aah_cv::{
-    template_matching::{find_matches, match_template, MatchTemplateMethod},
+    template_matching::{
+        find_matches_nms, match_template, match_template_channels, match_template_masked,
+        MatchTemplateMethod, NmsOptions,
+    },
     utils::{luma32f_to_luma8, normalize_luma32f},
 };
 use color_print::{cformat, cprintln};
@@ -9,7 +12,7 @@ use image::{DynamicImage, ImageBuffer, Luma};
 
 use crate::vision::{
     matcher::{
-        CCOEFF_THRESHOLD, CCORR_NORMED_THRESHOLD, CCORR_THRESHOLD, SSE_NORMED_THRESHOLD,
+        Channels, CCOEFF_THRESHOLD, CCORR_NORMED_THRESHOLD, CCORR_THRESHOLD, SSE_NORMED_THRESHOLD,
         SSE_THRESHOLD,
     },
     utils::Rect,
@@ -19,30 +22,43 @@ pub enum MultiMatcher {
     Template {
         image: ImageBuffer<Luma<f32>, Vec<f32>>,
         template: ImageBuffer<Luma<f32>, Vec<f32>>,
+        /// 模板的逐像素权重，为 0 的像素不参与匹配，见 [`match_template_masked`]
+        mask: Option<ImageBuffer<Luma<f32>, Vec<f32>>>,
+        /// 设置时在各个通道上匹配并加权，而不是只匹配灰度图 `image` 与 `template`
+        channels: Option<Channels>,
         method: MatchTemplateMethod,
         threshold: Option<f32>,
+        /// 非极大值抑制的选项
+        nms: NmsOptions,
     },
 }
 
 /// [`MultiMatcher`] 的结果
 ///
-/// - `rects`: 匹配出的矩形框
+/// - `rects`: 匹配出的矩形框，按得分从优到劣排序
+/// - `scores`: 各个矩形框的匹配值
+/// - `positions`: 各个矩形框左上角的亚像素位置，见 [`aah_cv::template_matching::refine_subpixel`]
 /// - `matched_img`: 匹配图
 pub struct MultiMatcherResult {
     pub rects: Vec<Rect>,
+    pub scores: Vec<f32>,
+    pub positions: Vec<(f32, f32)>,
     pub matched_img: Box<DynamicImage>,
 }
 
 impl MultiMatcher {
-    /// 执行匹配并获取结果
-    pub fn result(&self) -> MultiMatcherResult {
+    /// 执行匹配并获取结果，遮罩无效（见 [`aah_cv::template_matching::check_mask`]）时返回错误
+    pub fn result(&self) -> anyhow::Result<MultiMatcherResult> {
         // let log_tag = cformat!("<strong>[MultiMatcher]: </strong>");
         match self {
             Self::Template {
                 image,
                 template,
+                mask,
+                channels,
                 method,
                 threshold,
+                nms,
             } => {
                 let threshold = threshold.unwrap_or(match method {
                     MatchTemplateMethod::SumOfSquaredDifference => SSE_THRESHOLD,
@@ -65,7 +81,17 @@ impl MultiMatcher {
 
                 // TODO: deal with scale problem, maybe should do it when screen cap stage
                 let start_time = Instant::now();
-                let mut res = match_template(image, template, *method, false);
+                let mut res = match (channels, mask) {
+                    (Some(channels), mask) => match_template_channels(
+                        &channels.image,
+                        &channels.template,
+                        &channels.weights,
+                        mask.as_ref(),
+                        *method,
+                    )?,
+                    (None, Some(mask)) => match_template_masked(image, template, mask, *method)?,
+                    (None, None) => match_template(image, template, *method, false),
+                };
 
                 // Normalize
                 if matches!(
@@ -80,13 +106,16 @@ impl MultiMatcher {
                 let matched_img = luma32f_to_luma8(&res);
                 let matched_img = DynamicImage::ImageLuma8(matched_img);
 
-                let matches = find_matches(
+                let matches = find_matches_nms(
                     &res,
                     template.width(),
                     template.height(),
                     *method,
                     threshold,
+                    nms,
                 );
+                let scores = matches.iter().map(|m| m.value).collect();
+                let positions = matches.iter().map(|m| m.position(&res)).collect();
                 let rects: Vec<Rect> = matches
                     .into_iter()
                     .map(|m| Rect {
@@ -101,10 +130,12 @@ impl MultiMatcher {
                 //     start_time.elapsed().as_secs_f32(),
                 // );
 
-                return MultiMatcherResult {
+                return Ok(MultiMatcherResult {
                     rects,
+                    scores,
+                    positions,
                     matched_img: Box::new(matched_img),
-                };
+                });
             } // TODO: implement OcrMatcher
         }
     }
@@ -112,7 +143,7 @@ impl MultiMatcher {
 
 #[cfg(test)]
 mod test {
-    use aah_cv::template_matching::MatchTemplateMethod;
+    use aah_cv::template_matching::{MatchTemplateMethod, NmsOptions};
     use image::math::Rect;
 
     use crate::vision::{
@@ -148,11 +179,15 @@ mod test {
         let res = MultiMatcher::Template {
             image: image.to_luma32f(),
             template: template.to_luma32f(),
+            mask: None,
+            channels: None,
+            nms: NmsOptions::default(),
             method: MatchTemplateMethod::CrossCorrelationNormed,
             threshold: Some(0.85),
             // threshold: Some(40.0),
         }
-        .result();
+        .result()
+        .unwrap();
         res.matched_img
             .save(format!("./assets/output/matched_{image_filename}"))
             .unwrap();
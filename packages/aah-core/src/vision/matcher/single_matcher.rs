@@ -1,7 +1,9 @@
 use std::{sync::Arc, time::Instant};
 
 // use aah_cv::{find_extremes, match_template, MatchTemplateMethod};
-use aah_cv::template_matching::{match_template, MatchTemplateMethod};
+use aah_cv::template_matching::{
+    match_template, match_template_channels, match_template_masked, MatchTemplateMethod,
+};
 use color_print::{cformat, cprintln};
 use image::{DynamicImage, EncodableLayout, ImageBuffer, Luma};
 use imageproc::template_matching::find_extremes;
@@ -9,8 +11,8 @@ use ocrs::{ImageSource, OcrEngine};
 
 use crate::vision::{
     matcher::{
-        CCOEFF_NORMED_THRESHOLD, CCOEFF_THRESHOLD, CCORR_NORMED_THRESHOLD, CCORR_THRESHOLD,
-        SSE_NORMED_THRESHOLD, SSE_THRESHOLD,
+        Channels, CCOEFF_NORMED_THRESHOLD, CCOEFF_THRESHOLD, CCORR_NORMED_THRESHOLD,
+        CCORR_THRESHOLD, SSE_NORMED_THRESHOLD, SSE_THRESHOLD,
     },
     utils::Rect,
 };
@@ -18,17 +20,51 @@ use crate::vision::{
 /// [`SingleMatcher`] 的结果
 ///
 /// - `rect`: 匹配出的矩形框
+/// - `best_rect`: 得分最优的位置的矩形框，无论是否达到阈值
+/// - `score`: 最优位置的匹配值
+/// - `confidence`: 由 `score` 换算出的置信度，在 `[0.0, 1.0]` 之间，恰好达到阈值时为 0.5
 /// - `matched_img`: 匹配图
 pub struct SingleMatcherResult {
     pub rect: Option<Rect>,
+    pub best_rect: Rect,
+    pub score: f32,
+    pub confidence: f32,
     pub matched_img: Box<DynamicImage>,
 }
 
+/// 由匹配值 `score` 换算出置信度，见 [`SingleMatcherResult`]
+///
+/// 以恰好达到阈值为 0.5 进行线性换算：差值类方法在 0 处为 1.0，归一化的相关类方法在 1.0 处为 1.0。
+/// 阈值不为正数时无法换算，`success` 时为 1.0，否则为 0.0
+fn confidence(method: MatchTemplateMethod, score: f32, threshold: f32, success: bool) -> f32 {
+    let step = if success { 1.0 } else { 0.0 };
+    let confidence = match method {
+        _ if threshold <= 0.0 => step,
+        MatchTemplateMethod::SumOfSquaredDifference
+        | MatchTemplateMethod::SumOfSquaredDifferenceNormed => 1.0 - score / (2.0 * threshold),
+        MatchTemplateMethod::CrossCorrelationNormed
+        | MatchTemplateMethod::CorrelationCoefficientNormed
+            if threshold < 1.0 =>
+        {
+            0.5 + (score - threshold) / (2.0 * (1.0 - threshold))
+        }
+        _ => score / (2.0 * threshold),
+    };
+    if confidence.is_nan() {
+        return step;
+    }
+    confidence.clamp(0.0, 1.0)
+}
+
 /// 匹配器，目前只实现了模板匹配
 pub enum SingleMatcher {
     Template {
         image: ImageBuffer<Luma<f32>, Vec<f32>>,
         template: ImageBuffer<Luma<f32>, Vec<f32>>,
+        /// 模板的逐像素权重，为 0 的像素不参与匹配，见 [`match_template_masked`]
+        mask: Option<ImageBuffer<Luma<f32>, Vec<f32>>>,
+        /// 设置时在各个通道上匹配并加权，而不是只匹配灰度图 `image` 与 `template`
+        channels: Option<Channels>,
         method: Option<MatchTemplateMethod>,
         threshold: Option<f32>,
     },
@@ -40,13 +76,15 @@ pub enum SingleMatcher {
 }
 
 impl SingleMatcher {
-    /// 执行匹配并获取结果
-    pub fn result(&self) -> SingleMatcherResult {
+    /// 执行匹配并获取结果，遮罩无效（见 [`aah_cv::template_matching::check_mask`]）时返回错误
+    pub fn result(&self) -> anyhow::Result<SingleMatcherResult> {
         // let log_tag = cformat!("[SingleMatcher]: ");
         match self {
             Self::Template {
                 image,
                 template,
+                mask,
+                channels,
                 method,
                 threshold,
             } => {
@@ -71,7 +109,25 @@ impl SingleMatcher {
 
                 // TODO: deal with scale problem, maybe should do it when screen cap stage
                 // let start_time = Instant::now();
-                let res = match_template(image, template, method, false);
+                let res = match (channels, mask) {
+                    (Some(channels), mask) => match_template_channels(
+                        &channels.image,
+                        &channels.template,
+                        &channels.weights,
+                        mask.as_ref(),
+                        method,
+                    )?,
+                    (None, Some(mask)) => match_template_masked(image, template, mask, method)?,
+                    (None, None) => match_template(image, template, method, false),
+                };
+                anyhow::ensure!(
+                    res.width() > 0 && res.height() > 0,
+                    "the template ({}x{}) is larger than the image ({}x{})",
+                    template.width(),
+                    template.height(),
+                    image.width(),
+                    image.height()
+                );
 
                 // Normalize
                 let min = res
@@ -118,35 +174,36 @@ impl SingleMatcher {
                     }
                 };
 
-                let rect = if !success {
-                    // cprintln!("{log_tag}<red>failed</red>");
-                    None
-                } else {
-                    // cprintln!("{log_tag}<green>success!</green>");
-                    let (x, y) = match method {
-                        MatchTemplateMethod::SumOfSquaredDifference
-                        | MatchTemplateMethod::SumOfSquaredDifferenceNormed => {
-                            extrems.min_value_location
-                        }
-                        MatchTemplateMethod::CrossCorrelation
-                        | MatchTemplateMethod::CrossCorrelationNormed
-                        | MatchTemplateMethod::CorrelationCoefficient
-                        | MatchTemplateMethod::CorrelationCoefficientNormed => {
-                            extrems.max_value_location
-                        }
-                    };
-                    Some(Rect {
-                        x,
-                        y,
-                        width: template.width(),
-                        height: template.height(),
-                    })
+                let ((x, y), score) = match method {
+                    MatchTemplateMethod::SumOfSquaredDifference
+                    | MatchTemplateMethod::SumOfSquaredDifferenceNormed => {
+                        (extrems.min_value_location, extrems.min_value)
+                    }
+                    MatchTemplateMethod::CrossCorrelation
+                    | MatchTemplateMethod::CrossCorrelationNormed
+                    | MatchTemplateMethod::CorrelationCoefficient
+                    | MatchTemplateMethod::CorrelationCoefficientNormed => {
+                        (extrems.max_value_location, extrems.max_value)
+                    }
                 };
+                let best_rect = Rect {
+                    x,
+                    y,
+                    width: template.width(),
+                    height: template.height(),
+                };
+                // cprintln!("{log_tag}<green>success!</green>");
+                let rect = success.then(|| best_rect.clone());
+
+                let confidence = confidence(method, score, threshold, success);
 
-                SingleMatcherResult {
+                Ok(SingleMatcherResult {
                     rect,
+                    best_rect,
+                    score,
+                    confidence,
                     matched_img: Box::new(matched_img),
-                }
+                })
             }
             SingleMatcher::Ocr {
                 image,
@@ -171,16 +228,19 @@ impl SingleMatcher {
                     println!("{} {:?}", text, rect)
                 }
 
-                let rect = Some(Rect {
+                let best_rect = Rect {
                     x: 0,
                     y: 0,
                     width: 0,
                     height: 0,
-                });
-                SingleMatcherResult {
-                    rect,
+                };
+                Ok(SingleMatcherResult {
+                    rect: Some(best_rect.clone()),
+                    best_rect,
+                    score: 0.0,
+                    confidence: 1.0,
                     matched_img: Box::new(image.clone().into()),
-                }
+                })
             }
         }
     }
@@ -196,7 +256,24 @@ mod test {
 
     use crate::vision::matcher::test::{get_device_image, get_device_template_prepared, Device};
 
-    use super::SingleMatcher;
+    use super::{confidence, MatchTemplateMethod, SingleMatcher};
+
+    #[test]
+    fn test_confidence() {
+        let sse = MatchTemplateMethod::SumOfSquaredDifferenceNormed;
+        let ccorr = MatchTemplateMethod::CrossCorrelationNormed;
+        assert_eq!(confidence(sse, 0.0, 0.1, true), 1.0);
+        assert_eq!(confidence(sse, 0.1, 0.1, true), 0.5);
+        assert_eq!(confidence(ccorr, 0.9, 0.9, true), 0.5);
+        assert_eq!(confidence(ccorr, 1.0, 0.9, true), 1.0);
+
+        // 阈值为 0 时不会除以 0
+        assert_eq!(confidence(sse, 0.0, 0.0, true), 1.0);
+        assert_eq!(confidence(sse, 0.3, 0.0, false), 0.0);
+        let ccorr_unnormed = MatchTemplateMethod::CrossCorrelation;
+        assert_eq!(confidence(ccorr_unnormed, 0.0, 0.0, true), 1.0);
+        assert_eq!(confidence(ccorr, f32::NAN, 0.9, false), 0.0);
+    }
 
     #[test]
     fn test_ocr() {
@@ -220,7 +297,7 @@ mod test {
             text: "text".to_string(),
             engine,
         };
-        let res = matcher.result();
+        let res = matcher.result().unwrap();
         // println!("{:?}", res);
     }
 
@@ -270,10 +347,13 @@ mod test {
         let res = SingleMatcher::Template {
             image: image.to_luma32f(),
             template: template.to_luma32f(),
+            mask: None,
+            channels: None,
             threshold: None,
             method: None,
         }
-        .result();
+        .result()
+        .unwrap();
         println!("{:?}", res.rect);
     }
 }
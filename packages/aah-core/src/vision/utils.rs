@@ -1,9 +1,9 @@
 use std::ops::RangeInclusive;
 
 use image::{DynamicImage, GenericImage, Luma, Rgb, Rgba};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Rect {
     pub x: u32,
     pub y: u32,
@@ -12,6 +12,11 @@ pub struct Context {
 
 impl Context {
     pub async fn new() -> Self {
+        Self::try_new().await.expect("no available GPU adapter")
+    }
+
+    /// Returns `None` if there is no available adapter or device
+    pub async fn try_new() -> Option<Self> {
         // Instantiates instance of WebGPU
         let instance = wgpu::Instance::default();
 
@@ -21,22 +26,21 @@ impl Context {
                 power_preference: wgpu::PowerPreference::HighPerformance,
                 ..Default::default()
             })
-            .await
-            .unwrap();
+            .await?;
 
         // `request_device` instantiates the feature specific connection to the GPU, defining some parameters,
         //  `features` being the available features.
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor::default(), None)
             .await
-            .unwrap();
+            .ok()?;
 
-        Self {
+        Some(Self {
             instance,
             adapter,
             device,
             queue,
-        }
+        })
     }
 }
 
@@ -3,21 +3,40 @@
 //! # Template matching
 //! The [`template_matching`] module implements GPU accelerated template
 //! matching algorithm.
+//! When there is no available GPU adapter, it falls back to a CPU backend, see
+//! [`template_matching::Backend`].
 //!
 //! The implemented matching methods include Sum of Squared Differences (SSD),
 //! Cross Correlation (CC), Correlation Coefficient (CCoeff), and the normalized version of them.
 //!
+//! The matches can be extracted from the result with non-maximum suppression by
+//! [`template_matching::find_matches_nms`], or the `k` best ones by
+//! [`template_matching::top_k_matches`].
+//!
 //! ## Example
 //!
-//! ```rust
-//! use aah_cv::template_matching::{match_template, MatchTemplateMethod};
+//! ```rust,no_run
+//! use aah_cv::template_matching::{
+//!     find_matches_nms, match_template, MatchTemplateMethod, NmsOptions,
+//! };
 //!
-//! let image = image::open("path/to/image").unwrap();
-//! let template = image::open("path/to/template").unwrap();
-//! let res = match_template(image, template, MatchTemplateMethod::CorrelationCoefficientNormed);
-//! let matches = find_matches(res, template.width, template.height, MatchTemplateMethod::CorrelationCoefficientNormed, 0.9)
+//! let image = image::open("path/to/image").unwrap().to_luma32f();
+//! let template = image::open("path/to/template").unwrap().to_luma32f();
+//! let method = MatchTemplateMethod::CorrelationCoefficientNormed;
+//! let res = match_template(&image, &template, method, false);
+//! let matches = find_matches_nms(
+//!     &res,
+//!     template.width(),
+//!     template.height(),
+//!     method,
+//!     0.9,
+//!     &NmsOptions::default(),
+//! );
 //! ```
 pub mod convolve;
 pub mod gpu;
 pub mod template_matching;
 pub mod utils;
+
+#[cfg(test)]
+mod test_utils;
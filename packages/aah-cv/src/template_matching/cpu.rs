@@ -0,0 +1,293 @@
+//! CPU template matching backend
+//!
+//! Used when there is no available GPU adapter (e.g. on headless servers), or when it is
+//! forced, see [`super::Backend`].
+//!
+//! The cross correlation term `ΣI·T` of every method is computed with FFT, and the window
+//! sums like `ΣI²` and the window means are computed with integral images, so the cost
+//! does not grow with the template size. All the intermediate values are `f64`, and the
+//! results are numerically equivalent to the GPU implementation.
+
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+use image::{ImageBuffer, Luma};
+use rayon::prelude::*;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+use super::{MaskError, MatchTemplateMethod};
+
+type Luma32F = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// Same as [`super::match_template`], but always runs on CPU
+pub fn match_template(
+    image: &Luma32F,
+    template: &Luma32F,
+    method: MatchTemplateMethod,
+    padding: bool,
+) -> Luma32F {
+    let (tw, th) = template.dimensions();
+    if !padding && (tw > image.width() || th > image.height()) {
+        return ImageBuffer::new(0, 0);
+    }
+    let (image, template) = if matches!(
+        method,
+        MatchTemplateMethod::CorrelationCoefficient
+            | MatchTemplateMethod::CorrelationCoefficientNormed
+    ) {
+        (
+            subtract_window_mean(image, tw, th),
+            subtract_window_mean(template, tw, th),
+        )
+    } else {
+        (to_f64(image), to_f64(template))
+    };
+    let image = if padding {
+        image.padded(tw - 1, th - 1)
+    } else {
+        image
+    };
+
+    let (rw, rh) = (
+        image.width - tw as usize + 1,
+        image.height - th as usize + 1,
+    );
+    let ccorr = cross_correlation(&image, &template, rw, rh);
+
+    let normed = matches!(
+        method,
+        MatchTemplateMethod::SumOfSquaredDifference
+            | MatchTemplateMethod::SumOfSquaredDifferenceNormed
+            | MatchTemplateMethod::CrossCorrelationNormed
+            | MatchTemplateMethod::CorrelationCoefficientNormed
+    );
+    let image_sq = normed.then(|| Integral::new(&image, |v| v * v));
+    let template_sq = template.data.iter().map(|v| v * v).sum::<f64>();
+
+    let mut result = vec![0.0; rw * rh];
+    result.par_chunks_mut(rw).enumerate().for_each(|(y, row)| {
+        for (x, res) in row.iter_mut().enumerate() {
+            let cc = ccorr[y * rw + x];
+            let image_sq = || {
+                image_sq
+                    .as_ref()
+                    .unwrap()
+                    .sum(x, y, tw as usize, th as usize)
+            };
+            let value = match method {
+                MatchTemplateMethod::CrossCorrelation
+                | MatchTemplateMethod::CorrelationCoefficient => cc,
+                MatchTemplateMethod::SumOfSquaredDifference => {
+                    (image_sq() - 2.0 * cc + template_sq).max(0.0)
+                }
+                MatchTemplateMethod::SumOfSquaredDifferenceNormed => {
+                    let image_sq = image_sq();
+                    (image_sq - 2.0 * cc + template_sq).max(0.0) / (template_sq * image_sq).sqrt()
+                }
+                MatchTemplateMethod::CrossCorrelationNormed
+                | MatchTemplateMethod::CorrelationCoefficientNormed => {
+                    cc / (image_sq() * template_sq).sqrt()
+                }
+            };
+            *res = value as f32;
+        }
+    });
+
+    ImageBuffer::from_vec(rw as u32, rh as u32, result).unwrap()
+}
+
+/// Same as [`super::match_template_masked`], but always runs on CPU
+pub fn match_template_masked(
+    image: &Luma32F,
+    template: &Luma32F,
+    mask: &Luma32F,
+    method: MatchTemplateMethod,
+) -> Result<Luma32F, MaskError> {
+    super::masked::match_template_masked_with(image, template, mask, method, |image, template| {
+        match_template(
+            image,
+            template,
+            MatchTemplateMethod::CrossCorrelation,
+            false,
+        )
+    })
+}
+
+/// A row-major `f64` image
+#[derive(Clone)]
+struct Plane {
+    width: usize,
+    height: usize,
+    data: Vec<f64>,
+}
+
+impl Plane {
+    fn get(&self, x: usize, y: usize) -> f64 {
+        self.data[y * self.width + x]
+    }
+
+    /// Pads `right` and `bottom` zeros
+    fn padded(&self, right: u32, bottom: u32) -> Self {
+        let (width, height) = (self.width + right as usize, self.height + bottom as usize);
+        let mut data = vec![0.0; width * height];
+        for (dst, src) in data.chunks_mut(width).zip(self.data.chunks(self.width)) {
+            dst[..self.width].copy_from_slice(src);
+        }
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+fn to_f64(image: &Luma32F) -> Plane {
+    Plane {
+        width: image.width() as usize,
+        height: image.height() as usize,
+        data: image.as_raw().iter().map(|v| *v as f64).collect(),
+    }
+}
+
+/// Summed area table of `f(v)`, with an extra zero row and column at the top-left
+struct Integral {
+    width: usize,
+    height: usize,
+    data: Vec<f64>,
+}
+
+impl Integral {
+    fn new(plane: &Plane, f: impl Fn(f64) -> f64) -> Self {
+        let (width, height) = (plane.width + 1, plane.height + 1);
+        let mut data = vec![0.0; width * height];
+        for y in 0..plane.height {
+            let mut row_sum = 0.0;
+            for x in 0..plane.width {
+                row_sum += f(plane.get(x, y));
+                data[(y + 1) * width + x + 1] = data[y * width + x + 1] + row_sum;
+            }
+        }
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Sum of the `w`x`h` window at (`x`, `y`), the part outside the image counts as zero
+    fn sum(&self, x: usize, y: usize, w: usize, h: usize) -> f64 {
+        let (x1, y1) = ((x + w).min(self.width - 1), (y + h).min(self.height - 1));
+        let at = |x: usize, y: usize| self.data[y * self.width + x];
+        at(x1, y1) - at(x, y1) - at(x1, y) + at(x, y)
+    }
+}
+
+/// Subtracts the mean of the zero padded `tw`x`th` window starting at each pixel,
+/// which is what the GPU implementation does before CCOEFF
+fn subtract_window_mean(image: &Luma32F, tw: u32, th: u32) -> Plane {
+    let mut plane = to_f64(image);
+    let integral = Integral::new(&plane, |v| v);
+    let area = (tw * th) as f64;
+    let width = plane.width;
+    plane
+        .data
+        .par_chunks_mut(width)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, v) in row.iter_mut().enumerate() {
+                *v -= integral.sum(x, y, tw as usize, th as usize) / area;
+            }
+        });
+    plane
+}
+
+/// `ΣI·T` of every `rw`x`rh` valid position
+///
+/// Computed as the circular cross correlation of the image and the zero padded template,
+/// which equals the linear one on valid positions since the template never wraps around
+fn cross_correlation(image: &Plane, template: &Plane, rw: usize, rh: usize) -> Vec<f64> {
+    let (w, h) = (image.width, image.height);
+    let (fft, ifft) = {
+        let mut planner = fft_planner();
+        let fft = Fft2d {
+            row: planner.plan_fft_forward(w),
+            col: planner.plan_fft_forward(h),
+        };
+        let ifft = Fft2d {
+            row: planner.plan_fft_inverse(w),
+            col: planner.plan_fft_inverse(h),
+        };
+        (fft, ifft)
+    };
+
+    let mut image_freq = image
+        .data
+        .iter()
+        .map(|v| Complex::new(*v, 0.0))
+        .collect::<Vec<_>>();
+    let mut template_freq = vec![Complex::new(0.0, 0.0); w * h];
+    for (dst, src) in template_freq
+        .chunks_mut(w)
+        .zip(template.data.chunks(template.width))
+    {
+        for (dst, src) in dst.iter_mut().zip(src) {
+            *dst = Complex::new(*src, 0.0);
+        }
+    }
+    rayon::join(
+        || fft.process(&mut image_freq, w, h),
+        || fft.process(&mut template_freq, w, h),
+    );
+
+    image_freq
+        .par_iter_mut()
+        .zip(template_freq.par_iter())
+        .for_each(|(i, t)| *i *= t.conj());
+    ifft.process(&mut image_freq, w, h);
+
+    let scale = 1.0 / (w * h) as f64;
+    image_freq
+        .chunks(w)
+        .take(rh)
+        .flat_map(|row| row[..rw].iter().map(|v| v.re * scale))
+        .collect()
+}
+
+/// The shared planner, which caches the plan of every FFT size it has planned,
+/// since the screenshots and templates are mostly of the same few sizes
+fn fft_planner() -> MutexGuard<'static, FftPlanner<f64>> {
+    static PLANNER: OnceLock<Mutex<FftPlanner<f64>>> = OnceLock::new();
+    PLANNER
+        .get_or_init(|| Mutex::new(FftPlanner::new()))
+        .lock()
+        .unwrap()
+}
+
+struct Fft2d {
+    row: Arc<dyn Fft<f64>>,
+    col: Arc<dyn Fft<f64>>,
+}
+
+impl Fft2d {
+    /// In-place 2D FFT of a row-major `w`x`h` buffer, rows first then columns
+    fn process(&self, data: &mut [Complex<f64>], w: usize, h: usize) {
+        data.par_chunks_mut(w).for_each(|row| self.row.process(row));
+        let mut transposed = transpose(data, w, h);
+        transposed
+            .par_chunks_mut(h)
+            .for_each(|col| self.col.process(col));
+        data.copy_from_slice(&transpose(&transposed, h, w));
+    }
+}
+
+fn transpose(data: &[Complex<f64>], w: usize, h: usize) -> Vec<Complex<f64>> {
+    let mut transposed = vec![Complex::new(0.0, 0.0); w * h];
+    transposed
+        .par_chunks_mut(h)
+        .enumerate()
+        .for_each(|(x, col)| {
+            for (y, v) in col.iter_mut().enumerate() {
+                *v = data[y * w + x];
+            }
+        });
+    transposed
+}
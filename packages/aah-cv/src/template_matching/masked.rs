@@ -0,0 +1,120 @@
+//! Masked template matching
+//!
+//! Every masked term is a cross correlation of the image (or its square) with the mask
+//! or the masked template, so it is computed with the CCORR of a backend:
+//! - `Σm·I·T` = CCORR(I, m·T)
+//! - `Σm·I²` = CCORR(I², m)
+//! - `Σm·I` = CCORR(I, m)
+//!
+//! For CCOEFF, `Σm·(I - Ī)·(T - T̄)` equals `Σm·I·(T - T̄)`, where `Ī` and `T̄` are the
+//! masked means of the window and the template.
+
+use std::{error::Error, fmt::Display};
+
+use image::{ImageBuffer, Luma};
+
+use super::MatchTemplateMethod;
+
+type Luma32F = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// An invalid mask passed to [`match_template_masked`](super::match_template_masked)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaskError {
+    /// The mask does not have the same size as the template
+    SizeMismatch {
+        template: (u32, u32),
+        mask: (u32, u32),
+    },
+    /// No pixel of the mask is positive, so there is nothing to match
+    Empty,
+}
+
+impl Display for MaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaskError::SizeMismatch { template, mask } => write!(
+                f,
+                "the size of the mask {mask:?} differs from the template {template:?}"
+            ),
+            MaskError::Empty => write!(f, "the mask has no positive pixel"),
+        }
+    }
+}
+
+impl Error for MaskError {}
+
+/// Checks that `mask` can be used to match `template`
+pub fn check_mask(template: &Luma32F, mask: &Luma32F) -> Result<(), MaskError> {
+    if template.dimensions() != mask.dimensions() {
+        return Err(MaskError::SizeMismatch {
+            template: template.dimensions(),
+            mask: mask.dimensions(),
+        });
+    }
+    if !mask.as_raw().iter().any(|m| *m > 0.0) {
+        return Err(MaskError::Empty);
+    }
+    Ok(())
+}
+
+pub(super) fn match_template_masked_with(
+    image: &Luma32F,
+    template: &Luma32F,
+    mask: &Luma32F,
+    method: MatchTemplateMethod,
+    ccorr: impl Fn(&Luma32F, &Luma32F) -> Luma32F,
+) -> Result<Luma32F, MaskError> {
+    check_mask(template, mask)?;
+    let zip = |a: &Luma32F, b: &Luma32F, f: &dyn Fn(f32, f32) -> f32| {
+        let data = a.as_raw().iter().zip(b.as_raw()).map(|(a, b)| f(*a, *b));
+        ImageBuffer::from_vec(a.width(), a.height(), data.collect::<Vec<_>>()).unwrap()
+    };
+    let sum = |a: &Luma32F, b: &Luma32F, f: &dyn Fn(f32, f32) -> f32| {
+        a.as_raw()
+            .iter()
+            .zip(b.as_raw())
+            .map(|(a, b)| f(*a, *b) as f64)
+            .sum::<f64>()
+    };
+    let mask_sum = mask.as_raw().iter().map(|m| *m as f64).sum::<f64>();
+
+    let (template, template_sq) = match method {
+        MatchTemplateMethod::CorrelationCoefficient
+        | MatchTemplateMethod::CorrelationCoefficientNormed => {
+            let mean = sum(template, mask, &|t, m| t * m) / mask_sum;
+            let centered = zip(template, mask, &|t, _| t - mean as f32);
+            let centered_sq = sum(&centered, mask, &|t, m| m * t * t);
+            (centered, centered_sq)
+        }
+        _ => {
+            let template_sq = sum(template, mask, &|t, m| m * t * t);
+            (template.clone(), template_sq)
+        }
+    };
+    let cc = ccorr(image, &zip(&template, mask, &|t, m| t * m));
+    if matches!(
+        method,
+        MatchTemplateMethod::CrossCorrelation | MatchTemplateMethod::CorrelationCoefficient
+    ) {
+        return Ok(cc);
+    }
+
+    let image_sq = ccorr(&zip(image, image, &|i, _| i * i), mask);
+    let image_sq = match method {
+        MatchTemplateMethod::CorrelationCoefficientNormed => {
+            let image_sum = ccorr(image, mask);
+            zip(&image_sq, &image_sum, &|sq, s| {
+                sq - (s as f64 * s as f64 / mask_sum) as f32
+            })
+        }
+        _ => image_sq,
+    };
+    let template_sq = template_sq as f32;
+    Ok(zip(&cc, &image_sq, &|cc, image_sq| match method {
+        MatchTemplateMethod::SumOfSquaredDifference => (image_sq - 2.0 * cc + template_sq).max(0.0),
+        MatchTemplateMethod::SumOfSquaredDifferenceNormed => {
+            (image_sq - 2.0 * cc + template_sq).max(0.0) / (template_sq * image_sq).sqrt()
+        }
+        _ => cc / (image_sq.max(0.0) * template_sq).sqrt(),
+    }))
+}
@@ -1,6 +1,7 @@
 use std::{
+    env,
     fmt::Display,
-    sync::{Arc, Mutex, OnceLock},
+    sync::{Mutex, OnceLock},
 };
 
 use bytemuck::{Pod, Zeroable};
@@ -14,12 +15,30 @@ use wgpu::{
 
 use crate::gpu::Context;
 
+pub mod cpu;
+mod masked;
+mod nms;
+
+pub use masked::{check_mask, MaskError};
+pub use nms::{find_matches_nms, non_max_suppression, refine_subpixel, top_k_matches, NmsOptions};
+
+/// Set this environment variable to `cpu` to force the CPU backend, see [`Backend::current`]
+pub const BACKEND_ENV: &str = "AAH_CV_BACKEND";
+
 #[derive(Clone, Debug)]
 pub struct Match {
     pub location: (u32, u32),
     pub value: f32,
 }
 
+impl Match {
+    /// `location` refined to subpixel precision on the matching result `input`,
+    /// see [`refine_subpixel`]
+    pub fn position(&self, input: &ImageBuffer<Luma<f32>, Vec<f32>>) -> (f32, f32) {
+        refine_subpixel(input, self.location)
+    }
+}
+
 pub fn find_matches(
     input: &ImageBuffer<Luma<f32>, Vec<f32>>,
     template_width: u32,
@@ -104,20 +123,145 @@ impl Display for MatchTemplateMethod {
     }
 }
 
+/// The backend used by [`match_template`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// The wgpu compute shaders
+    Gpu,
+    /// [`cpu::match_template`]
+    Cpu,
+}
+
+impl Backend {
+    /// The backend in use, decided once on the first call:
+    /// - [`Backend::Cpu`] if the `cpu` feature is enabled, or [`BACKEND_ENV`] is `cpu`
+    /// - otherwise [`Backend::Gpu`] if there is an available adapter, or [`Backend::Cpu`] if not
+    pub fn current() -> Self {
+        match matcher() {
+            Some(_) => Self::Gpu,
+            None => Self::Cpu,
+        }
+    }
+}
+
+/// Matches `template` on every position of `image`, and on the zero padded positions past
+/// its right and bottom edges if `padding`
+///
+/// The result is empty if the template is larger than the image without padding.
 pub fn match_template(
     image: &ImageBuffer<Luma<f32>, Vec<f32>>,
     template: &ImageBuffer<Luma<f32>, Vec<f32>>,
     method: MatchTemplateMethod,
     padding: bool,
 ) -> ImageBuffer<Luma<f32>, Vec<f32>> {
-    let mut matcher = matcher().lock().unwrap();
-    matcher.match_template(image, template, method, padding)
+    if !padding && (template.width() > image.width() || template.height() > image.height()) {
+        return ImageBuffer::new(0, 0);
+    }
+    match matcher() {
+        Some(matcher) => {
+            let mut matcher = matcher.lock().unwrap();
+            matcher.match_template(image, template, method, padding)
+        }
+        None => cpu::match_template(image, template, method, padding),
+    }
 }
 
-/// internal
-fn matcher() -> &'static Arc<Mutex<Matcher>> {
-    static MATCHER: OnceLock<Arc<Mutex<Matcher>>> = OnceLock::new();
-    MATCHER.get_or_init(|| Arc::new(Mutex::new(Matcher::new())))
+/// Masked template matching, only the pixels where `mask` is non-zero are counted,
+/// weighted by the mask value
+///
+/// `mask` should have the same size as `template`, e.g. its alpha channel, and at least one
+/// positive pixel, otherwise a [`MaskError`] is returned, see [`check_mask`]
+pub fn match_template_masked(
+    image: &ImageBuffer<Luma<f32>, Vec<f32>>,
+    template: &ImageBuffer<Luma<f32>, Vec<f32>>,
+    mask: &ImageBuffer<Luma<f32>, Vec<f32>>,
+    method: MatchTemplateMethod,
+) -> Result<ImageBuffer<Luma<f32>, Vec<f32>>, MaskError> {
+    masked::match_template_masked_with(image, template, mask, method, |image, template| {
+        match_template(
+            image,
+            template,
+            MatchTemplateMethod::CrossCorrelation,
+            false,
+        )
+    })
+}
+
+/// Multi-channel template matching, e.g. over the RGB or HSV channels, so that templates
+/// that only differ in color do not match each other
+///
+/// Each channel of `images` is matched with the same channel of `templates` by
+/// [`match_template`], or [`match_template_masked`] if `mask` is given, so it runs on the
+/// current [`Backend`]. The channels are not batched: on the GPU backend every channel is a
+/// separate upload, dispatch and readback, so the cost grows linearly with the number of
+/// channels. The result is the mean of the results of the channels weighted by `weights`,
+/// channels with zero weight are skipped. An invalid `mask` is reported as with
+/// [`match_template_masked`].
+///
+/// # Panics
+///
+/// Panics if the numbers of channels differ, or if `weights` has a negative value or no
+/// positive value. Callers taking weights from user input should validate them first.
+pub fn match_template_channels(
+    images: &[ImageBuffer<Luma<f32>, Vec<f32>>],
+    templates: &[ImageBuffer<Luma<f32>, Vec<f32>>],
+    weights: &[f32],
+    mask: Option<&ImageBuffer<Luma<f32>, Vec<f32>>>,
+    method: MatchTemplateMethod,
+) -> Result<ImageBuffer<Luma<f32>, Vec<f32>>, MaskError> {
+    assert!(
+        images.len() == templates.len() && images.len() == weights.len(),
+        "images, templates and weights should have the same number of channels"
+    );
+    assert!(
+        weights.iter().all(|w| *w >= 0.0),
+        "weights should not be negative"
+    );
+    let total = weights.iter().sum::<f32>();
+    assert!(
+        total > 0.0,
+        "at least one channel should have a positive weight"
+    );
+
+    let mut result: Option<ImageBuffer<Luma<f32>, Vec<f32>>> = None;
+    for ((image, template), weight) in images.iter().zip(templates).zip(weights) {
+        if *weight == 0.0 {
+            continue;
+        }
+        let res = match mask {
+            Some(mask) => match_template_masked(image, template, mask, method)?,
+            None => match_template(image, template, method, false),
+        };
+        let weight = weight / total;
+        match &mut result {
+            Some(result) => {
+                for (acc, v) in result.iter_mut().zip(res.as_raw()) {
+                    *acc += v * weight;
+                }
+            }
+            None => {
+                result = Some(ImageBuffer::from_fn(res.width(), res.height(), |x, y| {
+                    Luma([res.get_pixel(x, y).0[0] * weight])
+                }))
+            }
+        }
+    }
+    Ok(result.unwrap())
+}
+
+/// internal, `None` if using the CPU backend
+fn matcher() -> Option<&'static Mutex<Matcher>> {
+    static MATCHER: OnceLock<Option<Mutex<Matcher>>> = OnceLock::new();
+    MATCHER
+        .get_or_init(|| {
+            let force_cpu = cfg!(feature = "cpu")
+                || env::var(BACKEND_ENV).is_ok_and(|backend| backend.eq_ignore_ascii_case("cpu"));
+            if force_cpu {
+                return None;
+            }
+            Matcher::try_new().map(Mutex::new)
+        })
+        .as_ref()
 }
 
 #[derive(Clone, Copy, Pod, Zeroable)]
@@ -150,8 +294,9 @@ struct Matcher {
 }
 
 impl Matcher {
-    fn new() -> Self {
-        let ctx = pollster::block_on(Context::new());
+    /// Returns `None` if there is no available GPU adapter
+    fn try_new() -> Option<Self> {
+        let ctx = pollster::block_on(Context::try_new())?;
         let Context { device, .. } = &ctx;
 
         let bind_group_layout = ctx
@@ -279,7 +424,7 @@ impl Matcher {
                 cache: None,
             });
 
-        Matcher {
+        Some(Matcher {
             ctx,
             input_buffer: None,
             template_buffer: None,
@@ -295,7 +440,7 @@ impl Matcher {
             pipeline_sqdiff_normed,
             pipeline_ccoeff,
             pipeline_ccoeff_normed,
-        }
+        })
     }
 
     fn create_new_bind_group(&self) -> BindGroup {
@@ -570,7 +715,7 @@ fn prepare_buffer_init_with_image(
 
 #[cfg(test)]
 mod test {
-    use crate::utils::save_luma32f;
+    use crate::{test_utils::noise, utils::save_luma32f};
 
     use super::*;
     use std::{error::Error, fs, path::Path, time::Instant};
@@ -633,6 +778,313 @@ mod test {
         Ok(())
     }
 
+    /// The GPU implementation executed directly on CPU
+    fn naive_match_template(
+        image: &ImageBuffer<Luma<f32>, Vec<f32>>,
+        template: &ImageBuffer<Luma<f32>, Vec<f32>>,
+        method: MatchTemplateMethod,
+        padding: bool,
+    ) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+        let (tw, th) = template.dimensions();
+        let (image, template) = if matches!(
+            method,
+            MatchTemplateMethod::CorrelationCoefficient
+                | MatchTemplateMethod::CorrelationCoefficientNormed
+        ) {
+            let avg_kernel = ImageBuffer::from_pixel(tw, th, Luma([1.0 / (tw * th) as f32]));
+            let sub_avg = |image: &ImageBuffer<Luma<f32>, Vec<f32>>| {
+                let avg = naive_match_template(
+                    image,
+                    &avg_kernel,
+                    MatchTemplateMethod::CrossCorrelation,
+                    true,
+                );
+                ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+                    Luma([image.get_pixel(x, y).0[0] - avg.get_pixel(x, y).0[0]])
+                })
+            };
+            (sub_avg(image), sub_avg(template))
+        } else {
+            (image.clone(), template.clone())
+        };
+        let (w, h) = if padding {
+            (image.width() + tw - 1, image.height() + th - 1)
+        } else {
+            image.dimensions()
+        };
+        let pixel = |x: u32, y: u32| match x < image.width() && y < image.height() {
+            true => image.get_pixel(x, y).0[0],
+            false => 0.0,
+        };
+        ImageBuffer::from_fn(w - tw + 1, h - th + 1, |x, y| {
+            let (mut cc, mut sqdiff, mut image_sq, mut template_sq) = (0.0, 0.0, 0.0, 0.0);
+            for j in 0..th {
+                for i in 0..tw {
+                    let (iv, tv) = (pixel(x + i, y + j), template.get_pixel(i, j).0[0]);
+                    cc += iv * tv;
+                    sqdiff += (iv - tv) * (iv - tv);
+                    image_sq += iv * iv;
+                    template_sq += tv * tv;
+                }
+            }
+            Luma([match method {
+                MatchTemplateMethod::SumOfSquaredDifference => sqdiff,
+                MatchTemplateMethod::SumOfSquaredDifferenceNormed => {
+                    sqdiff / (template_sq * image_sq).sqrt()
+                }
+                MatchTemplateMethod::CrossCorrelation
+                | MatchTemplateMethod::CorrelationCoefficient => cc,
+                MatchTemplateMethod::CrossCorrelationNormed
+                | MatchTemplateMethod::CorrelationCoefficientNormed => {
+                    cc / (image_sq * template_sq).sqrt()
+                }
+            }])
+        })
+    }
+
+    #[test]
+    fn test_gpu_result_bounds() {
+        if Backend::current() != Backend::Gpu {
+            return;
+        }
+        // the 33x17 result is not a multiple of the 8x8 workgroup, the invocations past
+        // the right edge used to overwrite the start of the next row
+        let image =
+            ImageBuffer::from_fn(41, 21, |x, y| Luma([((x * 7 + y * 13) % 17) as f32 / 17.0]));
+        let template =
+            ImageBuffer::from_fn(9, 5, |x, y| Luma([((x * 5 + y * 3) % 11) as f32 / 11.0]));
+        for method in [
+            MatchTemplateMethod::SumOfSquaredDifference,
+            MatchTemplateMethod::SumOfSquaredDifferenceNormed,
+            MatchTemplateMethod::CrossCorrelation,
+            MatchTemplateMethod::CrossCorrelationNormed,
+            MatchTemplateMethod::CorrelationCoefficient,
+            MatchTemplateMethod::CorrelationCoefficientNormed,
+        ] {
+            let expected = naive_match_template(&image, &template, method, false);
+            let res = match_template(&image, &template, method, false);
+            assert_eq!(res.dimensions(), expected.dimensions());
+            for (i, (a, b)) in res.as_raw().iter().zip(expected.as_raw()).enumerate() {
+                assert!(
+                    (a - b).abs() <= 1e-3 * b.abs().max(1.0),
+                    "{method} at {i}: {a} != {b}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_cpu_backend() {
+        // deterministic noise with the template planted at (23, 11)
+        let mut noise = noise(42);
+        let mut image = ImageBuffer::from_fn(61, 37, |_, _| Luma([noise()]));
+        let template = ImageBuffer::from_fn(9, 7, |_, _| Luma([noise()]));
+        for (x, y, p) in template.enumerate_pixels() {
+            image.put_pixel(x + 23, y + 11, *p);
+        }
+
+        for method in [
+            MatchTemplateMethod::SumOfSquaredDifference,
+            MatchTemplateMethod::SumOfSquaredDifferenceNormed,
+            MatchTemplateMethod::CrossCorrelation,
+            MatchTemplateMethod::CrossCorrelationNormed,
+            MatchTemplateMethod::CorrelationCoefficient,
+            MatchTemplateMethod::CorrelationCoefficientNormed,
+        ] {
+            for padding in [false, true] {
+                let expected = naive_match_template(&image, &template, method, padding);
+                let res = cpu::match_template(&image, &template, method, padding);
+                assert_eq!(res.dimensions(), expected.dimensions());
+                for (a, b) in res.as_raw().iter().zip(expected.as_raw()) {
+                    assert!(
+                        (a - b).abs() <= 1e-4 * b.abs().max(1.0),
+                        "{method}: {a} != {b}"
+                    );
+                }
+                if Backend::current() == Backend::Gpu {
+                    let res = match_template(&image, &template, method, padding);
+                    for (a, b) in res.as_raw().iter().zip(expected.as_raw()) {
+                        assert!(
+                            (a - b).abs() <= 1e-3 * b.abs().max(1.0),
+                            "{method}: {a} != {b}"
+                        );
+                    }
+                }
+            }
+
+            // the unnormalized methods and CCOEFF (which subtracts the mean of the window
+            // starting at each pixel instead of the centered one) do not peak at the template
+            if matches!(
+                method,
+                MatchTemplateMethod::SumOfSquaredDifference
+                    | MatchTemplateMethod::SumOfSquaredDifferenceNormed
+                    | MatchTemplateMethod::CrossCorrelationNormed
+            ) {
+                let res = cpu::match_template(&image, &template, method, false);
+                let threshold = match method {
+                    MatchTemplateMethod::SumOfSquaredDifference
+                    | MatchTemplateMethod::SumOfSquaredDifferenceNormed => f32::MAX,
+                    _ => f32::MIN,
+                };
+                let best = find_matches(&res, 9, 7, method, threshold);
+                assert_eq!(best[0].location, (23, 11), "{method}");
+            }
+
+            // no valid position for a template larger than the image
+            let res = cpu::match_template(&template, &image, method, false);
+            assert_eq!(res.dimensions(), (0, 0));
+            let res = cpu::match_template(&template, &image, method, true);
+            assert_eq!(res.dimensions(), template.dimensions());
+        }
+    }
+
+    #[test]
+    fn test_masked_matching() {
+        let mut noise = noise(7);
+        // only the left half of the template is opaque, the rest of the planted area is noise
+        let template = ImageBuffer::from_fn(10, 8, |_, _| Luma([noise()]));
+        let mask = ImageBuffer::from_fn(10, 8, |x, _| Luma([if x < 5 { 1.0 } else { 0.0 }]));
+        let mut image = ImageBuffer::from_fn(50, 40, |_, _| Luma([noise()]));
+        for (x, y, p) in template.enumerate_pixels() {
+            if x < 5 {
+                image.put_pixel(x + 17, y + 21, *p);
+            }
+        }
+
+        for method in [
+            MatchTemplateMethod::SumOfSquaredDifference,
+            MatchTemplateMethod::SumOfSquaredDifferenceNormed,
+            MatchTemplateMethod::CrossCorrelationNormed,
+            MatchTemplateMethod::CorrelationCoefficientNormed,
+        ] {
+            // the masked terms computed directly
+            let (tw, th) = template.dimensions();
+            let expected = ImageBuffer::from_fn(50 - tw + 1, 40 - th + 1, |x, y| {
+                let pixels = template.enumerate_pixels().filter(|(i, _, _)| *i < 5);
+                let pairs = pixels
+                    .map(|(i, j, t)| (image.get_pixel(x + i, y + j).0[0], t.0[0]))
+                    .collect::<Vec<_>>();
+                let n = pairs.len() as f32;
+                let (image_mean, template_mean) = match method {
+                    MatchTemplateMethod::CorrelationCoefficientNormed => (
+                        pairs.iter().map(|p| p.0).sum::<f32>() / n,
+                        pairs.iter().map(|p| p.1).sum::<f32>() / n,
+                    ),
+                    _ => (0.0, 0.0),
+                };
+                let (mut cc, mut sqdiff, mut image_sq, mut template_sq) = (0.0, 0.0, 0.0, 0.0);
+                for (iv, tv) in pairs {
+                    let (iv, tv) = (iv - image_mean, tv - template_mean);
+                    cc += iv * tv;
+                    sqdiff += (iv - tv) * (iv - tv);
+                    image_sq += iv * iv;
+                    template_sq += tv * tv;
+                }
+                Luma([match method {
+                    MatchTemplateMethod::SumOfSquaredDifference => sqdiff,
+                    MatchTemplateMethod::SumOfSquaredDifferenceNormed => {
+                        sqdiff / (image_sq * template_sq).sqrt()
+                    }
+                    _ => cc / (image_sq * template_sq).sqrt(),
+                }])
+            });
+
+            for res in [
+                cpu::match_template_masked(&image, &template, &mask, method).unwrap(),
+                match_template_masked(&image, &template, &mask, method).unwrap(),
+            ] {
+                for (a, b) in res.as_raw().iter().zip(expected.as_raw()) {
+                    assert!((a - b).abs() <= 1e-3, "{method}: {a} != {b}");
+                }
+                let threshold = match method {
+                    MatchTemplateMethod::SumOfSquaredDifference
+                    | MatchTemplateMethod::SumOfSquaredDifferenceNormed => f32::MAX,
+                    _ => f32::MIN,
+                };
+                let best = find_matches(&res, tw, th, method, threshold);
+                assert_eq!(best[0].location, (17, 21), "{method}");
+            }
+        }
+
+        // invalid masks are errors instead of NaN or a panic
+        let method = MatchTemplateMethod::CrossCorrelationNormed;
+        let empty = ImageBuffer::from_pixel(10, 8, Luma([0.0]));
+        assert_eq!(
+            match_template_masked(&image, &template, &empty, method).unwrap_err(),
+            MaskError::Empty
+        );
+        let smaller = ImageBuffer::from_pixel(5, 8, Luma([1.0]));
+        assert!(matches!(
+            match_template_masked(&image, &template, &smaller, method),
+            Err(MaskError::SizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_channels_matching() {
+        let mut noise = noise(11);
+        let templates = [0, 1, 2].map(|_| ImageBuffer::from_fn(12, 9, |_, _| Luma([noise()])));
+        let mut images = [0, 1, 2].map(|_| ImageBuffer::from_fn(60, 40, |_, _| Luma([noise()])));
+        // the planted template at (5, 7), and a decoy with the channels swapped at (40, 25),
+        // which has the same mean of the channels
+        for (c, image) in images.iter_mut().enumerate() {
+            for (x, y, p) in templates[c].enumerate_pixels() {
+                image.put_pixel(x + 5, y + 7, *p);
+                image.put_pixel(x + 40, y + 25, *templates[2 - c].get_pixel(x, y));
+            }
+        }
+        let mean = |channels: &[ImageBuffer<Luma<f32>, Vec<f32>>; 3]| {
+            let (w, h) = channels[0].dimensions();
+            ImageBuffer::from_fn(w, h, |x, y| {
+                Luma([channels.iter().map(|c| c.get_pixel(x, y).0[0]).sum::<f32>() / 3.0])
+            })
+        };
+
+        for method in [
+            MatchTemplateMethod::SumOfSquaredDifference,
+            MatchTemplateMethod::SumOfSquaredDifferenceNormed,
+            MatchTemplateMethod::CrossCorrelationNormed,
+        ] {
+            let threshold = match method {
+                MatchTemplateMethod::SumOfSquaredDifference
+                | MatchTemplateMethod::SumOfSquaredDifferenceNormed => f32::MAX,
+                _ => f32::MIN,
+            };
+            let gray = match_template(&mean(&images), &mean(&templates), method, false);
+            let res = match_template_channels(&images, &templates, &[1.0, 2.0, 1.0], None, method)
+                .unwrap();
+            assert_eq!(res.dimensions(), gray.dimensions());
+
+            // the result is the weighted mean of the channels
+            let channels =
+                [0, 1, 2].map(|c| match_template(&images[c], &templates[c], method, false));
+            for (i, v) in res.as_raw().iter().enumerate() {
+                let expected = (channels[0].as_raw()[i]
+                    + 2.0 * channels[1].as_raw()[i]
+                    + channels[2].as_raw()[i])
+                    / 4.0;
+                assert!(
+                    (v - expected).abs() <= 1e-3 * expected.abs().max(1.0),
+                    "{method}"
+                );
+            }
+
+            // the decoy matches as well as the template in gray, but not in channels
+            let at = |res: &ImageBuffer<Luma<f32>, Vec<f32>>, x, y| res.get_pixel(x, y).0[0];
+            assert!(
+                (at(&gray, 5, 7) - at(&gray, 40, 25)).abs() <= 1e-3,
+                "{method}"
+            );
+            assert!(is_x_more_match_than_y(
+                at(&res, 5, 7),
+                at(&res, 40, 25),
+                method
+            ));
+            let best = find_matches(&res, 12, 9, method, threshold);
+            assert_eq!(best[0].location, (5, 7), "{method}");
+        }
+    }
+
     #[test]
     fn test_btn_matching() -> Result<(), Box<dyn Error>> {
         let images = ["in_battle", "1-4_deploying", "1-4_deploying_direction"].map(|name| {
@@ -0,0 +1,233 @@
+//! Non-maximum suppression of the matching result
+//!
+//! [`super::find_matches`] merges every match within the template size of a better one
+//! while scanning, so the result depends on the scan order. The functions here collect the
+//! local peaks of the result instead, sort them by score and keep a peak only if it does
+//! not overlap too much with the better ones.
+
+use image::{ImageBuffer, Luma};
+use serde::{Deserialize, Serialize};
+
+use super::{is_x_more_match_than_y, Match, MatchTemplateMethod};
+
+type Luma32F = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// Options of the non-maximum suppression
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NmsOptions {
+    /// A match is suppressed if its IoU with a better one is larger than this,
+    /// defaults to 0.0, i.e. the matches never overlap
+    pub iou_threshold: f32,
+    /// Keep at most this many matches, defaults to all of them
+    pub top_k: Option<usize>,
+}
+
+impl Default for NmsOptions {
+    fn default() -> Self {
+        Self {
+            iou_threshold: 0.0,
+            top_k: None,
+        }
+    }
+}
+
+/// Intersection over union of two `width`x`height` boxes at `a` and `b`
+fn iou(a: (u32, u32), b: (u32, u32), width: u32, height: u32) -> f32 {
+    let overlap_w = width.saturating_sub(a.0.abs_diff(b.0));
+    let overlap_h = height.saturating_sub(a.1.abs_diff(b.1));
+    let intersection = (overlap_w * overlap_h) as f32;
+    let area = (width * height) as f32;
+    intersection / (2.0 * area - intersection)
+}
+
+/// Sorts `matches` by score and greedily removes the ones whose IoU with a better kept
+/// match is larger than `options.iou_threshold`, every match is a `width`x`height` box
+pub fn non_max_suppression(
+    mut matches: Vec<Match>,
+    width: u32,
+    height: u32,
+    method: MatchTemplateMethod,
+    options: &NmsOptions,
+) -> Vec<Match> {
+    matches.sort_by(|a, b| {
+        if is_x_more_match_than_y(a.value, b.value, method) {
+            std::cmp::Ordering::Less
+        } else if is_x_more_match_than_y(b.value, a.value, method) {
+            std::cmp::Ordering::Greater
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    });
+
+    let top_k = options.top_k.unwrap_or(usize::MAX);
+    let mut kept: Vec<Match> = Vec::new();
+    for m in matches {
+        if kept.len() >= top_k {
+            break;
+        }
+        if kept
+            .iter()
+            .all(|k| iou(k.location, m.location, width, height) <= options.iou_threshold)
+        {
+            kept.push(m);
+        }
+    }
+    kept
+}
+
+/// Whether no 8-neighbor of (`x`, `y`) matches better than it
+fn is_peak(input: &Luma32F, x: u32, y: u32, method: MatchTemplateMethod) -> bool {
+    let value = input.get_pixel(x, y).0[0];
+    let xs = x.saturating_sub(1)..=(x + 1).min(input.width() - 1);
+    xs.flat_map(|nx| {
+        let ys = y.saturating_sub(1)..=(y + 1).min(input.height() - 1);
+        ys.map(move |ny| (nx, ny))
+    })
+    .all(|(nx, ny)| !is_x_more_match_than_y(input.get_pixel(nx, ny).0[0], value, method))
+}
+
+/// Finds the local peaks of the matching result `input` that are better than `threshold`,
+/// and applies [`non_max_suppression`] to them
+pub fn find_matches_nms(
+    input: &Luma32F,
+    template_width: u32,
+    template_height: u32,
+    method: MatchTemplateMethod,
+    threshold: f32,
+    options: &NmsOptions,
+) -> Vec<Match> {
+    let mut peaks = Vec::new();
+    for (x, y, pixel) in input.enumerate_pixels() {
+        let value = pixel.0[0];
+        // NaN is neither better nor worse than anything
+        if value.is_nan() || is_x_more_match_than_y(threshold, value, method) {
+            continue;
+        }
+        if is_peak(input, x, y, method) {
+            peaks.push(Match {
+                location: (x, y),
+                value,
+            });
+        }
+    }
+    non_max_suppression(peaks, template_width, template_height, method, options)
+}
+
+/// The `k` best matches of the matching result `input` regardless of the threshold,
+/// suppressed by `iou_threshold`, see [`find_matches_nms`]
+pub fn top_k_matches(
+    input: &Luma32F,
+    template_width: u32,
+    template_height: u32,
+    method: MatchTemplateMethod,
+    k: usize,
+    iou_threshold: f32,
+) -> Vec<Match> {
+    let threshold = match method {
+        MatchTemplateMethod::SumOfSquaredDifference
+        | MatchTemplateMethod::SumOfSquaredDifferenceNormed => f32::INFINITY,
+        _ => f32::NEG_INFINITY,
+    };
+    let options = NmsOptions {
+        iou_threshold,
+        top_k: Some(k),
+    };
+    find_matches_nms(
+        input,
+        template_width,
+        template_height,
+        method,
+        threshold,
+        &options,
+    )
+}
+
+/// Refines the peak at `location` of the matching result to subpixel precision, by fitting
+/// a parabola to it and its neighbors along each axis
+///
+/// The offset is within 0.5 pixel, and is 0 on the border or when the neighbors are flat.
+pub fn refine_subpixel(input: &Luma32F, location: (u32, u32)) -> (f32, f32) {
+    let (x, y) = location;
+    let at = |x: u32, y: u32| input.get_pixel(x, y).0[0];
+    let offset = |prev: f32, center: f32, next: f32| {
+        let curvature = prev - 2.0 * center + next;
+        if curvature == 0.0 || !curvature.is_finite() {
+            return 0.0;
+        }
+        (0.5 * (prev - next) / curvature).clamp(-0.5, 0.5)
+    };
+    let dx = if x > 0 && x + 1 < input.width() {
+        offset(at(x - 1, y), at(x, y), at(x + 1, y))
+    } else {
+        0.0
+    };
+    let dy = if y > 0 && y + 1 < input.height() {
+        offset(at(x, y - 1), at(x, y), at(x, y + 1))
+    } else {
+        0.0
+    };
+    (x as f32 + dx, y as f32 + dy)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nms() {
+        // two overlapping peaks at (10, 10) and (14, 10), and a separate one at (40, 20)
+        let peaks = [((10, 10), 0.9), ((14, 10), 0.95), ((40, 20), 0.8)];
+        let input = ImageBuffer::from_fn(60, 40, |x, y| {
+            let value = peaks
+                .iter()
+                .map(|((px, py), v)| {
+                    let d2 = (x as f32 - *px as f32).powi(2) + (y as f32 - *py as f32).powi(2);
+                    v * (-d2 / 4.0).exp()
+                })
+                .fold(0.0, f32::max);
+            Luma([value])
+        });
+        let method = MatchTemplateMethod::CrossCorrelationNormed;
+
+        let matches = find_matches_nms(&input, 10, 10, method, 0.5, &NmsOptions::default());
+        let locations = matches.iter().map(|m| m.location).collect::<Vec<_>>();
+        assert_eq!(locations, vec![(14, 10), (40, 20)]);
+        assert_eq!(matches[0].value, 0.95);
+
+        // IoU of the overlapping boxes is 6 * 10 / (200 - 60) ≈ 0.43
+        let options = NmsOptions {
+            iou_threshold: 0.5,
+            top_k: None,
+        };
+        let matches = find_matches_nms(&input, 10, 10, method, 0.5, &options);
+        let locations = matches.iter().map(|m| m.location).collect::<Vec<_>>();
+        assert_eq!(locations, vec![(14, 10), (10, 10), (40, 20)]);
+
+        let matches = top_k_matches(&input, 10, 10, method, 2, 0.5);
+        let locations = matches.iter().map(|m| m.location).collect::<Vec<_>>();
+        assert_eq!(locations, vec![(14, 10), (10, 10)]);
+
+        // the minimum for SQDIFF
+        let inverted =
+            ImageBuffer::from_fn(60, 40, |x, y| Luma([1.0 - input.get_pixel(x, y).0[0]]));
+        let method = MatchTemplateMethod::SumOfSquaredDifference;
+        let matches = top_k_matches(&inverted, 10, 10, method, 2, 0.0);
+        let locations = matches.iter().map(|m| m.location).collect::<Vec<_>>();
+        assert_eq!(locations, vec![(14, 10), (40, 20)]);
+    }
+
+    #[test]
+    fn test_refine_subpixel() {
+        // a parabola peaked at (12.3, 7.8)
+        let input = ImageBuffer::from_fn(30, 20, |x, y| {
+            Luma([-(x as f32 - 12.3).powi(2) - (y as f32 - 7.8).powi(2)])
+        });
+        let (x, y) = refine_subpixel(&input, (12, 8));
+        assert!(
+            (x - 12.3).abs() < 1e-3 && (y - 7.8).abs() < 1e-3,
+            "{x}, {y}"
+        );
+        assert_eq!(refine_subpixel(&input, (0, 8)).0, 0.0);
+    }
+}
@@ -34,6 +34,11 @@ fn main_sqdiff(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var template_width = uniforms.template_width;
     var template_height = uniforms.template_height;
 
+    // the dispatched workgroups may exceed the result, which would overwrite the next row
+    if (x > input_width - template_width || y > input_height - template_height) {
+        return;
+    }
+
     var match_width = min(template_width, input_width - x);
     var match_height = min(template_height, input_height - y);
 
@@ -69,6 +74,11 @@ fn main_sqdiff_normed(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var template_width = uniforms.template_width;
     var template_height = uniforms.template_height;
 
+    // the dispatched workgroups may exceed the result, which would overwrite the next row
+    if (x > input_width - template_width || y > input_height - template_height) {
+        return;
+    }
+
     var match_width = min(template_width, input_width - x);
     var match_height = min(template_height, input_height - y);
 
@@ -108,6 +118,11 @@ fn main_ccorr(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var template_width = uniforms.template_width;
     var template_height = uniforms.template_height;
 
+    // the dispatched workgroups may exceed the result, which would overwrite the next row
+    if (x > input_width - template_width || y > input_height - template_height) {
+        return;
+    }
+
     var match_width = min(template_width, input_width - x);
     var match_height = min(template_height, input_height - y);
 
@@ -143,6 +158,11 @@ fn main_ccorr_normed(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var template_width = uniforms.template_width;
     var template_height = uniforms.template_height;
 
+    // the dispatched workgroups may exceed the result, which would overwrite the next row
+    if (x > input_width - template_width || y > input_height - template_height) {
+        return;
+    }
+
     var match_width = min(template_width, input_width - x);
     var match_height = min(template_height, input_height - y);
 
@@ -182,6 +202,11 @@ fn main_ccoeff(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var template_width = uniforms.template_width;
     var template_height = uniforms.template_height;
 
+    // the dispatched workgroups may exceed the result, which would overwrite the next row
+    if (x > input_width - template_width || y > input_height - template_height) {
+        return;
+    }
+
     var match_width = min(template_width, input_width - x);
     var match_height = min(template_height, input_height - y);
 
@@ -217,6 +242,11 @@ fn main_ccoeff_normed(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var template_width = uniforms.template_width;
     var template_height = uniforms.template_height;
 
+    // the dispatched workgroups may exceed the result, which would overwrite the next row
+    if (x > input_width - template_width || y > input_height - template_height) {
+        return;
+    }
+
     var match_width = min(template_width, input_width - x);
     var match_height = min(template_height, input_height - y);
 
@@ -0,0 +1,9 @@
+//! Helpers shared by the tests.
+
+/// A deterministic noise generator (a linear congruential generator) yielding values in `[0.0, 1.0)`.
+pub fn noise(mut seed: u32) -> impl FnMut() -> f32 {
+    move || {
+        seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+        (seed >> 8) as f32 / (1 << 24) as f32
+    }
+}
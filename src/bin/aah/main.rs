@@ -1,20 +1,42 @@
 // #![feature(associated_type_defaults)]
 // #![feature(path_file_prefix)]
 
-use std::sync::Arc;
+use std::{fs, path::PathBuf, sync::Arc};
 
+use aah_controller::{android::AahController, dry_run::DryRunController};
 use aah_core::{
-    arknights::{resource::AahResource, AahCore},
-    resource::GitRepoResource,
+    arknights::{
+        resource::{schema::schemas, AahResource},
+        supervisor::{DeviceConfig, DeviceGroupConfig, Supervisor},
+        AahCore,
+    },
+    resource::{schema::write_schemas, validate::Validate, GitRepoResource, Load},
+    schedule::{ScheduleConfig, Scheduler},
+    task::checkpoint::{Checkpoint, ResumeMode},
 };
 use clap::{CommandFactory, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(author, version, about)]
 struct Cli {
-    /// The serial number of the target device, default: 127.0.0.1:16384
+    /// The serial number of the target device, default: 127.0.0.1:16384,
+    /// can be given several times for `group`
+    #[arg(short, long)]
+    serial_number: Vec<String>,
+
+    /// Write a run report of the task or copilot to this path,
+    /// as a html page if it ends with `.html`, otherwise as json
     #[arg(short, long)]
-    serial_number: Option<String>,
+    report: Option<PathBuf>,
+
+    /// Run without sending any touch input to the device,
+    /// only print the clicks and swipes that would be performed
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Use this screenshot instead of the live screen in dry-run mode
+    #[arg(long, requires = "dry_run")]
+    screenshot: Option<PathBuf>,
 
     /// The task name want to execute
     #[command(subcommand)]
@@ -23,43 +45,162 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// run task
+    /// run task, the progress is saved to a checkpoint after each step
     Task {
         /// task name
         name: String,
+        /// resume from the checkpoint of the last interrupted run
+        #[arg(long, conflicts_with = "restart")]
+        resume: bool,
+        /// when resuming, skip the interrupted non-idempotent step instead of failing
+        #[arg(long, requires = "resume")]
+        skip_interrupted: bool,
+        /// start over and discard the checkpoint of the last interrupted run
+        #[arg(long)]
+        restart: bool,
     },
     /// run copilot
     Copilot {
         ///copilot name
         name: String,
     },
+    /// recognize the current scene of the device, matched scenes are marked with `*`
+    Scene,
+    /// validate tasks and copilots in the resource
+    Validate,
+    /// run tasks according to the schedule config, never exits
+    Schedule {
+        /// schedule config file
+        config: PathBuf,
+    },
+    /// run tasks on several devices in parallel
+    Group {
+        /// device group config, each device has its own task queue
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// tasks to run on every device given by `-s`, ignored when `config` is set
+        tasks: Vec<String>,
+    },
+    /// write json schemas of task and copilot files to the dir
+    Schema {
+        /// output dir
+        dir: PathBuf,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    let serial = cli.serial_number.unwrap_or("127.0.0.1:16384".to_string());
+    let serial = cli
+        .serial_number
+        .first()
+        .cloned()
+        .unwrap_or("127.0.0.1:16384".to_string());
     if cli.task.is_none() {
         Cli::command().print_help().unwrap();
         return;
     }
 
     let command = cli.task.as_ref().unwrap();
+    if let Commands::Schema { dir } = command {
+        write_schemas(dir, &schemas()).expect("failed to write schemas");
+        return;
+    }
+
     let resource = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .unwrap()
-        .block_on(GitRepoResource::<AahResource>::try_init(
+        .block_on(GitRepoResource::<AahResource>::try_load_or_init(
             "./.aah/resources",
             "https://github.com/AzurIce/aah-resources",
         ))
         .expect("failed to load resource");
-    let aah = AahCore::connect(serial, Arc::new(resource.inner))
-        .expect("failed to connect to the device");
+    if let Commands::Validate = command {
+        let issues = resource.validate();
+        for issue in &issues {
+            println!("{issue}");
+        }
+        if !issues.is_empty() {
+            println!("found {} problem(s)", issues.len());
+            std::process::exit(1);
+        }
+        return;
+    }
+    let resource = Arc::new(resource.inner);
+    if let Commands::Group { config, tasks } = command {
+        let config = match config {
+            Some(path) => {
+                DeviceGroupConfig::load(path).expect("failed to load device group config")
+            }
+            None => DeviceGroupConfig {
+                devices: cli
+                    .serial_number
+                    .iter()
+                    .map(|serial| DeviceConfig::new(serial, tasks.clone()))
+                    .collect(),
+            },
+        };
+        let supervisor = Supervisor::new(config)
+            .expect("no device to run, give them with --serial-number or --config");
+        let status = supervisor.run(resource);
+        for status in &status {
+            println!("{status}");
+        }
+        if !status.iter().all(|status| status.succeeded()) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    let mut aah = if cli.dry_run {
+        let controller = match &cli.screenshot {
+            Some(path) => DryRunController::with_screenshot(
+                image::open(path).expect("failed to open screenshot"),
+            ),
+            None => DryRunController::with_controller(Box::new(
+                AahController::connect(&serial).expect("failed to connect to the device"),
+            )),
+        };
+        AahCore::new_dry_run(controller, resource)
+    } else {
+        AahCore::connect(&serial, resource)
+    }
+    .expect("failed to create AahCore");
+    // 调度模式下不会退出，不记录报告
+    if cli.report.is_some() && !matches!(command, Commands::Schedule { .. }) {
+        aah.enable_report();
+    }
     match command {
-        Commands::Task { name } => {
-            if let Err(err) = aah.run_task(name) {
-                println!("task failed: {err}")
+        Commands::Task {
+            name,
+            resume,
+            skip_interrupted,
+            restart,
+        } => {
+            // 每台设备的每个任务的检查点分开保存，中断的任务不影响其他任务的运行
+            let checkpoint_path = PathBuf::from(format!(
+                "./.aah/checkpoints/{}/{}.json",
+                serial.replace([':', '.'], "_"),
+                name.replace(['/', '\\'], "_")
+            ));
+            let checkpoint = Checkpoint::load(&checkpoint_path).expect("failed to load checkpoint");
+            let mode = match (resume, skip_interrupted) {
+                (_, true) => ResumeMode::SkipInterrupted,
+                (true, false) => ResumeMode::Resume,
+                _ => ResumeMode::Restart,
+            };
+            match checkpoint {
+                Some(checkpoint) if mode == ResumeMode::Restart && !restart => {
+                    println!(
+                        "task {} was interrupted at step {}, use --resume to continue or --restart to start over",
+                        checkpoint.task, checkpoint.progress.step
+                    );
+                }
+                _ => {
+                    if let Err(err) = aah.run_task_with_checkpoint(name, &checkpoint_path, mode) {
+                        println!("task failed: {err}")
+                    }
+                }
             }
         }
         Commands::Copilot { name } => {
@@ -67,5 +208,39 @@ fn main() {
                 println!("copilot failed: {err}")
             }
         }
+        Commands::Scene => match aah.recognize_scene() {
+            Ok(output) => {
+                for candidate in &output.candidates {
+                    let mark = if candidate.matched { "*" } else { " " };
+                    println!("{mark} {} {:.3}", candidate.name, candidate.confidence);
+                }
+                match output.scene {
+                    Some(scene) => println!("current scene: {scene}"),
+                    None => println!("current scene: unknown"),
+                }
+            }
+            Err(err) => println!("failed to recognize scene: {err}"),
+        },
+        Commands::Schedule { config } => {
+            let config = ScheduleConfig::load(config).expect("failed to load schedule config");
+            // 每台设备的调度状态分开保存
+            let state_path = PathBuf::from(format!(
+                "./.aah/schedule/{}.toml",
+                serial.replace([':', '.'], "_")
+            ));
+            let mut scheduler =
+                Scheduler::new(config, Some(state_path)).expect("failed to create scheduler");
+            scheduler.run(|task| aah.run_task(task))
+        }
+        Commands::Validate | Commands::Schema { .. } | Commands::Group { .. } => unreachable!(),
+    }
+
+    if let (Some(path), Some(report)) = (&cli.report, aah.take_reports().pop()) {
+        let content = match path.extension().is_some_and(|ext| ext == "html") {
+            true => report.to_html(),
+            false => report.to_json(),
+        };
+        fs::write(path, content).expect("failed to write report");
+        println!("report written to {}", path.display());
     }
 }